@@ -0,0 +1,60 @@
+//! Criterion benchmarks of applying consecutive transactions of the same client as one run, with
+//! a single account lookup, against applying them one by one, on synthetic workloads where clients
+//! come in runs of up to 1, 16 and 64 transactions.
+//!
+//! Both sides go through [`ClientsDatabase::process_all`]. The one by one side enables retries,
+//! which need the database between transactions, so every row pays for its own lookup and
+//! report entry. The workloads have no disputes of unseen deposits, so nothing is retried.
+//!
+//! Run with `cargo bench --bench client_runs`.
+
+use std::hint::black_box;
+
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use payengine::{accounts::ClientsDatabase, synth::Workload};
+
+const TRANSACTIONS: usize = 1_000_000;
+
+fn client_runs(c: &mut Criterion) {
+    let mut group = c.benchmark_group("client_runs");
+    group.throughput(Throughput::Elements(TRANSACTIONS as u64));
+    group.sample_size(10);
+    for run_length in [1, 16, 64] {
+        let transactions = Workload {
+            transactions: TRANSACTIONS,
+            run_length,
+            ..Default::default()
+        }
+        .generate();
+        group.bench_function(format!("one_by_one/{run_length}"), |b| {
+            b.iter_batched(
+                || {
+                    let mut db = ClientsDatabase::new();
+                    db.enable_retry(TRANSACTIONS);
+                    db
+                },
+                |mut db| {
+                    let report = db.process_all(transactions.iter().copied());
+                    assert_eq!(report.retried, 0);
+                    black_box(report);
+                    db
+                },
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_function(format!("runs/{run_length}"), |b| {
+            b.iter_batched(
+                ClientsDatabase::new,
+                |mut db| {
+                    black_box(db.process_all(transactions.iter().copied()));
+                    db
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, client_runs);
+criterion_main!(benches);
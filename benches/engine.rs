@@ -0,0 +1,135 @@
+//! Criterion benchmarks of parsing and processing on synthetic workloads from
+//! [`payengine::synth`], as a baseline for performance work.
+//!
+//! Run with `cargo bench --bench engine`, or e.g. `cargo bench --bench engine -- process` for one
+//! group.
+
+use std::{hint::black_box, io::Cursor};
+
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use payengine::{
+    accounts::ClientsDatabase,
+    amount::Amount,
+    parser::Row,
+    pipeline::RowStream,
+    sharded::ShardedDatabase,
+    synth::{IdOrder, Workload},
+};
+
+const TRANSACTIONS: usize = 1_000_000;
+
+fn workload(id_order: IdOrder) -> Workload {
+    Workload {
+        transactions: TRANSACTIONS,
+        id_order,
+        ..Default::default()
+    }
+}
+
+fn csv(workload: &Workload) -> Vec<u8> {
+    let mut csv = Vec::new();
+    workload.write_csv(&mut csv).unwrap();
+    csv
+}
+
+fn parsing(c: &mut Criterion) {
+    let csv = csv(&workload(IdOrder::Sequential));
+    let lines = csv
+        .split(|b| *b == b'\n')
+        .skip(1)
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>();
+    let amounts = lines
+        .iter()
+        .filter_map(|l| l.rsplit(|b| *b == b',').next())
+        .map(|a| a.trim_ascii())
+        .filter(|a| !a.is_empty())
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Elements(lines.len() as u64));
+    group.bench_function("row", |b| {
+        b.iter(|| {
+            for line in &lines {
+                let _ = black_box(Row::parse(black_box(line)));
+            }
+        })
+    });
+    group.throughput(Throughput::Elements(amounts.len() as u64));
+    group.bench_function("amount", |b| {
+        b.iter(|| {
+            for amount in &amounts {
+                black_box(Amount::parse(black_box(amount)));
+            }
+        })
+    });
+    group.finish();
+}
+
+fn processing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process");
+    group.throughput(Throughput::Elements(TRANSACTIONS as u64));
+    group.sample_size(10);
+    for (name, id_order) in [
+        ("sequential", IdOrder::Sequential),
+        ("jittered", IdOrder::Jittered(1024)),
+        ("shuffled", IdOrder::Shuffled),
+    ] {
+        let transactions = workload(id_order).generate();
+        group.bench_function(format!("single/{name}"), |b| {
+            b.iter_batched(
+                ClientsDatabase::new,
+                |mut db| {
+                    black_box(db.process_all(transactions.iter().copied()));
+                    db
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    let transactions = workload(IdOrder::Sequential).generate();
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    group.bench_function("sharded", |b| {
+        b.iter_batched(
+            || ShardedDatabase::new(threads),
+            |mut db| {
+                black_box(db.process_parallel(transactions.iter().copied()));
+                db
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn end_to_end(c: &mut Criterion) {
+    let csv = csv(&workload(IdOrder::Sequential));
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+
+    let mut group = c.benchmark_group("end_to_end");
+    group.throughput(Throughput::Bytes(csv.len() as u64));
+    group.sample_size(10);
+    // Same as the CLI without features, minus writing the report.
+    group.bench_function("csv", |b| {
+        b.iter_batched(
+            || (csv.clone(), ShardedDatabase::new(threads)),
+            |(csv, mut db)| {
+                let rows = RowStream::spawn(Cursor::new(csv))
+                    .filter_map(Result::ok)
+                    .map(|row| (row.client_id, row.transaction));
+                black_box(db.process_parallel(rows));
+                db
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parsing, processing, end_to_end);
+criterion_main!(benches);
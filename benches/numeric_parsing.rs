@@ -0,0 +1,134 @@
+//! Criterion benchmarks of parsing the numeric columns of 10M synthetic rows with SWAR digit
+//! parsing, against the previous byte at a time parsing based on the `atoi` crate.
+//!
+//! Run with `cargo bench --bench numeric_parsing`.
+
+use std::hint::black_box;
+
+use atoi::FromRadix10Checked;
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use payengine::{amount::Amount, parser::Row, synth::Workload};
+
+const ROWS: usize = 10_000_000;
+
+/// Previous implementation of `Amount::parse`.
+fn atoi_amount(bytes: &[u8]) -> Option<u64> {
+    let (whole, size) = u64::from_radix_10_checked(bytes);
+    if size == 0 {
+        return None;
+    }
+    let whole = whole?.checked_mul(10_000)?;
+    match bytes.get(size).copied() {
+        Some(b'.') => {
+            let fract_b = &bytes[size + 1..];
+            let mut fract = 0;
+            for place in 0..4 {
+                let digit: u16 = match fract_b.get(place) {
+                    Some(b) => atoi::ascii_to_digit(*b)?,
+                    None => break,
+                };
+                fract += digit * 10u16.pow(4 - place as u32 - 1);
+            }
+            for byte in fract_b.get(4..).unwrap_or_default() {
+                atoi::ascii_to_digit::<u8>(*byte)?;
+            }
+            whole.checked_add(fract as u64)
+        }
+        Some(_) => None,
+        None => Some(whole),
+    }
+}
+
+/// Previous strict id parsing.
+fn atoi_id<T: FromRadix10Checked>(bytes: &[u8]) -> Option<T> {
+    match T::from_radix_10_checked(bytes) {
+        (value, used) if used > 0 && used == bytes.len() => value,
+        _ => None,
+    }
+}
+
+/// Previous implementation of `Row::parse`, returning a tuple instead of a `Row`.
+fn atoi_row(line: &[u8]) -> Option<(u8, u16, u32, u64)> {
+    // Same column splitting as Row::parse.
+    let mut columns = memchr::memchr_iter(b',', line)
+        .chain(Some(line.len()))
+        .scan(0usize, |start, end| {
+            let column = line[*start..end].trim_ascii();
+            *start = end + 1;
+            Some(column)
+        });
+    let kind = match columns.next()? {
+        b"deposit" => 0,
+        b"withdrawal" => 1,
+        b"dispute" => 2,
+        b"resolve" => 3,
+        b"chargeback" => 4,
+        _ => return None,
+    };
+    let client_id = atoi_id(columns.next()?)?;
+    let tx_id = atoi_id(columns.next()?)?;
+    let amount = columns.next()?;
+    let amount = match kind {
+        0 | 1 => atoi_amount(amount)?,
+        _ if amount.is_empty() => 0,
+        _ => return None,
+    };
+    Some((kind, client_id, tx_id, amount))
+}
+
+fn numeric_parsing(c: &mut Criterion) {
+    let mut csv = Vec::new();
+    Workload {
+        transactions: ROWS,
+        ..Default::default()
+    }
+    .write_csv(&mut csv)
+    .unwrap();
+    let lines = csv
+        .split(|b| *b == b'\n')
+        .skip(1)
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>();
+    let amounts = lines
+        .iter()
+        .filter_map(|l| l.rsplit(|b| *b == b',').next().map(<[u8]>::trim_ascii))
+        .filter(|a| !a.is_empty())
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("numeric_parsing");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(lines.len() as u64));
+    group.bench_function("rows/atoi", |b| {
+        b.iter(|| {
+            for line in &lines {
+                black_box(atoi_row(black_box(line)));
+            }
+        })
+    });
+    group.bench_function("rows/swar", |b| {
+        b.iter(|| {
+            for line in &lines {
+                let _ = black_box(Row::parse(black_box(line)));
+            }
+        })
+    });
+    group.throughput(Throughput::Elements(amounts.len() as u64));
+    group.bench_function("amounts/atoi", |b| {
+        b.iter(|| {
+            for amount in &amounts {
+                black_box(atoi_amount(black_box(amount)));
+            }
+        })
+    });
+    group.bench_function("amounts/swar", |b| {
+        b.iter(|| {
+            for amount in &amounts {
+                black_box(Amount::parse(black_box(amount)));
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, numeric_parsing);
+criterion_main!(benches);
@@ -0,0 +1,370 @@
+use std::{
+    hash::{BuildHasher, RandomState},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use payengine::accounts::ClientId;
+use reqwest::{RequestBuilder, Response, StatusCode, header};
+
+use crate::{
+    error::{Error, Rejection},
+    types::{Account, BatchOutcome, RawBatchOutcome, Transaction},
+};
+
+const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+/// A client of one server, cheap to clone and share between tasks, as connections are pooled.
+#[derive(Clone, Debug)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    retries: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+}
+
+/// Options of a [`Client`], from [`Client::builder`].
+#[derive(Clone, Debug)]
+pub struct ClientBuilder {
+    base_url: String,
+    retries: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+    timeout: Duration,
+}
+
+impl ClientBuilder {
+    /// Times a request is sent again after failing, 3 by default.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Wait before the first retry, doubled for each of the next ones up to `max`, unless the
+    /// server answers how long to wait with `Retry-After`, up to `max` as well. 100ms and 10s by
+    /// default.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Time an attempt may take, including reading the response, 30s by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn build(self) -> Result<Client, Error> {
+        let http = reqwest::Client::builder().timeout(self.timeout).build()?;
+        Ok(Client {
+            http,
+            base_url: self.base_url.trim_end_matches('/').to_owned(),
+            retries: self.retries,
+            backoff: self.backoff,
+            max_backoff: self.max_backoff,
+        })
+    }
+}
+
+impl Client {
+    /// A client of the server at `base_url`, e.g. `http://localhost:8080`, with the default
+    /// options.
+    pub fn new(base_url: impl Into<String>) -> Result<Self, Error> {
+        Self::builder(base_url).build()
+    }
+
+    pub fn builder(base_url: impl Into<String>) -> ClientBuilder {
+        ClientBuilder {
+            base_url: base_url.into(),
+            retries: 3,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Apply a transaction, with a new idempotency key.
+    pub async fn submit(&self, transaction: &Transaction) -> Result<(), Error> {
+        self.submit_with_key(transaction, &new_key()).await
+    }
+
+    /// Apply a transaction with the idempotency key, which should be unique to it, e.g. derived
+    /// from the id of what caused it. The server answers a transaction submitted again with the
+    /// same key like the first time without applying it again, as long as it remembers the key.
+    pub async fn submit_with_key(&self, transaction: &Transaction, key: &str) -> Result<(), Error> {
+        let body = serde_json::to_vec(transaction)?;
+        let response = self
+            .send(|| {
+                self.http
+                    .post(self.url("/transactions"))
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header(IDEMPOTENCY_KEY, key)
+                    .body(body.clone())
+            })
+            .await?;
+        match response.status() {
+            StatusCode::OK => Ok(()),
+            status => Err(error(status, response).await),
+        }
+    }
+
+    /// Apply transactions in order, with a new idempotency key for the batch. Those malformed or
+    /// rejected are skipped, so it may be partly applied.
+    pub async fn submit_batch(&self, transactions: &[Transaction]) -> Result<BatchOutcome, Error> {
+        self.submit_batch_with_key(transactions, &new_key()).await
+    }
+
+    /// Same as [`Self::submit_batch`], with the idempotency key as for [`Self::submit_with_key`].
+    pub async fn submit_batch_with_key(
+        &self,
+        transactions: &[Transaction],
+        key: &str,
+    ) -> Result<BatchOutcome, Error> {
+        let mut body = Vec::new();
+        for transaction in transactions {
+            serde_json::to_writer(&mut body, transaction)?;
+            body.push(b'\n');
+        }
+        let response = self
+            .send(|| {
+                self.http
+                    .post(self.url("/transactions"))
+                    .header(header::CONTENT_TYPE, "application/x-ndjson")
+                    .header(IDEMPOTENCY_KEY, key)
+                    .body(body.clone())
+            })
+            .await?;
+        if response.status() != StatusCode::OK {
+            return Err(error(response.status(), response).await);
+        }
+        let outcome: RawBatchOutcome = serde_json::from_slice(&response.bytes().await?)?;
+        Ok(outcome.into())
+    }
+
+    /// The client's balances, None without an account.
+    pub async fn account(&self, client: ClientId) -> Result<Option<Account>, Error> {
+        let url = self.url(&format!("/accounts/{client}"));
+        let response = self.send(|| self.http.get(&url)).await?;
+        match response.status() {
+            StatusCode::OK => Ok(Some(serde_json::from_slice(&response.bytes().await?)?)),
+            StatusCode::NOT_FOUND => Ok(None),
+            status => Err(error(status, response).await),
+        }
+    }
+
+    /// Balances of all accounts by client id.
+    pub async fn accounts(&self) -> Result<Vec<Account>, Error> {
+        let url = self.url("/accounts");
+        let response = self.send(|| self.http.get(&url)).await?;
+        if response.status() != StatusCode::OK {
+            return Err(error(response.status(), response).await);
+        }
+        let body = response.bytes().await?;
+        body.split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).map_err(Error::from))
+            .collect()
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+
+    /// Send the request built by `request` until it's answered without a reason to retry, or
+    /// retries are exhausted, in which case the last response or error is returned.
+    async fn send(&self, request: impl Fn() -> RequestBuilder) -> Result<Response, Error> {
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+        loop {
+            let result = request().send().await;
+            let retry_after = match &result {
+                Ok(response) if retryable(response.status()) => response
+                    .headers()
+                    .get(header::RETRY_AFTER)
+                    .and_then(|secs| secs.to_str().ok()?.parse().ok())
+                    .map(Duration::from_secs),
+                Err(e) if e.is_connect() || e.is_timeout() => None,
+                _ => return Ok(result?),
+            };
+            if attempt == self.retries {
+                return Ok(result?);
+            }
+            attempt += 1;
+            tokio::time::sleep(retry_after.unwrap_or(backoff).min(self.max_backoff)).await;
+            backoff = (backoff * 2).min(self.max_backoff);
+        }
+    }
+}
+
+/// Responses worth retrying: a request with the same idempotency key in progress, being over the
+/// rate limits, and server errors.
+fn retryable(status: StatusCode) -> bool {
+    status == StatusCode::CONFLICT
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status.is_server_error()
+}
+
+/// The error of a response which isn't a success.
+async fn error(status: StatusCode, response: Response) -> Error {
+    let body = match response.text().await {
+        Ok(body) => body,
+        Err(e) => return e.into(),
+    };
+    match serde_json::from_str::<Rejection>(&body) {
+        Ok(reason) => Error::Rejected {
+            status: status.as_u16(),
+            reason,
+        },
+        Err(_) => Error::Status {
+            status: status.as_u16(),
+            body,
+        },
+    }
+}
+
+/// A key unique to this process and call: a random prefix drawn once, and a counter.
+fn new_key() -> String {
+    static PREFIX: std::sync::OnceLock<u64> = std::sync::OnceLock::new();
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let prefix = PREFIX.get_or_init(|| RandomState::new().hash_one(std::process::id()));
+    format!("{prefix:016x}-{}", COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+    use payengine::{
+        alerts::AlertMonitor,
+        amount::Amount,
+        concurrent::ConcurrentClientsDatabase,
+        ratelimit::{RateLimiter, RateLimits},
+        server::{Readiness, serve},
+    };
+    use tokio::{net::TcpListener, sync::oneshot};
+
+    use crate::{Client, Error, Transaction, client::new_key};
+
+    async fn start(limits: RateLimits) -> (SocketAddr, oneshot::Sender<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        tokio::spawn(serve(
+            listener,
+            Arc::new(ConcurrentClientsDatabase::new(2)),
+            Readiness::default(),
+            AlertMonitor::default(),
+            RateLimiter::new(limits),
+            None,
+            async {
+                stopped.await.ok();
+            },
+        ));
+        (addr, stop)
+    }
+
+    fn amount(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_client() {
+        let (addr, _stop) = start(RateLimits::default()).await;
+        let client = Client::new(format!("http://{addr}/")).unwrap();
+
+        client
+            .submit(&Transaction::deposit(7, 1, amount("2.5")))
+            .await
+            .unwrap();
+        let e = client
+            .submit(&Transaction::withdrawal(7, 2, amount("5")))
+            .await
+            .unwrap_err();
+        assert!(matches!(e, Error::Rejected { status: 422, .. }));
+        assert_eq!(e.code(), Some("E_INSUFFICIENT_FUNDS"));
+        assert!(!e.is_retryable());
+
+        // Submitted again with the same key, not applied again.
+        let deposit = Transaction::deposit(8, 3, amount("1"));
+        client.submit_with_key(&deposit, "k").await.unwrap();
+        client.submit_with_key(&deposit, "k").await.unwrap();
+        let account = client.account(8).await.unwrap().unwrap();
+        assert_eq!(account.client, 8);
+        assert_eq!(account.balances.total, amount("1"));
+        assert_eq!(client.account(9).await.unwrap(), None);
+
+        let outcome = client
+            .submit_batch(&[
+                Transaction::dispute(8, 3),
+                Transaction::resolve(8, 3),
+                Transaction::chargeback(8, 3),
+            ])
+            .await
+            .unwrap();
+        assert_eq!((outcome.applied, outcome.rejected), (2, 1));
+        assert!(outcome.results[..2].iter().all(Result::is_ok));
+        assert_eq!(
+            outcome.results[2].as_ref().unwrap_err().code,
+            "E_CHARGEBACK_NOT_DISPUTED"
+        );
+
+        let accounts = client.accounts().await.unwrap();
+        assert_eq!(
+            accounts.iter().map(|a| a.client).collect::<Vec<_>>(),
+            [7, 8]
+        );
+        assert_eq!(accounts[0].balances.available, amount("2.5"));
+    }
+
+    #[tokio::test]
+    async fn test_retries() {
+        // Over the limit, then retried after the second answered by `Retry-After`.
+        let (addr, _stop) = start(RateLimits {
+            per_second: Some(1),
+            per_minute: None,
+        })
+        .await;
+        let client = Client::new(format!("http://{addr}")).unwrap();
+        client
+            .submit(&Transaction::deposit(7, 1, amount("1")))
+            .await
+            .unwrap();
+        client
+            .submit(&Transaction::deposit(7, 2, amount("1")))
+            .await
+            .unwrap();
+        let client = Client::builder(format!("http://{addr}"))
+            .retries(0)
+            .build()
+            .unwrap();
+        let e = client
+            .submit(&Transaction::deposit(7, 3, amount("1")))
+            .await
+            .unwrap_err();
+        assert_eq!(e.code(), Some("E_RATE_LIMITED"));
+        assert!(e.is_retryable());
+
+        // Nothing listening.
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let client = Client::builder(format!("http://{addr}"))
+            .retries(2)
+            .backoff(Duration::from_millis(1), Duration::from_millis(2))
+            .build()
+            .unwrap();
+        let e = client.accounts().await.unwrap_err();
+        assert!(matches!(e, Error::Http(_)) && e.is_retryable());
+    }
+
+    #[test]
+    fn test_new_key() {
+        let (a, b) = (new_key(), new_key());
+        assert_ne!(a, b);
+        assert_eq!(a.split_once('-').unwrap().0, b.split_once('-').unwrap().0);
+    }
+}
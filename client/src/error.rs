@@ -0,0 +1,55 @@
+use std::fmt;
+
+use serde::Deserialize;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// A transaction answered as malformed (400) or rejected (422), or over the client's rate
+    /// limits (429) still after all retries.
+    #[error("{reason}")]
+    Rejected { status: u16, reason: Rejection },
+    /// Any other response which isn't a success.
+    #[error("unexpected response {status}: {body}")]
+    Status { status: u16, body: String },
+    /// The request couldn't be sent or its response received, or the base URL is invalid.
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error("invalid response: {0}")]
+    Decode(#[from] serde_json::Error),
+}
+
+impl Error {
+    /// Reason code of a malformed or rejected transaction, e.g. `E_INSUFFICIENT_FUNDS`.
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            Error::Rejected { reason, .. } => Some(&reason.code),
+            _ => None,
+        }
+    }
+
+    /// Whether the transaction may be applied if submitted again later, i.e. the client was over
+    /// its rate limits, or the server couldn't be reached.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Rejected { status, .. } => *status == 429,
+            Error::Status { status, .. } => *status == 409 || *status >= 500,
+            Error::Http(e) => e.is_connect() || e.is_timeout(),
+            Error::Decode(_) => false,
+        }
+    }
+}
+
+/// Why a transaction wasn't applied, as answered by the server.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct Rejection {
+    /// E.g. `E_INSUFFICIENT_FUNDS`, see `payengine::error::ErrorKind::code`.
+    pub code: String,
+    #[serde(rename = "error")]
+    pub message: String,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
@@ -0,0 +1,31 @@
+//! Client of the HTTP API of `payengine serve`, with typed requests and responses, retries and
+//! idempotency keys, see `payengine::server::router` for the API itself:
+//!
+//! ```no_run
+//! use payengine_client::{Client, Transaction};
+//!
+//! # async fn run() -> Result<(), payengine_client::Error> {
+//! let client = Client::new("http://localhost:8080")?;
+//! client
+//!     .submit(&Transaction::deposit(7, 1, "2.5".parse().unwrap()))
+//!     .await?;
+//! let account = client.account(7).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Every submission has an `Idempotency-Key`, generated unless given, so it's retried on
+//! connection errors, 5xx responses and while the client is over its rate limits without being
+//! applied twice.
+
+mod client;
+mod error;
+mod types;
+
+pub use client::{Client, ClientBuilder};
+pub use error::{Error, Rejection};
+pub use payengine::{
+    accounts::{AccountView, ClientId, TransactionId, TransactionKind},
+    amount::Amount,
+};
+pub use types::{Account, BatchOutcome, Transaction};
@@ -0,0 +1,104 @@
+use payengine::{
+    accounts::{AccountView, ClientId, TransactionId, TransactionKind},
+    amount::Amount,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Rejection;
+
+/// A transaction of `POST /transactions`, e.g. `Transaction::deposit(7, 1, amount)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct Transaction {
+    #[serde(rename = "type")]
+    pub kind: TransactionKind,
+    pub client: ClientId,
+    pub tx: TransactionId,
+    /// Only deposits and withdrawals have one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<Amount>,
+}
+
+impl Transaction {
+    pub fn deposit(client: ClientId, tx: TransactionId, amount: Amount) -> Self {
+        Self::new(TransactionKind::Deposit, client, tx, Some(amount))
+    }
+
+    pub fn withdrawal(client: ClientId, tx: TransactionId, amount: Amount) -> Self {
+        Self::new(TransactionKind::Withdrawal, client, tx, Some(amount))
+    }
+
+    /// Dispute the deposit `tx` of the client.
+    pub fn dispute(client: ClientId, tx: TransactionId) -> Self {
+        Self::new(TransactionKind::Dispute, client, tx, None)
+    }
+
+    pub fn resolve(client: ClientId, tx: TransactionId) -> Self {
+        Self::new(TransactionKind::Resolve, client, tx, None)
+    }
+
+    pub fn chargeback(client: ClientId, tx: TransactionId) -> Self {
+        Self::new(TransactionKind::Chargeback, client, tx, None)
+    }
+
+    fn new(
+        kind: TransactionKind,
+        client: ClientId,
+        tx: TransactionId,
+        amount: Option<Amount>,
+    ) -> Self {
+        Transaction {
+            kind,
+            client,
+            tx,
+            amount,
+        }
+    }
+}
+
+/// Balances of a client, as of `GET /accounts/{client}`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub struct Account {
+    pub client: ClientId,
+    #[serde(flatten)]
+    pub balances: AccountView<'static>,
+}
+
+/// Outcome of a batch, of each transaction in order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchOutcome {
+    pub applied: usize,
+    pub rejected: usize,
+    pub results: Vec<Result<(), Rejection>>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RawBatchOutcome {
+    applied: usize,
+    rejected: usize,
+    results: Vec<RawResult>,
+}
+
+/// `{"error":"...","code":"E_..."}`, or `{"status":"applied"}`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawResult {
+    Rejected(Rejection),
+    Applied {},
+}
+
+impl From<RawBatchOutcome> for BatchOutcome {
+    fn from(raw: RawBatchOutcome) -> Self {
+        BatchOutcome {
+            applied: raw.applied,
+            rejected: raw.rejected,
+            results: raw
+                .results
+                .into_iter()
+                .map(|result| match result {
+                    RawResult::Applied { .. } => Ok(()),
+                    RawResult::Rejected(rejection) => Err(rejection),
+                })
+                .collect(),
+        }
+    }
+}
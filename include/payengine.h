@@ -0,0 +1,79 @@
+/*
+ * C API of the payments engine, built with the "ffi" feature, e.g.
+ *
+ *     cargo rustc --lib --release --features ffi --crate-type cdylib
+ *
+ * An engine keeps its accounts in memory. Functions taking an engine must not be called for the
+ * same engine from several threads at once; separate engines are independent.
+ *
+ * Amounts are fixed-point integers in ten-thousandths, e.g. 25000 for 2.5.
+ */
+
+#ifndef PAYENGINE_H
+#define PAYENGINE_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define PAYENGINE_OK 0
+/* A blank or header line, nothing was applied. */
+#define PAYENGINE_SKIPPED 1
+#define PAYENGINE_MALFORMED 2
+#define PAYENGINE_REJECTED 3
+#define PAYENGINE_NOT_FOUND 4
+/* A null pointer where one isn't allowed. */
+#define PAYENGINE_INVALID (-1)
+
+typedef struct payengine payengine;
+
+typedef struct {
+    uint16_t client;
+    uint64_t available;
+    uint64_t held;
+    uint64_t total;
+    bool locked;
+} payengine_account;
+
+/* A new engine without accounts, freed with payengine_free. */
+payengine *payengine_new(void);
+
+/* Free an engine. NULL is ignored. */
+void payengine_free(payengine *engine);
+
+/*
+ * Apply a row of the CSV input, e.g. "deposit, 7, 9, 2.5", of len bytes with or without its line
+ * ending. Returns PAYENGINE_OK if applied, PAYENGINE_SKIPPED for a blank or header line, or
+ * PAYENGINE_MALFORMED or PAYENGINE_REJECTED with the reason left for payengine_last_error.
+ */
+int payengine_process_line(payengine *engine, const char *line, size_t len);
+
+/*
+ * The reason code of the last line which wasn't applied, e.g. "E_INSUFFICIENT_FUNDS", or NULL if
+ * none failed yet. Valid until the next call with the engine.
+ */
+const char *payengine_last_error(const payengine *engine);
+
+/*
+ * Write the balances of client to out. Returns PAYENGINE_OK, or PAYENGINE_NOT_FOUND leaving out
+ * as it was if the client has no account.
+ */
+int payengine_get_account(const payengine *engine, uint16_t client, payengine_account *out);
+
+/*
+ * Write the report of all accounts by client id as CSV with its header into buf of cap bytes,
+ * NUL-terminated if there's room. Returns the length of the whole report without the NUL, like
+ * snprintf, so a buf too small can be retried with one of that length plus one. buf may be NULL
+ * if cap is 0.
+ */
+size_t payengine_export_csv(const payengine *engine, char *buf, size_t cap);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* PAYENGINE_H */
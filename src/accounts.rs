@@ -1,8 +1,42 @@
-use std::collections::{HashMap, hash_map::Entry};
+use std::{
+    collections::{HashMap, HashSet, hash_map::Entry},
+    hash::BuildHasher,
+    io::{BufRead, Write},
+    iter,
+    ops::{Bound, Range, RangeBounds},
+    path::Path,
+    sync::{
+        Arc,
+        mpsc::{Receiver, Sender},
+    },
+    time::{Duration, Instant},
+};
 
-use crate::{Error, amount::Amount};
+use crate::{
+    amount::Amount,
+    audit::{AuditLog, AuditRecord, Provenance, ProvenanceLog},
+    breaker::{BreakerConfig, CircuitBreaker},
+    deposits::{DepositStore, SortedVecDeposits},
+    error::{LedgerError, ParseError},
+    events::{AccountEvent, Subscribers},
+    evict::Eviction,
+    history::{BalanceHistory, Timestamp},
+    metrics::AppliedCounts,
+    parser::BalanceRow,
+    process::{PolicyMap, ProcessReport, RejectionMode},
+    progress::Progress,
+    reader::{DatabaseReader, DirtyChunks, Snapshot},
+    reservoir::RejectSamples,
+    retry::RetryQueue,
+    review::ReviewRules,
+    slowlog::{SlowLog, SlowOp, SlowOpKind},
+    spill::SpillStore,
+    stats::DatabaseStats,
+};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum TransactionKind {
     Deposit,
     Withdrawal,
@@ -12,6 +46,25 @@ pub enum TransactionKind {
 }
 
 impl TransactionKind {
+    pub const ALL: [TransactionKind; 5] = [
+        TransactionKind::Deposit,
+        TransactionKind::Withdrawal,
+        TransactionKind::Dispute,
+        TransactionKind::Resolve,
+        TransactionKind::Chargeback,
+    ];
+
+    /// Name as used in the CSV input.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionKind::Deposit => "deposit",
+            TransactionKind::Withdrawal => "withdrawal",
+            TransactionKind::Dispute => "dispute",
+            TransactionKind::Resolve => "resolve",
+            TransactionKind::Chargeback => "chargeback",
+        }
+    }
+
     pub fn has_amount(&self) -> bool {
         matches!(self, TransactionKind::Deposit | TransactionKind::Withdrawal)
     }
@@ -20,35 +73,77 @@ impl TransactionKind {
 pub type TransactionId = u32;
 pub type ClientId = u16;
 
+/// All client ids within the range, in order.
+pub(crate) fn client_ids(
+    range: impl RangeBounds<ClientId>,
+) -> std::iter::Map<Range<u32>, fn(u32) -> ClientId> {
+    let start = match range.start_bound() {
+        Bound::Included(s) => *s as u32,
+        Bound::Excluded(s) => *s as u32 + 1,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(e) => *e as u32 + 1,
+        Bound::Excluded(e) => *e as u32,
+        Bound::Unbounded => ClientId::MAX as u32 + 1,
+    };
+    (start..end).map(|c| c as ClientId)
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Transaction {
     pub kind: TransactionKind,
     pub id: TransactionId,
     pub amount: Amount,
 }
 
-struct Deposit {
-    transaction_id: TransactionId,
-    amount: Amount,
-    is_disputed: bool,
-}
-
 #[derive(Default)]
-pub struct Account {
+pub struct Account<D = SortedVecDeposits> {
     // We only store deposits as only deposits can be disputed (this isn't clearly specified but can
     // be deduced from the description of dispute section).
-    // Stored in TXID order for binary search.
     //
     // We could store other transactions to detect duplicate transaction IDs. However for the toy implementation
     // this would be overkill and would decrease perf just to detect one edge case.
-    deposits: Vec<Deposit>,
+    deposits: D,
     total: Amount,
     // Held can be greater than total, in case there's a transaction under dispute
     held: Amount,
+    // Number of deposits currently under dispute, kept so stats don't need to scan deposits.
+    open_disputes: usize,
     frozen: bool,
+    // Applied deposits and chargebacks, for the chargeback rate of `ReviewRules`.
+    deposits_applied: u64,
+    chargebacks: u64,
+    flagged: bool,
+    // Deposits charged back, which are settled for good: they can't be disputed, resolved or
+    // charged back again, e.g. once the account is unfrozen. Kept apart from the deposits, which
+    // may be spilled or evicted.
+    charged_back: Vec<TransactionId>,
 }
 
 impl Account {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<D: DepositStore> Account<D> {
+    /// An account with known balances but no deposits, e.g. carried over from a previous run.
+    pub(crate) fn with_balances(balances: AccountView) -> Self {
+        Account {
+            deposits: D::default(),
+            total: balances.total,
+            held: balances.held,
+            open_disputes: 0,
+            frozen: balances.locked,
+            deposits_applied: 0,
+            chargebacks: 0,
+            flagged: balances.flagged,
+            charged_back: Vec::new(),
+        }
+    }
+
     pub fn available_for_withdrawal(&self) -> Amount {
         if self.frozen {
             return Amount::zero();
@@ -68,127 +163,1254 @@ impl Account {
         self.frozen
     }
 
-    fn find_deposit_id(&self, tid: TransactionId) -> Result<usize, crate::Error> {
-        let deposit_idx = self
-            .deposits
-            .binary_search_by_key(&tid, |d| d.transaction_id)
-            .map_err(|_| Error::TransactionNotFound)?;
-        Ok(deposit_idx)
+    /// Whether the account was flagged for review, see [`ReviewRules`].
+    pub fn is_flagged(&self) -> bool {
+        self.flagged
+    }
+
+    /// Deposits applied since the account was created or imported.
+    pub fn deposits_applied(&self) -> u64 {
+        self.deposits_applied
+    }
+
+    /// Chargebacks applied since the account was created or imported.
+    pub fn chargebacks(&self) -> u64 {
+        self.chargebacks
+    }
+
+    /// After applying a transaction of `kind`, flag the account if its chargeback rate now
+    /// exceeds `rules`. Flags are never cleared here.
+    pub(crate) fn review(&mut self, kind: TransactionKind, rules: &ReviewRules) {
+        if matches!(kind, TransactionKind::Deposit | TransactionKind::Chargeback) {
+            self.flagged |= rules.exceeded(self.deposits_applied, self.chargebacks);
+        }
+    }
+
+    /// Number of deposits retained for potential disputes.
+    pub fn deposit_count(&self) -> usize {
+        self.deposits.len()
+    }
+
+    pub fn open_disputes(&self) -> usize {
+        self.open_disputes
+    }
+
+    /// Ids and amounts of deposits currently under dispute, by id. Deposits spilled to disk are
+    /// never disputed, so this is complete.
+    pub fn disputed_deposits(&self) -> Vec<(TransactionId, Amount)> {
+        let mut disputed = Vec::with_capacity(self.open_disputes);
+        self.deposits
+            .for_each_disputed(|id, amount| disputed.push((id, amount)));
+        disputed.sort_unstable_by_key(|(id, _)| *id);
+        disputed
+    }
+
+    /// Whether the held funds are exactly the deposits under dispute. Always true for accounts
+    /// built from transactions alone, barring bugs. Not for accounts imported with funds held, as
+    /// their deposits are unknown.
+    pub fn held_matches_disputes(&self) -> bool {
+        if self.open_disputes == 0 {
+            return self.held == Amount::zero();
+        }
+        let mut sum = Some(Amount::zero());
+        self.deposits
+            .for_each_disputed(|_, amount| sum = sum.and_then(|s| s.checked_add(amount)));
+        sum == Some(self.held)
+    }
+
+    /// The balances, without the audit trail kept by the database, see
+    /// [`ClientsDatabase::view`].
+    pub fn view(&self) -> AccountView<'static> {
+        AccountView::from(self)
+    }
+
+    /// Move all undisputed deposits out of the account, e.g. to spill them to disk.
+    pub(crate) fn take_undisputed(
+        &mut self,
+        client_id: ClientId,
+        out: &mut Vec<((ClientId, TransactionId), Amount)>,
+    ) {
+        self.deposits
+            .drain_undisputed(|tid, amount| out.push(((client_id, tid), amount)));
+    }
+
+    /// Same as [`Self::take_undisputed`], limited to deposits whose id matches `pred`.
+    pub(crate) fn take_undisputed_if(
+        &mut self,
+        client_id: ClientId,
+        pred: impl FnMut(TransactionId) -> bool,
+        out: &mut Vec<((ClientId, TransactionId), Amount)>,
+    ) {
+        self.deposits
+            .drain_undisputed_if(pred, |tid, amount| out.push(((client_id, tid), amount)));
+    }
+
+    /// Remove a single deposit if it's not under dispute. Balances are unaffected.
+    pub(crate) fn evict_deposit(&mut self, tid: TransactionId) -> Option<Amount> {
+        let key = self.deposits.find(tid)?;
+        if self.deposits.is_disputed(key) {
+            return None;
+        }
+        let amount = self.deposits.amount(key);
+        self.deposits.remove(tid);
+        Some(amount)
+    }
+
+    /// Put back a deposit previously removed by [`Self::take_undisputed`]. Balances are unaffected.
+    pub(crate) fn restore_deposit(&mut self, tid: TransactionId, amount: Amount) {
+        self.deposits.insert(tid, amount);
+    }
+
+    fn find_deposit(&self, tid: TransactionId) -> Result<D::Key, LedgerError> {
+        self.deposits
+            .find(tid)
+            .ok_or(LedgerError::TransactionNotFound)
+    }
+
+    pub(crate) fn save_state(&self) -> AccountState {
+        AccountState {
+            total: self.total,
+            held: self.held,
+            open_disputes: self.open_disputes,
+            frozen: self.frozen,
+            deposits_applied: self.deposits_applied,
+            chargebacks: self.chargebacks,
+            flagged: self.flagged,
+        }
+    }
+
+    /// Revert a transaction successfully applied by [`Self::process`], given the state saved right
+    /// before it. Transactions must be reverted in reverse order of application.
+    pub(crate) fn undo(&mut self, t: Transaction, state: AccountState) {
+        match t.kind {
+            TransactionKind::Deposit => {
+                self.deposits.remove(t.id);
+            }
+            TransactionKind::Dispute => {
+                let did = self.deposits.find(t.id).unwrap();
+                self.deposits.set_disputed(did, false);
+            }
+            TransactionKind::Resolve => {
+                let did = self.deposits.find(t.id).unwrap();
+                self.deposits.set_disputed(did, true);
+            }
+            TransactionKind::Chargeback => {
+                self.charged_back.pop();
+                let did = self.deposits.find(t.id).unwrap();
+                self.deposits.set_disputed(did, true);
+            }
+            TransactionKind::Withdrawal => {}
+        }
+        self.total = state.total;
+        self.held = state.held;
+        self.open_disputes = state.open_disputes;
+        self.frozen = state.frozen;
+        self.deposits_applied = state.deposits_applied;
+        self.chargebacks = state.chargebacks;
+        self.flagged = state.flagged;
     }
 
     /// Process the transaction and update the account if successful.
     /// If an error is returned, no modification was made to internal state.
-    pub fn process(&mut self, t: Transaction) -> Result<(), crate::Error> {
+    pub fn process(&mut self, t: Transaction) -> Result<(), LedgerError> {
         if self.frozen {
-            return Err(Error::AccountFrozen);
+            return Err(LedgerError::AccountFrozen);
+        }
+        // Withdrawal ids aren't checked.
+        if t.kind != TransactionKind::Withdrawal && self.charged_back.contains(&t.id) {
+            return Err(match t.kind {
+                TransactionKind::Deposit => LedgerError::DuplicateTransactionId,
+                TransactionKind::Dispute => LedgerError::DuplicateDispute,
+                TransactionKind::Resolve => LedgerError::ResolveNotDisputed,
+                _ => LedgerError::ChargebackNotDisputed,
+            });
         }
 
         match t.kind {
             TransactionKind::Deposit => {
-                let insert_at = match self
-                    .deposits
-                    .binary_search_by_key(&t.id, |t| t.transaction_id)
-                {
-                    Ok(_) => return Err(Error::DuplicateTransactionId),
-                    Err(insert_at) => insert_at,
-                };
-                self.total = self
+                let total = self
                     .total
                     .checked_add(t.amount)
-                    .ok_or(Error::DepositOverflow)?;
-                self.deposits.insert(
-                    insert_at,
-                    Deposit {
-                        transaction_id: t.id,
-                        amount: t.amount,
-                        is_disputed: false,
-                    },
-                );
+                    .ok_or(LedgerError::DepositOverflow)?;
+                if !self.deposits.insert(t.id, t.amount) {
+                    return Err(LedgerError::DuplicateTransactionId);
+                }
+                self.total = total;
+                self.deposits_applied += 1;
                 Ok(())
             }
             TransactionKind::Withdrawal => {
                 self.available_for_withdrawal()
                     .checked_sub(t.amount)
-                    .ok_or(Error::WithdrawOverflow)?;
+                    .ok_or(LedgerError::WithdrawOverflow)?;
                 // If this unwrap fails it's a bug.
                 self.total = self.total.checked_sub(t.amount).unwrap();
                 Ok(())
             }
             TransactionKind::Dispute => {
-                let did = self.find_deposit_id(t.id)?;
-                if self.deposits[did].is_disputed {
-                    return Err(Error::DuplicateDispute);
+                let did = self.find_deposit(t.id)?;
+                if self.deposits.is_disputed(did) {
+                    return Err(LedgerError::DuplicateDispute);
                 }
                 self.held = self
                     .held
-                    .checked_add(self.deposits[did].amount)
-                    .ok_or(Error::HeldOverflow)?;
-                self.deposits[did].is_disputed = true;
+                    .checked_add(self.deposits.amount(did))
+                    .ok_or(LedgerError::HeldOverflow)?;
+                self.deposits.set_disputed(did, true);
+                self.open_disputes += 1;
                 Ok(())
             }
             TransactionKind::Resolve => {
-                let did = self.find_deposit_id(t.id)?;
-                if !self.deposits[did].is_disputed {
-                    return Err(Error::ResolveNotDisputed);
+                let did = self.find_deposit(t.id)?;
+                if !self.deposits.is_disputed(did) {
+                    return Err(LedgerError::ResolveNotDisputed);
                 }
                 // If this fails it's a bug
-                self.held = self.held.checked_sub(self.deposits[did].amount).unwrap();
-                self.deposits[did].is_disputed = false;
+                self.held = self.held.checked_sub(self.deposits.amount(did)).unwrap();
+                self.deposits.set_disputed(did, false);
+                self.open_disputes -= 1;
                 Ok(())
             }
             TransactionKind::Chargeback => {
-                let did = self.find_deposit_id(t.id)?;
-                if !self.deposits[did].is_disputed {
-                    return Err(Error::ChargebackNotDisputed);
+                let did = self.find_deposit(t.id)?;
+                if !self.deposits.is_disputed(did) {
+                    return Err(LedgerError::ChargebackNotDisputed);
                 }
-                self.held = self.held.checked_sub(self.deposits[did].amount).unwrap();
+                self.held = self.held.checked_sub(self.deposits.amount(did)).unwrap();
                 // If the charged back transaction is more than available funds, set them to 0.
                 // We could go negative, but this isn't required by the spec, and negative numbers aren't
                 // supported.
                 self.total = self
                     .total
-                    .checked_sub(self.deposits[did].amount)
+                    .checked_sub(self.deposits.amount(did))
                     .unwrap_or_default();
+                self.deposits.set_disputed(did, false);
+                self.open_disputes -= 1;
                 self.frozen = true;
+                self.chargebacks += 1;
+                self.charged_back.push(t.id);
                 Ok(())
             }
         }
     }
 }
 
+/// Scalar state of an account saved to roll back a transaction.
+#[derive(Clone, Copy)]
+pub(crate) struct AccountState {
+    total: Amount,
+    held: Amount,
+    open_disputes: usize,
+    frozen: bool,
+    deposits_applied: u64,
+    chargebacks: u64,
+    flagged: bool,
+}
+
+/// A point-in-time copy of an account's balances, detached from the live [`Account`], and
+/// borrowing its audit trail from the database if taken with [`ClientsDatabase::view`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AccountView<'a> {
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+    /// Flagged for review, see [`ReviewRules`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub flagged: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) audit: &'a [AuditRecord],
+}
+
+impl<'a> AccountView<'a> {
+    /// Transactions applied to the account in order, with balances before and after each. Empty
+    /// unless the view was taken with [`ClientsDatabase::view`] and audit is enabled, see
+    /// [`ClientsDatabase::enable_audit`].
+    pub fn audit(&self) -> &'a [AuditRecord] {
+        self.audit
+    }
+}
+
+impl<D: DepositStore> From<&Account<D>> for AccountView<'static> {
+    fn from(account: &Account<D>) -> Self {
+        AccountView {
+            available: account.available_for_withdrawal(),
+            held: account.held(),
+            total: account.total(),
+            locked: account.is_frozen(),
+            flagged: account.is_flagged(),
+            audit: &[],
+        }
+    }
+}
+
+/// Spill `records`, recording it in `slow` if it took long.
+fn spill_timed(
+    spill: &mut SpillStore,
+    slow: &mut SlowLog,
+    records: &mut [((ClientId, TransactionId), Amount)],
+) -> std::io::Result<()> {
+    let start = slow.start();
+    let res = spill.spill(records);
+    if let Some(start) = start {
+        slow.record(SlowOp {
+            kind: SlowOpKind::Spill,
+            duration: start.elapsed(),
+            client_id: None,
+            transaction: None,
+            size: records.len(),
+        });
+    }
+    res
+}
+
+/// Hasher used for the client map by default. Client ids are small trusted integers, so a fast
+/// non-cryptographic hash is enough.
+pub type DefaultClientHasher = rustc_hash::FxBuildHasher;
+
 #[derive(Default)]
-pub struct ClientsDatabase {
-    clients: HashMap<ClientId, Account>,
+pub struct ClientsDatabase<S = DefaultClientHasher, D = SortedVecDeposits> {
+    clients: HashMap<ClientId, Account<D>, S>,
+    // Logical clock, incremented for every submitted transaction.
+    pub(crate) clock: Timestamp,
+    history: Option<BalanceHistory>,
+    audit: Option<AuditLog>,
+    provenance: Option<ProvenanceLog>,
+    // Input record of the transaction being processed, see `process_transaction_from`.
+    position: Option<Provenance>,
+    spill: Option<SpillStore>,
+    eviction: Option<Eviction>,
+    retry: Option<RetryQueue>,
+    breaker: Option<CircuitBreaker>,
+    progress: Option<Progress>,
+    // Last snapshot taken and what changed since, for cheap copy-on-write snapshots.
+    snapshot: Arc<Snapshot>,
+    dirty: DirtyChunks,
+    subscribers: Subscribers,
+    rejection_mode: RejectionMode,
+    error_policy: PolicyMap,
+    reject_samples: usize,
+    review: ReviewRules,
+    slow: SlowLog,
 }
 
 impl ClientsDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a database that keeps a balance checkpoint per applied transaction to answer
+    /// as-of queries. This costs memory proportional to the number of applied transactions.
+    pub fn with_history() -> Self {
+        ClientsDatabase {
+            history: Some(BalanceHistory::default()),
+            ..Default::default()
+        }
+    }
+
+    /// Create a database that bounds memory used by deposits, see [`Self::enable_spill`].
+    pub fn with_spill(dir: impl AsRef<Path>, window: usize) -> Self {
+        let mut db = Self::default();
+        db.enable_spill(dir, window);
+        db
+    }
+}
+
+impl<S: BuildHasher, D: DepositStore> ClientsDatabase<S, D> {
+    /// Create a database using a custom hasher for the client map.
+    pub fn with_hasher(hasher: S) -> Self {
+        ClientsDatabase {
+            clients: HashMap::with_hasher(hasher),
+            clock: 0,
+            history: None,
+            audit: None,
+            provenance: None,
+            position: None,
+            spill: None,
+            eviction: None,
+            retry: None,
+            breaker: None,
+            progress: None,
+            snapshot: Default::default(),
+            dirty: Default::default(),
+            subscribers: Default::default(),
+            rejection_mode: Default::default(),
+            error_policy: Default::default(),
+            reject_samples: 0,
+            review: Default::default(),
+            slow: Default::default(),
+        }
+    }
+
+    /// Keep at most roughly `window` undisputed deposits in memory (plus the ones under dispute).
+    /// Older ones are moved into sorted files in `dir` and read back when disputed, trading
+    /// dispute latency for bounded RAM.
+    ///
+    /// Duplicate deposit ids are only detected against deposits still in memory.
+    pub fn enable_spill(&mut self, dir: impl AsRef<Path>, window: usize) {
+        self.spill = Some(SpillStore::new(dir.as_ref(), window));
+    }
+
+    /// Streaming mode: stop retaining deposits that can no longer reasonably be disputed, so memory
+    /// doesn't grow forever on unbounded input. A deposit is evicted once its id is more than
+    /// `horizon` below the highest deposit id seen (ids are assumed to mostly increase), and, if
+    /// `evict_resolved` is set, as soon as a dispute of it is resolved.
+    ///
+    /// Evicted deposits can't be disputed anymore, and reusing their ids isn't detected as a
+    /// duplicate. If spilling is enabled (see [`Self::enable_spill`]) they're written to disk
+    /// instead, and stay disputable.
+    pub fn enable_eviction(&mut self, horizon: TransactionId, evict_resolved: bool) {
+        self.eviction = Some(Eviction::new(horizon, evict_resolved));
+    }
+
+    /// In bulk processing, defer disputes, resolves and chargebacks of deposits not seen yet, and
+    /// retry them once `window` more rows were processed or at the end of the input. Only those
+    /// failing again are rejected, with their original index. Retried transactions apply at the
+    /// time of the row they're retried before.
+    ///
+    /// Rows of other clients count towards the window too, and with several threads so do rows
+    /// of other shards.
+    pub fn enable_retry(&mut self, window: usize) {
+        self.retry = Some(RetryQueue::new(window));
+    }
+
+    /// Ride out failures of the storage deposits are spilled to (see [`Self::enable_spill`])
+    /// instead of rejecting transactions. Deposits which fail to spill stay in memory, up to
+    /// `max_buffered` beyond the window, checked at every window, after which processing pauses
+    /// until the storage recovers. Reading spilled deposits pauses too. Transactions are only
+    /// rejected with [`LedgerError::SpillIo`] once a pause reaches `max_pause_ms`.
+    ///
+    /// Deposits evicted while the storage fails stay in memory until a later eviction pass.
+    pub fn enable_breaker(&mut self, config: BreakerConfig) {
+        self.breaker = Some(CircuitBreaker::new(config));
+    }
+
+    pub fn breaker(&self) -> Option<&CircuitBreaker> {
+        self.breaker.as_ref()
+    }
+
+    /// Count transactions and rejections of bulk processing into `progress` after each batch, to
+    /// monitor a long run, see [`Progress::report_every`].
+    pub fn set_progress(&mut self, progress: Progress) {
+        self.progress = Some(progress);
+    }
+
+    pub fn progress(&self) -> Option<&Progress> {
+        self.progress.as_ref()
+    }
+
+    /// Number of deposits evicted from memory by [`Self::enable_eviction`] so far.
+    pub fn evicted_deposits(&self) -> usize {
+        self.eviction
+            .as_ref()
+            .map(|e| e.evicted())
+            .unwrap_or_default()
+    }
+
+    /// Whether bulk processing keeps rejection errors or only counts them, see [`RejectionMode`].
+    pub fn set_rejection_mode(&mut self, mode: RejectionMode) {
+        self.rejection_mode = mode;
+    }
+
+    pub fn rejection_mode(&self) -> RejectionMode {
+        self.rejection_mode
+    }
+
+    /// Whether bulk processing skips rejected transactions, stops at the first one, or keeps
+    /// them, see [`crate::process::ErrorPolicy`]. One policy for every kind of rejection, or a
+    /// [`PolicyMap`].
+    pub fn set_error_policy(&mut self, policy: impl Into<PolicyMap>) {
+        self.error_policy = policy.into();
+    }
+
+    pub fn error_policy(&self) -> PolicyMap {
+        self.error_policy
+    }
+
+    /// Keep a sample of up to `per_kind` rejected transactions of each kind of error in reports
+    /// of bulk processing, as CSV rows, see [`crate::process::ProcessReport::samples`].
+    pub fn set_reject_samples(&mut self, per_kind: usize) {
+        self.reject_samples = per_kind;
+    }
+
+    pub fn reject_samples(&self) -> usize {
+        self.reject_samples
+    }
+
+    /// Flag accounts for review by their chargeback rate from now on.
+    pub fn set_review(&mut self, rules: ReviewRules) {
+        self.review = rules;
+    }
+
+    pub fn review(&self) -> &ReviewRules {
+        &self.review
+    }
+
+    /// An empty report of bulk processing with this database's settings.
+    pub(crate) fn new_report(&self) -> ProcessReport {
+        let mut report = ProcessReport::new(self.rejection_mode, self.error_policy);
+        report.samples = RejectSamples::new(self.reject_samples);
+        report
+    }
+
+    /// Number of deposit files written to disk so far.
+    pub fn spilled_runs(&self) -> usize {
+        self.spill
+            .as_ref()
+            .map(|s| s.spilled_runs())
+            .unwrap_or_default()
+    }
+
     pub fn process_transaction(
         &mut self,
         client_id: ClientId,
         t: Transaction,
-    ) -> Result<(), crate::Error> {
-        match self.clients.entry(client_id) {
-            Entry::Occupied(mut occ) => occ.get_mut().process(t),
+    ) -> Result<(), LedgerError> {
+        let at = self.clock;
+        self.clock += 1;
+        self.process_transaction_at(client_id, t, at)
+    }
+
+    /// Same as [`Self::process_transaction`], with an explicit timestamp recorded in history.
+    pub fn process_transaction_at(
+        &mut self,
+        client_id: ClientId,
+        t: Transaction,
+        at: Timestamp,
+    ) -> Result<(), LedgerError> {
+        self.maybe_spill()?;
+        self.maybe_evict()?;
+        let before = self.is_observed().then(|| self.view_of(client_id));
+        self.apply(client_id, t)?;
+        if let Some(before) = before {
+            let after = self.view_of(client_id);
+            self.applied(client_id, t, at, before, after);
+        }
+        Ok(())
+    }
+
+    /// Record every applied transaction from now on, with balances before and after it. This costs
+    /// memory proportional to the number of applied transactions.
+    pub fn enable_audit(&mut self) {
+        self.audit.get_or_insert_default();
+    }
+
+    /// Transactions applied to the client's account in order. Empty if audit isn't enabled.
+    pub fn audit(&self, client_id: ClientId) -> &[AuditRecord] {
+        self.audit
+            .as_ref()
+            .map(|a| a.records(client_id))
+            .unwrap_or_default()
+    }
+
+    /// Write the audit trail of all accounts as CSV.
+    pub fn export_audit(&self, w: impl Write) -> std::io::Result<()> {
+        self.export_audit_of(w, |_| true)
+    }
+
+    /// Write the audit trail of the clients matching `filter` as CSV, e.g. when looking into a
+    /// single customer's dispute.
+    pub fn export_audit_of(
+        &self,
+        w: impl Write,
+        filter: impl Fn(ClientId) -> bool,
+    ) -> std::io::Result<()> {
+        let sources = |idx| self.source(idx).unwrap_or_default();
+        match &self.audit {
+            Some(audit) => audit.write_csv(w, filter, sources),
+            None => AuditLog::default().write_csv(w, filter, sources),
+        }
+    }
+
+    /// Record the input record of every deposit from now on, given with
+    /// [`Self::process_transaction_from`], so audit records of deposits and the disputes,
+    /// resolves and chargebacks of them lead back to it. This costs memory per deposit, also
+    /// after it's spilled or evicted.
+    pub fn enable_provenance(&mut self) {
+        self.provenance.get_or_insert_default();
+    }
+
+    /// An input transactions are processed from, e.g. a file name, for [`Provenance::source`].
+    /// Adding one again returns the same index. Panics if provenance isn't enabled.
+    pub fn add_source(&mut self, name: impl Into<String>) -> u32 {
+        self.provenance
+            .as_mut()
+            .expect("provenance not enabled")
+            .add_source(name.into())
+    }
+
+    /// The name of an input given to [`Self::add_source`].
+    pub fn source(&self, idx: u32) -> Option<&str> {
+        self.provenance.as_ref()?.source(idx)
+    }
+
+    /// Where a deposit of the client came from, if it was processed with provenance.
+    pub fn provenance(&self, client_id: ClientId, tx: TransactionId) -> Option<Provenance> {
+        self.provenance.as_ref()?.get(client_id, tx)
+    }
+
+    /// Same as [`Self::process_transaction`], for a transaction read from the input record `at`,
+    /// which is recorded for deposits with provenance enabled.
+    pub fn process_transaction_from(
+        &mut self,
+        client_id: ClientId,
+        t: Transaction,
+        at: Provenance,
+    ) -> Result<(), LedgerError> {
+        self.position = Some(at);
+        let res = self.process_transaction(client_id, t);
+        self.position = None;
+        res
+    }
+
+    /// Seed accounts from a previous run's report (`client, available, held, total, locked`), so
+    /// runs can be chained. Returns the number of imported accounts.
+    ///
+    /// Deposits of previous runs are unknown, so they can't be disputed, and amounts already held
+    /// stay held. Nothing is imported if any row fails; the error carries its 1-based line number.
+    pub fn import_balances(
+        &mut self,
+        reader: impl BufRead,
+    ) -> Result<usize, (usize, crate::Error)> {
+        let mut rows = Vec::new();
+        let mut seen = HashSet::new();
+        for (idx, line) in reader.split(b'\n').enumerate() {
+            let line_no = idx + 1;
+            let line = line.map_err(|e| (line_no, ParseError::Io(e).into()))?;
+            let line = line.trim_ascii();
+            if line.is_empty() || (idx == 0 && line.starts_with(b"client")) {
+                continue;
+            }
+            let row = BalanceRow::parse(line).map_err(|e| (line_no, e.into()))?;
+            if self.clients.contains_key(&row.client_id) || !seen.insert(row.client_id) {
+                return Err((line_no, LedgerError::AccountExists.into()));
+            }
+            rows.push(row);
+        }
+
+        let count = rows.len();
+        for row in rows {
+            self.clients
+                .insert(row.client_id, Account::with_balances(row.balances));
+            self.dirty.mark(row.client_id);
+        }
+        Ok(count)
+    }
+
+    /// Add an account with known balances and the deposits under dispute it holds, e.g. from a
+    /// saved snapshot, so those disputes can still be resolved or charged back. Its other
+    /// deposits are unknown, as with [`Self::import_balances`].
+    pub fn restore_account(
+        &mut self,
+        client_id: ClientId,
+        balances: AccountView,
+        disputes: &[(TransactionId, Amount)],
+    ) -> Result<(), LedgerError> {
+        if self.clients.contains_key(&client_id) {
+            return Err(LedgerError::AccountExists);
+        }
+        let mut account = Account::<D>::with_balances(balances);
+        for &(tid, amount) in disputes {
+            if account.deposits.insert(tid, amount) {
+                let key = account.deposits.find(tid).unwrap();
+                account.deposits.set_disputed(key, true);
+                account.open_disputes += 1;
+            }
+        }
+        self.clients.insert(client_id, account);
+        self.dirty.mark(client_id);
+        Ok(())
+    }
+
+    /// Mark deposits of a restored account as charged back, so they can't be disputed again, e.g.
+    /// from storage keeping all deposits. They should be restored by [`Self::restore_deposits`].
+    pub fn restore_charged_back(
+        &mut self,
+        client_id: ClientId,
+        ids: &[TransactionId],
+    ) -> Result<(), LedgerError> {
+        let account = self
+            .clients
+            .get_mut(&client_id)
+            .ok_or(LedgerError::AccountNotFound)?;
+        account.charged_back.extend_from_slice(ids);
+        Ok(())
+    }
+
+    /// Add deposits which aren't under dispute to a restored account, e.g. from storage keeping
+    /// all of them rather than a snapshot, so they can still be disputed. Those already held are
+    /// skipped.
+    pub fn restore_deposits(
+        &mut self,
+        client_id: ClientId,
+        deposits: &[(TransactionId, Amount)],
+    ) -> Result<(), LedgerError> {
+        let account = self
+            .clients
+            .get_mut(&client_id)
+            .ok_or(LedgerError::AccountNotFound)?;
+        for &(tid, amount) in deposits {
+            account.deposits.insert(tid, amount);
+        }
+        Ok(())
+    }
+
+    /// Lock or unlock an account outside of chargebacks, e.g. while support looks into it. A
+    /// locked account rejects every transaction, as after a chargeback.
+    pub fn set_frozen(&mut self, client_id: ClientId, frozen: bool) -> Result<(), LedgerError> {
+        let account = self
+            .clients
+            .get_mut(&client_id)
+            .ok_or(LedgerError::AccountNotFound)?;
+        account.frozen = frozen;
+        self.dirty.mark(client_id);
+        Ok(())
+    }
+
+    /// Flag an account for review, or clear its flag once reviewed. Flagged accounts are
+    /// processed as usual.
+    pub fn set_flagged(&mut self, client_id: ClientId, flagged: bool) -> Result<(), LedgerError> {
+        let account = self
+            .clients
+            .get_mut(&client_id)
+            .ok_or(LedgerError::AccountNotFound)?;
+        account.flagged = flagged;
+        self.dirty.mark(client_id);
+        Ok(())
+    }
+
+    /// Receive an event for every transaction applied from now on.
+    pub fn subscribe(&mut self) -> Receiver<AccountEvent> {
+        self.subscribers.subscribe()
+    }
+
+    /// Same as [`Self::subscribe`], sending events to an existing channel, e.g. one shared by
+    /// several databases.
+    pub fn subscribe_with(&mut self, tx: Sender<AccountEvent>) {
+        self.subscribers.add(tx);
+    }
+
+    /// Stop sending events, closing the channels of subscribers once they received the events
+    /// sent so far.
+    pub fn unsubscribe_all(&mut self) {
+        self.subscribers.clear();
+    }
+
+    /// Apply a group of transactions, possibly for different clients, atomically: either all of
+    /// them succeed, or none of them has any effect. On failure returns the index of the failed
+    /// transaction in `group` with its error.
+    pub fn process_atomic(
+        &mut self,
+        group: &[(ClientId, Transaction)],
+    ) -> Result<(), (usize, LedgerError)> {
+        // Spilling in the middle of a group would make deposits impossible to roll back.
+        self.maybe_spill().map_err(|e| (0, e))?;
+        self.maybe_evict().map_err(|e| (0, e))?;
+
+        let observed = self.is_observed();
+        // (client, transaction, state before it or None if the account was created by it)
+        let mut applied = Vec::with_capacity(group.len());
+        // Balances before and after every transaction, if anyone is interested.
+        let mut views = Vec::new();
+        for (idx, (client_id, t)) in group.iter().copied().enumerate() {
+            let state = self.clients.get(&client_id).map(|a| a.save_state());
+            let before = observed.then(|| self.view_of(client_id));
+            if let Err(e) = self.apply(client_id, t) {
+                for (client_id, t, state) in applied.into_iter().rev() {
+                    match state {
+                        Some(state) => self.clients.get_mut(&client_id).unwrap().undo(t, state),
+                        None => {
+                            self.clients.remove(&client_id);
+                        }
+                    }
+                }
+                return Err((idx, e));
+            }
+            applied.push((client_id, t, state));
+            if let Some(before) = before {
+                views.push((before, self.view_of(client_id)));
+            }
+        }
+
+        let first = self.clock;
+        self.clock += group.len() as Timestamp;
+        for (idx, ((client_id, t), (before, after))) in group.iter().zip(views).enumerate() {
+            self.applied(*client_id, *t, first + idx as Timestamp, before, after);
+        }
+        Ok(())
+    }
+
+    /// Whether anything records or listens to applied transactions.
+    fn is_observed(&self) -> bool {
+        self.history.is_some()
+            || self.audit.is_some()
+            || self.provenance.is_some()
+            || !self.subscribers.is_empty()
+    }
+
+    fn view_of(&self, client_id: ClientId) -> AccountView<'static> {
+        self.clients
+            .get(&client_id)
+            .map(|a| a.view())
+            .unwrap_or_default()
+    }
+
+    /// Record history and audit, and notify subscribers about a successfully applied transaction.
+    fn applied(
+        &mut self,
+        client_id: ClientId,
+        t: Transaction,
+        at: Timestamp,
+        before: AccountView<'static>,
+        after: AccountView<'static>,
+    ) {
+        if let Some(history) = self.history.as_mut() {
+            history.record(client_id, at, after);
+        }
+        let deposit = self.provenance.as_mut().and_then(|p| match t.kind {
+            TransactionKind::Deposit => {
+                let position = self.position?;
+                p.record(client_id, t.id, position);
+                Some(position)
+            }
+            TransactionKind::Withdrawal => None,
+            _ => p.get(client_id, t.id),
+        });
+        if let Some(audit) = self.audit.as_mut() {
+            audit.record(
+                client_id,
+                AuditRecord {
+                    at,
+                    transaction: t,
+                    before,
+                    after,
+                    deposit,
+                },
+            );
+        }
+        if !self.subscribers.is_empty() {
+            self.subscribers.send(AccountEvent {
+                at,
+                client_id,
+                transaction: t,
+                balances: after,
+            });
+        }
+    }
+
+    fn maybe_spill(&mut self) -> Result<(), LedgerError> {
+        let Some(spill) = self.spill.as_ref() else {
+            return Ok(());
+        };
+        if self.breaker.is_none() {
+            if spill.should_spill() {
+                return self.spill_deposits();
+            }
+            return Ok(());
+        }
+        if !spill.should_spill() && !self.over_buffer_limit() {
+            return Ok(());
+        }
+        let mut paused = Duration::ZERO;
+        loop {
+            let now = Instant::now();
+            if self.breaker.as_mut().unwrap().allow(now) {
+                let res = self.spill_deposits();
+                let breaker = self.breaker.as_mut().unwrap();
+                match res {
+                    Ok(()) => {
+                        breaker.record_success();
+                        return Ok(());
+                    }
+                    Err(e) => breaker.record_failure(now, &e),
+                }
+            }
+            // Deposits stay in memory meanwhile, within limits.
+            if !self.over_buffer_limit() {
+                return Ok(());
+            }
+            let breaker = self.breaker.as_mut().unwrap();
+            if paused >= breaker.max_pause() {
+                return Err(LedgerError::SpillIo(breaker.give_up()));
+            }
+            paused += breaker.pause(now, paused);
+        }
+    }
+
+    /// Whether deposits which failed to spill exceed the limit of the breaker.
+    fn over_buffer_limit(&self) -> bool {
+        let (Some(spill), Some(breaker)) = (&self.spill, &self.breaker) else {
+            return false;
+        };
+        spill.unspilled() >= spill.window() + breaker.config().max_buffered
+    }
+
+    fn maybe_evict(&mut self) -> Result<(), LedgerError> {
+        let Some(eviction) = self.eviction.as_mut() else {
+            return Ok(());
+        };
+        // Deposits can't go anywhere until the storage is tried again.
+        if self.spill.is_some()
+            && let Some(breaker) = self.breaker.as_mut()
+            && !breaker.allow(Instant::now())
+        {
+            return Ok(());
+        }
+        let mut records = Vec::new();
+        // Resolves rolled back by process_atomic left their deposits disputed, so they're skipped.
+        for (client_id, tid) in eviction.take_resolved() {
+            if let Some(account) = self.clients.get_mut(&client_id)
+                && let Some(amount) = account.evict_deposit(tid)
+            {
+                records.push(((client_id, tid), amount));
+            }
+        }
+        if let Some(below) = eviction.start_pass() {
+            for (client_id, account) in self.clients.iter_mut() {
+                account.take_undisputed_if(*client_id, |tid| tid < below, &mut records);
+            }
+        }
+        if records.is_empty() {
+            return Ok(());
+        }
+        if let Some(spill) = self.spill.as_mut() {
+            let res = spill_timed(spill, &mut self.slow, &mut records);
+            match (res, self.breaker.as_mut()) {
+                (Ok(()), Some(breaker)) => breaker.record_success(),
+                (Ok(()), None) => {}
+                (Err(e), breaker) => {
+                    for ((client_id, tid), amount) in records {
+                        if let Some(account) = self.clients.get_mut(&client_id) {
+                            account.restore_deposit(tid, amount);
+                        }
+                    }
+                    let Some(breaker) = breaker else {
+                        return Err(LedgerError::SpillIo(e));
+                    };
+                    breaker.record_failure(Instant::now(), &e);
+                    return Ok(());
+                }
+            }
+        }
+        eviction.note_evicted(records.len());
+        Ok(())
+    }
+
+    /// Apply transactions in order, recording results by their index. Timestamps are `first` plus
+    /// the index. Deferred transactions are retried as their turn comes.
+    pub(crate) fn process_indexed(
+        &mut self,
+        transactions: impl IntoIterator<Item = (usize, ClientId, Transaction)>,
+        first: Timestamp,
+        report: &mut ProcessReport,
+    ) {
+        let accounts = self.clients.len();
+        let before = (report.processed, report.rejected());
+        self.process_indexed_rows(transactions, first, report);
+        report.accounts_created += self.clients.len() - accounts;
+        self.add_progress(report, before);
+    }
+
+    /// Retry every deferred transaction, at the end of the input, see [`Self::enable_retry`].
+    pub(crate) fn retry_remaining(&mut self, first: Timestamp, report: &mut ProcessReport) {
+        let before = (report.processed, report.rejected());
+        self.retry_deferred(None, first, report);
+        self.add_progress(report, before);
+    }
+
+    /// Count what was processed since `report` had the given processed and rejected counts.
+    fn add_progress(&self, report: &ProcessReport, (processed, rejected): (usize, usize)) {
+        if let Some(progress) = &self.progress {
+            progress.add_rows(report.processed - processed, report.rejected() - rejected);
+        }
+    }
+
+    fn process_indexed_rows(
+        &mut self,
+        transactions: impl IntoIterator<Item = (usize, ClientId, Transaction)>,
+        first: Timestamp,
+        report: &mut ProcessReport,
+    ) {
+        // Spilling, eviction, retries and observers all need the database between transactions.
+        if self.spill.is_none()
+            && self.eviction.is_none()
+            && self.retry.is_none()
+            && !self.is_observed()
+        {
+            let mut transactions = transactions.into_iter().peekable();
+            while let Some((idx, client_id, t)) = transactions.next() {
+                let rest = iter::from_fn(|| {
+                    transactions
+                        .next_if(|(_, c, _)| *c == client_id)
+                        .map(|(idx, _, t)| (idx, t))
+                });
+                self.apply_run(client_id, iter::once((idx, t)).chain(rest), report);
+                if report.aborted.is_some() {
+                    return;
+                }
+            }
+            return;
+        }
+
+        for (idx, client_id, t) in transactions {
+            self.retry_deferred(Some(idx), first, report);
+            if report.aborted.is_some() {
+                return;
+            }
+            let res = self.process_transaction_at(client_id, t, first + idx as Timestamp);
+            if let (Err(e), Some(retry)) = (&res, &mut self.retry)
+                && RetryQueue::should_defer(&t, e)
+            {
+                retry.defer(idx, client_id, t);
+                continue;
+            }
+            report.record(idx, Some((client_id, t)), res);
+            if report.aborted.is_some() {
+                return;
+            }
+        }
+    }
+
+    /// Apply consecutive transactions of one client with a single lookup of its account, the same
+    /// as [`Self::apply`] for each when nothing needs the database between them.
+    fn apply_run(
+        &mut self,
+        client_id: ClientId,
+        run: impl Iterator<Item = (usize, Transaction)>,
+        report: &mut ProcessReport,
+    ) {
+        let mut run = run.peekable();
+        // Like apply, the account only gets created by a deposit.
+        let account = loop {
+            let Some(&(idx, t)) = run.peek() else {
+                return;
+            };
+            match self.clients.entry(client_id) {
+                Entry::Occupied(occ) => break occ.into_mut(),
+                Entry::Vacant(vac) if t.kind == TransactionKind::Deposit => {
+                    break vac.insert(Default::default());
+                }
+                Entry::Vacant(_) => {
+                    run.next();
+                    report.record(idx, Some((client_id, t)), Err(LedgerError::AccountNotFound));
+                    if report.aborted.is_some() {
+                        return;
+                    }
+                }
+            }
+        };
+        // Applied transactions are counted once for the run, rejected ones as they come.
+        let mut applied = AppliedCounts::default();
+        let mut zero_amounts = 0;
+        for (idx, t) in run {
+            let start = self.slow.start();
+            let res = account.process(t);
+            if let Some(start) = start {
+                self.slow.record(SlowOp {
+                    kind: SlowOpKind::Transaction,
+                    duration: start.elapsed(),
+                    client_id: Some(client_id),
+                    transaction: Some(t),
+                    size: account.deposit_count(),
+                });
+            }
+            if let Err(e) = res {
+                report.record(idx, Some((client_id, t)), Err(e));
+                if report.aborted.is_some() {
+                    break;
+                }
+                continue;
+            }
+            account.review(t.kind, &self.review);
+            applied.add(t.kind);
+            zero_amounts += usize::from(t.kind.has_amount() && t.amount == Amount::zero());
+        }
+        if applied.total() > 0 {
+            report.record_applied(client_id, applied, zero_amounts);
+            self.dirty.mark(client_id);
+        }
+    }
+
+    /// Retry deferred transactions due before the one at `idx`, or all of them at the end of the
+    /// input with None. Stops once `report` is aborted.
+    fn retry_deferred(&mut self, idx: Option<usize>, first: Timestamp, report: &mut ProcessReport) {
+        while report.aborted.is_none()
+            && let Some(retry) = self.retry.as_mut()
+            && let Some((deferred, client_id, t)) = retry.pop_due(idx)
+        {
+            let at = first + retry.latest() as Timestamp;
+            let res = self.process_transaction_at(client_id, t, at);
+            report.retried += usize::from(res.is_ok());
+            report.record(deferred, Some((client_id, t)), res);
+        }
+    }
+
+    /// Apply a single transaction without recording history.
+    fn apply(&mut self, client_id: ClientId, t: Transaction) -> Result<(), LedgerError> {
+        let Some(start) = self.slow.start() else {
+            return self.apply_untimed(client_id, t);
+        };
+        let res = self.apply_untimed(client_id, t);
+        self.slow.record(SlowOp {
+            kind: SlowOpKind::Transaction,
+            duration: start.elapsed(),
+            client_id: Some(client_id),
+            transaction: Some(t),
+            size: self
+                .clients
+                .get(&client_id)
+                .map_or(0, |a| a.deposit_count()),
+        });
+        res
+    }
+
+    fn apply_untimed(&mut self, client_id: ClientId, t: Transaction) -> Result<(), LedgerError> {
+        let account = match self.clients.entry(client_id) {
+            Entry::Occupied(occ) => occ.into_mut(),
             Entry::Vacant(vac) => {
                 if !matches!(t.kind, TransactionKind::Deposit) {
-                    return Err(Error::AccountNotFound);
+                    return Err(LedgerError::AccountNotFound);
+                }
+                vac.insert(Default::default())
+            }
+        };
+        match account.process(t) {
+            Ok(()) => {}
+            Err(LedgerError::TransactionNotFound) if self.spill.is_some() => {
+                // Disputed deposits are never spilled, so only a dispute can refer to one on disk.
+                let spill = self.spill.as_ref().unwrap();
+                let start = self.slow.start();
+                let spilled = match self.breaker.as_mut() {
+                    Some(breaker) => breaker.call(|| spill.get(client_id, t.id)),
+                    None => spill.get(client_id, t.id),
+                };
+                if let Some(start) = start {
+                    self.slow.record(SlowOp {
+                        kind: SlowOpKind::SpillRead,
+                        duration: start.elapsed(),
+                        client_id: Some(client_id),
+                        transaction: Some(t),
+                        size: spill.spilled_runs(),
+                    });
+                }
+                match spilled.map_err(LedgerError::SpillIo)? {
+                    Some(amount) if t.kind == TransactionKind::Dispute => {
+                        account.restore_deposit(t.id, amount);
+                        account.process(t)?;
+                    }
+                    _ => return Err(LedgerError::TransactionNotFound),
+                }
+            }
+            Err(e) => return Err(e),
+        }
+        account.review(t.kind, &self.review);
+        self.dirty.mark(client_id);
+        if let Some(eviction) = self.eviction.as_mut() {
+            eviction.note_applied(client_id, t);
+        }
+        if t.kind == TransactionKind::Deposit
+            && let Some(spill) = self.spill.as_mut()
+        {
+            spill.note_deposit();
+        }
+        Ok(())
+    }
+
+    /// Record operations taking at least `threshold` from now on, or stop if None, see
+    /// [`SlowLog`].
+    pub fn set_slow_threshold(&mut self, threshold: Option<Duration>) {
+        self.slow = SlowLog::new(threshold);
+    }
+
+    pub fn slow_log(&self) -> &SlowLog {
+        &self.slow
+    }
+
+    fn spill_deposits(&mut self) -> Result<(), LedgerError> {
+        let mut records = Vec::new();
+        for (client_id, account) in self.clients.iter_mut() {
+            account.take_undisputed(*client_id, &mut records);
+        }
+        let spill = self.spill.as_mut().unwrap();
+        if let Err(e) = spill_timed(spill, &mut self.slow, &mut records) {
+            // Put everything back so nothing is lost.
+            for ((client_id, tid), amount) in records {
+                if let Some(account) = self.clients.get_mut(&client_id) {
+                    account.restore_deposit(tid, amount);
                 }
-                vac.insert(Default::default()).process(t)
             }
+            return Err(LedgerError::SpillIo(e));
         }
+        Ok(())
+    }
+
+    /// Client's balances as of the given time. Returns None if history isn't enabled (see
+    /// [`Self::with_history`]) or the account didn't exist at that time.
+    pub fn balance_as_of(
+        &self,
+        client_id: ClientId,
+        at: Timestamp,
+    ) -> Option<AccountView<'static>> {
+        self.history.as_ref()?.as_of(client_id, at)
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = (ClientId, &Account)> {
+    /// Client's balances, with the audit trail of the account if audit is enabled.
+    pub fn view(&self, client_id: ClientId) -> Option<AccountView<'_>> {
+        let view = self.get(client_id)?.view();
+        Some(AccountView {
+            audit: self.audit(client_id),
+            ..view
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ClientId, &Account<D>)> {
         self.clients.iter().map(|(k, v)| (*k, v))
     }
+
+    pub fn get(&self, client_id: ClientId) -> Option<&Account<D>> {
+        self.clients.get(&client_id)
+    }
+
+    /// Accounts with client ids within the range, in client id order.
+    ///
+    /// Costs one lookup per id in the range, so it's cheaper than a full scan when the range is
+    /// narrow.
+    pub fn iter_range(
+        &self,
+        range: impl RangeBounds<ClientId>,
+    ) -> impl Iterator<Item = (ClientId, &Account<D>)> {
+        client_ids(range).filter_map(|c| Some((c, self.clients.get(&c)?)))
+    }
+
+    /// Point-in-time copy of all balances. Unchanged parts are shared with the previous snapshot,
+    /// so taking snapshots frequently is cheap.
+    pub fn snapshot(&mut self) -> Arc<Snapshot> {
+        let snapshot = self.snapshot.update(&mut self.dirty, |client_id| {
+            self.clients.get(&client_id).map(|a| a.view())
+        });
+        self.snapshot = Arc::new(snapshot);
+        self.snapshot.clone()
+    }
+
+    /// Make the current balances visible to readers of `reader`.
+    pub fn publish(&mut self, reader: &DatabaseReader) {
+        reader.publish(self.snapshot())
+    }
+
+    pub fn stats(&self) -> DatabaseStats {
+        DatabaseStats::collect(self.iter().map(|(_, a)| a))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
         Error,
-        accounts::{Account, Transaction, TransactionKind::*},
+        accounts::{Account, ClientId, ClientsDatabase, Transaction, TransactionKind::*},
         amount::Amount,
+        error::{ErrorKind, LedgerError},
+        review::ReviewRules,
     };
 
     fn amount(v: &str) -> Amount {
@@ -198,7 +1420,7 @@ mod tests {
     #[test]
     fn test_process_transaction_no_errors() {
         // Deposit 10.5
-        let mut acc = Account::default();
+        let mut acc = Account::new();
         acc.process(Transaction {
             kind: Deposit,
             id: 0,
@@ -241,6 +1463,7 @@ mod tests {
         assert_eq!(acc.total(), amount("11.5"));
         assert_eq!(acc.held(), amount("3"));
         assert_eq!(acc.available_for_withdrawal(), amount("8.5"));
+        assert!(acc.held_matches_disputes());
 
         // Resolve.
         acc.process(Transaction {
@@ -275,12 +1498,15 @@ mod tests {
         assert_eq!(acc.held(), amount("0"));
         assert_eq!(acc.available_for_withdrawal(), amount("0"));
         assert!(acc.frozen);
+        assert!(acc.held_matches_disputes());
+        acc.held = amount("1");
+        assert!(!acc.held_matches_disputes());
     }
 
     #[test]
     fn test_edge_case_chargeback_would_go_negative() {
         // Deposit 5
-        let mut acc = Account::default();
+        let mut acc = Account::new();
         acc.process(Transaction {
             kind: Deposit,
             id: 0,
@@ -350,7 +1576,7 @@ mod tests {
 
     #[test]
     fn test_withdraw_more_than_available() {
-        let mut acc = Account::default();
+        let mut acc = Account::new();
         assert!(matches!(
             acc.process(Transaction {
                 kind: Withdrawal,
@@ -358,7 +1584,7 @@ mod tests {
                 amount: amount("1")
             })
             .unwrap_err(),
-            Error::WithdrawOverflow
+            LedgerError::WithdrawOverflow
         ));
 
         acc.process(Transaction {
@@ -374,7 +1600,7 @@ mod tests {
                 amount: amount("6")
             })
             .unwrap_err(),
-            Error::WithdrawOverflow
+            LedgerError::WithdrawOverflow
         ));
 
         assert!(
@@ -386,4 +1612,259 @@ mod tests {
             .is_ok(),
         );
     }
+
+    #[test]
+    fn test_custom_hasher() {
+        let mut db: ClientsDatabase<_> =
+            ClientsDatabase::with_hasher(std::collections::hash_map::RandomState::new());
+        db.process_transaction(
+            7,
+            Transaction {
+                kind: Deposit,
+                id: 0,
+                amount: amount("1"),
+            },
+        )
+        .unwrap();
+        assert_eq!(db.iter().next().unwrap().1.total(), amount("1"));
+    }
+
+    #[test]
+    fn test_process_atomic_rolls_back() {
+        let mut db = ClientsDatabase::new();
+        db.process_transaction(
+            2,
+            Transaction {
+                kind: Deposit,
+                id: 1,
+                amount: amount("10"),
+            },
+        )
+        .unwrap();
+        let before = db.snapshot();
+
+        let group = [
+            (
+                1,
+                Transaction {
+                    kind: Deposit,
+                    id: 2,
+                    amount: amount("3"),
+                },
+            ),
+            (
+                2,
+                Transaction {
+                    kind: Withdrawal,
+                    id: 3,
+                    amount: amount("3"),
+                },
+            ),
+            (
+                2,
+                Transaction {
+                    kind: Dispute,
+                    id: 1,
+                    amount: Amount::zero(),
+                },
+            ),
+            (
+                2,
+                Transaction {
+                    kind: Chargeback,
+                    id: 1,
+                    amount: Amount::zero(),
+                },
+            ),
+            (
+                2,
+                Transaction {
+                    kind: Deposit,
+                    id: 4,
+                    amount: amount("1"),
+                },
+            ),
+        ];
+        let (idx, err) = db.process_atomic(&group).unwrap_err();
+        assert_eq!(idx, 4);
+        assert!(matches!(err, LedgerError::AccountFrozen));
+
+        let after = db.snapshot();
+        assert_eq!(
+            after.iter().collect::<Vec<_>>(),
+            before.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(db.stats().open_disputes, 0);
+        assert_eq!(db.stats().deposits, 1);
+
+        // Without the failing row everything is applied.
+        db.process_atomic(&group[..4]).unwrap();
+        assert_eq!(db.stats().accounts, 2);
+        assert_eq!(db.stats().frozen_accounts, 1);
+        assert_eq!(db.stats().total, amount("3"));
+    }
+
+    #[test]
+    fn test_chargeback_is_final() {
+        let t = |kind, id, a: &str| Transaction {
+            kind,
+            id,
+            amount: amount(a),
+        };
+        let mut db = ClientsDatabase::new();
+        for t in [
+            t(Deposit, 1, "5"),
+            t(Deposit, 2, "3"),
+            t(Dispute, 1, "0"),
+            t(Chargeback, 1, "0"),
+        ] {
+            db.process_transaction(1, t).unwrap();
+        }
+        db.set_frozen(1, false).unwrap();
+
+        // The charged back deposit can't be taken out of the account again.
+        for (t, expected) in [
+            (t(Dispute, 1, "0"), ErrorKind::DuplicateDispute),
+            (t(Resolve, 1, "0"), ErrorKind::ResolveNotDisputed),
+            (t(Chargeback, 1, "0"), ErrorKind::ChargebackNotDisputed),
+            (t(Deposit, 1, "5"), ErrorKind::DuplicateTransactionId),
+        ] {
+            let e = db.process_transaction(1, t).unwrap_err();
+            assert_eq!(e.kind(), expected);
+        }
+        assert_eq!(db.get(1).unwrap().total(), amount("3"));
+        assert_eq!(db.get(1).unwrap().open_disputes(), 0);
+
+        // A rolled back chargeback settles nothing.
+        let group = [
+            (1, t(Dispute, 2, "0")),
+            (1, t(Chargeback, 2, "0")),
+            (1, t(Deposit, 3, "1")),
+        ];
+        assert_eq!(db.process_atomic(&group).unwrap_err().0, 2);
+        db.process_atomic(&group[..2]).unwrap();
+        assert_eq!(db.get(1).unwrap().total(), amount("0"));
+        assert!(db.get(1).unwrap().held_matches_disputes());
+    }
+
+    #[test]
+    fn test_chargeback_review() {
+        let t = |kind, id| Transaction {
+            kind,
+            id,
+            amount: amount("1"),
+        };
+        let rules = ReviewRules {
+            chargeback_percent: Some(amount("20")),
+            min_deposits: 3,
+        };
+        // One chargeback of 3 deposits, one of 5 which is exactly the limit, and one of too few
+        // deposits to count.
+        let mut rows = Vec::new();
+        for (client, deposits) in [(1, 3), (2, 5), (3, 1)] {
+            let first = client as u32 * 10;
+            for id in first..first + deposits {
+                rows.push((client, t(Deposit, id)));
+            }
+            rows.push((client, t(Dispute, first)));
+            rows.push((client, t(Chargeback, first)));
+        }
+        let mut bulk = ClientsDatabase::new();
+        bulk.set_review(rules.clone());
+        assert_eq!(bulk.process_all(rows.iter().copied()).processed, rows.len());
+        let mut db = ClientsDatabase::new();
+        db.set_review(rules);
+        for (client, t) in rows {
+            db.process_transaction(client, t).unwrap();
+        }
+        let flagged = |db: &ClientsDatabase| {
+            db.iter()
+                .filter(|(_, a)| a.is_flagged())
+                .map(|(c, _)| c)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(flagged(&db), [1]);
+        assert_eq!(flagged(&bulk), [1]);
+        assert_eq!(db.get(1).unwrap().deposits_applied(), 3);
+        assert_eq!(db.get(1).unwrap().chargebacks(), 1);
+        assert!(db.get(1).unwrap().view().flagged);
+        assert_eq!(db.stats().flagged_accounts, 1);
+
+        // Flagged by a group, then rolled back with it as the account is frozen.
+        db.process_transaction(5, t(Deposit, 50)).unwrap();
+        let group = [
+            t(Deposit, 51),
+            t(Deposit, 52),
+            t(Dispute, 51),
+            t(Chargeback, 51),
+            t(Deposit, 53),
+        ]
+        .map(|t| (5, t));
+        assert_eq!(db.process_atomic(&group).unwrap_err().0, 4);
+        assert_eq!(flagged(&db), [1]);
+        assert_eq!(db.get(5).unwrap().deposits_applied(), 1);
+
+        db.set_flagged(1, false).unwrap();
+        assert!(flagged(&db).is_empty());
+        assert!(matches!(
+            db.set_flagged(9, true),
+            Err(LedgerError::AccountNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_import_balances() {
+        let report = b"client, available, held, total, locked\n2,0,10,9,false\n1,0,0,0.5,true\n";
+        let mut db = ClientsDatabase::new();
+        assert_eq!(db.import_balances(&report[..]).unwrap(), 2);
+        let snapshot = db.snapshot();
+        assert_eq!(snapshot.get(2).unwrap().held, amount("10"));
+        assert!(snapshot.get(1).unwrap().locked);
+
+        // Imported accounts accept new transactions.
+        db.process_transaction(
+            2,
+            Transaction {
+                kind: Deposit,
+                id: 10,
+                amount: amount("2"),
+            },
+        )
+        .unwrap();
+        assert_eq!(db.snapshot().get(2).unwrap().total, amount("11"));
+        assert_eq!(db.snapshot().get(2).unwrap().available, amount("1"));
+
+        // Already existing client, nothing is imported.
+        let (line, err) = db
+            .import_balances(&b"3,1,0,1,false\n2,1,0,1,false\n"[..])
+            .unwrap_err();
+        assert_eq!(line, 2);
+        assert!(matches!(err, Error::Ledger(LedgerError::AccountExists)));
+        assert!(db.snapshot().get(3).is_none());
+    }
+
+    #[test]
+    fn test_iter_range() {
+        let mut db = ClientsDatabase::new();
+        for client in [5, 1, 3, 65535, 0] {
+            db.process_transaction(
+                client,
+                Transaction {
+                    kind: Deposit,
+                    id: client as u32,
+                    amount: amount("1"),
+                },
+            )
+            .unwrap();
+        }
+        fn ids<T>(r: impl Iterator<Item = (ClientId, T)>) -> Vec<ClientId> {
+            r.map(|(c, _)| c).collect()
+        }
+        assert_eq!(ids(db.iter_range(1..5)), vec![1, 3]);
+        assert_eq!(ids(db.iter_range(1..=5)), vec![1, 3, 5]);
+        assert_eq!(ids(db.iter_range(..)), vec![0, 1, 3, 5, 65535]);
+        assert_eq!(ids(db.iter_range(6..)), vec![65535]);
+        assert_eq!(ids(db.snapshot().range(3..)), vec![3, 5, 65535]);
+        assert!(db.iter_range(6..6).next().is_none());
+    }
 }
@@ -0,0 +1,321 @@
+use std::{
+    fs::File,
+    io,
+    os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
+    net::{UnixListener, UnixStream},
+    task::JoinSet,
+};
+use tracing::{debug, info, warn};
+
+use crate::{accounts::ClientId, concurrent::ConcurrentClientsDatabase, report::ReportFormat};
+
+// Longer lines than any command close the connection.
+const MAX_LINE: u64 = 4096;
+
+/// Commands of operators to a running service, over a Unix domain socket rather than the network
+/// API, one per line, each answered with a line `OK`, followed by its result if any, or `ERR`
+/// and the reason:
+///
+/// - `freeze <client>` locks the client's account, so it rejects every transaction until
+///   `unfreeze <client>`, which also unlocks accounts locked by a chargeback. Charged back
+///   deposits stay settled, so they can't be disputed again.
+/// - `balance <client>` answers the client's balances like a row of the JSON report, e.g.
+///   `OK {"client":7,"available":"2.5","held":"0","total":"2.5","locked":false}`.
+/// - `snapshot [<path>]` saves a snapshot of all accounts like `process --snapshot` to the path,
+///   or to the default one if not given, replacing the file only once complete, and answers the
+///   path written.
+/// - `rotate-wal` moves the transactions of the write-ahead log into the database and truncates
+///   the log, if there's one.
+///
+/// The socket is only accessible to its owner, and connections of other users than the owner
+/// and root are also refused by their credentials, so access is that of the socket's directory
+/// and user. It's removed once the listener is dropped.
+///
+/// Freezing isn't a transaction, so it isn't journaled or sent to subscribers. It's kept across
+/// restarts by snapshots, but not by the SQLite file of `serve --sqlite`.
+pub struct AdminListener {
+    listener: UnixListener,
+    path: PathBuf,
+    owner: u32,
+    snapshot: Option<PathBuf>,
+    snapshot_history: usize,
+    rotate_wal: Option<Box<dyn Fn() -> io::Result<()> + Send + Sync>>,
+}
+
+impl AdminListener {
+    /// Listen on a socket at `path`, replacing that of a previous run if no one listens on it
+    /// anymore.
+    pub fn bind(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        if std::fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_socket()) {
+            if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    "another process listens on the socket",
+                ));
+            }
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+        let owner = std::fs::metadata(&path)?.uid();
+        Ok(AdminListener {
+            listener,
+            path,
+            owner,
+            snapshot: None,
+            snapshot_history: 0,
+            rotate_wal: None,
+        })
+    }
+
+    /// Where `snapshot` writes without a path, with up to `history` audit records per account.
+    pub fn snapshot(mut self, path: Option<PathBuf>, history: usize) -> Self {
+        self.snapshot = path;
+        self.snapshot_history = history;
+        self
+    }
+
+    /// What `rotate-wal` does, which fails without it.
+    pub fn rotate_wal(
+        mut self,
+        rotate: impl Fn() -> io::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.rotate_wal = Some(Box::new(rotate));
+        self
+    }
+
+    /// Accept connections until dropped, which closes those still open.
+    pub async fn run(self, db: Arc<ConcurrentClientsDatabase>) {
+        let admin = Arc::new(self);
+        let mut connections = JoinSet::new();
+        loop {
+            let stream = match admin.listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!(error = %e, "error accepting an admin connection");
+                    continue;
+                }
+            };
+            let uid = match stream.peer_cred() {
+                Ok(cred) => cred.uid(),
+                Err(e) => {
+                    warn!(error = %e, "error getting the credentials of an admin connection");
+                    continue;
+                }
+            };
+            if uid != admin.owner && uid != 0 {
+                warn!(uid, "refused an admin connection of another user");
+                continue;
+            }
+            let (admin, db) = (admin.clone(), db.clone());
+            connections.spawn(async move {
+                match admin.read_commands(stream, &db).await {
+                    Ok(()) => debug!(uid, "admin connection closed"),
+                    Err(e) => debug!(uid, error = %e, "admin connection failed"),
+                }
+            });
+            // Reap finished connections.
+            while connections.try_join_next().is_some() {}
+        }
+    }
+
+    /// Answer the commands of a connection until it's closed.
+    async fn read_commands(
+        &self,
+        stream: UnixStream,
+        db: &ConcurrentClientsDatabase,
+    ) -> io::Result<()> {
+        let (reader, writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        let mut writer = BufWriter::new(writer);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if (&mut reader)
+                .take(MAX_LINE)
+                .read_until(b'\n', &mut line)
+                .await?
+                == 0
+            {
+                return Ok(());
+            }
+            if !line.ends_with(b"\n") && line.len() as u64 == MAX_LINE {
+                return Err(io::Error::other("line too long"));
+            }
+            let command = String::from_utf8_lossy(&line);
+            let command = command.trim();
+            if command.is_empty() {
+                continue;
+            }
+            let reply = match self.execute(db, command) {
+                Ok(result) if result.is_empty() => "OK\n".to_owned(),
+                Ok(result) => format!("OK {result}\n"),
+                Err(e) => format!("ERR {e}\n"),
+            };
+            writer.write_all(reply.as_bytes()).await?;
+            writer.flush().await?;
+        }
+    }
+
+    /// Run a command, returning its result.
+    fn execute(&self, db: &ConcurrentClientsDatabase, command: &str) -> Result<String, String> {
+        info!(command, "admin command");
+        let mut words = command.split_ascii_whitespace();
+        let name = words.next().unwrap_or_default();
+        let arg = words.next();
+        if words.next().is_some() {
+            return Err(format!("too many arguments to {name}"));
+        }
+        let client = || -> Result<ClientId, String> {
+            arg.ok_or("missing client id")?
+                .parse()
+                .map_err(|_| "invalid client id".to_owned())
+        };
+        match name {
+            "freeze" | "unfreeze" => {
+                db.set_frozen(client()?, name == "freeze")
+                    .map_err(|e| format!("{}: {e}", e.code()))?;
+                Ok(String::new())
+            }
+            "balance" => {
+                let client_id = client()?;
+                let view = db
+                    .get(client_id)
+                    .ok_or_else(|| "account not found".to_owned())?;
+                let mut row = Vec::new();
+                ReportFormat::Ndjson
+                    .write(&mut row, [(client_id, view)], None)
+                    .unwrap();
+                Ok(String::from_utf8(row).unwrap().trim_end().to_owned())
+            }
+            "snapshot" => {
+                let path = arg
+                    .map(Path::new)
+                    .or(self.snapshot.as_deref())
+                    .ok_or("no default snapshot path, give one")?;
+                self.save_snapshot(db, path)
+                    .map_err(|e| format!("error writing {}: {e}", path.display()))?;
+                Ok(path.display().to_string())
+            }
+            "rotate-wal" => {
+                let rotate = self.rotate_wal.as_ref().ok_or("no write-ahead log")?;
+                rotate().map_err(|e| e.to_string())?;
+                Ok(String::new())
+            }
+            _ => Err(format!("unknown command {name:?}")),
+        }
+    }
+
+    fn save_snapshot(&self, db: &ConcurrentClientsDatabase, path: &Path) -> io::Result<()> {
+        let partial = path.with_extension("partial");
+        db.save_snapshot(File::create(&partial)?, self.snapshot_history)?;
+        std::fs::rename(&partial, path)
+    }
+}
+
+impl Drop for AdminListener {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::PermissionsExt, sync::Arc};
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::UnixStream,
+    };
+
+    use crate::{
+        accounts::{Transaction, TransactionKind},
+        admin::AdminListener,
+        concurrent::ConcurrentClientsDatabase,
+        saved::SavedSnapshot,
+    };
+
+    #[tokio::test]
+    async fn test_admin() {
+        let dir = std::env::temp_dir().join(format!("payengine-admin-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (socket, snapshot) = (dir.join("admin.sock"), dir.join("s.bin"));
+        let db = Arc::new(ConcurrentClientsDatabase::new(2));
+        let t = |kind, id, amount: &str| Transaction {
+            kind,
+            id,
+            amount: amount.parse().unwrap(),
+        };
+        for t in [
+            t(TransactionKind::Deposit, 1, "2.5"),
+            t(TransactionKind::Deposit, 2, "1"),
+            t(TransactionKind::Dispute, 2, "0"),
+            t(TransactionKind::Chargeback, 2, "0"),
+        ] {
+            db.process_transaction(7, t).unwrap();
+        }
+        let admin = AdminListener::bind(&socket)
+            .unwrap()
+            .snapshot(Some(snapshot.clone()), 0)
+            .rotate_wal(|| Ok(()));
+        let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let server = tokio::spawn(admin.run(db.clone()));
+
+        let stream = UnixStream::connect(&socket).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        for (command, reply) in [
+            ("freeze 7", "OK"),
+            (
+                "balance 7",
+                r#"OK {"client":7,"available":"0","held":"0","total":"2.5","locked":true}"#,
+            ),
+            ("unfreeze 7", "OK"),
+            ("unfreeze 8", "ERR E_ACCOUNT_NOT_FOUND: account not found"),
+            ("balance x", "ERR invalid client id"),
+            ("", ""),
+            ("rotate-wal", "OK"),
+            ("snapshot", &format!("OK {}", snapshot.display())),
+            ("launch", "ERR unknown command \"launch\""),
+        ] {
+            writer
+                .write_all(format!("{command}\n").as_bytes())
+                .await
+                .unwrap();
+            if !reply.is_empty() {
+                assert_eq!(lines.next_line().await.unwrap().unwrap(), reply);
+            }
+        }
+        let saved = SavedSnapshot::read(std::fs::File::open(&snapshot).unwrap()).unwrap();
+        assert!(!saved.get(7).unwrap().balances.locked);
+
+        // Unfreezing doesn't reopen the charged back deposit.
+        for t in [
+            t(TransactionKind::Dispute, 2, "0"),
+            t(TransactionKind::Chargeback, 2, "0"),
+        ] {
+            assert!(db.process_transaction(7, t).is_err());
+        }
+        assert_eq!(db.get(7).unwrap().total, "2.5".parse().unwrap());
+        db.process_transaction(7, t(TransactionKind::Dispute, 1, "0"))
+            .unwrap();
+        db.process_transaction(7, t(TransactionKind::Chargeback, 1, "0"))
+            .unwrap();
+        assert_eq!(db.get(7).unwrap().total, "0".parse().unwrap());
+
+        // Another instance can't take over the socket while this one listens.
+        assert!(AdminListener::bind(&socket).is_err());
+        server.abort();
+        let _ = server.await;
+        assert!(!socket.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
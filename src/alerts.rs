@@ -0,0 +1,258 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use tracing::warn;
+
+use crate::{
+    accounts::{AccountView, ClientId},
+    amount::Amount,
+};
+
+/// Limits on funds held for clients which raise an [`Alert`] when exceeded. Rules which are unset
+/// aren't checked.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct AlertRules {
+    /// Total funds of all accounts, the liability of the ledger to its clients.
+    pub total: Option<Amount>,
+    /// Total funds of any single account.
+    pub account_total: Option<Amount>,
+    /// Held funds of all accounts.
+    pub held: Option<Amount>,
+}
+
+impl AlertRules {
+    pub fn is_empty(&self) -> bool {
+        self.total.is_none() && self.account_total.is_none() && self.held.is_none()
+    }
+}
+
+/// A limit of [`AlertRules`] exceeded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Alert {
+    Liability {
+        total: Amount,
+        limit: Amount,
+    },
+    Account {
+        client_id: ClientId,
+        total: Amount,
+        limit: Amount,
+    },
+    Held {
+        held: Amount,
+        limit: Amount,
+    },
+}
+
+impl Alert {
+    /// Name of the rule, e.g. in JSON.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Alert::Liability { .. } => "liability",
+            Alert::Account { .. } => "account",
+            Alert::Held { .. } => "held",
+        }
+    }
+
+    /// One JSON object, e.g. `{"alert":"account","client":7,"amount":"600","limit":"500"}`.
+    pub fn to_json(&self) -> String {
+        let (client_id, amount, limit) = match *self {
+            Alert::Liability { total, limit } => (None, total, limit),
+            Alert::Account {
+                client_id,
+                total,
+                limit,
+            } => (Some(client_id), total, limit),
+            Alert::Held { held, limit } => (None, held, limit),
+        };
+        let client = client_id.map_or(String::new(), |c| format!(",\"client\":{c}"));
+        format!(
+            "{{\"alert\":\"{}\"{client},\"amount\":\"{amount}\",\"limit\":\"{limit}\"}}",
+            self.as_str()
+        )
+    }
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Alert::Liability { total, limit } => {
+                write!(f, "total funds {total} exceed {limit}")
+            }
+            Alert::Account {
+                client_id,
+                total,
+                limit,
+            } => write!(f, "client {client_id} funds {total} exceed {limit}"),
+            Alert::Held { held, limit } => write!(f, "held funds {held} exceed {limit}"),
+        }
+    }
+}
+
+/// Checks [`AlertRules`] as balances change. An alert is raised when its limit is first
+/// exceeded, and again only after going back under it.
+#[derive(Debug, Default)]
+pub struct AlertMonitor {
+    rules: AlertRules,
+    // Last seen total and held funds by client, and their sums, in raw amounts.
+    accounts: HashMap<ClientId, (u64, u64)>,
+    total: u128,
+    held: u128,
+    liability_raised: bool,
+    held_raised: bool,
+    accounts_raised: HashSet<ClientId>,
+    raised: Vec<Alert>,
+    quiet: bool,
+}
+
+impl AlertMonitor {
+    pub fn new(rules: AlertRules) -> Self {
+        AlertMonitor {
+            rules,
+            ..Default::default()
+        }
+    }
+
+    /// Without logging alerts as warnings, e.g. when another monitor of the same balances does.
+    pub fn quiet(mut self) -> Self {
+        self.quiet = true;
+        self
+    }
+
+    /// Take balances of accounts which changed, or all of them. Returns the alerts newly raised,
+    /// which are also logged as warnings and kept in [`Self::raised`].
+    pub fn update(
+        &mut self,
+        accounts: impl IntoIterator<Item = (ClientId, AccountView<'static>)>,
+    ) -> Vec<Alert> {
+        if self.rules.is_empty() {
+            return Vec::new();
+        }
+        let mut alerts = Vec::new();
+        for (client_id, view) in accounts {
+            let now = (view.total.to_raw(), view.held.to_raw());
+            let before = self.accounts.insert(client_id, now).unwrap_or_default();
+            self.total = self.total + u128::from(now.0) - u128::from(before.0);
+            self.held = self.held + u128::from(now.1) - u128::from(before.1);
+            if let Some(limit) = self.rules.account_total {
+                let exceeded = view.total > limit;
+                if raise(exceeded, self.accounts_raised.contains(&client_id)) {
+                    alerts.push(Alert::Account {
+                        client_id,
+                        total: view.total,
+                        limit,
+                    });
+                }
+                if exceeded {
+                    self.accounts_raised.insert(client_id);
+                } else {
+                    self.accounts_raised.remove(&client_id);
+                }
+            }
+        }
+        let sum = |raw: u128| Amount::from_raw(u64::try_from(raw).unwrap_or(u64::MAX));
+        if let Some(limit) = self.rules.total {
+            let total = sum(self.total);
+            let exceeded = self.total > u128::from(limit.to_raw());
+            if raise(exceeded, self.liability_raised) {
+                alerts.push(Alert::Liability { total, limit });
+            }
+            self.liability_raised = exceeded;
+        }
+        if let Some(limit) = self.rules.held {
+            let held = sum(self.held);
+            let exceeded = self.held > u128::from(limit.to_raw());
+            if raise(exceeded, self.held_raised) {
+                alerts.push(Alert::Held { held, limit });
+            }
+            self.held_raised = exceeded;
+        }
+        for alert in alerts.iter().filter(|_| !self.quiet) {
+            let client_id = match alert {
+                Alert::Account { client_id, .. } => Some(*client_id),
+                _ => None,
+            };
+            warn!(alert = alert.as_str(), client_id, "alert: {alert}");
+        }
+        self.raised.extend(&alerts);
+        alerts
+    }
+
+    /// Every alert raised so far, in order.
+    pub fn raised(&self) -> &[Alert] {
+        &self.raised
+    }
+}
+
+fn raise(exceeded: bool, raised: bool) -> bool {
+    exceeded && !raised
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::AccountView,
+        alerts::{Alert, AlertMonitor, AlertRules},
+        amount::Amount,
+    };
+
+    #[test]
+    fn test_alerts() {
+        let amount = |v: &str| Amount::parse(v.as_bytes()).unwrap();
+        let view = |total: &str, held: &str| AccountView {
+            available: amount(total).checked_sub(amount(held)).unwrap(),
+            held: amount(held),
+            total: amount(total),
+            locked: false,
+            flagged: false,
+            audit: &[],
+        };
+        let mut monitor = AlertMonitor::new(AlertRules {
+            total: Some(amount("100")),
+            account_total: Some(amount("50")),
+            held: Some(amount("20")),
+        });
+        assert_eq!(
+            monitor.update([(1, view("40", "0")), (2, view("40", "10"))]),
+            []
+        );
+        assert_eq!(
+            monitor.update([(1, view("60", "15"))]),
+            [
+                Alert::Account {
+                    client_id: 1,
+                    total: amount("60"),
+                    limit: amount("50")
+                },
+                Alert::Held {
+                    held: amount("25"),
+                    limit: amount("20")
+                },
+            ]
+        );
+        // Still over, not raised again.
+        assert_eq!(monitor.update([(1, view("55", "15"))]), []);
+        assert_eq!(
+            monitor.update([(2, view("50", "0"))]),
+            [Alert::Liability {
+                total: amount("105"),
+                limit: amount("100")
+            }]
+        );
+        // Back under and over again.
+        monitor.update([(1, view("10", "0"))]);
+        assert_eq!(monitor.update([(1, view("51", "0"))]).len(), 2);
+        assert_eq!(monitor.raised().len(), 5);
+        assert_eq!(
+            monitor.raised()[0].to_json(),
+            r#"{"alert":"account","client":1,"amount":"60","limit":"50"}"#
+        );
+
+        let mut unset = AlertMonitor::default();
+        assert_eq!(unset.update([(1, view("1000", "1000"))]), []);
+    }
+}
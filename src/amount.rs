@@ -1,5 +1,7 @@
 use atoi::FromRadix10Checked;
 
+use crate::digits;
+
 // 4 decimal places.
 const PLACES: usize = 4;
 const PLACES_MOD: u64 = 10u64.pow(PLACES as u32);
@@ -14,22 +16,44 @@ impl std::fmt::Display for Amount {
         let mut fract = self.0 % PLACES_MOD;
         write!(f, "{whole}")?;
         if fract > 0 {
-            // Strip trailing zeroes.
-            while fract % 10 == 0 {
+            // Strip trailing zeroes, keeping leading ones (e.g. 0.05).
+            let mut width = PLACES;
+            while fract.is_multiple_of(10) {
                 fract /= 10;
+                width -= 1;
             }
-            write!(f, ".{fract}")?;
+            write!(f, ".{fract:0width$}")?;
         }
         Ok(())
     }
 }
 
 impl Amount {
+    pub const ONE: Amount = Amount(PLACES_MOD);
+
     pub const fn zero() -> Self {
         Amount(0)
     }
 
+    /// Construct from the internal representation (value * 10^4).
+    pub const fn from_raw(raw: u64) -> Self {
+        Amount(raw)
+    }
+
+    /// The internal representation (value * 10^4).
+    pub const fn to_raw(self) -> u64 {
+        self.0
+    }
+
     pub fn parse(bytes: &[u8]) -> Option<Self> {
+        // Amounts of the usual sizes are parsed with SWAR, everything else one byte at a time.
+        match digits::parse_decimal(bytes, PLACES) {
+            Some(raw) => Some(Amount(raw)),
+            None => Self::parse_fallback(bytes),
+        }
+    }
+
+    fn parse_fallback(bytes: &[u8]) -> Option<Self> {
         if bytes.is_empty() {
             return None;
         }
@@ -63,6 +87,50 @@ impl Amount {
         }
     }
 
+    /// Append the same text as `Display` to `out`, without going through the formatting machinery.
+    pub(crate) fn write_ascii(self, out: &mut Vec<u8>) {
+        let whole = self.0 / PLACES_MOD;
+        let fract = self.0 % PLACES_MOD;
+        out.extend_from_slice(itoa::Buffer::new().format(whole).as_bytes());
+        if fract > 0 {
+            // Zero-padded to PLACES digits, then trailing zeroes stripped.
+            let mut digits = [b'0'; PLACES];
+            let mut rest = fract;
+            for d in digits.iter_mut().rev() {
+                *d += (rest % 10) as u8;
+                rest /= 10;
+            }
+            // Non-zero, so at least one digit stays.
+            let mut len = PLACES;
+            while digits[len - 1] == b'0' {
+                len -= 1;
+            }
+            out.push(b'.');
+            out.extend_from_slice(&digits[..len]);
+        }
+    }
+
+    /// Like `write_ascii`, but rounded half up to `places` decimal places (at most 4), and always
+    /// with that many, e.g. "1.50".
+    pub(crate) fn write_ascii_fixed(self, places: u8, out: &mut Vec<u8>) {
+        let places = (places as usize).min(PLACES);
+        let scale = 10u64.pow((PLACES - places) as u32);
+        // Can't overflow, as the quotient is at most u64::MAX / 10.
+        let rounded = self.0 / scale + u64::from(self.0 % scale >= scale.div_ceil(2));
+        let places_mod = 10u64.pow(places as u32);
+        out.extend_from_slice(itoa::Buffer::new().format(rounded / places_mod).as_bytes());
+        if places > 0 {
+            let mut digits = [b'0'; PLACES];
+            let mut rest = rounded % places_mod;
+            for d in digits[..places].iter_mut().rev() {
+                *d += (rest % 10) as u8;
+                rest /= 10;
+            }
+            out.push(b'.');
+            out.extend_from_slice(&digits[..places]);
+        }
+    }
+
     pub fn checked_add(self, rhs: Amount) -> Option<Self> {
         self.0.checked_add(rhs.0).map(Amount)
     }
@@ -70,6 +138,35 @@ impl Amount {
     pub fn checked_sub(self, rhs: Amount) -> Option<Self> {
         self.0.checked_sub(rhs.0).map(Amount)
     }
+
+    pub fn saturating_add(self, rhs: Amount) -> Self {
+        Amount(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl std::str::FromStr for Amount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Amount::parse(s.trim().as_bytes()).ok_or_else(|| format!("invalid amount {s:?}"))
+    }
+}
+
+/// Serialized as a decimal string (e.g. "1.5") so no precision is lost in formats like JSON.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Amount {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Amount {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        Amount::parse(s.as_bytes())
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid amount {s:?}")))
+    }
 }
 
 #[cfg(test)]
@@ -113,6 +210,37 @@ mod tests {
         assert_eq!(Amount::parse(b"1.12345f"), None);
     }
 
+    #[test]
+    fn test_parse_matches_fallback() {
+        let mut inputs = Vec::new();
+        for whole in 0..=10 {
+            for fraction in [None, Some(0), Some(1), Some(3), Some(4), Some(5), Some(8)] {
+                let mut s = "9876543210"[..whole].to_string();
+                if let Some(fraction) = fraction {
+                    s.push('.');
+                    s.push_str(&"1234567890"[..fraction]);
+                }
+                // Every single byte replaced by one that isn't a digit, or by a digit.
+                for i in 0..s.len() {
+                    for b in [b'0', b'9', b'.', b'/', b':', b' ', b'-', b'a', 0xFF] {
+                        let mut s = s.clone().into_bytes();
+                        s[i] = b;
+                        inputs.push(s);
+                    }
+                }
+                inputs.push(s.into_bytes());
+            }
+        }
+        for input in inputs {
+            assert_eq!(
+                Amount::parse(&input),
+                Amount::parse_fallback(&input),
+                "{:?}",
+                String::from_utf8_lossy(&input)
+            );
+        }
+    }
+
     #[test]
     fn test_fmt() {
         for amount in [
@@ -122,13 +250,51 @@ mod tests {
             "1.12",
             "1.123",
             "1.1234",
+            "0.0001",
+            "1.0203",
             "1844674407370955.1615",
         ] {
-            let value = Amount::parse(amount.as_bytes()).unwrap().to_string();
+            let parsed = Amount::parse(amount.as_bytes()).unwrap();
+            let value = parsed.to_string();
             assert_eq!(
                 value, amount,
                 "{amount}.parse().to_string(). Expected {amount}, got {value}"
-            )
+            );
+            let mut ascii = Vec::new();
+            parsed.write_ascii(&mut ascii);
+            assert_eq!(ascii, amount.as_bytes());
+        }
+
+        for (amount, places, expected) in [
+            ("1.5", 2, "1.50"),
+            ("1.005", 2, "1.01"),
+            ("1.0049", 2, "1.00"),
+            ("0.9999", 3, "1.000"),
+            ("2.5", 0, "3"),
+            ("1.1234", 4, "1.1234"),
+            ("1.1234", 9, "1.1234"),
+            ("1844674407370955.1615", 2, "1844674407370955.16"),
+            ("1844674407370955.1615", 0, "1844674407370955"),
+        ] {
+            let mut ascii = Vec::new();
+            Amount::parse(amount.as_bytes())
+                .unwrap()
+                .write_ascii_fixed(places, &mut ascii);
+            assert_eq!(
+                String::from_utf8(ascii).unwrap(),
+                expected,
+                "{amount} {places}"
+            );
         }
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let amount = Amount::parse(b"1.25").unwrap();
+        let json = serde_json::to_string(&amount).unwrap();
+        assert_eq!(json, "\"1.25\"");
+        assert_eq!(serde_json::from_str::<Amount>(&json).unwrap(), amount);
+        assert!(serde_json::from_str::<Amount>("\"1.x\"").is_err());
+    }
 }
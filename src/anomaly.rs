@@ -0,0 +1,398 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    io::{self, Write},
+};
+
+use crate::{
+    accounts::{ClientId, Transaction, TransactionId, TransactionKind},
+    amount::Amount,
+};
+
+/// When [`AnomalyDetector`] flags a client. The input has no timestamps, so bursts and
+/// structuring are measured in rows of the input rather than time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct AnomalyThresholds {
+    /// Consecutive deposits which, followed by a withdrawal of most of them, are flagged.
+    pub drain_deposits: usize,
+    /// Share of those deposits the withdrawal takes, in percent.
+    pub drain_percent: u64,
+    /// Share of a client's deposits disputed, in percent, above which it's flagged.
+    pub dispute_percent: u64,
+    /// Deposits a client needs before its dispute rate counts.
+    pub min_deposits: u64,
+    /// Transactions of a client within `burst_rows` rows of the input which are flagged.
+    pub burst_transactions: usize,
+    pub burst_rows: usize,
+    /// Amount deposits are kept just below to avoid reporting, e.g. 10000. Structuring isn't
+    /// looked for if unset.
+    pub structuring_limit: Option<Amount>,
+    /// How far below `structuring_limit` a deposit counts as just below it, in percent.
+    pub structuring_margin_percent: u64,
+    /// Deposits just below the limit within `structuring_rows` rows of the input which are
+    /// flagged.
+    pub structuring_deposits: usize,
+    pub structuring_rows: usize,
+}
+
+impl Default for AnomalyThresholds {
+    fn default() -> Self {
+        AnomalyThresholds {
+            drain_deposits: 3,
+            drain_percent: 90,
+            dispute_percent: 20,
+            min_deposits: 5,
+            burst_transactions: 20,
+            burst_rows: 100,
+            structuring_limit: None,
+            structuring_margin_percent: 10,
+            structuring_deposits: 3,
+            structuring_rows: 1000,
+        }
+    }
+}
+
+/// A pattern of a client's transactions worth a closer look, from [`AnomalyDetector`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Anomaly {
+    /// A withdrawal of `percent` of the `deposits` consecutive deposits just before it. The first
+    /// such withdrawal of the client.
+    DepositDrain { deposits: usize, percent: u64 },
+    /// Disputes above the threshold share of deposits, over the whole input.
+    DisputeRate { disputes: u64, deposits: u64 },
+    /// `transactions` within `rows` rows of the input. The first such burst of the client.
+    RapidFire { transactions: usize, rows: usize },
+    /// Deposits just below `limit` within `rows` rows of the input, possibly split up to avoid
+    /// reporting. The first such sequence of the client.
+    Structuring {
+        limit: Amount,
+        rows: usize,
+        transactions: Vec<TransactionId>,
+    },
+}
+
+impl Anomaly {
+    /// Name of the kind of anomaly, e.g. in the risk report.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Anomaly::DepositDrain { .. } => "deposit-drain",
+            Anomaly::DisputeRate { .. } => "dispute-rate",
+            Anomaly::RapidFire { .. } => "rapid-fire",
+            Anomaly::Structuring { .. } => "structuring",
+        }
+    }
+
+    /// Ids of the transactions making up the anomaly, for review. Only known for structuring.
+    pub fn transactions(&self) -> &[TransactionId] {
+        match self {
+            Anomaly::Structuring { transactions, .. } => transactions,
+            _ => &[],
+        }
+    }
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Anomaly::DepositDrain { deposits, percent } => {
+                write!(f, "withdrew {percent}% of {deposits} deposits at once")
+            }
+            Anomaly::DisputeRate { disputes, deposits } => {
+                write!(f, "disputed {disputes} of {deposits} deposits")
+            }
+            Anomaly::RapidFire { transactions, rows } => {
+                write!(f, "{transactions} transactions within {rows} rows")
+            }
+            Anomaly::Structuring {
+                limit,
+                rows,
+                transactions,
+            } => write!(
+                f,
+                "{} deposits just below {limit} within {rows} rows",
+                transactions.len()
+            ),
+        }
+    }
+}
+
+/// Flags clients with anomalous patterns in a stream of transactions, whether or not they'd
+/// apply, see [`AnomalyThresholds`].
+pub struct AnomalyDetector {
+    thresholds: AnomalyThresholds,
+    clients: HashMap<ClientId, Activity>,
+    rows: usize,
+}
+
+#[derive(Default)]
+struct Activity {
+    deposits: u64,
+    disputes: u64,
+    // Consecutive deposits up to the current transaction, and their raw sum.
+    run: usize,
+    run_sum: u128,
+    // Rows of the last transactions, up to `burst_transactions` of them.
+    recent: VecDeque<usize>,
+    // Rows and ids of deposits just below the structuring limit, within `structuring_rows`.
+    near_limit: VecDeque<(usize, TransactionId)>,
+    drain: Option<Anomaly>,
+    burst: Option<Anomaly>,
+    structuring: Option<Anomaly>,
+}
+
+impl AnomalyDetector {
+    pub fn new(thresholds: AnomalyThresholds) -> Self {
+        AnomalyDetector {
+            thresholds,
+            clients: HashMap::new(),
+            rows: 0,
+        }
+    }
+
+    pub fn observe(&mut self, client_id: ClientId, t: &Transaction) {
+        let row = self.rows;
+        self.rows += 1;
+        let thresholds = &self.thresholds;
+        let activity = self.clients.entry(client_id).or_default();
+        match t.kind {
+            TransactionKind::Deposit => {
+                activity.deposits += 1;
+                activity.run += 1;
+                activity.run_sum += u128::from(t.amount.to_raw());
+                if let Some(limit) = thresholds.structuring_limit {
+                    activity.observe_near_limit(row, t, limit, thresholds);
+                }
+            }
+            TransactionKind::Withdrawal => {
+                if activity.drain.is_none()
+                    && activity.run >= thresholds.drain_deposits.max(1)
+                    && activity.run_sum > 0
+                {
+                    let percent = u128::from(t.amount.to_raw()) * 100 / activity.run_sum;
+                    let percent = u64::try_from(percent).unwrap_or(u64::MAX);
+                    if percent >= thresholds.drain_percent {
+                        activity.drain = Some(Anomaly::DepositDrain {
+                            deposits: activity.run,
+                            percent,
+                        });
+                    }
+                }
+            }
+            TransactionKind::Dispute => activity.disputes += 1,
+            TransactionKind::Resolve | TransactionKind::Chargeback => {}
+        }
+        if t.kind != TransactionKind::Deposit {
+            activity.run = 0;
+            activity.run_sum = 0;
+        }
+
+        let burst = thresholds.burst_transactions.max(1);
+        activity.recent.push_back(row);
+        if activity.recent.len() > burst {
+            activity.recent.pop_front();
+        }
+        let first = activity.recent[0];
+        if activity.burst.is_none()
+            && activity.recent.len() == burst
+            && row - first < thresholds.burst_rows
+        {
+            activity.burst = Some(Anomaly::RapidFire {
+                transactions: burst,
+                rows: row - first + 1,
+            });
+        }
+    }
+
+    /// Anomalies of every flagged client, by client id.
+    pub fn finish(self) -> Vec<(ClientId, Anomaly)> {
+        let thresholds = self.thresholds;
+        let mut anomalies = Vec::new();
+        for (client_id, activity) in self.clients {
+            let rate = (activity.deposits >= thresholds.min_deposits.max(1)
+                && activity.disputes * 100 > thresholds.dispute_percent * activity.deposits)
+                .then_some(Anomaly::DisputeRate {
+                    disputes: activity.disputes,
+                    deposits: activity.deposits,
+                });
+            anomalies.extend(
+                [activity.drain, rate, activity.burst, activity.structuring]
+                    .into_iter()
+                    .flatten()
+                    .map(|a| (client_id, a)),
+            );
+        }
+        // Stable, keeping each client's anomalies in the order above.
+        anomalies.sort_by_key(|(client_id, _)| *client_id);
+        anomalies
+    }
+}
+
+impl Activity {
+    fn observe_near_limit(
+        &mut self,
+        row: usize,
+        t: &Transaction,
+        limit: Amount,
+        thresholds: &AnomalyThresholds,
+    ) {
+        let margin = thresholds.structuring_margin_percent.min(100);
+        let floor = u128::from(limit.to_raw()) * u128::from(100 - margin) / 100;
+        let amount = u128::from(t.amount.to_raw());
+        if amount < floor || t.amount >= limit {
+            return;
+        }
+        self.near_limit.push_back((row, t.id));
+        while let Some(&(first, _)) = self.near_limit.front()
+            && row - first >= thresholds.structuring_rows
+        {
+            self.near_limit.pop_front();
+        }
+        if self.structuring.is_none()
+            && self.near_limit.len() >= thresholds.structuring_deposits.max(1)
+        {
+            let (first, _) = self.near_limit[0];
+            self.structuring = Some(Anomaly::Structuring {
+                limit,
+                rows: row - first + 1,
+                transactions: self.near_limit.iter().map(|&(_, id)| id).collect(),
+            });
+        }
+    }
+}
+
+/// Write anomalies as a CSV risk report with `client,anomaly,detail,transactions` columns, the
+/// transactions separated by spaces.
+pub fn write_risk_report(mut w: impl Write, anomalies: &[(ClientId, Anomaly)]) -> io::Result<()> {
+    writeln!(w, "client,anomaly,detail,transactions")?;
+    for (client_id, anomaly) in anomalies {
+        // The details have no commas or quotes.
+        write!(w, "{client_id},{},{anomaly},", anomaly.as_str())?;
+        for (i, id) in anomaly.transactions().iter().enumerate() {
+            let sep = if i == 0 { "" } else { " " };
+            write!(w, "{sep}{id}")?;
+        }
+        writeln!(w)?;
+    }
+    w.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::{Transaction, TransactionKind::*},
+        amount::Amount,
+        anomaly::{Anomaly, AnomalyDetector, AnomalyThresholds, write_risk_report},
+    };
+
+    #[test]
+    fn test_detect_anomalies() {
+        let t = |kind, id, amount: &str| Transaction {
+            kind,
+            id,
+            amount: Amount::parse(amount.as_bytes()).unwrap_or_default(),
+        };
+        let mut detector = AnomalyDetector::new(AnomalyThresholds {
+            burst_transactions: 4,
+            burst_rows: 5,
+            structuring_limit: Amount::parse(b"100"),
+            ..Default::default()
+        });
+        let mut rows = vec![
+            // Drained.
+            (1, t(Deposit, 1, "10")),
+            (2, t(Deposit, 2, "1")),
+            (1, t(Deposit, 3, "10")),
+            (1, t(Deposit, 4, "10")),
+            (2, t(Deposit, 5, "1")),
+            (1, t(Withdrawal, 6, "28")),
+            // Not enough consecutive deposits, and only 80%.
+            (2, t(Withdrawal, 7, "2")),
+            (2, t(Deposit, 8, "1")),
+            (2, t(Deposit, 9, "1")),
+            (2, t(Deposit, 10, "1")),
+            (2, t(Withdrawal, 11, "2.4")),
+        ];
+        // Client 3 disputes 2 of 5 deposits, spread out.
+        for id in 20..25 {
+            rows.push((3, t(Deposit, id, "1")));
+            rows.extend((0..3).map(|i| (4, t(Deposit, 100 + id * 3 + i, "1"))));
+        }
+        rows.push((3, t(Dispute, 20, "")));
+        rows.push((3, t(Dispute, 21, "")));
+        // Client 5 deposits just below 100 three times, one of them too far below.
+        rows.extend([
+            (5, t(Deposit, 40, "99")),
+            (5, t(Deposit, 41, "80")),
+            (5, t(Deposit, 42, "100")),
+            (5, t(Withdrawal, 43, "1")),
+            (5, t(Deposit, 44, "95.5")),
+            (5, t(Deposit, 45, "90")),
+        ]);
+        for (client_id, t) in &rows {
+            detector.observe(*client_id, t);
+        }
+        let anomalies = detector.finish();
+        assert_eq!(
+            anomalies,
+            [
+                (
+                    1,
+                    Anomaly::DepositDrain {
+                        deposits: 3,
+                        percent: 93
+                    }
+                ),
+                // 4 rows apart.
+                (
+                    2,
+                    Anomaly::RapidFire {
+                        transactions: 4,
+                        rows: 5
+                    }
+                ),
+                (
+                    3,
+                    Anomaly::DisputeRate {
+                        disputes: 2,
+                        deposits: 5
+                    }
+                ),
+                // Every 4th row.
+                (
+                    4,
+                    Anomaly::RapidFire {
+                        transactions: 4,
+                        rows: 5
+                    }
+                ),
+                // Also in a row.
+                (
+                    5,
+                    Anomaly::RapidFire {
+                        transactions: 4,
+                        rows: 4
+                    }
+                ),
+                (
+                    5,
+                    Anomaly::Structuring {
+                        limit: Amount::parse(b"100").unwrap(),
+                        rows: 6,
+                        transactions: vec![40, 44, 45]
+                    }
+                ),
+            ]
+        );
+
+        let mut report = Vec::new();
+        write_risk_report(&mut report, &[anomalies[0].clone(), anomalies[5].clone()]).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,anomaly,detail,transactions\n\
+             1,deposit-drain,withdrew 93% of 3 deposits at once,\n\
+             5,structuring,3 deposits just below 100 within 6 rows,40 44 45\n"
+        );
+    }
+}
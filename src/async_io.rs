@@ -0,0 +1,75 @@
+use std::hash::BuildHasher;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+use crate::{
+    Error, accounts::ClientsDatabase, deposits::DepositStore, parser::Row, pipeline::raw_line,
+    process::ProcessReport,
+};
+
+/// Read CSV rows from an async source and apply them to the database, e.g. when receiving
+/// transactions over the network.
+///
+/// A leading header line (starting with "type") is skipped. Rows that fail to parse are rejected
+/// like transactions that fail to apply; report indices are 0-based data row positions.
+pub async fn process_stream<S: BuildHasher, D: DepositStore>(
+    mut reader: impl AsyncBufRead + Unpin,
+    db: &mut ClientsDatabase<S, D>,
+) -> std::io::Result<ProcessReport> {
+    let mut report = db.new_report();
+    let mut buf = Vec::new();
+    let mut first = true;
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf).await? == 0 {
+            return Ok(report);
+        }
+        if std::mem::take(&mut first) && buf.trim_ascii_start().starts_with(b"type") {
+            continue;
+        }
+        let (transaction, result) = match Row::parse(&buf) {
+            Ok(row) => {
+                let new = db.get(row.client_id).is_none();
+                let result = db.process_transaction(row.client_id, row.transaction);
+                if new && result.is_ok() {
+                    report.accounts_created += 1;
+                }
+                (
+                    Some((row.client_id, row.transaction)),
+                    result.map_err(Error::from),
+                )
+            }
+            Err(e) => {
+                report.samples.add(e.kind(), || raw_line(&buf).into());
+                (None, Err(e.into()))
+            }
+        };
+        report.record(report.processed, transaction, result);
+        if report.aborted.is_some() {
+            return Ok(report);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        Error, accounts::ClientsDatabase, amount::Amount, async_io::process_stream,
+        error::ParseError,
+    };
+
+    #[tokio::test]
+    async fn test_process_stream() {
+        let input = b"type, client, tx, amount\ndeposit, 1, 1, 2.0\nfoo\nwithdrawal, 1, 2, 0.5\n";
+        let mut db = ClientsDatabase::new();
+        let report = process_stream(&input[..], &mut db).await.unwrap();
+        assert_eq!(report.processed, 3);
+        assert_eq!(report.applied, 2);
+        assert_eq!(report.accounts_created, 1);
+        assert!(matches!(
+            report.rejections[..],
+            [(1, Error::Parse(ParseError::MissingColumn))]
+        ));
+        assert_eq!(db.get(1).unwrap().total(), Amount::parse(b"1.5").unwrap());
+    }
+}
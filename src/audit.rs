@@ -0,0 +1,231 @@
+use std::{collections::HashMap, io::Write};
+
+use crate::{
+    accounts::{AccountView, ClientId, Transaction, TransactionId},
+    history::Timestamp,
+};
+
+/// One applied transaction with the account's balances right before and after it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AuditRecord {
+    pub at: Timestamp,
+    pub transaction: Transaction,
+    pub before: AccountView<'static>,
+    pub after: AccountView<'static>,
+    /// Input record of the deposit this transaction is, or disputes, resolves or charges back,
+    /// with provenance enabled.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub deposit: Option<Provenance>,
+}
+
+/// Where a deposit came from, see [`crate::accounts::ClientsDatabase::enable_provenance`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Provenance {
+    /// The input, see [`crate::accounts::ClientsDatabase::add_source`].
+    pub source: u32,
+    /// 1-based line of the input including the header, or record of inputs without lines.
+    pub line: u64,
+}
+
+/// Inputs and the input record of each deposit.
+#[derive(Debug, Default)]
+pub(crate) struct ProvenanceLog {
+    sources: Vec<String>,
+    deposits: HashMap<(ClientId, TransactionId), Provenance>,
+}
+
+impl ProvenanceLog {
+    pub(crate) fn add_source(&mut self, name: String) -> u32 {
+        match self.sources.iter().position(|s| *s == name) {
+            Some(idx) => idx as u32,
+            None => {
+                self.sources.push(name);
+                (self.sources.len() - 1) as u32
+            }
+        }
+    }
+
+    pub(crate) fn source(&self, idx: u32) -> Option<&str> {
+        self.sources.get(idx as usize).map(String::as_str)
+    }
+
+    pub(crate) fn record(&mut self, client_id: ClientId, tx: TransactionId, at: Provenance) {
+        self.deposits.insert((client_id, tx), at);
+    }
+
+    pub(crate) fn get(&self, client_id: ClientId, tx: TransactionId) -> Option<Provenance> {
+        self.deposits.get(&(client_id, tx)).copied()
+    }
+}
+
+/// Append-only per-account list of applied transactions.
+#[derive(Debug, Default)]
+pub(crate) struct AuditLog {
+    accounts: HashMap<ClientId, Vec<AuditRecord>>,
+}
+
+impl AuditLog {
+    pub(crate) fn record(&mut self, client_id: ClientId, record: AuditRecord) {
+        self.accounts.entry(client_id).or_default().push(record);
+    }
+
+    pub(crate) fn records(&self, client_id: ClientId) -> &[AuditRecord] {
+        self.accounts
+            .get(&client_id)
+            .map(|r| r.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Write records of clients matching `filter` as CSV, ordered by client id and then by
+    /// application order, with names of inputs from `sources`.
+    pub(crate) fn write_csv<'a>(
+        &self,
+        mut w: impl Write,
+        filter: impl Fn(ClientId) -> bool,
+        sources: impl Fn(u32) -> &'a str,
+    ) -> std::io::Result<()> {
+        writeln!(
+            w,
+            "client, at, type, tx, amount, \
+             available_before, held_before, total_before, locked_before, \
+             available_after, held_after, total_after, locked_after, deposit_source, deposit_line"
+        )?;
+        let mut clients = self
+            .accounts
+            .keys()
+            .copied()
+            .filter(|c| filter(*c))
+            .collect::<Vec<_>>();
+        clients.sort_unstable();
+        for client_id in clients {
+            for r in self.records(client_id) {
+                let t = r.transaction;
+                let (b, a) = (r.before, r.after);
+                let (source, line) = match r.deposit {
+                    Some(p) => (sources(p.source), p.line.to_string()),
+                    None => ("", String::new()),
+                };
+                writeln!(
+                    w,
+                    "{client_id},{},{},{},{},{},{},{},{},{},{},{},{},{source},{line}",
+                    r.at,
+                    t.kind.as_str(),
+                    t.id,
+                    t.amount,
+                    b.available,
+                    b.held,
+                    b.total,
+                    b.locked,
+                    a.available,
+                    a.held,
+                    a.total,
+                    a.locked
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::{ClientsDatabase, Transaction, TransactionKind::*},
+        amount::Amount,
+        audit::Provenance,
+    };
+
+    #[test]
+    fn test_audit_trail() {
+        let mut db = ClientsDatabase::new();
+        db.enable_audit();
+        for (kind, id, amount) in [
+            (Deposit, 1, "3"),
+            (Withdrawal, 2, "5"), // rejected
+            (Withdrawal, 3, "1"),
+            (Dispute, 1, ""),
+        ] {
+            let _ = db.process_transaction(
+                1,
+                Transaction {
+                    kind,
+                    id,
+                    amount: Amount::parse(amount.as_bytes()).unwrap_or_default(),
+                },
+            );
+        }
+
+        let records = db.view(1).unwrap().audit();
+        assert_eq!(records, db.audit(1));
+        assert!(db.get(1).unwrap().view().audit().is_empty());
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].before.total, Amount::zero());
+        assert_eq!(records[0].after.total, Amount::parse(b"3").unwrap());
+        assert_eq!(records[1].at, 2);
+        assert_eq!(records[2].after.held, Amount::parse(b"3").unwrap());
+        assert!(db.audit(2).is_empty());
+
+        let mut csv = Vec::new();
+        db.export_audit(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[1], "1,0,deposit,1,3,0,0,0,false,3,0,3,false,,");
+        assert_eq!(lines[3], "1,3,dispute,1,0,2,0,2,false,0,3,2,false,,");
+
+        let mut csv = Vec::new();
+        db.export_audit_of(&mut csv, |c| c == 2).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn test_provenance() {
+        let mut db = ClientsDatabase::new();
+        db.enable_audit();
+        db.enable_provenance();
+        let source = db.add_source("a.csv");
+        assert_eq!(db.add_source("b.csv"), source + 1);
+        assert_eq!(db.add_source("a.csv"), source);
+        let t = |kind, id, amount: &str| Transaction {
+            kind,
+            id,
+            amount: Amount::parse(amount.as_bytes()).unwrap_or_default(),
+        };
+        let report = db.process_all_from(
+            source,
+            [
+                (2, 1, t(Deposit, 1, "3")),
+                (3, 1, t(Deposit, 2, "1")),
+                (5, 1, t(Withdrawal, 3, "1")),
+                (6, 1, t(Dispute, 1, "")),
+            ],
+        );
+        assert_eq!(report.applied, 4);
+        assert_eq!(report.accounts_created, 1);
+        // Not from an input.
+        db.process_transaction(1, t(Deposit, 4, "1")).unwrap();
+        db.process_transaction(1, t(Chargeback, 1, "")).unwrap();
+
+        assert_eq!(db.provenance(1, 2), Some(Provenance { source, line: 3 }));
+        assert_eq!(db.provenance(1, 4), None);
+        let records = db.audit(1);
+        let deposit = Some(Provenance { source, line: 2 });
+        assert_eq!(records[0].deposit, deposit);
+        assert_eq!(records[2].deposit, None);
+        assert_eq!(records[3].deposit, deposit);
+        assert_eq!(records[4].deposit, None);
+        assert_eq!(records[5].deposit, deposit);
+
+        let mut csv = Vec::new();
+        db.export_audit(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[6],
+            "1,5,chargeback,1,0,1,3,4,false,0,0,1,true,a.csv,2"
+        );
+        assert!(lines[5].ends_with(",,"));
+    }
+}
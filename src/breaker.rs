@@ -0,0 +1,250 @@
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+/// Settings of [`CircuitBreaker`], guarding the storage deposits are spilled to, see
+/// [`crate::accounts::ClientsDatabase::enable_breaker`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct BreakerConfig {
+    /// Consecutive failures after which the storage isn't tried until `cooldown_ms` passed.
+    pub failures: u32,
+    pub cooldown_ms: u64,
+    /// Deposits kept in memory beyond the spill window while the storage fails, after which
+    /// processing pauses until it recovers.
+    pub max_buffered: usize,
+    /// Longest pause waiting for the storage to recover, after which transactions are rejected
+    /// as before, failing the run with an abort policy.
+    pub max_pause_ms: u64,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        BreakerConfig {
+            failures: 3,
+            cooldown_ms: 1000,
+            max_buffered: 1_000_000,
+            max_pause_ms: 60_000,
+        }
+    }
+}
+
+impl BreakerConfig {
+    pub fn cooldown(&self) -> Duration {
+        Duration::from_millis(self.cooldown_ms)
+    }
+
+    pub fn max_pause(&self) -> Duration {
+        Duration::from_millis(self.max_pause_ms)
+    }
+}
+
+/// Whether a [`CircuitBreaker`] lets calls through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Calls go through, failures are counted.
+    Closed,
+    /// Too many failures, calls aren't made until the cooldown passed.
+    Open,
+    /// The cooldown passed, one call is let through to see whether the storage recovered.
+    HalfOpen,
+}
+
+/// Stops calling a failing backend for a while after repeated failures, then tries it again once.
+/// A success closes it, another failure opens it for another cooldown.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    config: BreakerConfig,
+    state: BreakerState,
+    // Consecutive ones.
+    failures: u32,
+    opened_at: Instant,
+    trips: usize,
+    last_error: Option<String>,
+    // Whether the last pause reached `max_pause`. Later calls don't pause until a success, so
+    // an outage outlasting it doesn't stall every transaction for that long.
+    gave_up: bool,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: BreakerConfig) -> Self {
+        CircuitBreaker {
+            config,
+            state: BreakerState::Closed,
+            failures: 0,
+            opened_at: Instant::now(),
+            trips: 0,
+            last_error: None,
+            gave_up: false,
+        }
+    }
+
+    pub fn config(&self) -> &BreakerConfig {
+        &self.config
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.state
+    }
+
+    /// Times the breaker opened.
+    pub fn trips(&self) -> usize {
+        self.trips
+    }
+
+    /// The error of the last failure, until a success.
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    /// Whether a call may be made at `now`.
+    pub fn allow(&mut self, now: Instant) -> bool {
+        match self.state {
+            BreakerState::Closed | BreakerState::HalfOpen => true,
+            BreakerState::Open if self.retry_in(now).is_zero() => {
+                self.state = BreakerState::HalfOpen;
+                true
+            }
+            BreakerState::Open => false,
+        }
+    }
+
+    /// How long until a call may be made again, zero unless open.
+    pub fn retry_in(&self, now: Instant) -> Duration {
+        match self.state {
+            BreakerState::Open => {
+                (self.opened_at + self.config.cooldown()).saturating_duration_since(now)
+            }
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Call `op`, pausing while the breaker is open and retrying it after failures, for up to
+    /// `max_pause` in all. Fails with the last error after that.
+    pub fn call<T>(&mut self, mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        let mut paused = Duration::ZERO;
+        loop {
+            let now = Instant::now();
+            if self.allow(now) {
+                match op() {
+                    Ok(value) => {
+                        self.record_success();
+                        return Ok(value);
+                    }
+                    Err(e) => {
+                        self.record_failure(now, &e);
+                        if paused >= self.max_pause() {
+                            self.gave_up = true;
+                            return Err(e);
+                        }
+                    }
+                }
+            } else if paused >= self.max_pause() {
+                return Err(self.give_up());
+            }
+            paused += self.pause(now, paused);
+        }
+    }
+
+    /// How long processing may still pause in all, none after giving up until a success.
+    pub fn max_pause(&self) -> Duration {
+        if self.gave_up {
+            Duration::ZERO
+        } else {
+            self.config.max_pause()
+        }
+    }
+
+    /// Sleep until the next try at the storage, given how long processing was `paused` so far,
+    /// at most until [`Self::max_pause`]. Returns how long it slept.
+    pub fn pause(&self, now: Instant, paused: Duration) -> Duration {
+        if paused.is_zero() {
+            tracing::warn!(
+                error = self.last_error().unwrap_or_default(),
+                "storage unavailable, pausing processing"
+            );
+        }
+        // Failures below the threshold are retried after a cooldown too.
+        let wait = match self.state {
+            BreakerState::Open => self.retry_in(now),
+            _ => self.config.cooldown(),
+        };
+        // Always some progress towards `max_pause`, even without a cooldown.
+        let wait = wait
+            .max(Duration::from_millis(1))
+            .min(self.max_pause().saturating_sub(paused));
+        std::thread::sleep(wait);
+        wait
+    }
+
+    /// Stop pausing until a success, see [`Self::max_pause`], with the error to fail with.
+    pub fn give_up(&mut self) -> io::Error {
+        self.gave_up = true;
+        io::Error::other(format!(
+            "storage unavailable: {}",
+            self.last_error().unwrap_or("circuit open")
+        ))
+    }
+
+    pub fn record_success(&mut self) {
+        if self.state != BreakerState::Closed {
+            tracing::info!(trips = self.trips, "storage recovered, circuit closed");
+        }
+        self.state = BreakerState::Closed;
+        self.failures = 0;
+        self.last_error = None;
+        self.gave_up = false;
+    }
+
+    pub fn record_failure(&mut self, now: Instant, error: &dyn std::fmt::Display) {
+        self.failures += 1;
+        self.last_error = Some(error.to_string());
+        if self.state == BreakerState::HalfOpen || self.failures >= self.config.failures.max(1) {
+            if self.state == BreakerState::Closed {
+                tracing::warn!(failures = self.failures, %error, "storage failing, circuit opened");
+            }
+            self.state = BreakerState::Open;
+            self.opened_at = now;
+            self.trips += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::breaker::{BreakerConfig, BreakerState, CircuitBreaker};
+
+    #[test]
+    fn test_circuit_breaker() {
+        let mut breaker = CircuitBreaker::new(BreakerConfig {
+            failures: 2,
+            cooldown_ms: 100,
+            ..Default::default()
+        });
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        assert!(breaker.allow(at(0)));
+        breaker.record_failure(at(0), &"disk full");
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        breaker.record_failure(at(10), &"disk full");
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert_eq!(breaker.last_error(), Some("disk full"));
+        assert!(!breaker.allow(at(50)));
+        assert_eq!(breaker.retry_in(at(50)), Duration::from_millis(60));
+
+        // One failed try opens it again right away.
+        assert!(breaker.allow(at(110)));
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        breaker.record_failure(at(110), &"disk full");
+        assert!(!breaker.allow(at(200)));
+        assert!(breaker.allow(at(210)));
+        breaker.record_success();
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert_eq!(breaker.last_error(), None);
+        assert_eq!(breaker.trips(), 2);
+    }
+}
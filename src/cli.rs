@@ -0,0 +1,38 @@
+//! Drivers of the subcommands, one module each, and what they share: exit codes and the summary
+//! line, reading inputs and writing reports.
+
+#[cfg(feature = "amqp")]
+pub mod amqp;
+pub mod completions;
+pub mod convert;
+pub mod diff;
+pub mod follow;
+pub mod input;
+pub mod inspect;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "nats")]
+pub mod nats;
+pub mod output;
+pub mod process;
+#[cfg(feature = "redis")]
+pub mod redis;
+pub mod repl;
+pub mod report;
+pub mod risk;
+pub mod sample;
+pub mod schema;
+#[cfg(feature = "server")]
+pub mod serve;
+pub mod split;
+#[cfg(any(
+    feature = "server",
+    feature = "kafka",
+    feature = "nats",
+    feature = "amqp"
+))]
+pub mod state;
+pub mod status;
+pub mod summary;
+pub mod validate;
+pub mod verify;
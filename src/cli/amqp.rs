@@ -0,0 +1,52 @@
+//! `amqp`: applying rows consumed from an AMQP queue until interrupted.
+
+use std::{io, time::Duration};
+
+use payengine::args::AmqpArgs;
+use payengine::config::Config;
+use tracing::info;
+
+use crate::cli::{
+    state::{database_with_state, snapshot_checkpoint},
+    status::{Status, fail},
+};
+
+pub fn run(config: &Config, args: &AmqpArgs) -> io::Result<()> {
+    let interval = Duration::try_from_secs_f64(args.interval)
+        .unwrap_or_else(|e| fail(Status::Usage, format_args!("invalid --interval: {e}")));
+    let mut db = database_with_state(config, args.state.as_deref());
+    let settings = payengine::amqp::AmqpSettings {
+        url: args.url.clone(),
+        queue: args.queue.clone(),
+        consumer_tag: args.consumer_tag.clone(),
+        prefetch: args.prefetch,
+        interval,
+    };
+    let checkpoint = snapshot_checkpoint(args.snapshot.as_deref(), config);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    info!(queue = args.queue, "consuming");
+    let stats = runtime
+        .block_on(payengine::amqp::run(
+            &settings,
+            &mut db,
+            checkpoint,
+            async {
+                tokio::signal::ctrl_c().await.ok();
+            },
+        ))
+        .unwrap_or_else(|e| {
+            fail(
+                Status::Io,
+                format_args!("error consuming from {}: {e}", args.queue),
+            )
+        });
+    info!(
+        messages = stats.messages,
+        applied = stats.applied,
+        rejected = stats.rejected,
+        "stopped"
+    );
+    Ok(())
+}
@@ -0,0 +1,11 @@
+//! `completions`: printing shell completion scripts.
+
+use std::io::{self, Write};
+
+use clap_complete::Shell;
+
+pub fn run(shell: Shell, mut command: clap::Command) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    clap_complete::generate(shell, &mut command, "payengine", &mut out);
+    out.flush()
+}
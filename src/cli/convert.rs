@@ -0,0 +1,35 @@
+//! `convert`: rewriting an input in another format.
+
+use std::io;
+
+use payengine::{args::ConvertArgs, config::Config, convert::TransactionFormat};
+use tracing::warn;
+
+use crate::cli::{
+    input::{Unparsed, input_format, parse_rows},
+    output::open_output,
+};
+
+pub fn run(config: &Config, args: &ConvertArgs) -> io::Result<()> {
+    let mut unparsed = Unparsed::default();
+    let from = args.from.unwrap_or_else(|| input_format(&args.input));
+    let rows = parse_rows(
+        &args.input,
+        from,
+        config,
+        args.rows,
+        &mut unparsed,
+        None,
+        None,
+    );
+    let output = args.output.as_ref().or(args.output_flag.as_ref());
+    let to = args
+        .to
+        .or_else(|| output.and_then(TransactionFormat::from_path))
+        .unwrap_or_default();
+    to.write(open_output(output), rows)?;
+    for (kind, count) in unparsed.counts.iter() {
+        warn!(error_kind = ?kind, count, "skipped malformed rows");
+    }
+    Ok(())
+}
@@ -0,0 +1,29 @@
+//! `diff`: comparing two balances reports or snapshots.
+
+use std::io::{self, Write};
+
+use payengine::args::DiffArgs;
+
+use crate::cli::input::read_balances;
+
+/// Whether the reports match.
+pub fn run(args: &DiffArgs) -> io::Result<bool> {
+    let diffs = payengine::diff::diff(read_balances(&args.left), read_balances(&args.right));
+    let mut out = io::stdout().lock();
+    for d in &diffs {
+        write!(out, "client {}: ", d.client_id)?;
+        match (d.left, d.right) {
+            (Some(_), None) => writeln!(out, "only in {}", args.left.display())?,
+            (None, _) => writeln!(out, "only in {}", args.right.display())?,
+            (Some(_), Some(_)) => {
+                for (idx, (name, left, right)) in d.changes().into_iter().enumerate() {
+                    let sep = if idx > 0 { ", " } else { "" };
+                    write!(out, "{sep}{name} {left} != {right}")?;
+                }
+                writeln!(out)?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(diffs.is_empty())
+}
@@ -0,0 +1,126 @@
+//! `process --follow`: applying rows appended to the input until killed.
+
+use std::{collections::BTreeSet, fs::File, io, path::PathBuf, time::Duration};
+
+use payengine::{
+    args::FollowArgs,
+    config::Config,
+    convert::TransactionFormat,
+    error::{ErrorKind, LedgerError},
+    follow::Follow,
+    process::{ErrorPolicy, RejectionCounts},
+    ratelimit::RateLimiter,
+};
+use tracing::{debug, trace, warn};
+
+use crate::cli::{
+    input::input_format,
+    output::{write, write_accounts},
+    process::{log_report, start_alerts},
+    status::{Status, fail, fail_with_code},
+};
+
+pub fn run(config: &Config, inputs: &[PathBuf], args: &FollowArgs) -> io::Result<()> {
+    let [input] = inputs else {
+        fail(Status::Usage, "--follow takes a single input")
+    };
+    if input_format(input) != TransactionFormat::Csv {
+        fail(Status::Usage, "--follow reads CSV only");
+    }
+    if config.error_policies().uses(ErrorPolicy::DeadLetter) {
+        fail(
+            Status::Usage,
+            "--follow doesn't support dead-letter policies",
+        );
+    }
+    let interval = Duration::try_from_secs_f64(args.interval)
+        .unwrap_or_else(|e| fail(Status::Usage, format_args!("invalid --interval: {e}")));
+    let strict = config.parser.strict || config.parser.on_error == ErrorPolicy::Abort;
+    let file = File::open(input).unwrap_or_else(|e| {
+        fail(
+            Status::Io,
+            format_args!("error opening {}: {e}", input.display()),
+        )
+    });
+    let mut follow = Follow::new(file);
+    let mut db = config.database();
+    // Raised as warnings, running as long as this does.
+    let _alerts = start_alerts(config, &mut db);
+    let limiter = RateLimiter::new(config.rate_limit.clone());
+    let limited_policy = config.error_policies().get(ErrorKind::RateLimited);
+    let mut changed = BTreeSet::new();
+    loop {
+        let mut unparsed = RejectionCounts::default();
+        let mut limited = 0;
+        let mut batch = Vec::new();
+        for row in follow.poll() {
+            match row {
+                Ok(row) if limiter.check(row.client_id).is_err() => {
+                    let e = LedgerError::RateLimited;
+                    let (client_id, tx_id) = (row.client_id, row.transaction.id);
+                    match limited_policy {
+                        ErrorPolicy::Abort => fail_with_code(
+                            Status::Rejected,
+                            Some(e.code()),
+                            format_args!(
+                                "{}: client {client_id}, tx {tx_id}: {e}",
+                                input.display()
+                            ),
+                        ),
+                        ErrorPolicy::Warn => warn!(client_id, tx_id, code = e.code(), "{e}"),
+                        ErrorPolicy::Skip | ErrorPolicy::DeadLetter => {}
+                    }
+                    limited += 1;
+                }
+                Ok(row) => {
+                    changed.insert(row.client_id);
+                    batch.push((row.client_id, row.transaction));
+                }
+                Err(f) if strict => fail_with_code(
+                    Status::Malformed,
+                    Some(f.error.code()),
+                    format_args!("{}:{}: {}", input.display(), f.line, f.error),
+                ),
+                Err(f) => {
+                    unparsed.add(f.error.kind());
+                    if config.parser.verbose {
+                        trace!(
+                            line = f.line,
+                            code = f.error.code(),
+                            "error parsing line: {}",
+                            f.error
+                        );
+                    }
+                }
+            }
+        }
+        if !batch.is_empty() {
+            let report = db.process_parallel(batch);
+            if let Some((idx, e)) = &report.aborted {
+                fail_with_code(
+                    Status::Rejected,
+                    Some(e.code()),
+                    format_args!("{}: transaction {idx}: {e}", input.display()),
+                );
+            }
+            log_report(&db, &report, &unparsed);
+        }
+        if limited > 0 {
+            let code = ErrorKind::RateLimited.code();
+            debug!(count = limited, code, "rate limited transactions");
+        }
+        // Balances only change with new rows, rejected ones included for simplicity.
+        if !changed.is_empty() {
+            if args.changed_only {
+                let accounts = changed
+                    .iter()
+                    .filter_map(|&client_id| Some((client_id, db.get(client_id)?.view())));
+                write_accounts(&config.output, config.review.is_enabled(), accounts)?;
+            } else {
+                write(&db, &config.output)?;
+            }
+            changed.clear();
+        }
+        std::thread::sleep(interval);
+    }
+}
@@ -0,0 +1,278 @@
+//! Reading inputs of any format into rows, and balances and snapshots of previous runs.
+
+use std::{
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+use payengine::{
+    accounts::{AccountView, ClientId, ClientsDatabase, Transaction},
+    args::RowRange,
+    config::Config,
+    convert::{self, TransactionFormat},
+    error::ErrorKind,
+    parser::Row,
+    pipeline::{ParseFailure, RowStream},
+    process::RejectionCounts,
+    profile::{Stopwatch, TimedIter, TimedReader},
+    progress::{CountingReader, Progress},
+    reservoir::RejectSamples,
+    saved::SavedSnapshot,
+    threads::ThreadConfig,
+};
+use tracing::trace;
+
+use crate::cli::{
+    status::{Status, fail},
+    summary::Profile,
+};
+
+/// Malformed rows of a single input.
+#[derive(Default)]
+pub struct Unparsed {
+    pub counts: RejectionCounts,
+    // Stop at the first malformed row, with --strict.
+    pub strict: bool,
+    // With `strict`, the row processing stopped at.
+    pub fatal: Option<ParseFailure>,
+    // Every malformed row, if requested.
+    pub kept: Option<Vec<ParseFailure>>,
+    // Log every malformed row at trace level.
+    pub verbose: bool,
+    // Raw lines of malformed rows, if requested.
+    pub samples: RejectSamples,
+}
+
+/// Balances of a CSV report or a snapshot, told apart by the snapshot's magic.
+pub fn read_balances(path: &Path) -> Vec<(ClientId, AccountView<'static>)> {
+    let bytes = std::fs::read(path).unwrap_or_else(|e| {
+        fail(
+            Status::Io,
+            format_args!("error reading {}: {e}", path.display()),
+        )
+    });
+    if SavedSnapshot::is_snapshot(&bytes) {
+        let snapshot = SavedSnapshot::read(bytes.as_slice()).unwrap_or_else(|e| {
+            fail(
+                Status::Malformed,
+                format_args!("error reading {}: {e}", path.display()),
+            )
+        });
+        return snapshot.iter().map(|a| (a.client_id, a.balances)).collect();
+    }
+    let mut db = ClientsDatabase::new();
+    if let Err((line, e)) = db.import_balances(bytes.as_slice()) {
+        fail(
+            Status::Malformed,
+            format_args!("{}:{line}: {e}", path.display()),
+        );
+    }
+    db.iter()
+        .map(|(client_id, account)| (client_id, account.view()))
+        .collect()
+}
+
+pub fn read_snapshot(path: &Path) -> SavedSnapshot {
+    File::open(path)
+        .and_then(SavedSnapshot::read)
+        .unwrap_or_else(|e| {
+            fail(
+                Status::Io,
+                format_args!("error reading {}: {e}", path.display()),
+            )
+        })
+}
+
+pub fn import_balances(db: &mut ClientsDatabase, balances: &PathBuf) {
+    let file = File::open(balances).unwrap_or_else(|e| {
+        fail(
+            Status::Io,
+            format_args!("error opening {}: {e}", balances.display()),
+        )
+    });
+    if let Err((line, e)) = db.import_balances(io::BufReader::new(file)) {
+        fail(
+            Status::Malformed,
+            format_args!("{}:{line}: {e}", balances.display()),
+        );
+    }
+}
+
+fn open_input(path: &PathBuf) -> File {
+    File::open(path).unwrap_or_else(|e| {
+        fail(
+            Status::Io,
+            format_args!("error opening {}: {e}", path.display()),
+        )
+    })
+}
+
+/// Format of an input file by its extension, CSV if unknown.
+pub fn input_format(path: &PathBuf) -> TransactionFormat {
+    TransactionFormat::from_path(path).unwrap_or_default()
+}
+
+/// Rows of a CSV input.
+fn csv_rows(
+    input: &PathBuf,
+    config: &ThreadConfig,
+    io: Stopwatch,
+    progress: Progress,
+) -> RowStream {
+    #[cfg(not(feature = "direct-io"))]
+    let file = File::open(input);
+    // Bypass the page cache, and read on a separate thread so reads overlap parsing.
+    #[cfg(feature = "direct-io")]
+    let file = payengine::readahead::open_direct(input).map(payengine::readahead::ReadAhead::new);
+    let file = file.unwrap_or_else(|e| {
+        fail(
+            Status::Io,
+            format_args!("error opening {}: {e}", input.display()),
+        )
+    });
+    let file = TimedReader::new(CountingReader::new(file, progress), io);
+
+    // One thread reads and parses, this thread distributes rows, and one worker per shard applies
+    // transactions.
+    #[cfg(not(any(feature = "rayon", feature = "direct-io")))]
+    let rows = RowStream::spawn_pinned(std::io::BufReader::new(file), config.parser_core());
+    // Already buffered.
+    #[cfg(all(feature = "direct-io", not(feature = "rayon")))]
+    let rows = RowStream::spawn_pinned(file, config.parser_core());
+    #[cfg(feature = "rayon")]
+    let rows = RowStream::spawn_parallel_pinned(file, config.parser_core());
+    rows
+}
+
+/// Rows of the input within `range`, skipping malformed ones, or stopping at the first one with
+/// `unparsed.strict`. Reading and parsing are timed into `profile` if given, and bytes read counted
+/// into `progress`.
+pub fn parse_rows<'a>(
+    input: &PathBuf,
+    format: TransactionFormat,
+    config: &Config,
+    range: RowRange,
+    unparsed: &'a mut Unparsed,
+    profile: Option<&mut Profile>,
+    progress: Option<&Progress>,
+) -> impl Iterator<Item = (ClientId, Transaction)> + 'a {
+    parse_lines(input, format, config, range, unparsed, profile, progress)
+        .map(|(_, client_id, t)| (client_id, t))
+}
+
+/// Same as [`parse_rows`], with the 1-based line of each row including the header, or its record
+/// in inputs without lines.
+pub fn parse_lines<'a>(
+    input: &PathBuf,
+    format: TransactionFormat,
+    config: &Config,
+    range: RowRange,
+    unparsed: &'a mut Unparsed,
+    profile: Option<&mut Profile>,
+    progress: Option<&Progress>,
+) -> impl Iterator<Item = (u64, ClientId, Transaction)> + 'a {
+    let first_line = if format == TransactionFormat::Csv {
+        2
+    } else {
+        1
+    };
+    let mut unprofiled = Profile::default();
+    let profile = profile.unwrap_or(&mut unprofiled);
+    let io = profile.io.clone();
+    let progress = progress.cloned().unwrap_or_default();
+    let open = |input| {
+        TimedReader::new(
+            CountingReader::new(open_input(input), progress.clone()),
+            io.clone(),
+        )
+    };
+    let rows: Box<dyn Iterator<Item = Result<Row, ParseFailure>>> = match format {
+        TransactionFormat::Csv => {
+            let rows = csv_rows(input, &config.threads, profile.io.clone(), progress.clone());
+            // Parsed on another thread, which times itself.
+            profile.parse = rows.busy();
+            Box::new(rows)
+        }
+        #[cfg(feature = "serde")]
+        TransactionFormat::Jsonl => Box::new(TimedIter::new(
+            convert::read_jsonl(io::BufReader::new(open(input))),
+            profile.parse.clone(),
+        )),
+        #[cfg(not(feature = "serde"))]
+        TransactionFormat::Jsonl => fail(
+            Status::Usage,
+            "reading JSON lines needs the \"serde\" feature",
+        ),
+        TransactionFormat::Binary => Box::new(TimedIter::new(
+            convert::read_binary(open(input)),
+            profile.parse.clone(),
+        )),
+        #[cfg(feature = "parquet")]
+        // Reads go through the parquet crate, so they're timed as parsing.
+        TransactionFormat::Parquet => Box::new(TimedIter::new(
+            convert::read_parquet(open_input(input)).unwrap_or_else(|e| {
+                fail(
+                    Status::Io,
+                    format_args!("error reading {}: {e}", input.display()),
+                )
+            }),
+            profile.parse.clone(),
+        )),
+        #[cfg(not(feature = "parquet"))]
+        TransactionFormat::Parquet => fail(
+            Status::Usage,
+            "reading Parquet needs the \"parquet\" feature",
+        ),
+        #[cfg(feature = "iso20022")]
+        TransactionFormat::Pain001 => Box::new(TimedIter::new(
+            payengine::iso20022::read_pain001(io::BufReader::new(open(input)), &config.pain001),
+            profile.parse.clone(),
+        )),
+        #[cfg(not(feature = "iso20022"))]
+        TransactionFormat::Pain001 => fail(
+            Status::Usage,
+            "reading pain.001 needs the \"iso20022\" feature",
+        ),
+    };
+
+    // Rows before the range are still parsed, as lines can only be found by reading. Read errors
+    // end the stream, so they're kept wherever they happen.
+    let rows = rows
+        .enumerate()
+        .filter(move |(idx, row)| {
+            *idx >= range.skip_rows || matches!(row, Err(f) if f.error.kind() == ErrorKind::CsvIo)
+        })
+        .take(range.max_rows.unwrap_or(usize::MAX));
+
+    rows.map_while(move |(idx, row)| match row {
+        Ok(row) => Some(Some((
+            idx as u64 + first_line,
+            row.client_id,
+            row.transaction,
+        ))),
+        Err(f) if unparsed.strict => {
+            unparsed.fatal = Some(f);
+            None
+        }
+        Err(f) => {
+            unparsed.counts.add(f.error.kind());
+            if unparsed.verbose {
+                trace!(
+                    line = f.line,
+                    code = f.error.code(),
+                    "error parsing line: {}",
+                    f.error
+                );
+            }
+            if let Some(raw) = &f.raw {
+                unparsed.samples.add(f.error.kind(), || raw.to_string());
+            }
+            if let Some(kept) = &mut unparsed.kept {
+                kept.push(f);
+            }
+            Some(None)
+        }
+    })
+    .flatten()
+}
@@ -0,0 +1,62 @@
+//! `inspect`: printing accounts of a snapshot.
+
+use std::io::{self, Write};
+
+use payengine::args::InspectArgs;
+
+use crate::cli::{
+    input::read_snapshot,
+    status::{Status, fail},
+};
+
+pub fn run(args: &InspectArgs) -> io::Result<()> {
+    let snapshot = read_snapshot(&args.snapshot);
+    let mut out = io::stdout().lock();
+    if args.clients.is_empty() {
+        let frozen = snapshot.iter().filter(|a| a.balances.locked).count();
+        let disputes = snapshot.iter().map(|a| a.disputes.len()).sum::<usize>();
+        return writeln!(
+            out,
+            "accounts {}, frozen {frozen}, open disputes {disputes}",
+            snapshot.len()
+        );
+    }
+    for &client_id in &args.clients {
+        let Some(account) = snapshot.get(client_id) else {
+            fail(
+                Status::Usage,
+                format_args!("client {client_id}: not in the snapshot"),
+            )
+        };
+        let b = account.balances;
+        writeln!(
+            out,
+            "client {client_id}: available {}, held {}, total {}, locked {}",
+            b.available, b.held, b.total, b.locked
+        )?;
+        if account.disputes.is_empty() {
+            writeln!(out, "no open disputes")?;
+        }
+        for (id, amount) in &account.disputes {
+            writeln!(out, "disputed tx {id}: {amount}")?;
+        }
+        if account.history.is_empty() {
+            writeln!(out, "no history")?;
+        }
+        for r in &account.history {
+            writeln!(
+                out,
+                "{}: {} tx {} {}, total {} -> {}, held {} -> {}",
+                r.at,
+                r.transaction.kind.as_str(),
+                r.transaction.id,
+                r.transaction.amount,
+                r.before.total,
+                r.after.total,
+                r.before.held,
+                r.after.held
+            )?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,42 @@
+//! `kafka`: applying rows consumed from Kafka topics until killed.
+
+use std::{io, time::Duration};
+
+use payengine::args::KafkaArgs;
+use payengine::config::Config;
+use tracing::info;
+
+use crate::cli::{
+    state::{database_with_state, snapshot_checkpoint},
+    status::{Status, fail},
+};
+
+pub fn run(config: &Config, args: &KafkaArgs) -> io::Result<()> {
+    let interval = Duration::try_from_secs_f64(args.interval)
+        .unwrap_or_else(|e| fail(Status::Usage, format_args!("invalid --interval: {e}")));
+    let mut db = database_with_state(config, args.state.as_deref());
+    let settings = payengine::kafka::KafkaSettings {
+        brokers: args.brokers.clone(),
+        group: args.group.clone(),
+        topics: args.topics.clone(),
+        rejections: args.rejections_topic.clone(),
+        balances: args.balances_topic.clone(),
+        interval,
+        options: args.options.clone(),
+    };
+    let checkpoint = snapshot_checkpoint(args.snapshot.as_deref(), config);
+    info!(brokers = args.brokers, topics = ?args.topics, "consuming");
+    payengine::kafka::run(
+        &settings,
+        &mut db,
+        checkpoint,
+        &std::sync::atomic::AtomicBool::new(false),
+    )
+    .unwrap_or_else(|e| {
+        fail(
+            Status::Io,
+            format_args!("error consuming from {}: {e}", args.brokers),
+        )
+    });
+    Ok(())
+}
@@ -0,0 +1,55 @@
+//! `nats`: applying rows consumed from a NATS JetStream stream until interrupted.
+
+use std::{io, time::Duration};
+
+use payengine::args::NatsArgs;
+use payengine::config::Config;
+use tracing::info;
+
+use crate::cli::{
+    state::{database_with_state, snapshot_checkpoint},
+    status::{Status, fail},
+};
+
+pub fn run(config: &Config, args: &NatsArgs) -> io::Result<()> {
+    let interval = Duration::try_from_secs_f64(args.interval)
+        .unwrap_or_else(|e| fail(Status::Usage, format_args!("invalid --interval: {e}")));
+    let mut db = database_with_state(config, args.state.as_deref());
+    let settings = payengine::nats::NatsSettings {
+        url: args.url.clone(),
+        stream: args.stream.clone(),
+        consumer: args.consumer.clone(),
+        subject: args.subject.clone(),
+        events: args.events_subject.clone(),
+        batch: args.batch,
+        interval,
+        max_pending: args.max_pending,
+    };
+    let checkpoint = snapshot_checkpoint(args.snapshot.as_deref(), config);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    info!(url = args.url, stream = args.stream, "consuming");
+    let stats = runtime
+        .block_on(payengine::nats::run(
+            &settings,
+            &mut db,
+            checkpoint,
+            async {
+                tokio::signal::ctrl_c().await.ok();
+            },
+        ))
+        .unwrap_or_else(|e| {
+            fail(
+                Status::Io,
+                format_args!("error consuming from {}: {e}", args.url),
+            )
+        });
+    info!(
+        messages = stats.messages,
+        applied = stats.applied,
+        rejected = stats.rejected,
+        "stopped"
+    );
+    Ok(())
+}
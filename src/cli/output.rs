@@ -0,0 +1,77 @@
+//! Writing balances reports and rejects files.
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use payengine::{
+    accounts::{AccountView, ClientId},
+    config::{Config, OutputConfig},
+    convert,
+    process::{DeadLetter, ErrorPolicy},
+    sharded::ShardedDatabase,
+};
+use tracing::debug;
+
+use crate::cli::status::{Status, fail};
+
+/// With `flagged`, accounts flagged for review have a column of their own.
+pub fn write_accounts(
+    output: &OutputConfig,
+    flagged: bool,
+    accounts: impl IntoIterator<Item = (ClientId, AccountView<'static>)>,
+) -> io::Result<()> {
+    let w = open_output(output.path.as_ref());
+    let accounts = accounts.into_iter().filter(|(client_id, _)| {
+        output
+            .clients
+            .as_ref()
+            .is_none_or(|clients| clients.contains(client_id))
+    });
+    if output.sort {
+        let mut accounts = accounts.into_iter().collect::<Vec<_>>();
+        accounts.sort_unstable_by_key(|(client_id, _)| *client_id);
+        output
+            .format
+            .write_with(w, accounts, output.precision, flagged)
+    } else {
+        output
+            .format
+            .write_with(w, accounts, output.precision, flagged)
+    }
+}
+
+pub fn open_output(path: Option<&PathBuf>) -> Box<dyn Write + Send> {
+    match path {
+        Some(path) => Box::new(File::create(path).unwrap_or_else(|e| {
+            fail(
+                Status::Io,
+                format_args!("error creating {}: {e}", path.display()),
+            )
+        })),
+        // Writers buffer, so the lock is only taken per buffer.
+        None => Box::new(io::stdout()),
+    }
+}
+
+pub fn write_rejects(config: &Config, dead_letters: &[DeadLetter]) -> io::Result<()> {
+    let Some(path) = &config.output.rejects else {
+        return Ok(());
+    };
+    if !config.error_policies().uses(ErrorPolicy::DeadLetter) {
+        return Ok(());
+    }
+    debug!(rejects = dead_letters.len(), path = %path.display(), "writing rejects");
+    convert::write_rejects(open_output(Some(path)), dead_letters)
+}
+
+pub fn write(db: &ShardedDatabase, output: &OutputConfig) -> io::Result<()> {
+    write_accounts(
+        output,
+        db.review().is_enabled(),
+        db.iter()
+            .map(|(client_id, account)| (client_id, account.view())),
+    )
+}
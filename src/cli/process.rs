@@ -0,0 +1,407 @@
+//! `process`: applying inputs and writing the balances report.
+
+use std::{
+    fs::File,
+    io,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use payengine::{
+    alerts::{Alert, AlertMonitor},
+    args::RowRange,
+    config::Config,
+    convert::TransactionFormat,
+    process::{DeadLetter, ErrorPolicy, ProcessReport, RejectionCounts},
+    progress::{Progress, ProgressRecord},
+    report::ReportFormat,
+    reservoir::RejectSamples,
+    sharded::ShardedDatabase,
+};
+use tracing::{Level, debug, info, trace, warn};
+
+use crate::cli::{
+    input::{Unparsed, input_format, parse_rows},
+    output::{open_output, write, write_rejects},
+    status::{Status, fail, fail_at},
+    summary::{Profile, Summary},
+};
+
+pub fn run(
+    config: &Config,
+    inputs: &[PathBuf],
+    range: RowRange,
+    profile: bool,
+    progress_interval: Option<Duration>,
+) -> io::Result<Summary> {
+    let _span = tracing::info_span!("process", inputs = inputs.len()).entered();
+    let start = Instant::now();
+    let mut db = config.database();
+    #[cfg(feature = "postgres")]
+    let postgres = config
+        .output
+        .postgres
+        .as_deref()
+        .map(|params| start_postgres(params, config.output.postgres_chunk(), &mut db));
+    #[cfg(not(feature = "postgres"))]
+    if config.output.postgres.is_some() {
+        fail(
+            Status::Usage,
+            "writing to Postgres needs the \"postgres\" feature",
+        );
+    }
+    let alerts = start_alerts(config, &mut db);
+    let progress = progress_interval.map(|_| Progress::default());
+    let _reporter = progress
+        .as_ref()
+        .zip(progress_interval)
+        .map(|(progress, interval)| {
+            db.set_progress(progress.clone());
+            progress.report_every(interval, log_progress)
+        });
+    let policies = config.error_policies();
+    let strict = config.parser.strict || config.parser.on_error == ErrorPolicy::Abort;
+    if policies.uses(ErrorPolicy::DeadLetter) && config.output.rejects.is_none() {
+        fail(Status::Usage, "dead-letter policies need a --rejects file");
+    }
+    if profile && inputs.len() > 1 {
+        fail(Status::Usage, "--profile takes a single input");
+    }
+    let mut profile = profile.then(Profile::default);
+    let mut summary = Summary::default();
+    let mut workers = Vec::new();
+    match inputs {
+        [input] => {
+            let mut unparsed = Unparsed {
+                strict,
+                verbose: config.parser.verbose,
+                samples: RejectSamples::new(config.parser.reject_samples),
+                ..Default::default()
+            };
+            let rows = parse_rows(
+                input,
+                input_format(input),
+                config,
+                range,
+                &mut unparsed,
+                profile.as_mut(),
+                progress.as_ref(),
+            );
+            // Shards which are done are written out while others are still processing. With
+            // --strict or abort policies, nothing may be written before the whole input is known
+            // to be valid. Parts are in shard order, with all clients and decimal places.
+            let output = &config.output;
+            let overlap = output.format == ReportFormat::Csv
+                && output.precision.is_none()
+                && !output.sort
+                && output.clients.is_none()
+                && !config.review.is_enabled()
+                && !strict
+                && !policies.uses(ErrorPolicy::Abort)
+                && profile.is_none();
+            let (mut report, written) = if overlap {
+                db.process_parallel_with_report(rows, open_output(config.output.path.as_ref()))
+            } else {
+                (db.process_parallel(rows), Ok(()))
+            };
+            let csv = input_format(input) == TransactionFormat::Csv;
+            if let Some(f) = unparsed.fatal {
+                fail_at(input, csv, Err(f));
+            }
+            if let Some(aborted) = report.aborted.take() {
+                // Indices are after the skipped rows.
+                fail_at(input, csv && range.skip_rows == 0, Ok(aborted));
+            }
+            log_report(&db, &report, &unparsed.counts);
+            summary.add(&report, &unparsed.counts);
+            summary.samples.merge(unparsed.samples);
+            check_balances(&db);
+            summary.slow = db.slow_log();
+            let output = Instant::now();
+            write_rejects(config, &report.dead_letters)?;
+            if overlap {
+                written?;
+            } else {
+                write(&db, &config.output)?;
+            }
+            if let Some(profile) = &mut profile {
+                profile.output += output.elapsed();
+            }
+            workers = report.workers;
+        }
+        // Partitions covering consecutive time ranges, in order.
+        inputs => {
+            if !range.is_all() {
+                fail(
+                    Status::Usage,
+                    "--skip-rows and --max-rows take a single input",
+                );
+            }
+            if inputs
+                .iter()
+                .any(|input| input_format(input) != TransactionFormat::Csv)
+            {
+                fail(Status::Usage, "several inputs must all be CSV");
+            }
+            let dead_letters = process_files(
+                &mut db,
+                inputs,
+                config.threads.threads(),
+                strict,
+                &mut summary,
+            );
+            check_balances(&db);
+            summary.slow = db.slow_log();
+            write_rejects(config, &dead_letters)?;
+            write(&db, &config.output)?;
+        }
+    }
+    if let Some(path) = &config.output.snapshot {
+        let file = File::create(path).unwrap_or_else(|e| {
+            fail(
+                Status::Io,
+                format_args!("error creating {}: {e}", path.display()),
+            )
+        });
+        debug!(path = %path.display(), "saving snapshot");
+        let output = Instant::now();
+        db.save_snapshot(file, config.output.snapshot_history())?;
+        if let Some(profile) = &mut profile {
+            profile.output += output.elapsed();
+        }
+    }
+    // Stops events to the Postgres writer too, which has got all of them by now.
+    summary.alerts = finish_alerts(alerts, &mut db);
+    #[cfg(feature = "postgres")]
+    if let Some(writer) = postgres {
+        finish_postgres(writer, &mut db);
+    }
+    if let Some(profile) = profile {
+        profile.print(&workers, start.elapsed());
+    }
+    Ok(summary)
+}
+
+#[cfg(feature = "postgres")]
+type PostgresWriter = std::thread::JoinHandle<
+    io::Result<(
+        payengine::postgres::PostgresSink,
+        payengine::postgres::PostgresStats,
+    )>,
+>;
+
+/// Connect to Postgres and write the transactions applied to `db` from now on, on a thread of its
+/// own until [`finish_postgres`].
+#[cfg(feature = "postgres")]
+fn start_postgres(params: &str, chunk: usize, db: &mut ShardedDatabase) -> PostgresWriter {
+    let mut sink = payengine::postgres::PostgresSink::connect(params, chunk).unwrap_or_else(|e| {
+        fail(
+            Status::Io,
+            format_args!("error connecting to Postgres: {e}"),
+        )
+    });
+    let events = db.subscribe();
+    std::thread::spawn(move || {
+        let mut stats = Default::default();
+        sink.write_transactions(events, &mut stats)?;
+        Ok((sink, stats))
+    })
+}
+
+/// Wait for the transactions to be written, then write the final balances.
+#[cfg(feature = "postgres")]
+fn finish_postgres(writer: PostgresWriter, db: &mut ShardedDatabase) {
+    db.unsubscribe_all();
+    let written = writer.join().unwrap().and_then(|(mut sink, mut stats)| {
+        sink.write_accounts(
+            db.iter()
+                .map(|(client_id, account)| (client_id, account.view())),
+            &mut stats,
+        )?;
+        Ok(stats)
+    });
+    match written {
+        Ok(stats) => debug!(
+            transactions = stats.transactions,
+            accounts = stats.accounts,
+            chunks = stats.chunks,
+            "written to Postgres"
+        ),
+        Err(e) => fail(Status::Io, format_args!("error writing to Postgres: {e}")),
+    }
+}
+
+pub fn apply_memory_limit(config: &mut Config, inputs: usize) {
+    let threads = config.threads.threads();
+    let plan = config
+        .apply_memory_limit(inputs)
+        .unwrap_or_else(|e| fail(Status::Usage, e));
+    if let Some(plan) = plan {
+        if plan.threads.get() < threads {
+            warn!(
+                threads = plan.threads,
+                requested = threads,
+                "using fewer threads to fit the memory limit"
+            );
+        }
+        debug!(
+            threads = plan.threads,
+            spill_window = plan.spill_window,
+            "bounded memory"
+        );
+    }
+}
+
+fn log_progress(r: ProgressRecord) {
+    let memory = r.memory.map(tracing::field::display);
+    if r.rows_per_sec == 0.0 {
+        warn!(rows = r.totals.rows, elapsed = ?r.elapsed, memory, "no progress");
+        return;
+    }
+    info!(
+        rows = r.totals.rows,
+        rows_per_sec = r.rows_per_sec.round(),
+        mb_per_sec = (r.mb_per_sec * 10.0).round() / 10.0,
+        rejects_per_sec = r.rejects_per_sec.round(),
+        memory,
+        "progress"
+    );
+}
+
+/// Fail if any account holds other than the deposits under dispute.
+fn check_balances(db: &ShardedDatabase) {
+    for (client_id, account) in db.iter() {
+        if !account.held_matches_disputes() {
+            fail(
+                Status::Invariant,
+                format_args!(
+                    "client {client_id}: held {} doesn't match the disputed deposits",
+                    account.held()
+                ),
+            );
+        }
+    }
+}
+
+pub type AlertChecker = std::thread::JoinHandle<Vec<Alert>>;
+
+/// Check the rules of the [alerts] config section against the balances after each transaction
+/// applied to `db` from now on, see [`AlertMonitor`], on a thread of its own until
+/// [`finish_alerts`]. None without any rules, so nothing listens to transactions.
+pub fn start_alerts(config: &Config, db: &mut ShardedDatabase) -> Option<AlertChecker> {
+    if config.alerts.is_empty() {
+        return None;
+    }
+    let mut monitor = AlertMonitor::new(config.alerts.clone());
+    let events = db.subscribe();
+    Some(std::thread::spawn(move || {
+        for event in events {
+            monitor.update([(event.client_id, event.balances)]);
+        }
+        monitor.raised().to_vec()
+    }))
+}
+
+/// Alerts raised since [`start_alerts`], once the transactions applied so far are checked. Stops
+/// events to every subscriber of `db`.
+pub fn finish_alerts(checker: Option<AlertChecker>, db: &mut ShardedDatabase) -> Vec<Alert> {
+    let Some(checker) = checker else {
+        return Vec::new();
+    };
+    db.unsubscribe_all();
+    checker.join().unwrap()
+}
+
+pub fn log_report(db: &ShardedDatabase, report: &ProcessReport, unparsed: &RejectionCounts) {
+    debug!(
+        processed = report.processed,
+        applied = report.applied,
+        rejected = report.rejected(),
+        retried = report.retried,
+        stats = ?db.stats(),
+        "finished processing"
+    );
+    for (kind, count) in unparsed.iter() {
+        debug!(error_kind = ?kind, code = kind.code(), count, "unparsed rows");
+    }
+    for (kind, count) in report.counts.iter() {
+        debug!(error_kind = ?kind, code = kind.code(), count, "rejected transactions");
+    }
+    for (shard, w) in report.workers.iter().enumerate() {
+        debug!(
+            shard,
+            transactions = w.transactions,
+            elapsed = ?w.elapsed,
+            idle = ?w.idle,
+            throughput = w.throughput(),
+            "worker stats"
+        );
+    }
+}
+
+/// Returns the dead letters of all files, in file order.
+fn process_files(
+    db: &mut ShardedDatabase,
+    inputs: &[PathBuf],
+    max_open: usize,
+    strict: bool,
+    summary: &mut Summary,
+) -> Vec<DeadLetter> {
+    let mut reports = db
+        .process_files(inputs, max_open)
+        .unwrap_or_else(|(path, e)| {
+            fail(
+                Status::Io,
+                format_args!("error opening {}: {e}", path.display()),
+            )
+        });
+    for r in &mut reports {
+        if strict && !r.parse_failures.is_empty() {
+            fail_at(&r.path, true, Err(r.parse_failures.swap_remove(0)));
+        }
+        if let Some(aborted) = r.process.aborted.take() {
+            fail_at(&r.path, true, Ok(aborted));
+        }
+        let mut malformed = RejectionCounts::default();
+        let mut samples = RejectSamples::new(db.reject_samples());
+        for f in &r.parse_failures {
+            malformed.add(f.error.kind());
+            if let Some(raw) = &f.raw {
+                samples.add(f.error.kind(), || raw.to_string());
+            }
+        }
+        summary.add(&r.process, &malformed);
+        summary.samples.merge(samples);
+        if tracing::enabled!(Level::TRACE) {
+            let errors = r.locate_errors().unwrap_or_else(|e| {
+                fail(
+                    Status::Io,
+                    format_args!("error reading {}: {e}", r.path.display()),
+                )
+            });
+            for e in errors {
+                trace!(
+                    file = %r.path.display(),
+                    line = e.line_no,
+                    byte_offset = e.byte_offset,
+                    client_id = e.client_id,
+                    tx_id = e.tx_id,
+                    code = e.error.code(),
+                    "error: {e}"
+                );
+            }
+        }
+        debug!(
+            file = %r.path.display(),
+            processed = r.process.processed,
+            applied = r.process.applied,
+            rejected = r.process.rejected(),
+            "finished processing file"
+        );
+    }
+    debug!(stats = ?db.stats(), "finished processing");
+    reports
+        .into_iter()
+        .flat_map(|r| r.process.dead_letters)
+        .collect()
+}
@@ -0,0 +1,63 @@
+//! `redis`: applying inputs to accounts kept in Redis.
+
+use std::io;
+
+use payengine::args::{RedisArgs, RowRange};
+use payengine::config::Config;
+use tracing::{debug, info};
+
+use crate::cli::{
+    input::{Unparsed, input_format, parse_lines},
+    output::write_accounts,
+    status::{Status, fail},
+};
+
+pub fn run(config: &Config, args: &RedisArgs) -> io::Result<()> {
+    let connection_failed = |e: io::Error| -> ! {
+        fail(
+            Status::Io,
+            format_args!("error applying to {}: {e}", args.url),
+        )
+    };
+    let mut ledger = payengine::redis::RedisLedger::open(&args.url, args.prefix.as_str())
+        .unwrap_or_else(|e| connection_failed(e));
+    let (mut applied, mut rejected) = (0_u64, 0_u64);
+    let mut unparsed = Unparsed::default();
+    for input in &args.inputs {
+        let rows = parse_lines(
+            input,
+            input_format(input),
+            config,
+            RowRange::default(),
+            &mut unparsed,
+            None,
+            None,
+        );
+        for (line, client_id, t) in rows {
+            match ledger
+                .process_transaction(client_id, t)
+                .unwrap_or_else(|e| connection_failed(e))
+            {
+                Ok(()) => applied += 1,
+                Err(e) => {
+                    rejected += 1;
+                    debug!(
+                        line,
+                        client_id,
+                        tx_id = t.id,
+                        code = e.code(),
+                        "rejected row: {e}"
+                    );
+                }
+            }
+        }
+    }
+    info!(
+        applied,
+        rejected,
+        malformed = unparsed.counts.total(),
+        "applied"
+    );
+    let accounts = ledger.accounts().unwrap_or_else(|e| connection_failed(e));
+    write_accounts(&config.output, false, accounts)
+}
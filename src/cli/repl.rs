@@ -0,0 +1,50 @@
+//! `repl`: answering commands about accounts on stdin.
+
+use std::io;
+
+use payengine::{
+    accounts::ClientsDatabase,
+    args::{ReplArgs, RowRange},
+    config::Config,
+    process::RejectionMode,
+};
+
+use crate::cli::input::{Unparsed, import_balances, input_format, parse_lines};
+
+pub fn run(config: &Config, args: &ReplArgs) -> io::Result<()> {
+    let mut db = ClientsDatabase::new();
+    if args.audit {
+        db.enable_audit();
+    }
+    if let Some(balances) = &args.balances {
+        import_balances(&mut db, balances);
+    }
+    if let Some(input) = &args.input {
+        let mut unparsed = Unparsed::default();
+        let rows = parse_lines(
+            input,
+            input_format(input),
+            config,
+            RowRange::default(),
+            &mut unparsed,
+            None,
+            None,
+        );
+        db.set_rejection_mode(RejectionMode::Count);
+        let report = if args.provenance {
+            db.enable_provenance();
+            let source = db.add_source(input.display().to_string());
+            db.process_all_from(source, rows)
+        } else {
+            db.process_all(rows.map(|(_, client_id, t)| (client_id, t)))
+        };
+        eprintln!(
+            "loaded {} accounts: {} transactions applied, {} malformed, {} rejected",
+            db.iter().count(),
+            report.applied,
+            unparsed.counts.total(),
+            report.rejected()
+        );
+    }
+    payengine::repl::run_stdio(&mut db)
+}
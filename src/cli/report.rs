@@ -0,0 +1,18 @@
+//! `report`: rendering the balances of a previous run in another format.
+
+use std::{io, path::PathBuf};
+
+use payengine::{accounts::ClientsDatabase, config::Config};
+
+use crate::cli::{input::import_balances, output::write_accounts};
+
+pub fn run(config: &Config, balances: &PathBuf) -> io::Result<()> {
+    let mut db = ClientsDatabase::new();
+    import_balances(&mut db, balances);
+    write_accounts(
+        &config.output,
+        config.review.is_enabled(),
+        db.iter()
+            .map(|(client_id, account)| (client_id, account.view())),
+    )
+}
@@ -0,0 +1,41 @@
+//! `risk`: flagging clients with anomalous activity.
+
+use std::io;
+
+use payengine::{
+    anomaly::{Anomaly, AnomalyDetector},
+    args::{RiskArgs, RowRange},
+    config::Config,
+};
+use tracing::{debug, warn};
+
+use crate::cli::{
+    input::{Unparsed, input_format, parse_rows},
+    output::open_output,
+};
+
+pub fn run(config: &Config, args: &RiskArgs) -> io::Result<()> {
+    let mut detector = AnomalyDetector::new(config.anomaly.clone());
+    let mut unparsed = Unparsed::default();
+    let rows = parse_rows(
+        &args.input,
+        input_format(&args.input),
+        config,
+        RowRange::default(),
+        &mut unparsed,
+        None,
+        None,
+    );
+    for (client_id, t) in rows {
+        detector.observe(client_id, &t);
+    }
+    let anomalies = detector.finish();
+    for (client_id, anomaly) in &anomalies {
+        if let Anomaly::Structuring { transactions, .. } = anomaly {
+            warn!(client_id, ?transactions, "possible structuring: {anomaly}");
+        }
+    }
+    payengine::anomaly::write_risk_report(open_output(args.output.as_ref()), &anomalies)?;
+    debug!(anomalies = anomalies.len(), "risk report written");
+    Ok(())
+}
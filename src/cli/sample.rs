@@ -0,0 +1,41 @@
+//! `sample`: extracting the rows of some clients.
+
+use std::{fs::File, io};
+
+use payengine::args::SampleArgs;
+use payengine::sample::Selection;
+use tracing::{debug, warn};
+
+use crate::cli::{
+    output::open_output,
+    status::{Status, fail},
+};
+
+pub fn run(args: &SampleArgs) -> io::Result<()> {
+    let selection = match args.fraction {
+        Some(fraction) => Selection::Random {
+            fraction,
+            seed: args.seed,
+        },
+        None => Selection::Clients(args.clients.iter().copied().collect()),
+    };
+    let file = File::open(&args.input).unwrap_or_else(|e| {
+        fail(
+            Status::Io,
+            format_args!("error opening {}: {e}", args.input.display()),
+        )
+    });
+    let stats = payengine::sample::sample(
+        io::BufReader::new(file),
+        open_output(args.output.as_ref()),
+        &selection,
+    )?;
+    if stats.unparsed > 0 {
+        warn!(
+            count = stats.unparsed,
+            "skipped rows without a valid client id"
+        );
+    }
+    debug!(rows = stats.rows, kept = stats.kept, "sampled");
+    Ok(())
+}
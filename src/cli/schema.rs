@@ -0,0 +1,18 @@
+//! `export-schema`: printing schemas of the input and report formats.
+
+use std::io::{self, Write};
+
+use payengine::args::ExportSchemaArgs;
+use payengine::schema;
+
+use crate::cli::output::open_output;
+
+pub fn run(args: &ExportSchemaArgs) -> io::Result<()> {
+    let schema = match args.schema {
+        Some(schema) => schema.render(args.format),
+        None => schema::render_all(args.format),
+    };
+    let mut out = open_output(args.output.as_ref());
+    writeln!(out, "{schema}")?;
+    out.flush()
+}
@@ -0,0 +1,198 @@
+//! `serve`: answering HTTP requests, and optionally taking rows over TCP, until interrupted.
+
+use std::{io, path::Path};
+
+use payengine::{alerts::AlertMonitor, args::ServeArgs, config::Config, ratelimit::RateLimiter};
+use tracing::info;
+
+use crate::cli::{
+    state::database_with_state,
+    status::{Status, fail},
+};
+
+pub fn run(config: &Config, args: &ServeArgs) -> io::Result<()> {
+    #[cfg_attr(not(any(feature = "sqlite", feature = "flight")), allow(unused_mut))]
+    let mut db = database_with_state(config, args.state.as_deref());
+    #[cfg(feature = "flight")]
+    if args.flight_listen.is_some() {
+        for shard in db.shards_mut() {
+            shard.enable_audit();
+        }
+    }
+    #[cfg(feature = "sqlite")]
+    let store = args
+        .sqlite
+        .as_deref()
+        .map(|path| open_sqlite(path, &mut db));
+    let db =
+        std::sync::Arc::new(payengine::concurrent::ConcurrentClientsDatabase::from_sharded(db));
+    #[cfg(feature = "sqlite")]
+    let sqlite = store.map(|store| start_sqlite(store, &db));
+    // Delivering until the process exits.
+    #[cfg(feature = "webhooks")]
+    let _webhooks = start_webhooks(config, args, &db);
+    #[cfg(not(feature = "webhooks"))]
+    if !config.webhooks.urls.is_empty() {
+        fail(
+            Status::Usage,
+            "sending webhooks needs the \"webhooks\" feature",
+        );
+    }
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    let served = runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind(&args.listen)
+            .await
+            .unwrap_or_else(|e| {
+                fail(
+                    Status::Io,
+                    format_args!("error listening on {}: {e}", args.listen),
+                )
+            });
+        info!(addr = %listener.local_addr()?, "listening");
+        #[cfg(unix)]
+        if let Some(path) = &args.admin_socket {
+            tokio::spawn(admin_listener(config, args, path).run(db.clone()));
+        }
+        #[cfg(feature = "flight")]
+        if let Some(addr) = &args.flight_listen {
+            let listener = tokio::net::TcpListener::bind(addr)
+                .await
+                .unwrap_or_else(|e| {
+                    fail(Status::Io, format_args!("error listening on {addr}: {e}"))
+                });
+            info!(addr = %listener.local_addr()?, "listening for Arrow Flight");
+            tokio::spawn(payengine::flight::serve(listener, db.clone()));
+        }
+        let mut lines = None;
+        if let Some(addr) = &args.tcp_listen {
+            let listener = tokio::net::TcpListener::bind(addr)
+                .await
+                .unwrap_or_else(|e| {
+                    fail(Status::Io, format_args!("error listening on {addr}: {e}"))
+                });
+            info!(addr = %listener.local_addr()?, "listening for rows over TCP");
+            lines = Some(payengine::tcp::LineListener {
+                listener,
+                replies: args.tcp_replies,
+            });
+        }
+        let readiness = payengine::server::Readiness {
+            state: args.state.clone(),
+            max_in_flight: args.max_in_flight,
+            storage: storage_check(args),
+        };
+        // Alerts of the loaded state are raised upfront.
+        let mut alerts = AlertMonitor::new(config.alerts.clone());
+        alerts.update(db.views());
+        let limiter = RateLimiter::new(config.rate_limit.clone());
+        payengine::server::serve(listener, db, readiness, alerts, limiter, lines, async {
+            tokio::signal::ctrl_c().await.ok();
+        })
+        .await
+    });
+    // Tasks still running hold on to the database, which stops events once dropped.
+    drop(runtime);
+    #[cfg(feature = "sqlite")]
+    if let Some(writer) = sqlite {
+        let written = writer.join().unwrap();
+        tracing::debug!(transactions = written, "written to SQLite");
+    }
+    served
+}
+
+/// The listener of `--admin-socket`, snapshotting to `--state` and checkpointing `--sqlite`.
+#[cfg(unix)]
+fn admin_listener(
+    config: &Config,
+    args: &ServeArgs,
+    path: &Path,
+) -> payengine::admin::AdminListener {
+    let admin = payengine::admin::AdminListener::bind(path).unwrap_or_else(|e| {
+        fail(
+            Status::Io,
+            format_args!("error listening on {}: {e}", path.display()),
+        )
+    });
+    info!(path = %path.display(), "listening for admin commands");
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_mut))]
+    let mut admin = admin.snapshot(args.state.clone(), config.output.snapshot_history());
+    #[cfg(feature = "sqlite")]
+    if let Some(sqlite) = args.sqlite.clone() {
+        admin =
+            admin.rotate_wal(move || payengine::sqlite::SqliteStore::open(&sqlite)?.checkpoint());
+    }
+    admin
+}
+
+/// Readiness check of the SQLite file of `--sqlite`, if any.
+fn storage_check(args: &ServeArgs) -> Option<payengine::server::StorageCheck> {
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = args.sqlite.clone() {
+        return Some(std::sync::Arc::new(move || {
+            payengine::sqlite::SqliteStore::open(&path)?
+                .check_writable(std::time::Duration::from_millis(100))
+        }));
+    }
+    #[cfg(not(feature = "sqlite"))]
+    let _ = args;
+    None
+}
+
+/// Open the SQLite file of `--sqlite`, adding its accounts to `db`.
+#[cfg(feature = "sqlite")]
+fn open_sqlite(
+    path: &Path,
+    db: &mut payengine::sharded::ShardedDatabase,
+) -> payengine::sqlite::SqliteStore {
+    let store = payengine::sqlite::SqliteStore::open(path).unwrap_or_else(|e| {
+        fail(
+            Status::Io,
+            format_args!("error opening {}: {e}", path.display()),
+        )
+    });
+    let accounts = store
+        .restore(db)
+        .unwrap_or_else(|e| fail(Status::Malformed, format_args!("{}: {e}", path.display())));
+    info!(accounts, path = %path.display(), "restored from SQLite");
+    store
+}
+
+/// Write the transactions applied to `db` from now on to `store`, on a thread of its own until
+/// the database is dropped. Exits if writing fails, rather than going on without it.
+#[cfg(feature = "sqlite")]
+fn start_sqlite(
+    mut store: payengine::sqlite::SqliteStore,
+    db: &payengine::concurrent::ConcurrentClientsDatabase,
+) -> std::thread::JoinHandle<u64> {
+    let events = db.subscribe();
+    std::thread::spawn(move || {
+        store
+            .write_events(events)
+            .unwrap_or_else(|e| fail(Status::Io, format_args!("error writing to SQLite: {e}")))
+    })
+}
+
+/// Send callbacks for transactions applied from now on, if there are webhook URLs.
+#[cfg(feature = "webhooks")]
+fn start_webhooks(
+    config: &Config,
+    args: &ServeArgs,
+    db: &payengine::concurrent::ConcurrentClientsDatabase,
+) -> Option<payengine::webhooks::Webhooks> {
+    let mut webhooks = config.webhooks.clone();
+    webhooks.urls.extend(args.webhooks.iter().cloned());
+    if webhooks.urls.is_empty() {
+        return None;
+    }
+    let started = payengine::webhooks::Webhooks::start(
+        &webhooks,
+        config.alerts.clone(),
+        db.views(),
+        db.subscribe(),
+    )
+    .unwrap_or_else(|e| fail(Status::Io, format_args!("error starting webhooks: {e}")));
+    info!(urls = ?webhooks.urls, "sending webhooks");
+    Some(started)
+}
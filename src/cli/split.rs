@@ -0,0 +1,39 @@
+//! `split`: partitioning an input by client id into several files.
+
+use std::{fs::File, io};
+
+use tracing::{debug, warn};
+
+use payengine::args::SplitArgs;
+
+use crate::cli::status::{Status, fail};
+
+pub fn run(args: &SplitArgs) -> io::Result<()> {
+    let file = File::open(&args.input).unwrap_or_else(|e| {
+        fail(
+            Status::Io,
+            format_args!("error opening {}: {e}", args.input.display()),
+        )
+    });
+    std::fs::create_dir_all(&args.out_dir)?;
+    let outputs = (0..args.shards)
+        .map(|idx| {
+            let path = args.out_dir.join(format!("shard-{idx}.csv"));
+            File::create(&path).unwrap_or_else(|e| {
+                fail(
+                    Status::Io,
+                    format_args!("error creating {}: {e}", path.display()),
+                )
+            })
+        })
+        .collect();
+    let stats = payengine::split::split(io::BufReader::new(file), outputs)?;
+    if stats.unparsed > 0 {
+        warn!(
+            count = stats.unparsed,
+            "rows without a valid client id written to shard-0.csv"
+        );
+    }
+    debug!(rows = ?stats.rows, "split");
+    Ok(())
+}
@@ -0,0 +1,42 @@
+//! State of the long-running subcommands: loaded from a `--state` snapshot, and saved at
+//! checkpoints of queue consumers.
+
+use std::path::Path;
+
+use payengine::{config::Config, sharded::ShardedDatabase};
+use tracing::debug;
+
+use crate::cli::{
+    input::read_snapshot,
+    status::{Status, fail},
+};
+
+/// The database of a long-running subcommand, with the accounts of a `--state` snapshot if any.
+pub fn database_with_state(config: &Config, state: Option<&Path>) -> ShardedDatabase {
+    let mut db = config.database();
+    if let Some(path) = state {
+        let snapshot = read_snapshot(path);
+        db.restore_snapshot(&snapshot)
+            .unwrap_or_else(|e| fail(Status::Malformed, format_args!("{}: {e}", path.display())));
+        debug!(accounts = snapshot.len(), "loaded state");
+    }
+    db
+}
+
+/// Checkpoints of a queue consumer, saving a snapshot to `path` if any. It's replaced only once
+/// complete, so a restart never finds half of one.
+#[cfg(any(feature = "kafka", feature = "nats", feature = "amqp"))]
+pub fn snapshot_checkpoint<'a>(
+    path: Option<&'a Path>,
+    config: &Config,
+) -> impl FnMut(&ShardedDatabase) -> std::io::Result<()> + use<'a> {
+    let history = config.output.snapshot_history();
+    move |db| match path {
+        Some(path) => {
+            let partial = path.with_extension("partial");
+            db.save_snapshot(std::fs::File::create(&partial)?, history)?;
+            std::fs::rename(&partial, path)
+        }
+        None => Ok(()),
+    }
+}
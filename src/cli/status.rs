@@ -0,0 +1,126 @@
+//! Exit codes and failing with the summary line of the failure.
+
+use std::{fmt::Display, fs::File, io, path::Path};
+
+use payengine::{
+    Error, context,
+    pipeline::ParseFailure,
+    report::push_json_string,
+    reporter::{self, Incident, IncidentKind},
+};
+use tracing::error;
+
+use crate::logging;
+
+/// Exit codes, so scripts can tell why a run failed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok = 0,
+    /// Compared reports differ.
+    Differ = 1,
+    /// Invalid options, settings or clients file. Same as clap's usage errors.
+    Usage = 2,
+    /// Finished, but some rows were malformed or rejected.
+    Partial = 3,
+    /// Stopped at a malformed row.
+    Malformed = 4,
+    /// Stopped at a rejected transaction.
+    Rejected = 5,
+    Io = 6,
+    /// Balances don't add up after processing, which is a bug.
+    Invariant = 7,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Differ => "differ",
+            Status::Usage => "usage",
+            Status::Partial => "partial",
+            Status::Malformed => "malformed",
+            Status::Rejected => "rejected",
+            Status::Io => "io",
+            Status::Invariant => "invariant",
+        }
+    }
+}
+
+/// Log the error, print the summary line of the failure on stderr and exit.
+pub fn fail(status: Status, message: impl Display) -> ! {
+    fail_with_code(status, None, message)
+}
+
+/// Same as [`fail`], with the reason code of the row processing stopped at, e.g.
+/// `{"status":"rejected","exit_code":5,"code":"E_ACCOUNT_FROZEN","message":"..."}`.
+pub fn fail_with_code(status: Status, code: Option<&str>, message: impl Display) -> ! {
+    let message = message.to_string();
+    error!(code, "{message}");
+    let kind = match status {
+        Status::Invariant => Some(IncidentKind::Invariant),
+        Status::Malformed | Status::Rejected | Status::Io => Some(IncidentKind::Aborted),
+        Status::Ok | Status::Differ | Status::Usage | Status::Partial => None,
+    };
+    if let Some(kind) = kind {
+        reporter::report(&Incident {
+            kind,
+            code,
+            message: &message,
+        });
+        reporter::flush();
+    }
+    let mut line = format!(
+        r#"{{"status":"{}","exit_code":{},"#,
+        status.as_str(),
+        status as u8
+    );
+    if let Some(code) = code {
+        line.push_str(r#""code":"#);
+        push_json_string(&mut line, code);
+        line.push(',');
+    }
+    line.push_str(r#""message":"#);
+    push_json_string(&mut line, &message);
+    line.push('}');
+    eprintln!("{line}");
+    logging::flush();
+    std::process::exit(status as i32)
+}
+
+/// Fail at the malformed row or rejected transaction processing stopped at, with where it is in
+/// the input if `located`, which only CSV inputs can be.
+pub fn fail_at(input: &Path, located: bool, stopped: Result<(usize, Error), ParseFailure>) -> ! {
+    let (status, code) = match &stopped {
+        Ok((_, e)) => (Status::Rejected, e.code()),
+        Err(f) => (Status::Malformed, f.error.code()),
+    };
+    if !located {
+        match stopped {
+            Ok((idx, e)) => fail_with_code(
+                status,
+                Some(code),
+                format_args!("{}: transaction {idx}: {e}", input.display()),
+            ),
+            Err(f) => fail_with_code(
+                status,
+                Some(code),
+                format_args!("{}:{}: {}", input.display(), f.line, f.error),
+            ),
+        }
+    }
+    let (rejections, failures) = match stopped {
+        Ok(rejection) => (vec![rejection], Vec::new()),
+        Err(f) => (Vec::new(), vec![f]),
+    };
+    let errors = File::open(input)
+        .and_then(|file| {
+            context::locate(io::BufReader::new(file), Some(input), rejections, failures)
+        })
+        .unwrap_or_else(|e| {
+            fail(
+                Status::Io,
+                format_args!("error reading {}: {e}", input.display()),
+            )
+        });
+    fail_with_code(status, Some(code), &errors[0])
+}
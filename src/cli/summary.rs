@@ -0,0 +1,124 @@
+//! Lines printed on stderr at the end of a run: the summary of `process` and `validate`, and the
+//! profile of `process --profile`.
+
+use std::{process::ExitCode, time::Duration};
+
+use payengine::{
+    alerts::Alert,
+    metrics::{ClientRejections, Metrics},
+    process::{ProcessReport, RejectionCounts},
+    profile::Stopwatch,
+    quality::DataQuality,
+    reservoir::RejectSamples,
+    slowlog::SlowLog,
+    threads::WorkerStats,
+};
+
+use crate::cli::status::Status;
+
+/// Counts of a run that got to the end, for the summary line.
+#[derive(Default)]
+pub struct Summary {
+    rows: usize,
+    applied: usize,
+    malformed: usize,
+    rejected: usize,
+    metrics: Metrics,
+    clients: ClientRejections,
+    pub alerts: Vec<Alert>,
+    pub samples: RejectSamples,
+    quality: DataQuality,
+    pub slow: SlowLog,
+}
+
+/// Clients listed in the summary line, those with the highest share of rows rejected.
+const SUMMARY_CLIENTS: usize = 10;
+
+impl Summary {
+    pub fn add(&mut self, report: &ProcessReport, malformed: &RejectionCounts) {
+        self.rows += report.processed + malformed.total();
+        self.applied += report.applied;
+        self.malformed += malformed.total();
+        self.rejected += report.rejected();
+        self.metrics.merge(&report.metrics());
+        self.clients.merge(&report.clients);
+        self.samples.merge(report.samples.clone());
+        self.quality.add(report, malformed);
+    }
+
+    /// Print the summary line on stderr.
+    pub fn finish(&self) -> ExitCode {
+        let status = if self.malformed + self.rejected > 0 {
+            Status::Partial
+        } else {
+            Status::Ok
+        };
+        let clients = self
+            .clients
+            .worst()
+            .iter()
+            .take(SUMMARY_CLIENTS)
+            .map(|c| c.to_json())
+            .collect::<Vec<_>>()
+            .join(",");
+        let alerts = self
+            .alerts
+            .iter()
+            .map(Alert::to_json)
+            .collect::<Vec<_>>()
+            .join(",");
+        let samples = if self.samples.is_enabled() {
+            format!(",\"samples\":{}", self.samples.to_json())
+        } else {
+            String::new()
+        };
+        let slow = if self.slow.is_enabled() {
+            format!(",\"slow\":{}", self.slow.to_json())
+        } else {
+            String::new()
+        };
+        eprintln!(
+            r#"{{"status":"{}","exit_code":{},"rows":{},"applied":{},"malformed":{},"rejected":{},"metrics":{},"clients":[{clients}],"alerts":[{alerts}],"quality":{}{samples}{slow}}}"#,
+            status.as_str(),
+            status as u8,
+            self.rows,
+            self.applied,
+            self.malformed,
+            self.rejected,
+            self.metrics.to_json(),
+            self.quality.to_json()
+        );
+        ExitCode::from(status as u8)
+    }
+}
+
+/// Time spent in each phase of a single input's run, for --profile.
+#[derive(Default)]
+pub struct Profile {
+    /// Waiting for input bytes.
+    pub io: Stopwatch,
+    /// Reading and parsing rows, including `io`.
+    pub parse: Stopwatch,
+    pub output: Duration,
+}
+
+impl Profile {
+    /// Print the profile line on stderr. Processing is the busy time of all workers added up, so
+    /// with several threads it may exceed the wall time.
+    pub fn print(&self, workers: &[WorkerStats], wall: Duration) {
+        let io = self.io.elapsed();
+        let processing = workers
+            .iter()
+            .map(|w| w.elapsed.saturating_sub(w.idle))
+            .sum::<Duration>();
+        eprintln!(
+            r#"{{"profile":{{"wall":{:.6},"io":{:.6},"parse":{:.6},"processing":{:.6},"output":{:.6},"threads":{}}}}}"#,
+            wall.as_secs_f64(),
+            io.as_secs_f64(),
+            self.parse.elapsed().saturating_sub(io).as_secs_f64(),
+            processing.as_secs_f64(),
+            self.output.as_secs_f64(),
+            workers.len()
+        );
+    }
+}
@@ -0,0 +1,112 @@
+//! `validate`: applying an input only to list what would be rejected.
+
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use payengine::{
+    args::RowRange,
+    config::Config,
+    process::{ErrorPolicy, RejectionMode},
+    reservoir::RejectSamples,
+};
+
+use crate::cli::{
+    input::{Unparsed, input_format, parse_rows},
+    process::{finish_alerts, start_alerts},
+    summary::Summary,
+};
+
+pub fn run(
+    config: &Config,
+    input: &PathBuf,
+    range: RowRange,
+    verbose: bool,
+) -> io::Result<Summary> {
+    let mut db = config.database();
+    db.set_rejection_mode(RejectionMode::Count);
+    // Dead letters keep the rejected transactions to list them.
+    db.set_error_policy(if verbose {
+        ErrorPolicy::DeadLetter
+    } else {
+        ErrorPolicy::Skip
+    });
+    let alerts = start_alerts(config, &mut db);
+    let mut unparsed = Unparsed {
+        kept: verbose.then(Vec::new),
+        samples: RejectSamples::new(config.parser.reject_samples),
+        ..Default::default()
+    };
+    let rows = parse_rows(
+        input,
+        input_format(input),
+        config,
+        range,
+        &mut unparsed,
+        None,
+        None,
+    );
+    let report = db.process_parallel(rows);
+
+    let mut out = io::stdout().lock();
+    writeln!(
+        out,
+        "{} rows: {} applied, {} malformed, {} rejected",
+        report.processed + unparsed.counts.total(),
+        report.applied,
+        unparsed.counts.total(),
+        report.rejected()
+    )?;
+    for (kind, count) in unparsed.counts.iter().chain(report.counts.iter()) {
+        writeln!(out, "{kind:?}: {count}")?;
+    }
+    for c in report.clients.worst() {
+        let reasons = c
+            .rejections
+            .iter()
+            .map(|(kind, count)| format!("{kind:?} {count}"))
+            .collect::<Vec<_>>();
+        writeln!(
+            out,
+            "client {}: {} of {} rows rejected ({:.0}%): {}",
+            c.client_id,
+            c.rejected(),
+            c.rows,
+            c.percent(),
+            reasons.join(", ")
+        )?;
+    }
+    // Every line read after the header is either a transaction or malformed, so the line of a
+    // transaction is its index plus the lines before the first one read and the malformed lines
+    // in between.
+    let first_line = range.skip_rows + 2;
+    let mut malformed = unparsed.kept.iter().flatten().peekable();
+    let mut skipped = 0;
+    for d in &report.dead_letters {
+        while let Some(f) = malformed.next_if(|f| f.line <= d.index + skipped + first_line) {
+            writeln!(out, "line {}: {}", f.line, f.error)?;
+            skipped += 1;
+        }
+        if let Some((client_id, t)) = d.transaction {
+            writeln!(
+                out,
+                "line {}: {} ({} of client {client_id}, tx {})",
+                d.index + skipped + first_line,
+                d.error,
+                t.kind.as_str(),
+                t.id,
+            )?;
+        }
+    }
+    for f in malformed {
+        writeln!(out, "line {}: {}", f.line, f.error)?;
+    }
+    out.flush()?;
+    let mut summary = Summary::default();
+    summary.add(&report, &unparsed.counts);
+    summary.samples.merge(unparsed.samples);
+    summary.alerts = finish_alerts(alerts, &mut db);
+    summary.slow = db.slow_log();
+    Ok(summary)
+}
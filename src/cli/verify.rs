@@ -0,0 +1,55 @@
+//! `verify`: checking the invariants of a snapshot, and that the input gives the same state.
+
+use std::io::{self, Write};
+
+use payengine::{
+    args::{RowRange, VerifyArgs},
+    config::Config,
+    process::RejectionMode,
+    sharded::ShardedDatabase,
+};
+use tracing::debug;
+
+use crate::cli::{
+    input::{Unparsed, input_format, parse_rows, read_snapshot},
+    status::{Status, fail},
+};
+
+pub fn run(config: &Config, args: &VerifyArgs) -> io::Result<()> {
+    let snapshot = read_snapshot(&args.snapshot);
+    let mut violations = payengine::verify::check(&snapshot);
+    if let Some(input) = &args.input {
+        let mut db = ShardedDatabase::with_threads(&config.threads);
+        db.set_rejection_mode(RejectionMode::Count);
+        let mut unparsed = Unparsed::default();
+        let rows = parse_rows(
+            input,
+            input_format(input),
+            config,
+            RowRange::default(),
+            &mut unparsed,
+            None,
+            None,
+        );
+        db.process_parallel(rows);
+        violations.extend(payengine::verify::compare(&snapshot, &db));
+        violations.sort_by_key(|(client_id, _)| *client_id);
+    }
+    let mut out = io::stdout().lock();
+    for (client_id, violation) in &violations {
+        writeln!(out, "client {client_id}: {violation}")?;
+    }
+    out.flush()?;
+    if !violations.is_empty() {
+        fail(
+            Status::Invariant,
+            format_args!(
+                "{} violations in {}",
+                violations.len(),
+                args.snapshot.display()
+            ),
+        );
+    }
+    debug!(accounts = snapshot.len(), "verified");
+    Ok(())
+}
@@ -0,0 +1,276 @@
+use std::{
+    io::{self, Write},
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+        mpsc::{Receiver, channel},
+    },
+};
+
+use crate::{
+    accounts::{AccountView, ClientId, ClientsDatabase, Transaction},
+    audit::AuditRecord,
+    error::LedgerError,
+    events::AccountEvent,
+    history::Timestamp,
+    process::ProcessReport,
+    review::ReviewRules,
+    saved::SavedSnapshot,
+    sharded::ShardedDatabase,
+    stats::DatabaseStats,
+};
+
+/// Clients partitioned across N databases by `client_id % N`, each behind its own lock, so that
+/// several ingestion threads (e.g. one per input partition) can write through a shared reference.
+///
+/// Ordering guarantees:
+/// - Transactions of one client submitted from one thread are applied in that thread's order.
+/// - Transactions of one client submitted from several threads are applied in the order their
+///   calls acquire the client's shard, so callers that care must route each client to a single
+///   thread (as partitioned inputs usually do).
+/// - Timestamps are taken while holding the shard lock, so they increase in application order for
+///   every client, and history queries see a consistent sequence.
+///
+/// Threads only contend when they write clients of the same shard at the same time, so use more
+/// shards than writers.
+pub struct ConcurrentClientsDatabase {
+    shards: Vec<Mutex<ClientsDatabase>>,
+    // Shared logical clock so timestamps are comparable across shards.
+    clock: AtomicU64,
+}
+
+impl ConcurrentClientsDatabase {
+    /// Create a database with `shards` partitions. Panics if `shards` is 0.
+    pub fn new(shards: usize) -> Self {
+        assert!(shards > 0, "shard count must be positive");
+        Self::from_shards((0..shards).map(|_| ClientsDatabase::new()).collect())
+    }
+
+    /// Same as [`Self::new`], with balance history enabled in every shard.
+    pub fn with_history(shards: usize) -> Self {
+        assert!(shards > 0, "shard count must be positive");
+        Self::from_shards(
+            (0..shards)
+                .map(|_| ClientsDatabase::with_history())
+                .collect(),
+        )
+    }
+
+    fn from_shards(shards: Vec<ClientsDatabase>) -> Self {
+        Self {
+            shards: shards.into_iter().map(Mutex::new).collect(),
+            clock: AtomicU64::new(0),
+        }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Rules flagging accounts for review, the same in every shard.
+    pub fn review(&self) -> ReviewRules {
+        self.shards[0]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .review()
+            .clone()
+    }
+
+    /// Receive an event for every transaction applied from now on, in any shard. Events of a
+    /// client arrive in the order its transactions were applied.
+    pub fn subscribe(&self) -> Receiver<AccountEvent> {
+        let (tx, rx) = channel();
+        for shard in &self.shards {
+            shard
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .subscribe_with(tx.clone());
+        }
+        rx
+    }
+
+    fn shard(&self, client_id: ClientId) -> std::sync::MutexGuard<'_, ClientsDatabase> {
+        // A panic while holding the lock can't leave an account half-updated in a way later
+        // transactions would trip over, so keep going with the poisoned shard.
+        self.shards[client_id as usize % self.shards.len()]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn process_transaction(
+        &self,
+        client_id: ClientId,
+        t: Transaction,
+    ) -> Result<(), LedgerError> {
+        self.process_transaction_opening(client_id, t).map(|_| ())
+    }
+
+    /// Same as [`Self::process_transaction`], also telling whether the transaction opened the
+    /// client's account.
+    pub fn process_transaction_opening(
+        &self,
+        client_id: ClientId,
+        t: Transaction,
+    ) -> Result<bool, LedgerError> {
+        let mut shard = self.shard(client_id);
+        let at: Timestamp = self.clock.fetch_add(1, Ordering::Relaxed);
+        let new = shard.get(client_id).is_none();
+        shard.process_transaction_at(client_id, t, at)?;
+        Ok(new)
+    }
+
+    /// Apply all transactions in order. Can be called from several threads at once, each with its
+    /// own stream. Rejection indices in the report are positions in this call's input.
+    pub fn process_all(
+        &self,
+        transactions: impl IntoIterator<Item = (ClientId, Transaction)>,
+    ) -> ProcessReport {
+        let mut report = ProcessReport::default();
+        for (idx, (client_id, t)) in transactions.into_iter().enumerate() {
+            let result = self.process_transaction_opening(client_id, t);
+            report.accounts_created += matches!(result, Ok(true)) as usize;
+            report.record(idx, Some((client_id, t)), result.map(|_| ()));
+        }
+        report
+    }
+
+    /// Current balances of the client. Accounts are only borrowed under a lock, so this returns a
+    /// copy.
+    pub fn get(&self, client_id: ClientId) -> Option<AccountView<'static>> {
+        self.shard(client_id).get(client_id).map(|a| a.view())
+    }
+
+    pub fn balance_as_of(
+        &self,
+        client_id: ClientId,
+        at: Timestamp,
+    ) -> Option<AccountView<'static>> {
+        self.shard(client_id).balance_as_of(client_id, at)
+    }
+
+    /// Lock or unlock an account, see [`ClientsDatabase::set_frozen`].
+    pub fn set_frozen(&self, client_id: ClientId, frozen: bool) -> Result<(), LedgerError> {
+        self.shard(client_id).set_frozen(client_id, frozen)
+    }
+
+    /// Take over the shards and clock of a database, e.g. one with state restored from a file.
+    pub fn from_sharded(db: ShardedDatabase) -> Self {
+        let clock = db.clock();
+        Self {
+            shards: db.into_shards().into_iter().map(Mutex::new).collect(),
+            clock: AtomicU64::new(clock),
+        }
+    }
+
+    /// Balances of all accounts, in no particular order. Shards are copied one at a time, so
+    /// transactions applied meanwhile may show in some shards and not others.
+    pub fn views(&self) -> Vec<(ClientId, AccountView<'static>)> {
+        let mut views = Vec::new();
+        for shard in &self.shards {
+            let shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+            views.extend(shard.iter().map(|(client_id, a)| (client_id, a.view())));
+        }
+        views
+    }
+
+    /// Balances of the accounts of one shard, to go through all of them one shard at a time.
+    pub fn shard_views(&self, shard: usize) -> Vec<(ClientId, AccountView<'static>)> {
+        let shard = self.shards[shard].lock().unwrap_or_else(|e| e.into_inner());
+        shard
+            .iter()
+            .map(|(client_id, a)| (client_id, a.view()))
+            .collect()
+    }
+
+    /// Audit records of the accounts of one shard by client, to go through those of all accounts
+    /// one shard at a time. Empty if audit isn't enabled.
+    pub fn shard_audit(&self, shard: usize) -> Vec<(ClientId, AuditRecord)> {
+        let shard = self.shards[shard].lock().unwrap_or_else(|e| e.into_inner());
+        shard
+            .iter()
+            .flat_map(|(client_id, _)| shard.audit(client_id).iter().map(move |r| (client_id, *r)))
+            .collect()
+    }
+
+    /// Write all accounts as a [`SavedSnapshot`], with up to `history` audit records each. Shards
+    /// are copied one at a time like [`Self::views`], and written once all are.
+    pub fn save_snapshot(&self, w: impl Write, history: usize) -> io::Result<()> {
+        let mut accounts = Vec::new();
+        for shard in &self.shards {
+            let shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+            accounts.extend(
+                shard
+                    .iter()
+                    .filter_map(|(client_id, _)| shard.saved_account(client_id, history)),
+            );
+        }
+        SavedSnapshot::write(w, accounts)
+    }
+
+    /// Counters over all accounts, taken one shard at a time like [`Self::views`].
+    pub fn stats(&self) -> DatabaseStats {
+        let mut stats = DatabaseStats::default();
+        for shard in &self.shards {
+            stats.merge(&shard.lock().unwrap_or_else(|e| e.into_inner()).stats());
+        }
+        stats
+    }
+
+    /// Finish ingestion, keeping the same shards and clock for single-owner reads and reporting.
+    pub fn into_sharded(self) -> ShardedDatabase {
+        let shards = self
+            .shards
+            .into_iter()
+            .map(|s| s.into_inner().unwrap_or_else(|e| e.into_inner()))
+            .collect();
+        ShardedDatabase::from_parts(shards, self.clock.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::{Transaction, TransactionKind::*},
+        amount::Amount,
+        concurrent::ConcurrentClientsDatabase,
+    };
+
+    #[test]
+    fn test_concurrent_writers() {
+        let db = ConcurrentClientsDatabase::with_history(4);
+        let deposit = |id| Transaction {
+            kind: Deposit,
+            id,
+            amount: Amount::parse(b"1").unwrap(),
+        };
+        std::thread::scope(|s| {
+            // Every writer touches every client, with distinct transaction ids.
+            for writer in 0..4u32 {
+                let db = &db;
+                s.spawn(move || {
+                    let report = db.process_all(
+                        (0..1000u32).map(|i| ((i % 10) as u16, deposit(writer * 1000 + i))),
+                    );
+                    assert_eq!(report.applied, 1000);
+                    assert!(report.accounts_created <= 10);
+                });
+            }
+        });
+
+        for client_id in 0..10 {
+            assert_eq!(
+                db.get(client_id).unwrap().total,
+                Amount::parse(b"400").unwrap()
+            );
+        }
+        // Timestamps increase in application order, so the last one sees the final balance.
+        assert_eq!(
+            db.balance_as_of(3, 3999).unwrap().total,
+            Amount::parse(b"400").unwrap()
+        );
+
+        let sharded = db.into_sharded();
+        assert_eq!(sharded.stats().accounts, 10);
+        assert_eq!(sharded.stats().deposits, 4000);
+    }
+}
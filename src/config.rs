@@ -0,0 +1,961 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use crate::{
+    Error,
+    accounts::{ClientId, TransactionId},
+    alerts::AlertRules,
+    anomaly::AnomalyThresholds,
+    breaker::BreakerConfig,
+    error::ErrorKind,
+    memory::{ByteSize, MemoryPlan},
+    process::{self, ErrorPolicy, PolicyMap, RejectionMode},
+    ratelimit::RateLimits,
+    report::ReportFormat,
+    review::ReviewRules,
+    sharded::ShardedDatabase,
+    slowlog::SlowLogConfig,
+    threads::ThreadConfig,
+};
+
+/// Settings of a processing run, usually from [`Config::load`]: a TOML file overridden by
+/// environment variables.
+///
+/// Every field is optional in the file. Unknown keys are an error, so typos don't silently fall
+/// back to defaults. An example with all keys:
+///
+/// ```toml
+/// [parser]
+/// strict = false
+/// verbose = false
+/// on_error = "dead-letter"
+/// retry_window = 100
+/// reject_samples = 0
+///
+/// [parser.policies]
+/// DuplicateTransactionId = "abort"
+/// TransactionNotFound = "skip"
+///
+/// [limits]
+/// memory = "8G"
+///
+/// [limits.eviction]
+/// horizon = 1000000
+/// evict_resolved = true
+///
+/// [limits.spill]
+/// dir = "/var/tmp/payengine"
+/// window = 1000000
+///
+/// [limits.breaker]
+/// failures = 3
+/// cooldown_ms = 1000
+/// max_buffered = 1000000
+/// max_pause_ms = 60000
+///
+/// [threads]
+/// threads = 8
+/// pin_cores = false
+///
+/// [output]
+/// format = "json"
+/// path = "balances.json"
+/// rejects = "rejects.csv"
+/// precision = 2
+/// sort = true
+/// clients = [42, 7]
+/// snapshot = "snapshot.bin"
+/// snapshot_history = 10
+/// postgres = "host=localhost user=payengine dbname=warehouse"
+/// postgres_chunk = 1000
+///
+/// [anomaly]
+/// drain_deposits = 3
+/// drain_percent = 90
+/// dispute_percent = 20
+/// min_deposits = 5
+/// burst_transactions = 20
+/// burst_rows = 100
+/// structuring_limit = "10000"
+/// structuring_margin_percent = 10
+/// structuring_deposits = 3
+/// structuring_rows = 1000
+///
+/// [alerts]
+/// total = "1000000"
+/// account_total = "50000"
+/// held = "100000"
+///
+/// [rate_limit]
+/// per_second = 100
+/// per_minute = 1000
+///
+/// [review]
+/// chargeback_percent = "1.5"
+/// min_deposits = 10
+///
+/// [slow_log]
+/// threshold_ms = 100
+///
+/// [webhooks]
+/// urls = ["https://risk.example.com/payengine"]
+/// secret = "..."
+/// retries = 5
+/// backoff_ms = 1000
+///
+/// [pain001]
+/// currency = "EUR"
+///
+/// [pain001.accounts]
+/// DE89370400440532013000 = 42
+/// ```
+///
+/// Each key can also be set by an environment variable named after its path, e.g.
+/// `PAYENGINE_PARSER_ON_ERROR=abort`, `PAYENGINE_LIMITS_SPILL_DIR=/var/tmp/payengine` or
+/// `PAYENGINE_THREADS_THREADS=8`. `PAYENGINE_OUTPUT_CLIENTS` and `PAYENGINE_WEBHOOKS_URLS`
+/// are comma-separated lists, `PAYENGINE_PARSER_POLICIES` one of `KIND=POLICY`, e.g.
+/// `DuplicateTransactionId=abort`, and `PAYENGINE_PAIN001_ACCOUNTS` one of `ACCOUNT=CLIENT`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct Config {
+    pub parser: ParserConfig,
+    pub limits: LimitsConfig,
+    pub threads: ThreadConfig,
+    pub output: OutputConfig,
+    /// Thresholds of the `risk` report.
+    pub anomaly: AnomalyThresholds,
+    /// Limits on funds raising alerts while processing and serving.
+    pub alerts: AlertRules,
+    /// Transactions per client while serving or following an input.
+    pub rate_limit: RateLimits,
+    /// Chargeback rate above which accounts are flagged in the report.
+    pub review: ReviewRules,
+    /// Operations taking long enough to be logged with their context.
+    pub slow_log: SlowLogConfig,
+    /// Callbacks of `serve` on chargebacks, freezes and alerts.
+    pub webhooks: WebhookConfig,
+    /// Clients of the accounts of ISO 20022 pain.001 inputs.
+    pub pain001: Pain001Config,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct ParserConfig {
+    /// Fail on the first malformed row instead of skipping it.
+    pub strict: bool,
+    /// Keep every rejection error instead of only counting them, see [`RejectionMode`].
+    pub verbose: bool,
+    /// What to do with rows that can't be applied. `Abort` also stops at malformed rows, like
+    /// `strict`.
+    pub on_error: ErrorPolicy,
+    /// Policies of particular kinds of rejected transactions, by [`ErrorKind`] name, overriding
+    /// `on_error`. Malformed rows aren't affected.
+    pub policies: BTreeMap<ErrorKind, ErrorPolicy>,
+    /// Retry disputes, resolves and chargebacks of deposits not seen yet after this many rows,
+    /// see [`crate::accounts::ClientsDatabase::enable_retry`].
+    pub retry_window: Option<usize>,
+    /// Raw lines of malformed rows and rejected transactions to keep for the summary, by kind of
+    /// error, none if 0.
+    pub reject_samples: usize,
+}
+
+/// Bounds on memory used by deposits retained for disputes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct LimitsConfig {
+    /// Memory to stay under, see [`Config::apply_memory_limit`].
+    pub memory: Option<ByteSize>,
+    pub eviction: Option<EvictionConfig>,
+    pub spill: Option<SpillConfig>,
+    /// Riding out failures of the spill storage, see
+    /// [`crate::accounts::ClientsDatabase::enable_breaker`].
+    pub breaker: Option<BreakerConfig>,
+}
+
+/// See [`crate::accounts::ClientsDatabase::enable_eviction`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct EvictionConfig {
+    pub horizon: TransactionId,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub evict_resolved: bool,
+}
+
+/// See [`crate::accounts::ClientsDatabase::enable_spill`]. The window applies per shard.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct SpillConfig {
+    pub dir: PathBuf,
+    pub window: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct OutputConfig {
+    pub format: ReportFormat,
+    /// Standard output if unset.
+    pub path: Option<PathBuf>,
+    /// Where rejected transactions go with [`ErrorPolicy::DeadLetter`].
+    pub rejects: Option<PathBuf>,
+    /// Decimal places of amounts, see [`ReportFormat::write`].
+    pub precision: Option<u8>,
+    /// Order accounts by client id.
+    pub sort: bool,
+    /// Only report these clients.
+    pub clients: Option<BTreeSet<ClientId>>,
+    /// Also save balances, open disputes and recent history of every account here, see
+    /// [`crate::saved::SavedSnapshot`].
+    pub snapshot: Option<PathBuf>,
+    /// Transactions kept per account in the snapshot, 10 if unset. Keeping any records every
+    /// applied transaction in memory until the end of the run.
+    pub snapshot_history: Option<usize>,
+    /// Also write applied transactions and the final balances to the Postgres database with this
+    /// connection string, with the "postgres" feature, see `postgres::PostgresSink`.
+    pub postgres: Option<String>,
+    /// Rows written to Postgres per transaction, 1000 if unset.
+    pub postgres_chunk: Option<usize>,
+}
+
+impl OutputConfig {
+    pub fn snapshot_history(&self) -> usize {
+        self.snapshot_history.unwrap_or(10)
+    }
+
+    pub fn postgres_chunk(&self) -> usize {
+        self.postgres_chunk.unwrap_or(1000)
+    }
+}
+
+/// Where and how `serve` sends webhook callbacks, with the "webhooks" feature. None are sent
+/// without URLs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct WebhookConfig {
+    /// Each callback is posted to every one of them.
+    pub urls: Vec<String>,
+    /// Key of the HMAC-SHA256 signature of callbacks. They're unsigned without one.
+    pub secret: Option<String>,
+    /// Attempts after the first one failed, 5 by default.
+    pub retries: Option<u32>,
+    /// Wait before the first retry, doubled for each next one up to a minute, 1000 by default.
+    pub backoff_ms: Option<u64>,
+}
+
+impl WebhookConfig {
+    pub fn retries(&self) -> u32 {
+        self.retries.unwrap_or(5)
+    }
+
+    pub fn backoff(&self) -> Duration {
+        Duration::from_millis(self.backoff_ms.unwrap_or(1000))
+    }
+}
+
+/// How credit transfers of pain.001 inputs map to transactions, with the "iso20022" feature, see
+/// `iso20022::read_pain001`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct Pain001Config {
+    /// Client of each account, by IBAN or other identification. Spaces and case don't matter.
+    pub accounts: BTreeMap<String, ClientId>,
+    /// Only accept amounts in this currency, e.g. `EUR`. Any currency is accepted if unset.
+    pub currency: Option<String>,
+}
+
+/// Prefix of the environment variables read by [`Config::load`].
+pub const ENV_PREFIX: &str = "PAYENGINE_";
+
+impl Config {
+    /// Settings from the TOML file at `path`, or at `PAYENGINE_CONFIG` if not given, overridden
+    /// by `PAYENGINE_*` environment variables. Without a file, the defaults are overridden.
+    ///
+    /// Command line options are meant to override the result, so the precedence is flags, then
+    /// environment, then file. Unknown `PAYENGINE_*` variables are an error, like unknown keys in
+    /// the file.
+    pub fn load(path: Option<&Path>) -> Result<Self, Error> {
+        let path = path
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::var_os(format!("{ENV_PREFIX}CONFIG")).map(PathBuf::from));
+        let mut config = match path {
+            #[cfg(feature = "config")]
+            Some(path) => Self::load_file(path)?,
+            #[cfg(not(feature = "config"))]
+            Some(path) => {
+                return Err(Error::Config(format!(
+                    "{}: config files need the \"config\" feature",
+                    path.display()
+                )));
+            }
+            None => Config::default(),
+        };
+        let vars = std::env::vars_os()
+            .filter_map(|(key, value)| Some((key.into_string().ok()?, value)))
+            .filter(|(key, _)| key.starts_with(ENV_PREFIX))
+            .map(|(key, value)| match value.into_string() {
+                Ok(value) => Ok((key, value)),
+                Err(_) => Err(Error::Config(format!("{key}: not valid UTF-8"))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        config.apply_env(vars)?;
+        Ok(config)
+    }
+
+    #[cfg(feature = "config")]
+    pub fn from_toml(s: &str) -> Result<Self, Error> {
+        toml::from_str(s).map_err(|e| Error::Config(e.to_string()))
+    }
+
+    #[cfg(feature = "config")]
+    pub fn load_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let s = std::fs::read_to_string(path)
+            .map_err(|e| Error::Config(format!("{}: {e}", path.display())))?;
+        toml::from_str(&s).map_err(|e| Error::Config(format!("{}: {e}", path.display())))
+    }
+
+    /// Override settings with environment variables given as name and value, see [`Config`] for
+    /// the names. Variables without the `PAYENGINE_` prefix are ignored.
+    pub fn apply_env(
+        &mut self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<(), Error> {
+        let mut env = EnvVars(
+            vars.into_iter()
+                .filter_map(|(key, value)| Some((key.strip_prefix(ENV_PREFIX)?.to_owned(), value)))
+                .collect(),
+        );
+        // Read by `load`.
+        env.0.remove("CONFIG");
+
+        if let Some(strict) = env.get("PARSER_STRICT")? {
+            self.parser.strict = strict;
+        }
+        if let Some(verbose) = env.get("PARSER_VERBOSE")? {
+            self.parser.verbose = verbose;
+        }
+        if let Some(on_error) = env.get("PARSER_ON_ERROR")? {
+            self.parser.on_error = on_error;
+        }
+        if let Some(policies) = env.0.remove("PARSER_POLICIES") {
+            for policy in policies.split(',').filter(|s| !s.trim().is_empty()) {
+                let (kind, policy) = process::parse_kind_policy(policy)
+                    .map_err(|e| invalid("PARSER_POLICIES", e))?;
+                self.parser.policies.insert(kind, policy);
+            }
+        }
+        if let Some(window) = env.get("PARSER_RETRY_WINDOW")? {
+            self.parser.retry_window = Some(window);
+        }
+        if let Some(samples) = env.get("PARSER_REJECT_SAMPLES")? {
+            self.parser.reject_samples = samples;
+        }
+
+        if let Some(memory) = env.get("LIMITS_MEMORY")? {
+            self.limits.memory = Some(memory);
+        }
+        // Tables whose required keys may come from the file or the environment.
+        let horizon = env.get("LIMITS_EVICTION_HORIZON")?;
+        let evict_resolved = env.get("LIMITS_EVICTION_EVICT_RESOLVED")?;
+        if horizon.is_some() || evict_resolved.is_some() {
+            let eviction = match &mut self.limits.eviction {
+                Some(eviction) => eviction,
+                None => self.limits.eviction.insert(EvictionConfig {
+                    horizon: horizon.ok_or_else(|| missing("LIMITS_EVICTION_HORIZON"))?,
+                    evict_resolved: false,
+                }),
+            };
+            if let Some(horizon) = horizon {
+                eviction.horizon = horizon;
+            }
+            if let Some(evict_resolved) = evict_resolved {
+                eviction.evict_resolved = evict_resolved;
+            }
+        }
+        let dir = env.get::<PathBuf>("LIMITS_SPILL_DIR")?;
+        let window = env.get("LIMITS_SPILL_WINDOW")?;
+        if dir.is_some() || window.is_some() {
+            let spill = match &mut self.limits.spill {
+                Some(spill) => spill,
+                None => self.limits.spill.insert(SpillConfig {
+                    dir: dir.clone().ok_or_else(|| missing("LIMITS_SPILL_DIR"))?,
+                    window: window.ok_or_else(|| missing("LIMITS_SPILL_WINDOW"))?,
+                }),
+            };
+            if let Some(dir) = dir {
+                spill.dir = dir;
+            }
+            if let Some(window) = window {
+                spill.window = window;
+            }
+        }
+        // Every key has a default.
+        let failures = env.get("LIMITS_BREAKER_FAILURES")?;
+        let cooldown_ms = env.get("LIMITS_BREAKER_COOLDOWN_MS")?;
+        let max_buffered = env.get("LIMITS_BREAKER_MAX_BUFFERED")?;
+        let max_pause_ms = env.get("LIMITS_BREAKER_MAX_PAUSE_MS")?;
+        if failures.is_some()
+            || cooldown_ms.is_some()
+            || max_buffered.is_some()
+            || max_pause_ms.is_some()
+        {
+            let breaker = self.limits.breaker.get_or_insert_default();
+            if let Some(failures) = failures {
+                breaker.failures = failures;
+            }
+            if let Some(cooldown_ms) = cooldown_ms {
+                breaker.cooldown_ms = cooldown_ms;
+            }
+            if let Some(max_buffered) = max_buffered {
+                breaker.max_buffered = max_buffered;
+            }
+            if let Some(max_pause_ms) = max_pause_ms {
+                breaker.max_pause_ms = max_pause_ms;
+            }
+        }
+
+        if let Some(threads) = env.get("THREADS_THREADS")? {
+            self.threads.threads = Some(threads);
+        }
+        if let Some(pin_cores) = env.get("THREADS_PIN_CORES")? {
+            self.threads.pin_cores = pin_cores;
+        }
+
+        if let Some(format) = env.get("OUTPUT_FORMAT")? {
+            self.output.format = format;
+        }
+        if let Some(path) = env.get("OUTPUT_PATH")? {
+            self.output.path = Some(path);
+        }
+        if let Some(rejects) = env.get("OUTPUT_REJECTS")? {
+            self.output.rejects = Some(rejects);
+        }
+        if let Some(precision) = env.get("OUTPUT_PRECISION")? {
+            self.output.precision = Some(precision);
+        }
+        if let Some(sort) = env.get("OUTPUT_SORT")? {
+            self.output.sort = sort;
+        }
+        if let Some(clients) = env.0.remove("OUTPUT_CLIENTS") {
+            let clients = clients
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(|s| s.parse())
+                .collect::<Result<_, _>>()
+                .map_err(|e| invalid("OUTPUT_CLIENTS", e))?;
+            self.output.clients = Some(clients);
+        }
+        if let Some(snapshot) = env.get("OUTPUT_SNAPSHOT")? {
+            self.output.snapshot = Some(snapshot);
+        }
+        if let Some(history) = env.get("OUTPUT_SNAPSHOT_HISTORY")? {
+            self.output.snapshot_history = Some(history);
+        }
+        if let Some(postgres) = env.get("OUTPUT_POSTGRES")? {
+            self.output.postgres = Some(postgres);
+        }
+        if let Some(chunk) = env.get("OUTPUT_POSTGRES_CHUNK")? {
+            self.output.postgres_chunk = Some(chunk);
+        }
+
+        let anomaly = &mut self.anomaly;
+        if let Some(deposits) = env.get("ANOMALY_DRAIN_DEPOSITS")? {
+            anomaly.drain_deposits = deposits;
+        }
+        if let Some(percent) = env.get("ANOMALY_DRAIN_PERCENT")? {
+            anomaly.drain_percent = percent;
+        }
+        if let Some(percent) = env.get("ANOMALY_DISPUTE_PERCENT")? {
+            anomaly.dispute_percent = percent;
+        }
+        if let Some(deposits) = env.get("ANOMALY_MIN_DEPOSITS")? {
+            anomaly.min_deposits = deposits;
+        }
+        if let Some(transactions) = env.get("ANOMALY_BURST_TRANSACTIONS")? {
+            anomaly.burst_transactions = transactions;
+        }
+        if let Some(rows) = env.get("ANOMALY_BURST_ROWS")? {
+            anomaly.burst_rows = rows;
+        }
+        if let Some(limit) = env.get("ANOMALY_STRUCTURING_LIMIT")? {
+            anomaly.structuring_limit = Some(limit);
+        }
+        if let Some(percent) = env.get("ANOMALY_STRUCTURING_MARGIN_PERCENT")? {
+            anomaly.structuring_margin_percent = percent;
+        }
+        if let Some(deposits) = env.get("ANOMALY_STRUCTURING_DEPOSITS")? {
+            anomaly.structuring_deposits = deposits;
+        }
+        if let Some(rows) = env.get("ANOMALY_STRUCTURING_ROWS")? {
+            anomaly.structuring_rows = rows;
+        }
+
+        if let Some(total) = env.get("ALERTS_TOTAL")? {
+            self.alerts.total = Some(total);
+        }
+        if let Some(total) = env.get("ALERTS_ACCOUNT_TOTAL")? {
+            self.alerts.account_total = Some(total);
+        }
+        if let Some(held) = env.get("ALERTS_HELD")? {
+            self.alerts.held = Some(held);
+        }
+
+        if let Some(limit) = env.get("RATE_LIMIT_PER_SECOND")? {
+            self.rate_limit.per_second = Some(limit);
+        }
+        if let Some(limit) = env.get("RATE_LIMIT_PER_MINUTE")? {
+            self.rate_limit.per_minute = Some(limit);
+        }
+
+        if let Some(percent) = env.get("REVIEW_CHARGEBACK_PERCENT")? {
+            self.review.chargeback_percent = Some(percent);
+        }
+        if let Some(deposits) = env.get("REVIEW_MIN_DEPOSITS")? {
+            self.review.min_deposits = deposits;
+        }
+
+        if let Some(threshold) = env.get("SLOW_LOG_THRESHOLD_MS")? {
+            self.slow_log.threshold_ms = Some(threshold);
+        }
+
+        if let Some(urls) = env.0.remove("WEBHOOKS_URLS") {
+            self.webhooks.urls = urls
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_owned)
+                .collect();
+        }
+        if let Some(secret) = env.0.remove("WEBHOOKS_SECRET") {
+            self.webhooks.secret = Some(secret);
+        }
+        if let Some(retries) = env.get("WEBHOOKS_RETRIES")? {
+            self.webhooks.retries = Some(retries);
+        }
+        if let Some(backoff) = env.get("WEBHOOKS_BACKOFF_MS")? {
+            self.webhooks.backoff_ms = Some(backoff);
+        }
+
+        if let Some(accounts) = env.0.remove("PAIN001_ACCOUNTS") {
+            for account in accounts.split(',').filter(|s| !s.trim().is_empty()) {
+                let (account, client) = account
+                    .split_once('=')
+                    .and_then(|(account, client)| Some((account, client.trim().parse().ok()?)))
+                    .ok_or_else(|| invalid("PAIN001_ACCOUNTS", "expected ACCOUNT=CLIENT"))?;
+                self.pain001
+                    .accounts
+                    .insert(account.trim().to_owned(), client);
+            }
+        }
+        if let Some(currency) = env.0.remove("PAIN001_CURRENCY") {
+            self.pain001.currency = Some(currency);
+        }
+
+        match env.0.into_keys().next() {
+            Some(key) => Err(Error::Config(format!("{ENV_PREFIX}{key}: unknown setting"))),
+            None => Ok(()),
+        }
+    }
+
+    /// Turn `limits.memory` into settings staying under it for a run over `inputs` files: spill
+    /// deposits once a window of them is in memory, to `limits.spill.dir` or the temporary
+    /// directory, and use fewer threads if buffers of that many don't fit. See [`MemoryPlan`].
+    ///
+    /// Fails upfront if the limit is too low, or other settings keep something for every row,
+    /// rather than running out of memory partway through.
+    pub fn apply_memory_limit(&mut self, inputs: usize) -> Result<Option<MemoryPlan>, Error> {
+        let Some(limit) = self.limits.memory else {
+            return Ok(None);
+        };
+        let unbounded = if self.parser.verbose {
+            Some("verbose, which keeps every rejection")
+        } else if self.error_policies().uses(ErrorPolicy::DeadLetter) {
+            Some("on_error dead-letter, which keeps every rejected transaction")
+        } else if self.output.snapshot.is_some() && self.output.snapshot_history() > 0 {
+            Some("snapshot history, which keeps every applied transaction")
+        } else {
+            None
+        };
+        if let Some(setting) = unbounded {
+            return Err(Error::Config(format!(
+                "memory limit {limit} can't be kept with {setting}"
+            )));
+        }
+        let plan = MemoryPlan::new(limit, self.threads.threads(), inputs).map_err(Error::Config)?;
+        self.threads.threads = Some(plan.threads);
+        match &mut self.limits.spill {
+            Some(spill) => spill.window = spill.window.min(plan.spill_window),
+            None => {
+                self.limits.spill = Some(SpillConfig {
+                    dir: std::env::temp_dir(),
+                    window: plan.spill_window,
+                })
+            }
+        }
+        Ok(Some(plan))
+    }
+
+    pub fn rejection_mode(&self) -> RejectionMode {
+        if self.parser.verbose {
+            RejectionMode::Record
+        } else {
+            RejectionMode::Count
+        }
+    }
+
+    /// `on_error` for every kind of rejection but those in `policies`.
+    pub fn error_policies(&self) -> PolicyMap {
+        let mut policies = PolicyMap::new(self.parser.on_error);
+        for (&kind, &policy) in &self.parser.policies {
+            policies.set(kind, policy);
+        }
+        policies
+    }
+
+    /// An empty database with threads, limits and handling of rejections set up as configured.
+    pub fn database(&self) -> ShardedDatabase {
+        let mut db = ShardedDatabase::with_threads(&self.threads);
+        db.set_rejection_mode(self.rejection_mode());
+        db.set_error_policy(self.error_policies());
+        db.set_reject_samples(self.parser.reject_samples);
+        db.set_review(&self.review);
+        db.set_slow_threshold(self.slow_log.threshold());
+        for shard in db.shards_mut() {
+            if let Some(spill) = &self.limits.spill {
+                shard.enable_spill(&spill.dir, spill.window);
+            }
+            if let Some(eviction) = &self.limits.eviction {
+                shard.enable_eviction(eviction.horizon, eviction.evict_resolved);
+            }
+            if let Some(breaker) = &self.limits.breaker {
+                shard.enable_breaker(breaker.clone());
+            }
+            if let Some(window) = self.parser.retry_window {
+                shard.enable_retry(window);
+            }
+            if self.output.snapshot.is_some() && self.output.snapshot_history() > 0 {
+                shard.enable_audit();
+            }
+        }
+        db
+    }
+}
+
+/// `PAYENGINE_*` variables not read yet, by name without the prefix.
+struct EnvVars(BTreeMap<String, String>);
+
+impl EnvVars {
+    fn get<T: FromStr>(&mut self, key: &str) -> Result<Option<T>, Error>
+    where
+        T::Err: Display,
+    {
+        self.0
+            .remove(key)
+            .map(|value| value.parse().map_err(|e| invalid(key, e)))
+            .transpose()
+    }
+}
+
+fn invalid(key: &str, e: impl Display) -> Error {
+    Error::Config(format!("{ENV_PREFIX}{key}: {e}"))
+}
+
+fn missing(key: &str) -> Error {
+    Error::Config(format!("{ENV_PREFIX}{key} is required"))
+}
+
+#[cfg(all(test, feature = "config"))]
+mod tests {
+    use std::{num::NonZeroUsize, time::Duration};
+
+    use crate::{
+        Error,
+        amount::Amount,
+        breaker::BreakerConfig,
+        config::{Config, EvictionConfig, SpillConfig},
+        error::ErrorKind,
+        memory::ByteSize,
+        process::ErrorPolicy,
+        report::ReportFormat,
+    };
+
+    #[test]
+    fn test_from_toml() {
+        let config = Config::from_toml(
+            r#"
+            [parser]
+            verbose = true
+            on_error = "dead-letter"
+
+            [parser.policies]
+            DuplicateTransactionId = "abort"
+
+            [limits.eviction]
+            horizon = 1000
+
+            [limits.breaker]
+            max_pause_ms = 5000
+
+            [threads]
+            threads = 3
+
+            [output]
+            format = "json"
+
+            [anomaly]
+            burst_rows = 50
+            structuring_limit = "10000"
+
+            [alerts]
+            held = "5.5"
+
+            [rate_limit]
+            per_minute = 600
+
+            [review]
+            chargeback_percent = "2.5"
+
+            [slow_log]
+            threshold_ms = 250
+
+            [webhooks]
+            urls = ["http://localhost:9000/hook"]
+
+            [pain001]
+            currency = "EUR"
+
+            [pain001.accounts]
+            DE89370400440532013000 = 42
+            "#,
+        )
+        .unwrap();
+        assert!(config.parser.verbose);
+        assert!(!config.parser.strict);
+        assert_eq!(config.parser.on_error, ErrorPolicy::DeadLetter);
+        let policies = config.error_policies();
+        assert_eq!(
+            policies.get(ErrorKind::DuplicateTransactionId),
+            ErrorPolicy::Abort
+        );
+        assert_eq!(
+            policies.get(ErrorKind::TransactionNotFound),
+            ErrorPolicy::DeadLetter
+        );
+        assert_eq!(
+            config.limits.eviction,
+            Some(EvictionConfig {
+                horizon: 1000,
+                evict_resolved: false
+            })
+        );
+        assert_eq!(
+            config.limits.breaker,
+            Some(BreakerConfig {
+                max_pause_ms: 5000,
+                ..Default::default()
+            })
+        );
+        assert_eq!(config.threads.threads, NonZeroUsize::new(3));
+        assert_eq!(config.output.format, ReportFormat::Json);
+        assert_eq!(config.database().shard_count(), 3);
+        assert_eq!(config.anomaly.burst_rows, 50);
+        assert_eq!(config.anomaly.burst_transactions, 20);
+        assert_eq!(config.anomaly.structuring_limit, Amount::parse(b"10000"));
+        assert_eq!(config.alerts.held, Amount::parse(b"5.5"));
+        assert_eq!(config.alerts.total, None);
+        assert_eq!(config.rate_limit.per_minute, Some(600));
+        assert_eq!(config.rate_limit.per_second, None);
+        assert_eq!(config.review.chargeback_percent, Amount::parse(b"2.5"));
+        assert_eq!(config.review.min_deposits, 10);
+        assert!(config.database().review().is_enabled());
+        assert_eq!(
+            config.database().slow_log().threshold(),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(config.webhooks.urls, ["http://localhost:9000/hook"]);
+        assert_eq!(config.webhooks.retries(), 5);
+        assert_eq!(config.pain001.currency.as_deref(), Some("EUR"));
+        assert_eq!(
+            config.pain001.accounts,
+            [("DE89370400440532013000".to_owned(), 42)].into()
+        );
+
+        assert_eq!(Config::from_toml("").unwrap(), Config::default());
+        assert!(matches!(
+            Config::from_toml("[fees]\ndeposit = 1"),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            Config::from_toml("[threads]\nthreads = 0"),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            Config::from_toml("[parser.policies]\nDuplicateTx = \"abort\""),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_apply_env() {
+        let env = |vars: &[(&str, &str)]| {
+            vars.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Vec<_>>()
+        };
+        let mut config = Config::from_toml("[limits.eviction]\nhorizon = 1000").unwrap();
+        config
+            .apply_env(env(&[
+                ("PAYENGINE_PARSER_ON_ERROR", "abort"),
+                (
+                    "PAYENGINE_PARSER_POLICIES",
+                    "TransactionNotFound=warn, AccountFrozen=skip",
+                ),
+                ("PAYENGINE_PARSER_RETRY_WINDOW", "20"),
+                ("PAYENGINE_PARSER_REJECT_SAMPLES", "50"),
+                ("PAYENGINE_LIMITS_EVICTION_EVICT_RESOLVED", "true"),
+                ("PAYENGINE_LIMITS_SPILL_DIR", "/tmp/spill"),
+                ("PAYENGINE_LIMITS_SPILL_WINDOW", "50"),
+                ("PAYENGINE_LIMITS_MEMORY", "2G"),
+                ("PAYENGINE_LIMITS_BREAKER_FAILURES", "5"),
+                ("PAYENGINE_THREADS_THREADS", "2"),
+                ("PAYENGINE_OUTPUT_FORMAT", "ndjson"),
+                ("PAYENGINE_OUTPUT_CLIENTS", "7, 42"),
+                ("PAYENGINE_OUTPUT_POSTGRES_CHUNK", "500"),
+                ("PAYENGINE_ANOMALY_DISPUTE_PERCENT", "5"),
+                ("PAYENGINE_ANOMALY_STRUCTURING_LIMIT", "3000"),
+                ("PAYENGINE_ALERTS_ACCOUNT_TOTAL", "100"),
+                ("PAYENGINE_RATE_LIMIT_PER_SECOND", "10"),
+                ("PAYENGINE_REVIEW_MIN_DEPOSITS", "20"),
+                ("PAYENGINE_SLOW_LOG_THRESHOLD_MS", "50"),
+                ("PAYENGINE_WEBHOOKS_URLS", "http://a/hook, http://b/hook"),
+                ("PAYENGINE_WEBHOOKS_SECRET", "s3cret"),
+                (
+                    "PAYENGINE_PAIN001_ACCOUNTS",
+                    "DE02 1001 0010 0006 8201 01=7, FR76=8",
+                ),
+                ("PAYENGINE_CONFIG", "ignored.toml"),
+                ("HOME", "/root"),
+            ]))
+            .unwrap();
+        assert_eq!(config.parser.on_error, ErrorPolicy::Abort);
+        assert_eq!(config.parser.retry_window, Some(20));
+        assert_eq!(config.parser.reject_samples, 50);
+        assert_eq!(
+            config.parser.policies,
+            [
+                (ErrorKind::TransactionNotFound, ErrorPolicy::Warn),
+                (ErrorKind::AccountFrozen, ErrorPolicy::Skip),
+            ]
+            .into()
+        );
+        // The horizon comes from the file.
+        assert_eq!(
+            config.limits.eviction,
+            Some(EvictionConfig {
+                horizon: 1000,
+                evict_resolved: true
+            })
+        );
+        assert_eq!(
+            config.limits.spill,
+            Some(SpillConfig {
+                dir: "/tmp/spill".into(),
+                window: 50
+            })
+        );
+        assert_eq!(config.limits.memory, Some(ByteSize(2 << 30)));
+        assert_eq!(config.limits.breaker.as_ref().unwrap().failures, 5);
+        assert_eq!(config.limits.breaker.as_ref().unwrap().cooldown_ms, 1000);
+        assert_eq!(config.threads.threads, NonZeroUsize::new(2));
+        assert_eq!(config.output.format, ReportFormat::Ndjson);
+        assert_eq!(config.output.clients, Some([7, 42].into()));
+        assert_eq!(config.output.postgres_chunk(), 500);
+        assert_eq!(config.anomaly.dispute_percent, 5);
+        assert_eq!(config.anomaly.structuring_limit, Amount::parse(b"3000"));
+        assert_eq!(config.alerts.account_total, Amount::parse(b"100"));
+        assert_eq!(config.rate_limit.per_second, Some(10));
+        assert_eq!(config.review.min_deposits, 20);
+        assert_eq!(config.review.chargeback_percent, None);
+        assert_eq!(config.slow_log.threshold_ms, Some(50));
+        assert_eq!(config.webhooks.urls, ["http://a/hook", "http://b/hook"]);
+        assert_eq!(config.webhooks.secret.as_deref(), Some("s3cret"));
+        assert_eq!(
+            config.pain001.accounts,
+            [
+                ("DE02 1001 0010 0006 8201 01".to_owned(), 7),
+                ("FR76".to_owned(), 8)
+            ]
+            .into()
+        );
+
+        for vars in [
+            &[("PAYENGINE_PARSER_STRICT", "yes")][..],
+            &[("PAYENGINE_THREADS", "2")],
+            &[("PAYENGINE_LIMITS_SPILL_DIR", "/tmp/spill")],
+            &[("PAYENGINE_OUTPUT_CLIENTS", "1,x")],
+            &[("PAYENGINE_LIMITS_MEMORY", "lots")],
+            &[("PAYENGINE_ANOMALY_STRUCTURING_LIMIT", "-1")],
+            &[("PAYENGINE_PARSER_POLICIES", "AccountFrozen")],
+            &[("PAYENGINE_PARSER_POLICIES", "AccountFrozen=retry")],
+            &[("PAYENGINE_PAIN001_ACCOUNTS", "DE02=x")],
+        ] {
+            assert!(matches!(
+                Config::default().apply_env(env(vars)),
+                Err(Error::Config(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_apply_memory_limit() {
+        let mut config = Config::from_toml(
+            "[limits]\nmemory = \"1G\"\n[limits.spill]\ndir = \"/tmp/spill\"\nwindow = 100000\n\
+             [threads]\nthreads = 4",
+        )
+        .unwrap();
+        let plan = config.apply_memory_limit(1).unwrap().unwrap();
+        assert_eq!(plan.threads, NonZeroUsize::new(4).unwrap());
+        // A lower configured window is kept.
+        assert_eq!(config.limits.spill.as_ref().unwrap().window, 100000);
+
+        config.limits.spill = None;
+        config.apply_memory_limit(1).unwrap();
+        let spill = config.limits.spill.unwrap();
+        assert_eq!(spill.window, plan.spill_window);
+        assert_eq!(spill.dir, std::env::temp_dir());
+
+        let mut config = Config::default();
+        assert_eq!(config.apply_memory_limit(1).unwrap(), None);
+        config.limits.memory = Some(ByteSize(1 << 30));
+        config.parser.on_error = ErrorPolicy::DeadLetter;
+        assert!(matches!(
+            config.apply_memory_limit(1),
+            Err(Error::Config(e)) if e.contains("dead-letter")
+        ));
+        config.parser.on_error = ErrorPolicy::Skip;
+        config
+            .parser
+            .policies
+            .insert(ErrorKind::AccountFrozen, ErrorPolicy::DeadLetter);
+        assert!(config.apply_memory_limit(1).is_err());
+        config.parser.policies.clear();
+        config.limits.memory = Some(ByteSize(1 << 20));
+        assert!(matches!(
+            config.apply_memory_limit(1),
+            Err(Error::Config(e)) if e.starts_with("memory limit 1M is too low")
+        ));
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Error,
+    accounts::{ClientId, TransactionId},
+    multifile::FileReport,
+    parser::Row,
+    pipeline::ParseFailure,
+};
+
+/// A malformed row or rejected transaction with where it came from, e.g.
+/// `b.csv:7 (byte 112), client 2, tx 9: account not found`.
+///
+/// Fields are None where unknown: client and transaction ids of rows that couldn't be parsed,
+/// positions in inputs that aren't CSV, and the file of input that wasn't read from one.
+#[derive(Debug)]
+pub struct ContextualError {
+    pub error: Error,
+    /// 1-based, including the header.
+    pub line_no: Option<usize>,
+    /// Of the start of the line.
+    pub byte_offset: Option<u64>,
+    pub client_id: Option<ClientId>,
+    pub tx_id: Option<TransactionId>,
+    pub source_file: Option<PathBuf>,
+}
+
+impl ContextualError {
+    /// Only the error, without context yet.
+    pub fn new(error: Error) -> Self {
+        ContextualError {
+            error,
+            line_no: None,
+            byte_offset: None,
+            client_id: None,
+            tx_id: None,
+            source_file: None,
+        }
+    }
+}
+
+impl fmt::Display for ContextualError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        match (&self.source_file, self.line_no) {
+            (Some(path), Some(line)) => parts.push(format!("{}:{line}", path.display())),
+            (Some(path), None) => parts.push(path.display().to_string()),
+            (None, Some(line)) => parts.push(format!("line {line}")),
+            (None, None) => {}
+        }
+        if let Some(offset) = self.byte_offset {
+            match parts.last_mut() {
+                Some(location) => location.push_str(&format!(" (byte {offset})")),
+                None => parts.push(format!("byte {offset}")),
+            }
+        }
+        if let Some(client_id) = self.client_id {
+            parts.push(format!("client {client_id}"));
+        }
+        if let Some(tx_id) = self.tx_id {
+            parts.push(format!("tx {tx_id}"));
+        }
+        if !parts.is_empty() {
+            write!(f, "{}: ", parts.join(", "))?;
+        }
+        write!(f, "{}", self.error)
+    }
+}
+
+impl std::error::Error for ContextualError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Find where rejections and parse failures of a CSV input came from, by reading it again, as
+/// positions aren't tracked while processing. Rejections are by index among the rows that parsed,
+/// as in [`crate::process::ProcessReport::rejections`], and must be sorted, as must failures by
+/// line.
+///
+/// Returns all of them in input order. Ones not found, e.g. because the input changed since, are
+/// last, without a position.
+pub fn locate(
+    mut input: impl BufRead,
+    source_file: Option<&Path>,
+    rejections: Vec<(usize, Error)>,
+    failures: Vec<ParseFailure>,
+) -> io::Result<Vec<ContextualError>> {
+    let mut rejections = rejections.into_iter().peekable();
+    let mut failures = failures.into_iter().peekable();
+    let mut located = Vec::with_capacity(rejections.len() + failures.len());
+    let context = |error, line_no, byte_offset, row: Option<&Row>| ContextualError {
+        error,
+        line_no: Some(line_no),
+        byte_offset: Some(byte_offset),
+        client_id: row.map(|r| r.client_id),
+        tx_id: row.map(|r| r.transaction.id),
+        source_file: source_file.map(Path::to_owned),
+    };
+    let mut buf = Vec::new();
+    let mut line = 0;
+    let mut offset = 0;
+    let mut parsed = 0;
+    while rejections.peek().is_some() || failures.peek().is_some() {
+        buf.clear();
+        let len = input.read_until(b'\n', &mut buf)?;
+        if len == 0 {
+            break;
+        }
+        line += 1;
+        let start = offset;
+        offset += len as u64;
+        if let Some(f) = failures.next_if(|f| f.line == line) {
+            located.push(context(f.error.into(), line, start, None));
+        }
+        if line == 1 {
+            continue;
+        }
+        if let Ok(row) = Row::parse(&buf) {
+            if let Some((_, e)) = rejections.next_if(|(idx, _)| *idx == parsed) {
+                located.push(context(e, line, start, Some(&row)));
+            }
+            parsed += 1;
+        }
+    }
+    located.extend(
+        failures
+            .map(|f| f.error.into())
+            .chain(rejections.map(|(_, e)| e))
+            .map(|error| ContextualError {
+                source_file: source_file.map(Path::to_owned),
+                ..ContextualError::new(error)
+            }),
+    );
+    Ok(located)
+}
+
+impl FileReport {
+    /// Take the file's parse failures and rejections, with where they came from, see [`locate`].
+    pub fn locate_errors(&mut self) -> io::Result<Vec<ContextualError>> {
+        let file = BufReader::new(File::open(&self.path)?);
+        locate(
+            file,
+            Some(&self.path),
+            std::mem::take(&mut self.process.rejections),
+            std::mem::take(&mut self.parse_failures),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        context::{ContextualError, locate},
+        error::{LedgerError, ParseError},
+        pipeline::{ParseFailure, RowStream},
+    };
+
+    #[test]
+    fn test_locate() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 5\n\
+                     bogus, 1, 2, 3\n\
+                     withdrawal, 2, 3, 1\n\
+                     deposit, 2, 4, 2\n";
+        let failures = RowStream::spawn(input.as_bytes())
+            .filter_map(Result::err)
+            .collect::<Vec<_>>();
+        assert_eq!(failures[0].line, 3);
+        let rejections = vec![
+            (1, LedgerError::AccountNotFound.into()),
+            (7, LedgerError::DuplicateDispute.into()),
+        ];
+        let located = locate(
+            input.as_bytes(),
+            Some(Path::new("a.csv")),
+            rejections,
+            failures,
+        )
+        .unwrap();
+        let messages = located.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "a.csv:3 (byte 42): unknown transaction type",
+                "a.csv:4 (byte 57), client 2, tx 3: account not found",
+                // Past the end.
+                "a.csv: duplicate dispute",
+            ]
+        );
+        assert_eq!(located[1].byte_offset, Some(57));
+        assert_eq!(&input[57..67], "withdrawal");
+
+        let failure = ParseFailure::new(1, ParseError::MissingColumn);
+        let header = locate(input.as_bytes(), None, Vec::new(), vec![failure]).unwrap();
+        assert_eq!(
+            header[0].to_string(),
+            "line 1 (byte 0): CSV missing an expected column"
+        );
+        assert_eq!(
+            ContextualError::new(LedgerError::AccountFrozen.into()).to_string(),
+            "account if frozen"
+        );
+    }
+}
@@ -0,0 +1,670 @@
+use std::{
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::{
+    accounts::{ClientId, Transaction, TransactionKind},
+    amount::Amount,
+    error::ParseError,
+    parser::Row,
+    pipeline::ParseFailure,
+    process::DeadLetter,
+};
+
+// Same as the report.
+const BUFFER_CAPACITY: usize = 1 << 20;
+
+/// Formats transactions can be read from and written to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TransactionFormat {
+    /// The CSV input format. Written normalized, without whitespace.
+    #[default]
+    Csv,
+    /// One JSON object per line, see [`write_jsonl`].
+    Jsonl,
+    /// Fixed-size records, see [`write_binary`]. The fastest to read back.
+    Binary,
+    /// Apache Parquet, with amounts as decimal strings. Needs the "parquet" feature.
+    Parquet,
+    /// ISO 20022 pain.001 credit transfers, see `crate::iso20022::read_pain001`. Can only be read,
+    /// with the "iso20022" feature.
+    Pain001,
+}
+
+impl TransactionFormat {
+    pub const ALL: [TransactionFormat; 5] = [
+        TransactionFormat::Csv,
+        TransactionFormat::Jsonl,
+        TransactionFormat::Binary,
+        TransactionFormat::Parquet,
+        TransactionFormat::Pain001,
+    ];
+
+    /// Formats [`Self::write`] supports.
+    pub const WRITABLE: [TransactionFormat; 4] = [
+        TransactionFormat::Csv,
+        TransactionFormat::Jsonl,
+        TransactionFormat::Binary,
+        TransactionFormat::Parquet,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionFormat::Csv => "csv",
+            TransactionFormat::Jsonl => "jsonl",
+            TransactionFormat::Binary => "binary",
+            TransactionFormat::Parquet => "parquet",
+            TransactionFormat::Pain001 => "pain001",
+        }
+    }
+
+    /// Format of a file by its extension: `.csv`, `.jsonl` or `.ndjson`, `.bin`, `.parquet`,
+    /// `.xml` for pain.001.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        match path.as_ref().extension()?.to_str()? {
+            "csv" => Some(TransactionFormat::Csv),
+            "jsonl" | "ndjson" => Some(TransactionFormat::Jsonl),
+            "bin" => Some(TransactionFormat::Binary),
+            "parquet" => Some(TransactionFormat::Parquet),
+            "xml" => Some(TransactionFormat::Pain001),
+            _ => None,
+        }
+    }
+
+    /// Write transactions in this format.
+    pub fn write(
+        self,
+        w: impl Write + Send,
+        transactions: impl IntoIterator<Item = (ClientId, Transaction)>,
+    ) -> std::io::Result<()> {
+        match self {
+            TransactionFormat::Csv => write_csv(w, transactions),
+            TransactionFormat::Jsonl => write_jsonl(w, transactions),
+            TransactionFormat::Binary => write_binary(w, transactions),
+            #[cfg(feature = "parquet")]
+            TransactionFormat::Parquet => write_parquet(w, transactions),
+            #[cfg(not(feature = "parquet"))]
+            TransactionFormat::Parquet => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "built without the \"parquet\" feature",
+            )),
+            TransactionFormat::Pain001 => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "pain.001 can only be read",
+            )),
+        }
+    }
+}
+
+impl std::str::FromStr for TransactionFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|f| f.as_str() == s)
+            .ok_or_else(|| format!("unknown transaction format {s:?}"))
+    }
+}
+
+/// Write transactions as CSV input, with a header and no extra whitespace.
+pub fn write_csv(
+    w: impl Write,
+    transactions: impl IntoIterator<Item = (ClientId, Transaction)>,
+) -> std::io::Result<()> {
+    let mut w = BufWriter::with_capacity(BUFFER_CAPACITY, w);
+    w.write_all(b"type,client,tx,amount\n")?;
+    let mut row = Vec::with_capacity(64);
+    for (client_id, t) in transactions {
+        row.clear();
+        push_csv(&mut row, client_id, t);
+        row.push(b'\n');
+        w.write_all(&row)?;
+    }
+    w.flush()
+}
+
+/// Write rejected transactions as CSV input with extra `code` and `error` columns, the reason code
+/// (see [`crate::error::ErrorKind::code`]) and message, so they can be fixed and submitted again. Rows
+/// that couldn't be parsed have empty transaction columns.
+pub fn write_rejects(w: impl Write, rejects: &[DeadLetter]) -> std::io::Result<()> {
+    let mut w = BufWriter::with_capacity(BUFFER_CAPACITY, w);
+    w.write_all(b"type,client,tx,amount,code,error\n")?;
+    let mut row = Vec::with_capacity(128);
+    for reject in rejects {
+        row.clear();
+        match reject.transaction {
+            Some((client_id, t)) => push_csv(&mut row, client_id, t),
+            None => row.extend_from_slice(b",,,"),
+        }
+        row.push(b',');
+        row.extend_from_slice(reject.error.code().as_bytes());
+        // Messages may contain quotes.
+        row.extend_from_slice(b",\"");
+        row.extend_from_slice(reject.error.to_string().replace('"', "\"\"").as_bytes());
+        row.extend_from_slice(b"\"\n");
+        w.write_all(&row)?;
+    }
+    w.flush()
+}
+
+pub(crate) fn push_csv(row: &mut Vec<u8>, client_id: ClientId, t: Transaction) {
+    row.extend_from_slice(t.kind.as_str().as_bytes());
+    row.push(b',');
+    row.extend_from_slice(itoa::Buffer::new().format(client_id).as_bytes());
+    row.push(b',');
+    row.extend_from_slice(itoa::Buffer::new().format(t.id).as_bytes());
+    row.push(b',');
+    if t.kind.has_amount() {
+        t.amount.write_ascii(row);
+    }
+}
+
+/// Write transactions as JSON lines, one object per transaction. Amounts are decimal strings, as
+/// with the "serde" feature, and are left out for transaction types without one.
+pub fn write_jsonl(
+    w: impl Write,
+    transactions: impl IntoIterator<Item = (ClientId, Transaction)>,
+) -> std::io::Result<()> {
+    let mut w = BufWriter::with_capacity(BUFFER_CAPACITY, w);
+    let mut row = Vec::with_capacity(96);
+    for (client_id, t) in transactions {
+        row.clear();
+        row.extend_from_slice(b"{\"type\":\"");
+        row.extend_from_slice(t.kind.as_str().as_bytes());
+        row.extend_from_slice(b"\",\"client\":");
+        row.extend_from_slice(itoa::Buffer::new().format(client_id).as_bytes());
+        row.extend_from_slice(b",\"tx\":");
+        row.extend_from_slice(itoa::Buffer::new().format(t.id).as_bytes());
+        if t.kind.has_amount() {
+            row.extend_from_slice(b",\"amount\":\"");
+            t.amount.write_ascii(&mut row);
+            row.push(b'"');
+        }
+        row.extend_from_slice(b"}\n");
+        w.write_all(&row)?;
+    }
+    w.flush()
+}
+
+/// Leads binary files, so other files aren't read as transactions by mistake.
+const BINARY_MAGIC: &[u8; 8] = b"PAYENGB1";
+const BINARY_RECORD: usize = 15;
+
+/// Write transactions as fixed-size records after an 8-byte magic: the kind as its index in
+/// [`TransactionKind::ALL`], then the client id (2 bytes), transaction id (4 bytes) and raw amount
+/// (8 bytes, see [`Amount::to_raw`]), little-endian. Amounts are 0 for types without one.
+///
+/// Reading needs no parsing of text, which makes this suited to replaying the same input many
+/// times.
+pub fn write_binary(
+    w: impl Write,
+    transactions: impl IntoIterator<Item = (ClientId, Transaction)>,
+) -> std::io::Result<()> {
+    let mut w = BufWriter::with_capacity(BUFFER_CAPACITY, w);
+    w.write_all(BINARY_MAGIC)?;
+    for (client_id, t) in transactions {
+        let mut record = [0; BINARY_RECORD];
+        record[0] = t.kind as u8;
+        record[1..3].copy_from_slice(&client_id.to_le_bytes());
+        record[3..7].copy_from_slice(&t.id.to_le_bytes());
+        record[7..].copy_from_slice(&t.amount.to_raw().to_le_bytes());
+        w.write_all(&record)?;
+    }
+    w.flush()
+}
+
+/// Read transactions written by [`write_binary`]. A failure's `line` is the 1-based record
+/// number, counting the magic as the first, like the CSV header. Read errors, including a
+/// truncated last record, end the stream.
+pub fn read_binary(r: impl Read) -> impl Iterator<Item = Result<Row, ParseFailure>> {
+    let mut r = BufReader::with_capacity(BUFFER_CAPACITY, r);
+    let mut line = 0;
+    let mut done = false;
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        line += 1;
+        let res = (|| {
+            if line == 1 {
+                let mut magic = [0; BINARY_MAGIC.len()];
+                r.read_exact(&mut magic)?;
+                if &magic != BINARY_MAGIC {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "not a binary transactions file",
+                    ));
+                }
+                line += 1;
+            }
+            if r.fill_buf()?.is_empty() {
+                return Ok(None);
+            }
+            let mut record = [0; BINARY_RECORD];
+            r.read_exact(&mut record)?;
+            Ok(Some(record))
+        })();
+        match res {
+            Ok(Some(record)) => {
+                Some(decode_binary(&record).map_err(|error| ParseFailure::new(line, error)))
+            }
+            Ok(None) => None,
+            Err(e) => {
+                done = true;
+                Some(Err(ParseFailure::new(line, ParseError::Io(e))))
+            }
+        }
+    })
+}
+
+fn decode_binary(record: &[u8; BINARY_RECORD]) -> Result<Row, ParseError> {
+    let kind = *TransactionKind::ALL
+        .get(usize::from(record[0]))
+        .ok_or(ParseError::UnknownTransactionType)?;
+    let client_id = u16::from_le_bytes([record[1], record[2]]);
+    let id = u32::from_le_bytes(record[3..7].try_into().unwrap());
+    let amount = Amount::from_raw(u64::from_le_bytes(record[7..].try_into().unwrap()));
+    if !kind.has_amount() && amount != Amount::zero() {
+        return Err(ParseError::UnexpectedAmount);
+    }
+    Ok(Row {
+        client_id,
+        transaction: Transaction { kind, id, amount },
+    })
+}
+
+/// Read transactions written by [`write_jsonl`]. Keys may come in any order, and amounts may also
+/// be JSON numbers or null. Blank lines are skipped. A read error ends the stream. Needs the
+/// "serde" feature.
+#[cfg(feature = "serde")]
+pub fn read_jsonl(r: impl BufRead) -> impl Iterator<Item = Result<Row, ParseFailure>> {
+    let mut done = false;
+    r.split(b'\n')
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim_ascii().is_empty()))
+        .map_while(move |(idx, line)| {
+            if done {
+                return None;
+            }
+            let line_no = idx + 1;
+            Some(match line {
+                Ok(line) => parse_json_row(&line)
+                    .map_err(|error| ParseFailure::with_raw(line_no, error, &line)),
+                Err(e) => {
+                    done = true;
+                    Err(ParseFailure::new(line_no, ParseError::Io(e)))
+                }
+            })
+        })
+}
+
+/// Same checks as CSV rows, with the same errors. String values are unescaped, other values are
+/// checked as their JSON text. A line which isn't a JSON object misses every column.
+#[cfg(feature = "serde")]
+pub(crate) fn parse_json_row(line: &[u8]) -> Result<Row, ParseError> {
+    use std::borrow::Cow;
+
+    use serde_json::{Map, Value};
+
+    let fields = serde_json::from_slice::<Map<String, Value>>(line)
+        .map_err(|_| ParseError::MissingColumn)?;
+    fn text(value: &Value) -> Cow<'_, str> {
+        match value {
+            Value::String(s) => Cow::Borrowed(s),
+            value => Cow::Owned(value.to_string()),
+        }
+    }
+    let field = |key| fields.get(key).map(text);
+    let ttype = field("type").ok_or(ParseError::MissingColumn)?;
+    let client_id = field("client").ok_or(ParseError::MissingColumn)?;
+    let tx_id = field("tx").ok_or(ParseError::MissingColumn)?;
+    let amount = fields
+        .get("amount")
+        .filter(|v| !v.is_null())
+        .map_or(Cow::Borrowed(""), text);
+    Row::from_columns(
+        ttype.as_bytes(),
+        client_id.as_bytes(),
+        tx_id.as_bytes(),
+        amount.as_bytes(),
+    )
+}
+
+#[cfg(feature = "parquet")]
+const PARQUET_SCHEMA: &str = "
+    message transactions {
+        REQUIRED BYTE_ARRAY type (STRING);
+        REQUIRED INT32 client (INTEGER(16, false));
+        REQUIRED INT32 tx (INTEGER(32, false));
+        REQUIRED BYTE_ARRAY amount (STRING);
+    }
+";
+
+/// Write transactions to Parquet in row groups of a million, with empty amounts for types
+/// without one.
+#[cfg(feature = "parquet")]
+pub fn write_parquet(
+    w: impl Write + Send,
+    transactions: impl IntoIterator<Item = (ClientId, Transaction)>,
+) -> std::io::Result<()> {
+    use std::sync::Arc;
+
+    use parquet::{
+        column::writer::ColumnWriter, data_type::ByteArray, errors::ParquetError,
+        file::writer::SerializedFileWriter, schema::parser::parse_message_type,
+    };
+
+    const ROW_GROUP: usize = 1 << 20;
+
+    let write = || -> Result<(), ParquetError> {
+        let schema = Arc::new(parse_message_type(PARQUET_SCHEMA)?);
+        let mut writer = SerializedFileWriter::new(w, schema, Default::default())?;
+        let mut transactions = transactions.into_iter().peekable();
+        let (mut kinds, mut amounts) = (Vec::new(), Vec::new());
+        let (mut clients, mut ids) = (Vec::new(), Vec::new());
+        while transactions.peek().is_some() {
+            kinds.clear();
+            amounts.clear();
+            clients.clear();
+            ids.clear();
+            for (client_id, t) in transactions.by_ref().take(ROW_GROUP) {
+                kinds.push(ByteArray::from(t.kind.as_str()));
+                clients.push(i32::from(client_id));
+                // Unsigned, as annotated in the schema.
+                ids.push(t.id as i32);
+                let mut amount = Vec::new();
+                if t.kind.has_amount() {
+                    t.amount.write_ascii(&mut amount);
+                }
+                amounts.push(ByteArray::from(amount));
+            }
+            let mut row_group = writer.next_row_group()?;
+            let mut byte_arrays = [&kinds, &amounts].into_iter();
+            let mut ints = [&clients, &ids].into_iter();
+            while let Some(mut column) = row_group.next_column()? {
+                match column.untyped() {
+                    ColumnWriter::ByteArrayColumnWriter(c) => {
+                        c.write_batch(byte_arrays.next().unwrap(), None, None)?
+                    }
+                    ColumnWriter::Int32ColumnWriter(c) => {
+                        c.write_batch(ints.next().unwrap(), None, None)?
+                    }
+                    _ => unreachable!("not in the schema"),
+                };
+                column.close()?;
+            }
+            row_group.close()?;
+        }
+        writer.close()?;
+        Ok(())
+    };
+    write().map_err(std::io::Error::other)
+}
+
+/// Read transactions written by [`write_parquet`]. A failure's `line` is the 1-based row number.
+/// Read errors end the stream.
+#[cfg(feature = "parquet")]
+pub fn read_parquet(
+    file: std::fs::File,
+) -> std::io::Result<impl Iterator<Item = Result<Row, ParseFailure>>> {
+    use parquet::{
+        file::reader::SerializedFileReader,
+        record::{Row as Record, RowAccessor},
+    };
+
+    fn decode(record: &Record) -> Result<Row, ParseError> {
+        let ttype = record
+            .get_string(0)
+            .map_err(|_| ParseError::MissingColumn)?;
+        let ttype = crate::parser::parse_kind(ttype.as_bytes())?;
+        let client_id = record
+            .get_ushort(1)
+            .map_err(|_| ParseError::InvalidClientId)?;
+        let id = record.get_uint(2).map_err(|_| ParseError::InvalidTxId)?;
+        let amount = record
+            .get_string(3)
+            .map_err(|_| ParseError::InvalidAmount)?;
+        Row::with_amount(ttype, client_id, id, amount.as_bytes())
+    }
+
+    let reader = SerializedFileReader::new(file).map_err(std::io::Error::other)?;
+    let mut done = false;
+    Ok(reader
+        .into_iter()
+        .enumerate()
+        .map_while(move |(idx, record)| {
+            if done {
+                return None;
+            }
+            let line = idx + 1;
+            Some(match record {
+                Ok(record) => decode(&record).map_err(|error| ParseFailure::new(line, error)),
+                Err(e) => {
+                    done = true;
+                    Err(ParseFailure::new(
+                        line,
+                        ParseError::Io(std::io::Error::other(e)),
+                    ))
+                }
+            })
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::{Transaction, TransactionKind::*},
+        amount::Amount,
+        convert::{
+            TransactionFormat, read_binary, write_binary, write_csv, write_jsonl, write_rejects,
+        },
+        error::ErrorKind,
+        error::LedgerError,
+        parser::Row,
+        process::DeadLetter,
+    };
+
+    #[test]
+    fn test_convert() {
+        let transactions = [
+            (
+                1,
+                Transaction {
+                    kind: Deposit,
+                    id: 7,
+                    amount: Amount::parse(b"1.05").unwrap(),
+                },
+            ),
+            (
+                2,
+                Transaction {
+                    kind: Dispute,
+                    id: 7,
+                    amount: Amount::zero(),
+                },
+            ),
+        ];
+
+        let mut csv = Vec::new();
+        write_csv(&mut csv, transactions).unwrap();
+        assert_eq!(
+            csv,
+            b"type,client,tx,amount\ndeposit,1,7,1.05\ndispute,2,7,\n"
+        );
+        let parsed = csv
+            .split(|b| *b == b'\n')
+            .skip(1)
+            .filter(|l| !l.is_empty())
+            .map(|l| Row::parse(l).unwrap())
+            .map(|r| (r.client_id, r.transaction))
+            .collect::<Vec<_>>();
+        assert_eq!(parsed, transactions);
+
+        let mut jsonl = Vec::new();
+        write_jsonl(&mut jsonl, transactions).unwrap();
+        assert_eq!(
+            String::from_utf8(jsonl).unwrap(),
+            "{\"type\":\"deposit\",\"client\":1,\"tx\":7,\"amount\":\"1.05\"}\n\
+             {\"type\":\"dispute\",\"client\":2,\"tx\":7}\n"
+        );
+
+        let rejects = [
+            DeadLetter {
+                index: 1,
+                transaction: Some(transactions[1]),
+                error: LedgerError::AccountNotFound.into(),
+            },
+            DeadLetter {
+                index: 2,
+                transaction: None,
+                error: LedgerError::HeldOverflow.into(),
+            },
+        ];
+        let mut out = Vec::new();
+        write_rejects(&mut out, &rejects).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "type,client,tx,amount,code,error\n\
+             dispute,2,7,,E_ACCOUNT_NOT_FOUND,\"account not found\"\n\
+             ,,,,E_HELD_OVERFLOW,\"overflow increasing \"\"held\"\"\"\n"
+        );
+    }
+
+    #[test]
+    fn test_read_back() {
+        let transactions = [
+            (
+                1,
+                Transaction {
+                    kind: Deposit,
+                    id: 7,
+                    amount: Amount::parse(b"1.05").unwrap(),
+                },
+            ),
+            (
+                65535,
+                Transaction {
+                    kind: Chargeback,
+                    id: u32::MAX,
+                    amount: Amount::zero(),
+                },
+            ),
+        ];
+        let rows = |rows: Vec<Result<Row, _>>| {
+            rows.into_iter()
+                .map(|r| r.map(|r| (r.client_id, r.transaction)))
+                .collect::<Vec<_>>()
+        };
+
+        let mut binary = Vec::new();
+        write_binary(&mut binary, transactions).unwrap();
+        assert_eq!(binary.len(), 8 + 2 * 15);
+        let read = rows(read_binary(binary.as_slice()).collect());
+        assert_eq!(read.len(), 2);
+        assert_eq!(*read[0].as_ref().unwrap(), transactions[0]);
+        assert_eq!(*read[1].as_ref().unwrap(), transactions[1]);
+        // Truncated, then not a binary file at all.
+        let read = read_binary(&binary[..binary.len() - 1]).collect::<Vec<_>>();
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[1].as_ref().unwrap_err().line, 3);
+        assert_eq!(read[1].as_ref().unwrap_err().error.kind(), ErrorKind::CsvIo);
+        let read = read_binary(b"type,client,tx,amount\n".as_slice()).collect::<Vec<_>>();
+        assert_eq!(read.len(), 1);
+        assert!(read[0].is_err());
+
+        #[cfg(feature = "serde")]
+        {
+            let mut jsonl = Vec::new();
+            write_jsonl(&mut jsonl, transactions).unwrap();
+            jsonl.extend_from_slice(
+                b"\n{ \"amount\": 2.5, \"tx\": 8, \"type\": \"withdrawal\", \"client\": 1 }\n\
+                  {\"type\":\"dispute\",\"client\":1,\"tx\":7,\"amount\":null}\n\
+                  {\"type\":\"dispute\",\"client\":1}\n\
+                  {\"type\":\"deposit\",\"client\":1,\"tx\":9,\"amount\":\"x\"}\n\
+                  [1, 2]\n\
+                  {\"type\":\"dep\\u006fsit\",\"client\":1,\"tx\":10,\"amount\":\"1\",\
+                   \"note\":{\"a\":\"}, \\\"tx\\\": 11\"},\"tags\":[1,2]}\n\
+                  {\"type\":\"deposit\\\"\",\"client\":1,\"tx\":11,\"amount\":\"1\"}\n",
+            );
+            let read = crate::convert::read_jsonl(jsonl.as_slice()).collect::<Vec<_>>();
+            let failures = read
+                .iter()
+                .filter_map(|r| r.as_ref().err())
+                .map(|f| (f.line, f.error.kind()))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                failures,
+                [
+                    (6, ErrorKind::CsvMissingColumn),
+                    (7, ErrorKind::CsvInvalidAmount),
+                    (8, ErrorKind::CsvMissingColumn),
+                    (10, ErrorKind::CsvUnknownTransactionType)
+                ]
+            );
+            let read = rows(read.into_iter().filter(|r| r.is_ok()).collect())
+                .into_iter()
+                .map(Result::unwrap)
+                .collect::<Vec<_>>();
+            assert_eq!(read[..2], transactions);
+            assert_eq!(read[2].1.kind, Withdrawal);
+            assert_eq!(read[2].1.amount, Amount::parse(b"2.5").unwrap());
+            assert_eq!(read[3].1.kind, Dispute);
+            // Escapes are decoded, and nested values don't end the object.
+            assert_eq!(read[4].1.kind, Deposit);
+            assert_eq!(read[4].1.id, 10);
+        }
+
+        assert_eq!(
+            TransactionFormat::from_path("in.bin"),
+            Some(TransactionFormat::Binary)
+        );
+        assert_eq!(
+            TransactionFormat::from_path("transfers.xml"),
+            Some(TransactionFormat::Pain001)
+        );
+        assert_eq!(TransactionFormat::from_path("in"), None);
+        assert!(
+            TransactionFormat::Pain001
+                .write(Vec::new(), transactions)
+                .is_err()
+        );
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_read_back_parquet() {
+        use crate::convert::{read_parquet, write_parquet};
+
+        let transactions = [
+            (
+                1,
+                Transaction {
+                    kind: Withdrawal,
+                    id: u32::MAX,
+                    amount: Amount::parse(b"0.0001").unwrap(),
+                },
+            ),
+            (
+                65535,
+                Transaction {
+                    kind: Resolve,
+                    id: 3,
+                    amount: Amount::zero(),
+                },
+            ),
+        ];
+        let path = std::env::temp_dir().join(format!("payengine-convert-{}", std::process::id()));
+        write_parquet(std::fs::File::create(&path).unwrap(), transactions).unwrap();
+        let read = read_parquet(std::fs::File::open(&path).unwrap())
+            .unwrap()
+            .map(|r| {
+                let r = r.unwrap();
+                (r.client_id, r.transaction)
+            })
+            .collect::<Vec<_>>();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read, transactions);
+    }
+}
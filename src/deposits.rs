@@ -0,0 +1,656 @@
+use std::{cell::RefCell, collections::hash_map::Entry};
+
+use rustc_hash::FxHashMap;
+
+use crate::{accounts::TransactionId, amount::Amount, slab::SizeClassPool};
+
+/// Storage of an account's deposits, retained for potential disputes.
+///
+/// The business logic in `Account::process` only goes through this trait, so different layouts can
+/// be swapped in and benchmarked against each other.
+pub trait DepositStore: Default {
+    /// Cheap handle to a stored deposit, valid until the store is modified by anything other than
+    /// [`Self::set_disputed`].
+    type Key: Copy;
+
+    fn find(&self, id: TransactionId) -> Option<Self::Key>;
+
+    fn amount(&self, key: Self::Key) -> Amount;
+
+    fn is_disputed(&self, key: Self::Key) -> bool;
+
+    fn set_disputed(&mut self, key: Self::Key, disputed: bool);
+
+    /// Store a new undisputed deposit. Returns false without modifying anything if a deposit with
+    /// this id is already stored.
+    fn insert(&mut self, id: TransactionId, amount: Amount) -> bool;
+
+    /// Remove a deposit, used to roll back its insertion. Returns false if it isn't stored.
+    fn remove(&mut self, id: TransactionId) -> bool;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all undisputed deposits, passing each of them to `f`.
+    fn drain_undisputed(&mut self, f: impl FnMut(TransactionId, Amount)) {
+        self.drain_undisputed_if(|_| true, f)
+    }
+
+    /// Remove undisputed deposits whose id matches `pred`, passing each of them to `f`.
+    fn drain_undisputed_if(
+        &mut self,
+        pred: impl FnMut(TransactionId) -> bool,
+        f: impl FnMut(TransactionId, Amount),
+    );
+
+    /// Pass each deposit under dispute to `f`, in no particular order.
+    fn for_each_disputed(&self, f: impl FnMut(TransactionId, Amount));
+}
+
+/// Growable bitset that keeps bit positions in sync with indices of a Vec, including inserts and
+/// removals in the middle.
+#[derive(Default)]
+struct Bits {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl Bits {
+    fn get(&self, idx: usize) -> bool {
+        self.words[idx / 64] & (1 << (idx % 64)) != 0
+    }
+
+    fn set(&mut self, idx: usize, value: bool) {
+        let mask = 1 << (idx % 64);
+        if value {
+            self.words[idx / 64] |= mask;
+        } else {
+            self.words[idx / 64] &= !mask;
+        }
+    }
+
+    /// Insert a bit at `idx`, moving all following bits one position up.
+    fn insert(&mut self, idx: usize, value: bool) {
+        if self.len.is_multiple_of(64) {
+            self.words.push(0);
+        }
+        self.len += 1;
+        let word = idx / 64;
+        for i in (word + 1..self.words.len()).rev() {
+            self.words[i] = (self.words[i] << 1) | (self.words[i - 1] >> 63);
+        }
+        let low = (1u64 << (idx % 64)) - 1;
+        let w = self.words[word];
+        self.words[word] = (w & low) | ((w & !low) << 1);
+        self.set(idx, value);
+    }
+
+    /// Remove the bit at `idx`, moving all following bits one position down.
+    fn remove(&mut self, idx: usize) {
+        let word = idx / 64;
+        let low = (1u64 << (idx % 64)) - 1;
+        let w = self.words[word];
+        self.words[word] = (w & low) | ((w >> 1) & !low);
+        for i in word + 1..self.words.len() {
+            self.words[i - 1] |= self.words[i] << 63;
+            self.words[i] >>= 1;
+        }
+        self.len -= 1;
+        if self.len.is_multiple_of(64) {
+            self.words.pop();
+        }
+    }
+}
+
+// Pending out-of-order deposits are merged once there are more than this many, or the square root
+// of the deposit count if it's larger.
+const MIN_MERGE_THRESHOLD: usize = 32;
+
+/// Deposits stored in TXID order for binary search.
+///
+/// Compact and fast for mostly increasing transaction ids, which is the common case. Stored as
+/// struct-of-arrays: the binary search only touches the dense id array, and with the dispute flags
+/// packed into a bitset a deposit takes 12 bytes and a bit instead of a padded 16-byte struct.
+///
+/// Deposits arriving out of order go to a small sorted side buffer, merged into the main arrays
+/// once it grows past the square root of the deposit count. That bounds an insert to O(sqrt(n))
+/// amortized, instead of an O(n) shift of the arrays, so adversarial id orders can't make
+/// throughput quadratic.
+#[derive(Default)]
+pub struct SortedVecDeposits {
+    ids: Vec<TransactionId>,
+    amounts: Vec<Amount>,
+    disputed: Bits,
+    // Sorted by id: (id, amount, is_disputed). Keys past the main arrays point here.
+    pending: Vec<(TransactionId, Amount, bool)>,
+}
+
+impl SortedVecDeposits {
+    fn find_pending(&self, id: TransactionId) -> Result<usize, usize> {
+        self.pending.binary_search_by_key(&id, |p| p.0)
+    }
+
+    fn merge_threshold(&self) -> usize {
+        self.len().isqrt().max(MIN_MERGE_THRESHOLD)
+    }
+
+    /// Merge pending deposits into the main arrays.
+    fn merge(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let len = self.len();
+        let mut ids = Vec::with_capacity(len);
+        let mut amounts = Vec::with_capacity(len);
+        let mut disputed = Bits::default();
+        let mut pending = std::mem::take(&mut self.pending).into_iter().peekable();
+        for idx in 0..self.ids.len() {
+            while let Some((id, amount, is_disputed)) =
+                pending.next_if(|(id, _, _)| *id < self.ids[idx])
+            {
+                ids.push(id);
+                amounts.push(amount);
+                disputed.insert(disputed.len, is_disputed);
+            }
+            ids.push(self.ids[idx]);
+            amounts.push(self.amounts[idx]);
+            disputed.insert(disputed.len, self.disputed.get(idx));
+        }
+        for (id, amount, is_disputed) in pending {
+            ids.push(id);
+            amounts.push(amount);
+            disputed.insert(disputed.len, is_disputed);
+        }
+        self.ids = ids;
+        self.amounts = amounts;
+        self.disputed = disputed;
+    }
+}
+
+impl DepositStore for SortedVecDeposits {
+    type Key = usize;
+
+    fn find(&self, id: TransactionId) -> Option<usize> {
+        match self.ids.binary_search(&id) {
+            Ok(idx) => Some(idx),
+            Err(_) => Some(self.ids.len() + self.find_pending(id).ok()?),
+        }
+    }
+
+    fn amount(&self, key: usize) -> Amount {
+        match key.checked_sub(self.ids.len()) {
+            None => self.amounts[key],
+            Some(p) => self.pending[p].1,
+        }
+    }
+
+    fn is_disputed(&self, key: usize) -> bool {
+        match key.checked_sub(self.ids.len()) {
+            None => self.disputed.get(key),
+            Some(p) => self.pending[p].2,
+        }
+    }
+
+    fn set_disputed(&mut self, key: usize, disputed: bool) {
+        match key.checked_sub(self.ids.len()) {
+            None => self.disputed.set(key, disputed),
+            Some(p) => self.pending[p].2 = disputed,
+        }
+    }
+
+    fn insert(&mut self, id: TransactionId, amount: Amount) -> bool {
+        // Fast path for increasing ids.
+        if self.ids.last().is_none_or(|last| *last < id)
+            && self.pending.last().is_none_or(|last| last.0 < id)
+        {
+            self.ids.push(id);
+            self.amounts.push(amount);
+            self.disputed.insert(self.disputed.len, false);
+            return true;
+        }
+        if self.ids.binary_search(&id).is_ok() {
+            return false;
+        }
+        let insert_at = match self.find_pending(id) {
+            Ok(_) => return false,
+            Err(insert_at) => insert_at,
+        };
+        self.pending.insert(insert_at, (id, amount, false));
+        if self.pending.len() > self.merge_threshold() {
+            self.merge();
+        }
+        true
+    }
+
+    fn remove(&mut self, id: TransactionId) -> bool {
+        match self.find(id) {
+            Some(idx) if idx < self.ids.len() => {
+                self.ids.remove(idx);
+                self.amounts.remove(idx);
+                self.disputed.remove(idx);
+                true
+            }
+            Some(idx) => {
+                self.pending.remove(idx - self.ids.len());
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.ids.len() + self.pending.len()
+    }
+
+    fn drain_undisputed_if(
+        &mut self,
+        mut pred: impl FnMut(TransactionId) -> bool,
+        mut f: impl FnMut(TransactionId, Amount),
+    ) {
+        self.merge();
+        // Compact the kept deposits to the front of all three arrays.
+        let mut kept = 0;
+        let mut kept_disputed = Bits::default();
+        for idx in 0..self.ids.len() {
+            let disputed = self.disputed.get(idx);
+            if disputed || !pred(self.ids[idx]) {
+                self.ids[kept] = self.ids[idx];
+                self.amounts[kept] = self.amounts[idx];
+                kept_disputed.insert(kept, disputed);
+                kept += 1;
+            } else {
+                f(self.ids[idx], self.amounts[idx]);
+            }
+        }
+        self.ids.truncate(kept);
+        self.amounts.truncate(kept);
+        self.disputed = kept_disputed;
+    }
+
+    fn for_each_disputed(&self, mut f: impl FnMut(TransactionId, Amount)) {
+        for idx in 0..self.ids.len() {
+            if self.disputed.get(idx) {
+                f(self.ids[idx], self.amounts[idx]);
+            }
+        }
+        for &(id, amount, disputed) in &self.pending {
+            if disputed {
+                f(id, amount);
+            }
+        }
+    }
+}
+
+/// Deposits in insertion order with a hash index by TXID.
+///
+/// Lookups (disputes, resolves, chargebacks) and inserts are O(1) regardless of the order of
+/// transaction ids, at the cost of roughly 2-3x the memory of [`SortedVecDeposits`]. Worth it for
+/// accounts with many deposits and shuffled ids.
+#[derive(Default)]
+pub struct HashIndexedDeposits {
+    ids: Vec<TransactionId>,
+    amounts: Vec<Amount>,
+    disputed: Vec<bool>,
+    index: FxHashMap<TransactionId, u32>,
+}
+
+impl DepositStore for HashIndexedDeposits {
+    type Key = u32;
+
+    fn find(&self, id: TransactionId) -> Option<u32> {
+        self.index.get(&id).copied()
+    }
+
+    fn amount(&self, key: u32) -> Amount {
+        self.amounts[key as usize]
+    }
+
+    fn is_disputed(&self, key: u32) -> bool {
+        self.disputed[key as usize]
+    }
+
+    fn set_disputed(&mut self, key: u32, disputed: bool) {
+        self.disputed[key as usize] = disputed;
+    }
+
+    fn insert(&mut self, id: TransactionId, amount: Amount) -> bool {
+        // TransactionId is u32, so there can't be more deposits than u32 keys.
+        match self.index.entry(id) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(e) => {
+                e.insert(self.ids.len() as u32);
+                self.ids.push(id);
+                self.amounts.push(amount);
+                self.disputed.push(false);
+                true
+            }
+        }
+    }
+
+    fn remove(&mut self, id: TransactionId) -> bool {
+        let Some(key) = self.index.remove(&id) else {
+            return false;
+        };
+        let key = key as usize;
+        self.ids.swap_remove(key);
+        self.amounts.swap_remove(key);
+        self.disputed.swap_remove(key);
+        // The last deposit moved into the freed slot.
+        if let Some(moved) = self.ids.get(key) {
+            self.index.insert(*moved, key as u32);
+        }
+        true
+    }
+
+    fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    fn drain_undisputed_if(
+        &mut self,
+        mut pred: impl FnMut(TransactionId) -> bool,
+        mut f: impl FnMut(TransactionId, Amount),
+    ) {
+        let mut kept = 0;
+        for idx in 0..self.ids.len() {
+            if self.disputed[idx] || !pred(self.ids[idx]) {
+                self.ids[kept] = self.ids[idx];
+                self.amounts[kept] = self.amounts[idx];
+                self.disputed[kept] = self.disputed[idx];
+                kept += 1;
+            } else {
+                f(self.ids[idx], self.amounts[idx]);
+            }
+        }
+        self.ids.truncate(kept);
+        self.amounts.truncate(kept);
+        self.disputed.truncate(kept);
+        self.index.clear();
+        self.index
+            .extend(self.ids.iter().enumerate().map(|(k, id)| (*id, k as u32)));
+    }
+
+    fn for_each_disputed(&self, mut f: impl FnMut(TransactionId, Amount)) {
+        for idx in 0..self.ids.len() {
+            if self.disputed[idx] {
+                f(self.ids[idx], self.amounts[idx]);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct PooledDeposit {
+    id: TransactionId,
+    is_disputed: bool,
+    amount: Amount,
+}
+
+// Smallest buffer handed out, to skip the tiniest reallocations.
+const MIN_POOLED_CAPACITY: usize = 4;
+
+thread_local! {
+    // Per thread, so taking and returning buffers is lock free. A buffer may be returned on another
+    // thread than it was taken on, which only moves it between pools.
+    static DEPOSIT_BUFFERS: RefCell<SizeClassPool<PooledDeposit>> =
+        const { RefCell::new(SizeClassPool::new(64 << 20)) };
+}
+
+/// Deposits stored in TXID order like [`SortedVecDeposits`], in buffers taken from a pool shared by
+/// all accounts of the thread.
+///
+/// On runs creating tens of millions of deposits across many accounts, every growing account would
+/// otherwise reallocate its own `Vec` repeatedly. Here outgrown buffers go back to the pool and are
+/// reused by accounts that grow into their size, and all capacities are powers of two, reducing
+/// allocator pressure and fragmentation.
+#[derive(Default)]
+pub struct PooledDeposits {
+    deposits: Vec<PooledDeposit>,
+}
+
+impl PooledDeposits {
+    /// Move the deposits into a pooled buffer of at least `capacity`, returning the old one.
+    fn move_to_buffer(&mut self, capacity: usize) {
+        DEPOSIT_BUFFERS.with_borrow_mut(|pool| {
+            let mut buf = pool.take(capacity.max(MIN_POOLED_CAPACITY));
+            buf.extend_from_slice(&self.deposits);
+            pool.give(std::mem::replace(&mut self.deposits, buf));
+        });
+    }
+}
+
+impl Drop for PooledDeposits {
+    fn drop(&mut self) {
+        let deposits = std::mem::take(&mut self.deposits);
+        // The pool may already be gone if the thread is exiting.
+        let _ = DEPOSIT_BUFFERS.try_with(|pool| pool.borrow_mut().give(deposits));
+    }
+}
+
+impl DepositStore for PooledDeposits {
+    type Key = usize;
+
+    fn find(&self, id: TransactionId) -> Option<usize> {
+        self.deposits.binary_search_by_key(&id, |d| d.id).ok()
+    }
+
+    fn amount(&self, key: usize) -> Amount {
+        self.deposits[key].amount
+    }
+
+    fn is_disputed(&self, key: usize) -> bool {
+        self.deposits[key].is_disputed
+    }
+
+    fn set_disputed(&mut self, key: usize, disputed: bool) {
+        self.deposits[key].is_disputed = disputed;
+    }
+
+    fn insert(&mut self, id: TransactionId, amount: Amount) -> bool {
+        let insert_at = match self.deposits.binary_search_by_key(&id, |d| d.id) {
+            Ok(_) => return false,
+            Err(insert_at) => insert_at,
+        };
+        if self.deposits.len() == self.deposits.capacity() {
+            self.move_to_buffer(self.deposits.len() * 2);
+        }
+        self.deposits.insert(
+            insert_at,
+            PooledDeposit {
+                id,
+                is_disputed: false,
+                amount,
+            },
+        );
+        true
+    }
+
+    fn remove(&mut self, id: TransactionId) -> bool {
+        match self.find(id) {
+            Some(idx) => {
+                self.deposits.remove(idx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.deposits.len()
+    }
+
+    fn drain_undisputed_if(
+        &mut self,
+        mut pred: impl FnMut(TransactionId) -> bool,
+        mut f: impl FnMut(TransactionId, Amount),
+    ) {
+        self.deposits.retain(|d| {
+            let drain = !d.is_disputed && pred(d.id);
+            if drain {
+                f(d.id, d.amount);
+            }
+            !drain
+        });
+        // Give most of the memory back after large drains, e.g. spilling or eviction.
+        if self.deposits.len() * 4 <= self.deposits.capacity()
+            && self.deposits.capacity() > MIN_POOLED_CAPACITY
+        {
+            self.move_to_buffer(self.deposits.len());
+        }
+    }
+
+    fn for_each_disputed(&self, mut f: impl FnMut(TransactionId, Amount)) {
+        for d in self.deposits.iter().filter(|d| d.is_disputed) {
+            f(d.id, d.amount);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        amount::Amount,
+        deposits::{Bits, DepositStore, HashIndexedDeposits, PooledDeposits, SortedVecDeposits},
+    };
+
+    #[test]
+    fn test_bits_insert_remove() {
+        let mut bits = Bits::default();
+        let mut expected = Vec::new();
+        // Pseudo-random positions, crossing word boundaries in both directions.
+        for i in 0..300usize {
+            let idx = (i * 37) % (expected.len() + 1);
+            let value = i % 3 == 0;
+            bits.insert(idx, value);
+            expected.insert(idx, value);
+        }
+        for i in 0..250usize {
+            let idx = (i * 53) % expected.len();
+            bits.remove(idx);
+            expected.remove(idx);
+        }
+        assert_eq!(bits.len, expected.len());
+        assert_eq!(bits.words.len(), expected.len().div_ceil(64));
+        for (idx, value) in expected.iter().enumerate() {
+            assert_eq!(bits.get(idx), *value, "bit {idx}");
+        }
+    }
+
+    #[test]
+    fn test_sorted_vec_deposits() {
+        check_store::<SortedVecDeposits>();
+    }
+
+    #[test]
+    fn test_hash_indexed_deposits() {
+        check_store::<HashIndexedDeposits>();
+
+        // Removal moves the last deposit, its key must follow.
+        let mut store = HashIndexedDeposits::default();
+        for id in [5, 1, 3] {
+            store.insert(id, Amount::from_raw(id as u64));
+        }
+        assert!(store.remove(5));
+        assert!(!store.remove(5));
+        let k = store.find(3).unwrap();
+        assert_eq!(store.amount(k), Amount::from_raw(3));
+    }
+
+    #[test]
+    fn test_pooled_deposits() {
+        check_store::<PooledDeposits>();
+
+        // Capacity released by a grown account is reused by the next one.
+        let mut first = PooledDeposits::default();
+        for id in 0..100 {
+            first.insert(id, Amount::zero());
+        }
+        let capacity = first.deposits.capacity();
+        drop(first);
+        let mut second = PooledDeposits::default();
+        for id in 0..100 {
+            second.insert(id, Amount::zero());
+        }
+        assert_eq!(second.deposits.capacity(), capacity);
+        assert!(capacity.is_power_of_two());
+    }
+
+    #[test]
+    fn test_sorted_vec_deposits_out_of_order() {
+        let mut store = SortedVecDeposits::default();
+        // Descending ids all go through the side buffer and get merged repeatedly.
+        for id in (0..10_000).rev() {
+            assert!(store.insert(id * 2, Amount::from_raw(id as u64)));
+        }
+        assert!(store.pending.len() <= store.merge_threshold());
+        assert!(!store.pending.is_empty());
+        assert!(store.ids.is_sorted());
+        assert_eq!(store.len(), 10_000);
+        for id in (0..10_000).step_by(7) {
+            let k = store.find(id * 2).unwrap();
+            assert_eq!(store.amount(k), Amount::from_raw(id as u64));
+            assert!(store.find(id * 2 + 1).is_none());
+            assert!(!store.insert(id * 2, Amount::zero()));
+        }
+
+        // Disputes and removals work wherever a deposit currently lives.
+        let pending = store.pending.last().unwrap().0;
+        let k = store.find(pending).unwrap();
+        store.set_disputed(k, true);
+        assert!(store.is_disputed(store.find(pending).unwrap()));
+        assert!(store.remove(0));
+        assert!(store.find(0).is_none());
+
+        let mut drained = Vec::new();
+        store.drain_undisputed(|id, _| drained.push(id));
+        assert_eq!(drained.len(), 9_998);
+        assert!(drained.is_sorted());
+        assert_eq!(store.len(), 1);
+        assert!(store.is_disputed(store.find(pending).unwrap()));
+    }
+
+    fn check_store<D: DepositStore>() {
+        let mut store = D::default();
+        for id in [5, 1, 3] {
+            assert!(store.insert(id, Amount::from_raw(id as u64)));
+        }
+        assert!(!store.insert(3, Amount::zero()));
+        assert_eq!(store.len(), 3);
+        assert!(store.find(2).is_none());
+
+        let k = store.find(3).unwrap();
+        assert_eq!(store.amount(k), Amount::from_raw(3));
+        store.set_disputed(k, true);
+        assert!(store.is_disputed(store.find(3).unwrap()));
+
+        let mut drained = Vec::new();
+        store.drain_undisputed(|id, _| drained.push(id));
+        drained.sort();
+        assert_eq!(drained, vec![1, 5]);
+        assert_eq!(store.len(), 1);
+        assert!(store.find(3).is_some());
+
+        for id in [10, 11, 12] {
+            store.insert(id, Amount::from_raw(id as u64));
+        }
+        let k = store.find(11).unwrap();
+        store.set_disputed(k, true);
+        let mut drained = Vec::new();
+        store.drain_undisputed_if(|id| id < 12, |id, _| drained.push(id));
+        assert_eq!(drained, vec![10]);
+        assert_eq!(store.len(), 3);
+        assert!(store.is_disputed(store.find(11).unwrap()));
+        assert!(!store.is_disputed(store.find(12).unwrap()));
+
+        let mut disputed = Vec::new();
+        store.for_each_disputed(|id, amount| disputed.push((id, amount)));
+        disputed.sort();
+        assert_eq!(
+            disputed,
+            [(3, Amount::from_raw(3)), (11, Amount::from_raw(11))]
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+
+use crate::accounts::{AccountView, ClientId};
+
+/// A client whose balances differ between two reports, from [`diff`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AccountDiff {
+    pub client_id: ClientId,
+    /// `None` if the client isn't in the first report.
+    pub left: Option<AccountView<'static>>,
+    /// `None` if the client isn't in the second report.
+    pub right: Option<AccountView<'static>>,
+}
+
+impl AccountDiff {
+    /// Name and both values of each column that differs, if the client is in both reports.
+    pub fn changes(&self) -> Vec<(&'static str, String, String)> {
+        let (Some(left), Some(right)) = (self.left, self.right) else {
+            return Vec::new();
+        };
+        let mut changes = Vec::new();
+        for (name, l, r) in [
+            ("available", left.available, right.available),
+            ("held", left.held, right.held),
+            ("total", left.total, right.total),
+        ] {
+            if l != r {
+                changes.push((name, l.to_string(), r.to_string()));
+            }
+        }
+        if left.locked != right.locked {
+            changes.push(("locked", left.locked.to_string(), right.locked.to_string()));
+        }
+        changes
+    }
+}
+
+/// Clients whose balances differ between two reports, or that are in only one of them, by
+/// client id. Empty if the reports match, regardless of row order.
+pub fn diff(
+    left: impl IntoIterator<Item = (ClientId, AccountView<'static>)>,
+    right: impl IntoIterator<Item = (ClientId, AccountView<'static>)>,
+) -> Vec<AccountDiff> {
+    let mut accounts = BTreeMap::<ClientId, (Option<AccountView>, Option<AccountView>)>::new();
+    for (client_id, view) in left {
+        accounts.entry(client_id).or_default().0 = Some(view);
+    }
+    for (client_id, view) in right {
+        accounts.entry(client_id).or_default().1 = Some(view);
+    }
+    accounts
+        .into_iter()
+        .filter(|(_, (left, right))| left != right)
+        .map(|(client_id, (left, right))| AccountDiff {
+            client_id,
+            left,
+            right,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::AccountView,
+        amount::Amount,
+        diff::{AccountDiff, diff},
+    };
+
+    #[test]
+    fn test_diff() {
+        let view = |available: &str, held: &str, locked| {
+            let available = Amount::parse(available.as_bytes()).unwrap();
+            let held = Amount::parse(held.as_bytes()).unwrap();
+            AccountView {
+                available,
+                held,
+                total: available.checked_add(held).unwrap(),
+                locked,
+                flagged: false,
+                audit: &[],
+            }
+        };
+        let left = [
+            (3, view("1", "0", false)),
+            (1, view("2.5", "1", false)),
+            (2, view("0", "0", true)),
+        ];
+        assert!(diff(left, [left[2], left[0], left[1]]).is_empty());
+
+        let right = [
+            (1, view("2.5", "0", true)),
+            (2, view("0", "0", true)),
+            (4, view("1", "0", false)),
+        ];
+        let diffs = diff(left, right);
+        assert_eq!(
+            diffs,
+            [
+                AccountDiff {
+                    client_id: 1,
+                    left: Some(left[1].1),
+                    right: Some(right[0].1),
+                },
+                AccountDiff {
+                    client_id: 3,
+                    left: Some(left[0].1),
+                    right: None,
+                },
+                AccountDiff {
+                    client_id: 4,
+                    left: None,
+                    right: Some(right[2].1),
+                },
+            ]
+        );
+        assert_eq!(
+            diffs[0].changes(),
+            [
+                ("held", "1".into(), "0".into()),
+                ("total", "3.5".into(), "2.5".into()),
+                ("locked", "false".into(), "true".into()),
+            ]
+        );
+        assert!(diffs[1].changes().is_empty());
+    }
+}
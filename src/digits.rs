@@ -0,0 +1,223 @@
+//! Strict parsing of ASCII digits, so that e.g. `1x` isn't read as 1.
+//!
+//! Up to 8 digits are validated and converted at once with SWAR ("SIMD within a register")
+//! arithmetic on a u64, which covers ids and amounts of the usual sizes. Longer inputs fall back
+//! to `atoi`, one byte at a time.
+
+use atoi::FromRadix10Checked;
+
+const ZEROS: u64 = u64::from_le_bytes([b'0'; 8]);
+const HIGH_NIBBLES: u64 = 0xF0F0_F0F0_F0F0_F0F0;
+
+/// Load 1 to 8 bytes as a little-endian u64, first byte lowest. Missing bytes are 0.
+fn load(bytes: &[u8]) -> u64 {
+    let len = bytes.len();
+    debug_assert!(len > 0);
+    // A few possibly overlapping fixed size loads instead of a variable length copy, which is
+    // much slower for such short inputs. Overlapping bytes are the same, so OR-ing them is fine.
+    if len >= 8 {
+        u64::from_le_bytes(bytes[..8].try_into().unwrap())
+    } else if len >= 4 {
+        let lo = u32::from_le_bytes(bytes[..4].try_into().unwrap()) as u64;
+        let hi = u32::from_le_bytes(bytes[len - 4..].try_into().unwrap()) as u64;
+        lo | hi << (8 * (len - 4))
+    } else {
+        bytes[0] as u64
+            | (bytes[len / 2] as u64) << (8 * (len / 2))
+            | (bytes[len - 1] as u64) << (8 * (len - 1))
+    }
+}
+
+/// Mask of the lowest `len` (1 to 8) bytes.
+fn mask(len: usize) -> u64 {
+    u64::MAX >> (64 - 8 * len)
+}
+
+/// The high nibble of every byte that isn't an ASCII digit, set. Bytes from the lowest up to the
+/// first such byte are always right; carries may flag the ones above it.
+fn non_digits(chunk: u64) -> u64 {
+    // Digits become 0 to 9 once XOR-ed with '0': high nibble clear, even after adding 6.
+    let x = chunk ^ ZEROS;
+    (x | x.wrapping_add(0x0606_0606_0606_0606)) & HIGH_NIBBLES
+}
+
+/// Value of `len` (1 to 8) digits in the lowest bytes of `chunk`, first digit lowest. Bytes above
+/// `len` are ignored.
+fn convert(chunk: u64, len: usize) -> u64 {
+    // Digits moved to the top, so the empty bytes below are leading zeros.
+    let v = ((chunk ^ ZEROS) & mask(len)) << (8 * (8 - len));
+    // Combine adjacent digits, then adjacent pairs, then adjacent quads.
+    let v = (v * 10 + (v >> 8)) & 0x00FF_00FF_00FF_00FF;
+    let v = (v * 100 + (v >> 16)) & 0x0000_FFFF_0000_FFFF;
+    (v * 10_000 + (v >> 32)) & 0xFFFF_FFFF
+}
+
+/// Parse 1 to 8 ASCII digits.
+fn parse_short(bytes: &[u8]) -> Option<u64> {
+    let chunk = load(bytes);
+    (non_digits(chunk) & mask(bytes.len()) == 0).then(|| convert(chunk, bytes.len()))
+}
+
+/// Parse a non-empty string consisting only of ASCII digits with `atoi`. Returns None on any
+/// other byte (including signs or whitespace) or on overflow.
+fn parse_fallback<T: FromRadix10Checked>(bytes: &[u8]) -> Option<T> {
+    match T::from_radix_10_checked(bytes) {
+        (value, used) if used > 0 && used == bytes.len() => value,
+        _ => None,
+    }
+}
+
+/// Same as [`parse_fallback`], with SWAR for up to 8 digits.
+fn parse<T: FromRadix10Checked + TryFrom<u64>>(bytes: &[u8]) -> Option<T> {
+    match bytes.len() {
+        0 => None,
+        1..=8 => parse_short(bytes)?.try_into().ok(),
+        _ => parse_fallback(bytes),
+    }
+}
+
+pub(crate) fn parse_u64(bytes: &[u8]) -> Option<u64> {
+    parse(bytes)
+}
+
+pub(crate) fn parse_u32(bytes: &[u8]) -> Option<u32> {
+    parse(bytes)
+}
+
+pub(crate) fn parse_u16(bytes: &[u8]) -> Option<u16> {
+    parse(bytes)
+}
+
+/// Parse `whole` or `whole.fraction` with 1 to 8 whole digits and at most `places` (up to 8)
+/// fraction digits, as a number of 10^-places units, e.g. `1.5` with 4 places is 15000. None if
+/// the input isn't of that shape, which leaves it to a general parser to accept or not.
+pub(crate) fn parse_decimal(bytes: &[u8], places: usize) -> Option<u64> {
+    debug_assert!((1..=8).contains(&places));
+    if bytes.is_empty() {
+        return None;
+    }
+    let head = load(bytes);
+    let whole_len = (non_digits(head).trailing_zeros() / 8) as usize;
+    if whole_len == 0 {
+        return None;
+    }
+    let scale = 10u64.pow(places as u32);
+    let whole = convert(head, whole_len) * scale;
+    if whole_len == bytes.len() {
+        return Some(whole);
+    }
+    // Also catches a 9th whole digit.
+    if bytes[whole_len] != b'.' {
+        return None;
+    }
+    let fraction = &bytes[whole_len + 1..];
+    if fraction.is_empty() {
+        return Some(whole);
+    }
+    if fraction.len() > places {
+        return None;
+    }
+    let chunk = load(fraction);
+    if non_digits(chunk) & mask(fraction.len()) != 0 {
+        return None;
+    }
+    // Missing digits up to `places` are trailing zeros.
+    let padded = chunk & mask(fraction.len()) | ZEROS & !mask(fraction.len());
+    Some(whole + convert(padded, places))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::digits::{parse_decimal, parse_fallback, parse_u16, parse_u32, parse_u64};
+
+    #[test]
+    fn test_parse_ints() {
+        for v in [
+            0u64,
+            1,
+            9,
+            10,
+            12345678,
+            123456789,
+            u32::MAX as u64,
+            u64::MAX,
+        ] {
+            assert_eq!(parse_u64(v.to_string().as_bytes()), Some(v));
+        }
+        assert_eq!(parse_u64(b"00000000000000000000000042"), Some(42));
+        assert_eq!(parse_u64(b"18446744073709551616"), None);
+        assert_eq!(parse_u32(b"4294967296"), None);
+        assert_eq!(parse_u16(b"65535"), Some(65535));
+        assert_eq!(parse_u16(b"65536"), None);
+
+        for invalid in [
+            &b""[..],
+            b"+1",
+            b"-1",
+            b"1x",
+            b"x1",
+            b" 1",
+            b"1234567/",
+            b"1234567:",
+            b"123456789a",
+        ] {
+            assert_eq!(
+                parse_u64(invalid),
+                None,
+                "{:?}",
+                std::str::from_utf8(invalid)
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_matches_fallback() {
+        for len in 1..=12 {
+            let digits = &b"987654321098"[..len];
+            assert_eq!(parse_u64(digits), parse_fallback(digits));
+            // Every single byte replaced by one that isn't a digit, or by a digit.
+            for i in 0..len {
+                for b in [b'0', b'9', b'/', b':', b' ', b'.', b'a', 0x80, 0xFF] {
+                    let mut input = digits.to_vec();
+                    input[i] = b;
+                    assert_eq!(
+                        parse_u64(&input),
+                        parse_fallback::<u64>(&input),
+                        "{input:?}"
+                    );
+                    assert_eq!(
+                        parse_u16(&input),
+                        parse_fallback::<u16>(&input),
+                        "{input:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_decimal() {
+        assert_eq!(parse_decimal(b"0", 4), Some(0));
+        assert_eq!(parse_decimal(b"12", 4), Some(120000));
+        assert_eq!(parse_decimal(b"12.", 4), Some(120000));
+        assert_eq!(parse_decimal(b"12.5", 4), Some(125000));
+        assert_eq!(parse_decimal(b"12.0005", 4), Some(120005));
+        assert_eq!(parse_decimal(b"98765432.1234", 4), Some(987654321234));
+        assert_eq!(parse_decimal(b"1.12345678", 8), Some(112345678));
+
+        // Not of the shape handled here, valid or not.
+        for input in [
+            &b""[..],
+            b".5",
+            b"123456789",
+            b"123456789.5",
+            b"1.12345",
+            b"1x",
+            b"1.x",
+            b"1.2.3",
+            b"-1",
+        ] {
+            assert_eq!(parse_decimal(input, 4), None, "{input:?}");
+        }
+    }
+}
@@ -0,0 +1,264 @@
+//! The whole pipeline of `payengine process` behind a few calls, for embedding it:
+//!
+//! ```no_run
+//! use payengine::{config::Config, engine::Engine};
+//!
+//! # fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut engine = Engine::new(Config::load(None)?);
+//! engine.ingest_file("transactions.csv")?;
+//! engine.report_to(std::io::stdout())?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use tracing::trace;
+
+use crate::{
+    Error,
+    config::Config,
+    context::ContextualError,
+    error::{ErrorKind, ParseError},
+    metrics::Metrics,
+    pipeline::{ParseFailure, RowStream},
+    process::{ErrorPolicy, ProcessReport, RejectionCounts},
+    sharded::ShardedDatabase,
+};
+
+/// A database set up from a [`Config`], applying CSV inputs one after the other and writing the
+/// balances report as configured by its `output` section.
+pub struct Engine {
+    config: Config,
+    db: ShardedDatabase,
+    metrics: Metrics,
+    malformed: RejectionCounts,
+}
+
+/// Outcome of one input.
+#[derive(Debug)]
+pub struct Ingested {
+    /// Of the transactions parsed, with dead letters of [`ErrorPolicy::DeadLetter`] policies.
+    pub report: ProcessReport,
+    /// Rows skipped as malformed, by kind of error.
+    pub malformed: RejectionCounts,
+}
+
+impl Engine {
+    pub fn new(config: Config) -> Self {
+        Engine {
+            db: config.database(),
+            config,
+            metrics: Metrics::default(),
+            malformed: RejectionCounts::default(),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn database(&self) -> &ShardedDatabase {
+        &self.db
+    }
+
+    /// Counters of all inputs so far. Malformed rows aren't counted, see [`Self::malformed`].
+    pub fn metrics(&self) -> Metrics {
+        self.metrics
+    }
+
+    /// Rows of all inputs so far skipped as malformed, by kind of error.
+    pub fn malformed(&self) -> &RejectionCounts {
+        &self.malformed
+    }
+
+    /// Apply the transactions of a CSV file, see [`Self::ingest_reader`]. Errors have the path.
+    pub fn ingest_file(&mut self, path: impl AsRef<Path>) -> Result<Ingested, ContextualError> {
+        let path = path.as_ref();
+        let in_file = |mut e: ContextualError| {
+            e.source_file = Some(path.to_owned());
+            e
+        };
+        let file = File::open(path)
+            .map_err(|e| in_file(ContextualError::new(Error::Parse(ParseError::Io(e)))))?;
+        self.ingest_reader(file).map_err(in_file)
+    }
+
+    /// Apply the transactions of CSV input, with a header line, as `payengine process` does.
+    ///
+    /// Malformed rows are skipped, unless the parser is `strict` or its policy `Abort`, in which
+    /// case the first one is returned with its line. A rejection whose policy is `Abort` is
+    /// returned likewise. Either way, transactions before it stay applied. Read errors are
+    /// always returned.
+    pub fn ingest_reader(
+        &mut self,
+        reader: impl Read + Send + 'static,
+    ) -> Result<Ingested, ContextualError> {
+        let _span = tracing::info_span!("ingest").entered();
+        let core = self.config.threads.parser_core();
+        #[cfg(feature = "rayon")]
+        let rows = RowStream::spawn_parallel_pinned(reader, core);
+        #[cfg(not(feature = "rayon"))]
+        let rows = RowStream::spawn_pinned(io::BufReader::new(reader), core);
+
+        let strict = self.config.parser.strict || self.config.parser.on_error == ErrorPolicy::Abort;
+        let verbose = self.config.parser.verbose;
+        let mut malformed = RejectionCounts::default();
+        let mut fatal = None;
+        let rows = rows
+            .map_while(|row| match row {
+                Ok(row) => Some(Some((row.client_id, row.transaction))),
+                Err(f) if strict || f.error.kind() == ErrorKind::CsvIo => {
+                    fatal = Some(f);
+                    None
+                }
+                Err(f) => {
+                    if verbose {
+                        trace!(
+                            line = f.line,
+                            code = f.error.code(),
+                            "error parsing line: {}",
+                            f.error
+                        );
+                    }
+                    malformed.add(f.error.kind());
+                    Some(None)
+                }
+            })
+            .flatten();
+        let mut report = self.db.process_parallel(rows);
+
+        self.metrics.merge(&report.metrics());
+        self.malformed.merge(&malformed);
+        if let Some(ParseFailure { line, error, .. }) = fatal {
+            return Err(ContextualError {
+                line_no: Some(line),
+                ..ContextualError::new(error.into())
+            });
+        }
+        if let Some((_, error)) = report.aborted.take() {
+            return Err(ContextualError::new(error));
+        }
+        Ok(Ingested { report, malformed })
+    }
+
+    /// Write the balances of all accounts in the configured format, with the configured
+    /// precision, order and clients. `output.path` is left to the caller.
+    pub fn report_to(&self, w: impl Write) -> io::Result<()> {
+        let output = &self.config.output;
+        let flagged = self.db.review().is_enabled();
+        let accounts = self
+            .db
+            .iter()
+            .map(|(client_id, account)| (client_id, account.view()))
+            .filter(|(client_id, _)| {
+                output
+                    .clients
+                    .as_ref()
+                    .is_none_or(|clients| clients.contains(client_id))
+            });
+        if output.sort {
+            let mut accounts = accounts.collect::<Vec<_>>();
+            accounts.sort_unstable_by_key(|(client_id, _)| *client_id);
+            output
+                .format
+                .write_with(w, accounts, output.precision, flagged)
+        } else {
+            output
+                .format
+                .write_with(w, accounts, output.precision, flagged)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::Config,
+        engine::Engine,
+        error::{ErrorKind, LedgerError},
+        process::ErrorPolicy,
+        report::ReportFormat,
+    };
+
+    const INPUT: &str = "type,client,tx,amount
+deposit,2,1,5
+deposit,1,2,3
+withdrawal,1,3,4
+nonsense,1,4,1
+withdrawal,2,5,1.5
+";
+
+    #[test]
+    fn test_engine() {
+        let mut config = Config::default();
+        config.output.sort = true;
+        let mut engine = Engine::new(config);
+        let ingested = engine.ingest_reader(INPUT.as_bytes()).unwrap();
+        assert_eq!(ingested.report.applied, 3);
+        assert_eq!(ingested.report.counts.get(ErrorKind::WithdrawOverflow), 1);
+        assert_eq!(
+            ingested.malformed.get(ErrorKind::CsvUnknownTransactionType),
+            1
+        );
+
+        // Inputs add up.
+        engine
+            .ingest_reader(&b"type,client,tx,amount\ndeposit,3,6,1\n"[..])
+            .unwrap();
+        assert_eq!(engine.metrics().rows, 5);
+        assert_eq!(engine.malformed().total(), 1);
+
+        let mut out = Vec::new();
+        engine.report_to(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client, available, held, total, locked
+1,3,0,3,false
+2,3.5,0,3.5,false
+3,1,0,1,false
+"
+        );
+
+        let mut config = Config::default();
+        config.output.format = ReportFormat::Json;
+        config.output.clients = Some([3].into());
+        let mut engine = Engine::new(config);
+        engine.ingest_reader(INPUT.as_bytes()).unwrap();
+        let mut out = Vec::new();
+        engine.report_to(&mut out).unwrap();
+        assert_eq!(out, b"[]\n");
+    }
+
+    #[test]
+    fn test_engine_errors() {
+        let mut config = Config::default();
+        config.parser.strict = true;
+        let e = Engine::new(config)
+            .ingest_reader(INPUT.as_bytes())
+            .unwrap_err();
+        assert_eq!(e.line_no, Some(5));
+        assert_eq!(e.error.kind(), ErrorKind::CsvUnknownTransactionType);
+
+        let mut config = Config::default();
+        config
+            .parser
+            .policies
+            .insert(ErrorKind::WithdrawOverflow, ErrorPolicy::Abort);
+        let mut engine = Engine::new(config);
+        let e = engine.ingest_reader(INPUT.as_bytes()).unwrap_err();
+        assert!(matches!(
+            e.error,
+            crate::Error::Ledger(LedgerError::WithdrawOverflow)
+        ));
+        assert_eq!(engine.metrics().applied.total(), 2);
+
+        let e = engine.ingest_file("/nonexistent.csv").unwrap_err();
+        assert_eq!(e.error.kind(), ErrorKind::CsvIo);
+        assert!(e.to_string().starts_with("/nonexistent.csv"));
+    }
+}
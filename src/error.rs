@@ -1,5 +1,44 @@
+/// Any error of this crate: malformed input, a transaction the ledger can't apply, or invalid
+/// settings. Match on the class to handle them differently, e.g. retry only [`LedgerError`]s.
 #[derive(thiserror::Error, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Error {
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error(transparent)]
+    Ledger(#[from] LedgerError),
+    #[error("invalid config: {0}")]
+    Config(String),
+}
+
+/// Input that couldn't be read or parsed into a transaction or balances row.
+#[derive(thiserror::Error, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ParseError {
+    #[error("CSV missing an expected column")]
+    MissingColumn,
+    #[error("unknown transaction type")]
+    UnknownTransactionType,
+    #[error("invalid client id")]
+    InvalidClientId,
+    #[error("invalid transaction id")]
+    InvalidTxId,
+    #[error("invalid amount")]
+    InvalidAmount,
+    #[error("expected amount to be empty for this transaction type")]
+    UnexpectedAmount,
+    #[error("invalid locked flag, expected true or false")]
+    InvalidLocked,
+    #[error("available doesn't match total and held")]
+    InconsistentBalances,
+    #[error("error reading CSV: {0}")]
+    Io(#[cfg_attr(feature = "serde", serde(with = "io_error_as_string"))] std::io::Error),
+}
+
+/// A well-formed transaction the ledger rejected, leaving balances unchanged.
+#[derive(thiserror::Error, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LedgerError {
     #[error("deposit overflowed - too much money in the account")]
     DepositOverflow,
     #[error("duplicate transaction id")]
@@ -20,17 +59,239 @@ pub enum Error {
     AccountFrozen,
     #[error("account not found")]
     AccountNotFound,
+    #[error("account already exists")]
+    AccountExists,
+    #[error("too many transactions of the client, retry later")]
+    RateLimited,
+    #[error("error accessing spilled deposits: {0}")]
+    SpillIo(#[cfg_attr(feature = "serde", serde(with = "io_error_as_string"))] std::io::Error),
+}
 
-    #[error("CSV missing an expected column")]
+/// Fieldless counterpart of [`Error`], e.g. for counting rejections by reason without keeping
+/// the errors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ErrorKind {
+    DepositOverflow,
+    DuplicateTransactionId,
+    WithdrawOverflow,
+    TransactionNotFound,
+    DuplicateDispute,
+    ResolveNotDisputed,
+    ChargebackNotDisputed,
+    HeldOverflow,
+    AccountFrozen,
+    AccountNotFound,
+    AccountExists,
+    RateLimited,
+    SpillIo,
     CsvMissingColumn,
-    #[error("unknown transaction type")]
     CsvUnknownTransactionType,
-    #[error("invalid client id")]
     CsvInvalidClientId,
-    #[error("invalid transaction id")]
     CsvInvalidTxId,
-    #[error("invalid amount")]
     CsvInvalidAmount,
-    #[error("expected amount to be empty for this transaction type")]
     CsvUnexpectedAmount,
+    CsvInvalidLocked,
+    CsvInconsistentBalances,
+    CsvIo,
+    Config,
+}
+
+impl ErrorKind {
+    pub const ALL: [ErrorKind; 23] = [
+        ErrorKind::DepositOverflow,
+        ErrorKind::DuplicateTransactionId,
+        ErrorKind::WithdrawOverflow,
+        ErrorKind::TransactionNotFound,
+        ErrorKind::DuplicateDispute,
+        ErrorKind::ResolveNotDisputed,
+        ErrorKind::ChargebackNotDisputed,
+        ErrorKind::HeldOverflow,
+        ErrorKind::AccountFrozen,
+        ErrorKind::AccountNotFound,
+        ErrorKind::AccountExists,
+        ErrorKind::RateLimited,
+        ErrorKind::SpillIo,
+        ErrorKind::CsvMissingColumn,
+        ErrorKind::CsvUnknownTransactionType,
+        ErrorKind::CsvInvalidClientId,
+        ErrorKind::CsvInvalidTxId,
+        ErrorKind::CsvInvalidAmount,
+        ErrorKind::CsvUnexpectedAmount,
+        ErrorKind::CsvInvalidLocked,
+        ErrorKind::CsvInconsistentBalances,
+        ErrorKind::CsvIo,
+        ErrorKind::Config,
+    ];
+
+    /// Stable reason code, e.g. `E_ACCOUNT_FROZEN`, for other systems to branch on rather than
+    /// the message. Codes are never reused or changed once released.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::DepositOverflow => "E_DEPOSIT_OVERFLOW",
+            ErrorKind::DuplicateTransactionId => "E_DUP_TX",
+            ErrorKind::WithdrawOverflow => "E_INSUFFICIENT_FUNDS",
+            ErrorKind::TransactionNotFound => "E_TX_NOT_FOUND",
+            ErrorKind::DuplicateDispute => "E_DUP_DISPUTE",
+            ErrorKind::ResolveNotDisputed => "E_RESOLVE_NOT_DISPUTED",
+            ErrorKind::ChargebackNotDisputed => "E_CHARGEBACK_NOT_DISPUTED",
+            ErrorKind::HeldOverflow => "E_HELD_OVERFLOW",
+            ErrorKind::AccountFrozen => "E_ACCOUNT_FROZEN",
+            ErrorKind::AccountNotFound => "E_ACCOUNT_NOT_FOUND",
+            ErrorKind::AccountExists => "E_ACCOUNT_EXISTS",
+            ErrorKind::RateLimited => "E_RATE_LIMITED",
+            ErrorKind::SpillIo => "E_SPILL_IO",
+            ErrorKind::CsvMissingColumn => "E_MISSING_COLUMN",
+            ErrorKind::CsvUnknownTransactionType => "E_UNKNOWN_TYPE",
+            ErrorKind::CsvInvalidClientId => "E_INVALID_CLIENT",
+            ErrorKind::CsvInvalidTxId => "E_INVALID_TX",
+            ErrorKind::CsvInvalidAmount => "E_INVALID_AMOUNT",
+            ErrorKind::CsvUnexpectedAmount => "E_UNEXPECTED_AMOUNT",
+            ErrorKind::CsvInvalidLocked => "E_INVALID_LOCKED",
+            ErrorKind::CsvInconsistentBalances => "E_INCONSISTENT_BALANCES",
+            ErrorKind::CsvIo => "E_READ_IO",
+            ErrorKind::Config => "E_CONFIG",
+        }
+    }
+
+    /// Whether the same transaction may succeed if submitted again later, as it was only turned
+    /// away for the moment, e.g. with [`ErrorKind::RateLimited`].
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, ErrorKind::RateLimited)
+    }
+
+    /// Numeric form of [`Self::code`], as stable: 1xx for rejected transactions, 2xx for
+    /// malformed input and 3xx for IO and settings.
+    pub fn number(&self) -> u16 {
+        match self {
+            ErrorKind::DepositOverflow => 101,
+            ErrorKind::DuplicateTransactionId => 102,
+            ErrorKind::WithdrawOverflow => 103,
+            ErrorKind::TransactionNotFound => 104,
+            ErrorKind::DuplicateDispute => 105,
+            ErrorKind::ResolveNotDisputed => 106,
+            ErrorKind::ChargebackNotDisputed => 107,
+            ErrorKind::HeldOverflow => 108,
+            ErrorKind::AccountFrozen => 109,
+            ErrorKind::AccountNotFound => 110,
+            ErrorKind::AccountExists => 111,
+            ErrorKind::RateLimited => 112,
+            ErrorKind::CsvMissingColumn => 201,
+            ErrorKind::CsvUnknownTransactionType => 202,
+            ErrorKind::CsvInvalidClientId => 203,
+            ErrorKind::CsvInvalidTxId => 204,
+            ErrorKind::CsvInvalidAmount => 205,
+            ErrorKind::CsvUnexpectedAmount => 206,
+            ErrorKind::CsvInvalidLocked => 207,
+            ErrorKind::CsvInconsistentBalances => 208,
+            ErrorKind::SpillIo => 301,
+            ErrorKind::CsvIo => 302,
+            ErrorKind::Config => 303,
+        }
+    }
+}
+
+impl std::str::FromStr for ErrorKind {
+    type Err = String;
+
+    /// By variant name, as in metrics, e.g. `DuplicateTransactionId`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| format!("{kind:?}") == s)
+            .ok_or_else(|| format!("unknown error kind {s:?}"))
+    }
+}
+
+impl Error {
+    /// See [`ErrorKind::code`].
+    pub fn code(&self) -> &'static str {
+        self.kind().code()
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Parse(e) => e.kind(),
+            Error::Ledger(e) => e.kind(),
+            Error::Config(_) => ErrorKind::Config,
+        }
+    }
+}
+
+impl ParseError {
+    /// See [`ErrorKind::code`].
+    pub fn code(&self) -> &'static str {
+        self.kind().code()
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ParseError::MissingColumn => ErrorKind::CsvMissingColumn,
+            ParseError::UnknownTransactionType => ErrorKind::CsvUnknownTransactionType,
+            ParseError::InvalidClientId => ErrorKind::CsvInvalidClientId,
+            ParseError::InvalidTxId => ErrorKind::CsvInvalidTxId,
+            ParseError::InvalidAmount => ErrorKind::CsvInvalidAmount,
+            ParseError::UnexpectedAmount => ErrorKind::CsvUnexpectedAmount,
+            ParseError::InvalidLocked => ErrorKind::CsvInvalidLocked,
+            ParseError::InconsistentBalances => ErrorKind::CsvInconsistentBalances,
+            ParseError::Io(_) => ErrorKind::CsvIo,
+        }
+    }
+}
+
+impl LedgerError {
+    /// See [`ErrorKind::code`].
+    pub fn code(&self) -> &'static str {
+        self.kind().code()
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            LedgerError::DepositOverflow => ErrorKind::DepositOverflow,
+            LedgerError::DuplicateTransactionId => ErrorKind::DuplicateTransactionId,
+            LedgerError::WithdrawOverflow => ErrorKind::WithdrawOverflow,
+            LedgerError::TransactionNotFound => ErrorKind::TransactionNotFound,
+            LedgerError::DuplicateDispute => ErrorKind::DuplicateDispute,
+            LedgerError::ResolveNotDisputed => ErrorKind::ResolveNotDisputed,
+            LedgerError::ChargebackNotDisputed => ErrorKind::ChargebackNotDisputed,
+            LedgerError::HeldOverflow => ErrorKind::HeldOverflow,
+            LedgerError::AccountFrozen => ErrorKind::AccountFrozen,
+            LedgerError::AccountNotFound => ErrorKind::AccountNotFound,
+            LedgerError::AccountExists => ErrorKind::AccountExists,
+            LedgerError::RateLimited => ErrorKind::RateLimited,
+            LedgerError::SpillIo(_) => ErrorKind::SpillIo,
+        }
+    }
+}
+
+/// IO errors can't be reconstructed faithfully, so they round-trip through their message.
+#[cfg(feature = "serde")]
+mod io_error_as_string {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(e: &std::io::Error, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(e)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<std::io::Error, D::Error> {
+        Ok(std::io::Error::other(String::deserialize(d)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn test_codes_are_unique() {
+        let codes = ErrorKind::ALL.map(|k| k.code());
+        assert_eq!(codes.iter().collect::<HashSet<_>>().len(), codes.len());
+        assert!(codes.iter().all(|c| c.starts_with("E_")));
+        let numbers = ErrorKind::ALL.map(|k| k.number());
+        assert_eq!(numbers.iter().collect::<HashSet<_>>().len(), numbers.len());
+        assert_eq!(ErrorKind::AccountFrozen.code(), "E_ACCOUNT_FROZEN");
+        assert_eq!(ErrorKind::DuplicateTransactionId.number(), 102);
+    }
 }
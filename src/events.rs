@@ -0,0 +1,135 @@
+use std::{
+    fmt::Write,
+    sync::mpsc::{Receiver, Sender, channel},
+};
+
+use crate::{
+    accounts::{AccountView, ClientId, Transaction},
+    history::Timestamp,
+};
+
+/// A transaction successfully applied to the database, with the resulting balances.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AccountEvent {
+    pub at: Timestamp,
+    pub client_id: ClientId,
+    pub transaction: Transaction,
+    pub balances: AccountView<'static>,
+}
+
+impl AccountEvent {
+    /// One JSON object, e.g. `{"at":3,"client":7,"type":"deposit","tx":9,"amount":"2.5",
+    /// "available":"2.5","held":"0","total":"2.5","locked":false}`.
+    pub fn to_json(&self) -> String {
+        let (t, b) = (self.transaction, self.balances);
+        let mut out = format!(
+            "{{\"at\":{},\"client\":{},\"type\":\"{}\",\"tx\":{}",
+            self.at,
+            self.client_id,
+            t.kind.as_str(),
+            t.id
+        );
+        if t.kind.has_amount() {
+            write!(out, ",\"amount\":\"{}\"", t.amount).unwrap();
+        }
+        write!(
+            out,
+            ",\"available\":\"{}\",\"held\":\"{}\",\"total\":\"{}\",\"locked\":{}}}",
+            b.available, b.held, b.total, b.locked
+        )
+        .unwrap();
+        out
+    }
+}
+
+/// Fan-out of events to all subscribers.
+///
+/// Channels are unbounded so a slow subscriber never stalls processing; it's up to the subscriber
+/// to keep up. Subscribers that dropped their receiver are removed on the next event.
+#[derive(Default)]
+pub(crate) struct Subscribers {
+    senders: Vec<Sender<AccountEvent>>,
+}
+
+impl Subscribers {
+    pub(crate) fn subscribe(&mut self) -> Receiver<AccountEvent> {
+        let (tx, rx) = channel();
+        self.senders.push(tx);
+        rx
+    }
+
+    pub(crate) fn add(&mut self, tx: Sender<AccountEvent>) {
+        self.senders.push(tx);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.senders.is_empty()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.senders.clear();
+    }
+
+    pub(crate) fn send(&mut self, event: AccountEvent) {
+        self.senders.retain(|s| s.send(event).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::{ClientsDatabase, Transaction, TransactionKind::*},
+        amount::Amount,
+    };
+
+    #[test]
+    fn test_subscribe() {
+        let mut db = ClientsDatabase::new();
+        let rx = db.subscribe();
+        let deposit = Transaction {
+            kind: Deposit,
+            id: 1,
+            amount: Amount::parse(b"2").unwrap(),
+        };
+        db.process_transaction(1, deposit).unwrap();
+        // Rejected, no event.
+        db.process_transaction(1, deposit).unwrap_err();
+
+        let events = rx.try_iter().collect::<Vec<_>>();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].client_id, 1);
+        assert_eq!(events[0].transaction, deposit);
+        assert_eq!(events[0].balances.available, Amount::parse(b"2").unwrap());
+        assert_eq!(
+            events[0].to_json(),
+            r#"{"at":0,"client":1,"type":"deposit","tx":1,"amount":"2","available":"2","held":"0","total":"2","locked":false}"#
+        );
+
+        // Dropped receivers don't break processing.
+        drop(rx);
+        db.process_transaction(
+            1,
+            Transaction {
+                kind: Deposit,
+                id: 2,
+                amount: Amount::zero(),
+            },
+        )
+        .unwrap();
+
+        // Receiving ends once unsubscribed, after the events sent so far.
+        let rx = db.subscribe();
+        db.process_transaction(
+            1,
+            Transaction {
+                kind: Withdrawal,
+                id: 3,
+                amount: Amount::zero(),
+            },
+        )
+        .unwrap();
+        db.unsubscribe_all();
+        assert_eq!(rx.iter().count(), 1);
+    }
+}
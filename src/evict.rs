@@ -0,0 +1,127 @@
+use crate::accounts::{ClientId, Transaction, TransactionId, TransactionKind};
+
+/// Bookkeeping for evicting settled deposits from memory in streaming mode.
+///
+/// Deposit ids are used as their age: once a deposit id is more than `horizon` below the highest
+/// deposit id seen, the deposit is considered too old to be disputed. This assumes ids mostly
+/// increase over the stream, which live sources generally guarantee.
+pub(crate) struct Eviction {
+    horizon: TransactionId,
+    evict_resolved: bool,
+    max_deposit_id: Option<TransactionId>,
+    // Deposits applied since the last pass over all accounts.
+    since_pass: usize,
+    // Resolved deposits to evict before the next transaction, if still undisputed then.
+    resolved: Vec<(ClientId, TransactionId)>,
+    evicted: usize,
+}
+
+impl Eviction {
+    pub(crate) fn new(horizon: TransactionId, evict_resolved: bool) -> Self {
+        Eviction {
+            horizon,
+            evict_resolved,
+            max_deposit_id: None,
+            since_pass: 0,
+            resolved: Vec::new(),
+            evicted: 0,
+        }
+    }
+
+    pub(crate) fn note_applied(&mut self, client_id: ClientId, t: Transaction) {
+        match t.kind {
+            TransactionKind::Deposit => {
+                self.max_deposit_id = self.max_deposit_id.max(Some(t.id));
+                self.since_pass += 1;
+            }
+            TransactionKind::Resolve if self.evict_resolved => {
+                self.resolved.push((client_id, t.id));
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn take_resolved(&mut self) -> Vec<(ClientId, TransactionId)> {
+        std::mem::take(&mut self.resolved)
+    }
+
+    /// Deposit ids below the returned one are due for eviction, if it's time for a pass. Passes
+    /// run once per `horizon` deposits, so their cost is amortized over the deposits.
+    pub(crate) fn start_pass(&mut self) -> Option<TransactionId> {
+        if self.since_pass < (self.horizon as usize).max(1) {
+            return None;
+        }
+        self.since_pass = 0;
+        self.max_deposit_id?.checked_sub(self.horizon)
+    }
+
+    pub(crate) fn note_evicted(&mut self, count: usize) {
+        self.evicted += count;
+    }
+
+    pub(crate) fn evicted(&self) -> usize {
+        self.evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::{ClientsDatabase, Transaction, TransactionId, TransactionKind},
+        amount::Amount,
+        error::LedgerError,
+    };
+
+    fn t(kind: TransactionKind, id: TransactionId) -> Transaction {
+        Transaction {
+            kind,
+            id,
+            amount: Amount::parse(b"1").unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_evict_old_and_resolved_deposits() {
+        use TransactionKind::*;
+
+        let mut db = ClientsDatabase::new();
+        db.enable_eviction(10, true);
+        for id in 1..=20 {
+            db.process_transaction(1, t(Deposit, id)).unwrap();
+        }
+        db.process_transaction(1, t(Dispute, 15)).unwrap();
+        // Passes ran before deposits 11 (nothing old enough yet) and 21 (this dispute).
+        assert_eq!(db.evicted_deposits(), 9);
+        assert_eq!(db.stats().deposits, 11);
+
+        assert!(matches!(
+            db.process_transaction(1, t(Dispute, 4)).unwrap_err(),
+            LedgerError::TransactionNotFound
+        ));
+        db.process_transaction(1, t(Resolve, 15)).unwrap();
+        // Resolved deposits go on the next transaction.
+        db.process_transaction(1, t(Dispute, 16)).unwrap();
+        assert_eq!(db.evicted_deposits(), 10);
+        assert!(matches!(
+            db.process_transaction(1, t(Dispute, 15)).unwrap_err(),
+            LedgerError::TransactionNotFound
+        ));
+        // Balances never change by evicting.
+        assert_eq!(db.get(1).unwrap().total(), Amount::parse(b"20").unwrap());
+    }
+
+    #[test]
+    fn test_evicted_deposits_spill_to_disk() {
+        use TransactionKind::*;
+
+        let mut db = ClientsDatabase::with_spill(std::env::temp_dir(), 1000);
+        db.enable_eviction(10, false);
+        for id in 1..=21 {
+            db.process_transaction(1, t(Deposit, id)).unwrap();
+        }
+        assert_eq!(db.evicted_deposits(), 9);
+        assert_eq!(db.spilled_runs(), 1);
+        db.process_transaction(1, t(Dispute, 4)).unwrap();
+        assert_eq!(db.get(1).unwrap().held(), Amount::parse(b"1").unwrap());
+    }
+}
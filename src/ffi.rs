@@ -0,0 +1,290 @@
+//! C ABI of the engine, declared in `include/payengine.h`, for embedding it in-process into
+//! programs in other languages. Build it as a library with e.g. `cargo rustc --lib --release
+//! --features ffi --crate-type cdylib`.
+//!
+//! The only unsafe code of the crate: the pointers passed in can't be checked. Null pointers are
+//! refused with [`PAYENGINE_INVALID`], anything else is up to the caller as documented in the
+//! header. Panics abort the process rather than unwinding into C.
+
+use std::{
+    ffi::{CString, c_char, c_int},
+    ptr,
+};
+
+use crate::{
+    accounts::{ClientId, ClientsDatabase},
+    parser::message_rows,
+    report::ReportFormat,
+};
+
+pub const PAYENGINE_OK: c_int = 0;
+/// A blank or header line, nothing was applied.
+pub const PAYENGINE_SKIPPED: c_int = 1;
+pub const PAYENGINE_MALFORMED: c_int = 2;
+pub const PAYENGINE_REJECTED: c_int = 3;
+pub const PAYENGINE_NOT_FOUND: c_int = 4;
+/// A null pointer where one isn't allowed.
+pub const PAYENGINE_INVALID: c_int = -1;
+
+/// An engine, opaque to C.
+pub struct Engine {
+    db: ClientsDatabase,
+    // Reason code of the last line failing, NUL-terminated for `payengine_last_error`.
+    last_error: Option<CString>,
+}
+
+/// Balances of an account, amounts in ten-thousandths like [`crate::amount::Amount::to_raw`].
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PayengineAccount {
+    pub client: u16,
+    pub available: u64,
+    pub held: u64,
+    pub total: u64,
+    pub locked: bool,
+}
+
+/// A new engine without accounts, freed with [`payengine_free`].
+#[unsafe(no_mangle)]
+pub extern "C" fn payengine_new() -> *mut Engine {
+    Box::into_raw(Box::new(Engine {
+        db: ClientsDatabase::new(),
+        last_error: None,
+    }))
+}
+
+/// Free an engine created by [`payengine_new`]. Null is ignored.
+///
+/// # Safety
+///
+/// `engine` is null or was returned by [`payengine_new`] and not freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn payengine_free(engine: *mut Engine) {
+    if !engine.is_null() {
+        // SAFETY: created by `Box::into_raw` in `payengine_new`, and not freed yet.
+        drop(unsafe { Box::from_raw(engine) });
+    }
+}
+
+/// Apply a row of the CSV input, e.g. `deposit, 7, 9, 2.5`, with or without its line ending.
+/// Returns [`PAYENGINE_OK`] if applied, [`PAYENGINE_SKIPPED`] for a blank or header line, or
+/// [`PAYENGINE_MALFORMED`] or [`PAYENGINE_REJECTED`] with the reason code left for
+/// [`payengine_last_error`].
+///
+/// # Safety
+///
+/// `engine` is a live engine not used by another thread meanwhile, and `line` points to `len`
+/// readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn payengine_process_line(
+    engine: *mut Engine,
+    line: *const c_char,
+    len: usize,
+) -> c_int {
+    if engine.is_null() || (line.is_null() && len > 0) {
+        return PAYENGINE_INVALID;
+    }
+    // SAFETY: a live engine, not aliased per the contract.
+    let engine = unsafe { &mut *engine };
+    let line = if len == 0 {
+        &[]
+    } else {
+        // SAFETY: `len` readable bytes per the contract.
+        unsafe { std::slice::from_raw_parts(line.cast::<u8>(), len) }
+    };
+    engine.process_line(line)
+}
+
+/// The reason code of the last line which wasn't applied, e.g. `E_INSUFFICIENT_FUNDS`, or null if
+/// none failed yet. Valid until the next call with the engine.
+///
+/// # Safety
+///
+/// `engine` is null or a live engine.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn payengine_last_error(engine: *const Engine) -> *const c_char {
+    // SAFETY: null or a live engine per the contract.
+    match unsafe { engine.as_ref() }.and_then(|engine| engine.last_error.as_ref()) {
+        Some(code) => code.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Write the balances of `client` to `out`. Returns [`PAYENGINE_OK`], or [`PAYENGINE_NOT_FOUND`]
+/// leaving `out` as it was if the client has no account.
+///
+/// # Safety
+///
+/// `engine` is a live engine, and `out` points to a writable [`PayengineAccount`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn payengine_get_account(
+    engine: *const Engine,
+    client: u16,
+    out: *mut PayengineAccount,
+) -> c_int {
+    // SAFETY: null or a live engine per the contract.
+    let Some(engine) = (unsafe { engine.as_ref() }) else {
+        return PAYENGINE_INVALID;
+    };
+    if out.is_null() {
+        return PAYENGINE_INVALID;
+    }
+    let Some(account) = engine.account(client) else {
+        return PAYENGINE_NOT_FOUND;
+    };
+    // SAFETY: writable per the contract.
+    unsafe { out.write(account) };
+    PAYENGINE_OK
+}
+
+/// Write the report of all accounts by client id as CSV with its header, like the binary's output,
+/// into `buf` of `cap` bytes, NUL-terminated if there's room. Returns the length of the whole
+/// report without the NUL, so a `buf` too small can be retried with one of that length plus one,
+/// like `snprintf`. `buf` may be null if `cap` is 0.
+///
+/// # Safety
+///
+/// `engine` is a live engine, and `buf` points to `cap` writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn payengine_export_csv(
+    engine: *const Engine,
+    buf: *mut c_char,
+    cap: usize,
+) -> usize {
+    // SAFETY: null or a live engine per the contract.
+    let Some(engine) = (unsafe { engine.as_ref() }) else {
+        return 0;
+    };
+    let report = engine.export_csv();
+    if !buf.is_null() && cap > 0 {
+        let n = report.len().min(cap - 1);
+        // SAFETY: `cap` writable bytes per the contract, of which `n + 1` are written, and the
+        // report is a separate allocation.
+        unsafe {
+            ptr::copy_nonoverlapping(report.as_ptr(), buf.cast::<u8>(), n);
+            buf.add(n).write(0);
+        }
+    }
+    report.len()
+}
+
+impl Engine {
+    fn process_line(&mut self, line: &[u8]) -> c_int {
+        // A single line, so at most one row.
+        let Some((_, row)) = message_rows(line).next() else {
+            return PAYENGINE_SKIPPED;
+        };
+        let result = match row {
+            Ok(row) => self
+                .db
+                .process_transaction(row.client_id, row.transaction)
+                .map_err(|e| (PAYENGINE_REJECTED, e.code())),
+            Err(e) => Err((PAYENGINE_MALFORMED, e.code())),
+        };
+        match result {
+            Ok(()) => PAYENGINE_OK,
+            Err((status, code)) => {
+                // Codes are ASCII identifiers, without NUL.
+                self.last_error = CString::new(code).ok();
+                status
+            }
+        }
+    }
+
+    fn account(&self, client: ClientId) -> Option<PayengineAccount> {
+        let view = self.db.get(client)?.view();
+        Some(PayengineAccount {
+            client,
+            available: view.available.to_raw(),
+            held: view.held.to_raw(),
+            total: view.total.to_raw(),
+            locked: view.locked,
+        })
+    }
+
+    fn export_csv(&self) -> Vec<u8> {
+        let mut accounts = self
+            .db
+            .iter()
+            .map(|(client_id, account)| (client_id, account.view()))
+            .collect::<Vec<_>>();
+        accounts.sort_unstable_by_key(|(client_id, _)| *client_id);
+        let mut report = Vec::new();
+        ReportFormat::Csv
+            .write(&mut report, accounts, None)
+            .unwrap();
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::CStr, ptr};
+
+    use crate::ffi::{
+        PAYENGINE_INVALID, PAYENGINE_MALFORMED, PAYENGINE_NOT_FOUND, PAYENGINE_OK,
+        PAYENGINE_REJECTED, PAYENGINE_SKIPPED, PayengineAccount, payengine_export_csv,
+        payengine_free, payengine_get_account, payengine_last_error, payengine_new,
+        payengine_process_line,
+    };
+
+    #[test]
+    fn test_ffi() {
+        let engine = payengine_new();
+        let process = |line: &str| unsafe {
+            payengine_process_line(engine, line.as_ptr().cast(), line.len())
+        };
+        unsafe {
+            assert!(payengine_last_error(engine).is_null());
+            assert_eq!(process("type, client, tx, amount\n"), PAYENGINE_SKIPPED);
+            assert_eq!(process(""), PAYENGINE_SKIPPED);
+            assert_eq!(process("deposit, 7, 1, 2.5\r\n"), PAYENGINE_OK);
+            assert_eq!(process("withdrawal, 7, 2, 5"), PAYENGINE_REJECTED);
+            assert_eq!(
+                CStr::from_ptr(payengine_last_error(engine)),
+                c"E_INSUFFICIENT_FUNDS"
+            );
+            assert_eq!(process("refund, 7, 3, 1"), PAYENGINE_MALFORMED);
+            assert_eq!(
+                CStr::from_ptr(payengine_last_error(engine)),
+                c"E_UNKNOWN_TYPE"
+            );
+            assert_eq!(
+                payengine_process_line(ptr::null_mut(), ptr::null(), 0),
+                PAYENGINE_INVALID
+            );
+
+            let mut account = PayengineAccount::default();
+            assert_eq!(payengine_get_account(engine, 7, &mut account), PAYENGINE_OK);
+            assert_eq!(
+                account,
+                PayengineAccount {
+                    client: 7,
+                    available: 25_000,
+                    held: 0,
+                    total: 25_000,
+                    locked: false,
+                }
+            );
+            assert_eq!(
+                payengine_get_account(engine, 8, &mut account),
+                PAYENGINE_NOT_FOUND
+            );
+
+            // Too small a buffer, then one of the length needed.
+            let mut buf = [0xff_u8; 8];
+            let len = payengine_export_csv(engine, buf.as_mut_ptr().cast(), buf.len());
+            assert_eq!(CStr::from_bytes_until_nul(&buf).unwrap(), c"client,");
+            assert_eq!(payengine_export_csv(engine, ptr::null_mut(), 0), len);
+            let mut buf = vec![0xff_u8; len + 1];
+            payengine_export_csv(engine, buf.as_mut_ptr().cast(), buf.len());
+            assert_eq!(
+                CStr::from_bytes_with_nul(&buf).unwrap(),
+                c"client, available, held, total, locked\n7,2.5,0,2.5,false\n"
+            );
+
+            payengine_free(engine);
+            payengine_free(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,615 @@
+//! An [Arrow Flight](https://arrow.apache.org/docs/format/Flight.html) endpoint, for analytics
+//! clients pulling accounts and transaction history in bulk as Arrow record batches rather than
+//! JSON, e.g. with pyarrow:
+//!
+//! ```python
+//! from pyarrow import flight
+//! table = flight.connect("grpc://localhost:8815").do_get(flight.Ticket(b"accounts")).read_all()
+//! ```
+//!
+//! There are two datasets, listed by `ListFlights`, each with a path descriptor and a ticket of
+//! its name:
+//! - `accounts`: the balances of each account, a row per client.
+//! - `transactions`: every applied transaction with the balances after it, in the order applied
+//!   for each client. Only kept with audit enabled, see
+//!   [`crate::accounts::ClientsDatabase::enable_audit`], which `serve --flight-listen` does.
+//!
+//! Amounts are `decimal128(20, 4)`, so they're exact. `ListFlights`, `GetFlightInfo`,
+//! `GetSchema` and `DoGet` are served, the other methods aren't.
+
+use std::{
+    convert::Infallible,
+    future::{Ready, ready},
+    io,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use arrow_array::{
+    ArrayRef, BooleanArray, Decimal128Array, RecordBatch, StringArray, UInt16Array, UInt32Array,
+    UInt64Array,
+};
+use arrow_ipc::writer::{DictionaryTracker, EncodedData, IpcDataGenerator, IpcWriteOptions};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use futures_util::stream::{self, Stream};
+use tonic::{
+    Status,
+    codegen::{Body, BoxFuture, Service, StdError, http},
+    server::{Grpc, NamedService},
+    service::Routes,
+};
+use tonic_prost::ProstCodec;
+
+use crate::{
+    accounts::{AccountView, ClientId},
+    amount::Amount,
+    audit::AuditRecord,
+    concurrent::ConcurrentClientsDatabase,
+};
+
+/// Rows per record batch at most.
+pub const BATCH_ROWS: usize = 65536;
+
+/// Serve Flight requests of clients connecting to `listener`, over HTTP/2 without TLS.
+pub async fn serve(
+    listener: tokio::net::TcpListener,
+    db: Arc<ConcurrentClientsDatabase>,
+) -> io::Result<()> {
+    let router = Routes::new(FlightService { db }).into_axum_router();
+    axum::serve(listener, router).await
+}
+
+/// The `arrow.flight.protocol.FlightService` gRPC service.
+#[derive(Clone)]
+pub struct FlightService {
+    db: Arc<ConcurrentClientsDatabase>,
+}
+
+impl FlightService {
+    pub fn new(db: Arc<ConcurrentClientsDatabase>) -> Self {
+        FlightService { db }
+    }
+}
+
+impl NamedService for FlightService {
+    const NAME: &'static str = "arrow.flight.protocol.FlightService";
+}
+
+impl<B> Service<http::Request<B>> for FlightService
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<tonic::body::Body>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Infallible>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let db = self.db.clone();
+        Box::pin(async move {
+            let method = req.uri().path().rsplit('/').next().unwrap_or_default();
+            Ok(match method {
+                "ListFlights" => {
+                    Grpc::new(ProstCodec::default())
+                        .server_streaming(Handler(|_: Criteria| Ok(list_flights())), req)
+                        .await
+                }
+                "GetFlightInfo" => {
+                    Grpc::new(ProstCodec::default())
+                        .unary(
+                            Handler(|d: FlightDescriptor| flight_info(d.dataset()?)),
+                            req,
+                        )
+                        .await
+                }
+                "GetSchema" => {
+                    Grpc::new(ProstCodec::default())
+                        .unary(
+                            Handler(|d: FlightDescriptor| {
+                                Ok(SchemaResult {
+                                    schema: schema_message(&d.dataset()?.schema()),
+                                })
+                            }),
+                            req,
+                        )
+                        .await
+                }
+                "DoGet" => {
+                    Grpc::new(ProstCodec::default())
+                        .server_streaming(
+                            Handler(move |t: Ticket| {
+                                Ok(do_get(db.clone(), Dataset::named(&t.ticket)?))
+                            }),
+                            req,
+                        )
+                        .await
+                }
+                _ => Status::unimplemented(format!("{method} isn't served")).into_http(),
+            })
+        })
+    }
+}
+
+/// A gRPC method answering a request message right away, with a message or a stream of them.
+struct Handler<F>(F);
+
+impl<F, Req, Resp> Service<tonic::Request<Req>> for Handler<F>
+where
+    F: FnMut(Req) -> Result<Resp, Status>,
+{
+    type Response = tonic::Response<Resp>;
+    type Error = Status;
+    type Future = Ready<Result<Self::Response, Status>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Status>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: tonic::Request<Req>) -> Self::Future {
+        ready((self.0)(req.into_inner()).map(tonic::Response::new))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Dataset {
+    Accounts,
+    Transactions,
+}
+
+impl Dataset {
+    const ALL: [Dataset; 2] = [Dataset::Accounts, Dataset::Transactions];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Dataset::Accounts => "accounts",
+            Dataset::Transactions => "transactions",
+        }
+    }
+
+    fn named(name: &[u8]) -> Result<Self, Status> {
+        Self::ALL
+            .into_iter()
+            .find(|d| d.as_str().as_bytes() == name)
+            .ok_or_else(|| {
+                Status::not_found(format!(
+                    "no dataset {:?}, only accounts and transactions",
+                    String::from_utf8_lossy(name)
+                ))
+            })
+    }
+
+    fn schema(self) -> SchemaRef {
+        let amount = |name| Field::new(name, DataType::Decimal128(20, 4), false);
+        let fields = match self {
+            Dataset::Accounts => vec![
+                Field::new("client", DataType::UInt16, false),
+                amount("available"),
+                amount("held"),
+                amount("total"),
+                Field::new("locked", DataType::Boolean, false),
+            ],
+            Dataset::Transactions => vec![
+                Field::new("at", DataType::UInt64, false),
+                Field::new("client", DataType::UInt16, false),
+                Field::new("type", DataType::Utf8, false),
+                Field::new("tx", DataType::UInt32, false),
+                // Null for disputes, resolves and chargebacks.
+                Field::new("amount", DataType::Decimal128(20, 4), true),
+                amount("available"),
+                amount("held"),
+                amount("total"),
+                Field::new("locked", DataType::Boolean, false),
+            ],
+        };
+        Arc::new(Schema::new(fields))
+    }
+}
+
+fn list_flights() -> impl Stream<Item = Result<FlightInfo, Status>> + Send + 'static {
+    stream::iter(Dataset::ALL.map(flight_info))
+}
+
+fn flight_info(dataset: Dataset) -> Result<FlightInfo, Status> {
+    Ok(FlightInfo {
+        schema: schema_message(&dataset.schema()),
+        flight_descriptor: Some(FlightDescriptor {
+            r#type: FlightDescriptor::PATH,
+            cmd: Vec::new(),
+            path: vec![dataset.as_str().to_owned()],
+        }),
+        endpoint: vec![FlightEndpoint {
+            ticket: Some(Ticket {
+                ticket: dataset.as_str().into(),
+            }),
+        }],
+        // Unknown until read.
+        total_records: -1,
+        total_bytes: -1,
+        ordered: false,
+    })
+}
+
+/// The schema of a dataset, then its rows in batches encoded as they're sent. Rows are copied a
+/// shard at a time, like [`ConcurrentClientsDatabase::views`], as batches of a shard go out.
+fn do_get(
+    db: Arc<ConcurrentClientsDatabase>,
+    dataset: Dataset,
+) -> impl Stream<Item = Result<FlightData, Status>> + Send + 'static {
+    let schema = dataset.schema();
+    let shards = 0..db.shard_count();
+    let batches: Box<dyn Iterator<Item = RecordBatch> + Send> = match dataset {
+        Dataset::Accounts => {
+            let schema = schema.clone();
+            Box::new(shards.flat_map(move |shard| {
+                batches(db.shard_views(shard), schema.clone(), accounts_batch)
+            }))
+        }
+        Dataset::Transactions => {
+            let schema = schema.clone();
+            Box::new(shards.flat_map(move |shard| {
+                batches(db.shard_audit(shard), schema.clone(), transactions_batch)
+            }))
+        }
+    };
+    let generator = IpcDataGenerator::default();
+    let mut tracker = DictionaryTracker::new(false);
+    let options = IpcWriteOptions::default();
+    let header = generator.schema_to_bytes_with_dictionary_tracker(&schema, &mut tracker, &options);
+    let data = batches.flat_map(move |batch| {
+        match generator.encoded_batch(&batch, &mut tracker, &options) {
+            Ok((dictionaries, batch)) => dictionaries
+                .into_iter()
+                .chain([batch])
+                .map(|encoded| Ok(FlightData::from(encoded)))
+                .collect(),
+            Err(e) => vec![Err(Status::internal(e.to_string()))],
+        }
+    });
+    stream::iter(std::iter::once(Ok(FlightData::from(header))).chain(data))
+}
+
+/// `rows` in record batches of up to [`BATCH_ROWS`], each built once reached.
+fn batches<T: Send + 'static>(
+    rows: Vec<T>,
+    schema: SchemaRef,
+    columns: fn(&[T]) -> Vec<ArrayRef>,
+) -> impl Iterator<Item = RecordBatch> + Send {
+    let mut rows = rows.into_iter();
+    std::iter::from_fn(move || {
+        let chunk = rows.by_ref().take(BATCH_ROWS).collect::<Vec<_>>();
+        (!chunk.is_empty()).then(|| RecordBatch::try_new(schema.clone(), columns(&chunk)).unwrap())
+    })
+}
+
+fn decimals(amounts: impl Iterator<Item = Option<Amount>>) -> ArrayRef {
+    Arc::new(
+        amounts
+            .map(|a| a.map(|a| i128::from(a.to_raw())))
+            .collect::<Decimal128Array>()
+            .with_precision_and_scale(20, 4)
+            .unwrap(),
+    )
+}
+
+/// The columns of balances, after `client` or transaction columns.
+fn balances<'a>(views: impl Iterator<Item = &'a AccountView<'a>> + Clone) -> [ArrayRef; 4] {
+    [
+        decimals(views.clone().map(|v| Some(v.available))),
+        decimals(views.clone().map(|v| Some(v.held))),
+        decimals(views.clone().map(|v| Some(v.total))),
+        Arc::new(views.map(|v| Some(v.locked)).collect::<BooleanArray>()),
+    ]
+}
+
+fn accounts_batch(rows: &[(ClientId, AccountView)]) -> Vec<ArrayRef> {
+    let mut columns: Vec<ArrayRef> = vec![Arc::new(
+        rows.iter().map(|(c, _)| *c).collect::<UInt16Array>(),
+    )];
+    columns.extend(balances(rows.iter().map(|(_, v)| v)));
+    columns
+}
+
+fn transactions_batch(rows: &[(ClientId, AuditRecord)]) -> Vec<ArrayRef> {
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(rows.iter().map(|(_, r)| r.at).collect::<UInt64Array>()),
+        Arc::new(rows.iter().map(|(c, _)| *c).collect::<UInt16Array>()),
+        Arc::new(
+            rows.iter()
+                .map(|(_, r)| Some(r.transaction.kind.as_str()))
+                .collect::<StringArray>(),
+        ),
+        Arc::new(
+            rows.iter()
+                .map(|(_, r)| r.transaction.id)
+                .collect::<UInt32Array>(),
+        ),
+        decimals(rows.iter().map(|(_, r)| {
+            let t = r.transaction;
+            t.kind.has_amount().then_some(t.amount)
+        })),
+    ];
+    columns.extend(balances(rows.iter().map(|(_, r)| &r.after)));
+    columns
+}
+
+/// A schema as an IPC message with its length prefix, as `FlightInfo` and `SchemaResult` have it.
+fn schema_message(schema: &Schema) -> Vec<u8> {
+    let options = IpcWriteOptions::default();
+    let encoded = IpcDataGenerator::default().schema_to_bytes_with_dictionary_tracker(
+        schema,
+        &mut DictionaryTracker::new(false),
+        &options,
+    );
+    let mut out = Vec::new();
+    arrow_ipc::writer::write_message(&mut out, encoded, &options).unwrap();
+    out
+}
+
+// Messages of Flight.proto, with only the fields used here.
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Ticket {
+    #[prost(bytes = "vec", tag = "1")]
+    pub ticket: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Criteria {
+    #[prost(bytes = "vec", tag = "1")]
+    pub expression: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FlightDescriptor {
+    #[prost(int32, tag = "1")]
+    pub r#type: i32,
+    #[prost(bytes = "vec", tag = "2")]
+    pub cmd: Vec<u8>,
+    #[prost(string, repeated, tag = "3")]
+    pub path: Vec<String>,
+}
+
+impl FlightDescriptor {
+    pub const PATH: i32 = 1;
+
+    fn dataset(&self) -> Result<Dataset, Status> {
+        match self.path.as_slice() {
+            [name] if self.r#type == Self::PATH => Dataset::named(name.as_bytes()),
+            _ => Err(Status::invalid_argument(
+                "expected a path descriptor of a dataset name",
+            )),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FlightEndpoint {
+    #[prost(message, optional, tag = "1")]
+    pub ticket: Option<Ticket>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FlightInfo {
+    #[prost(bytes = "vec", tag = "1")]
+    pub schema: Vec<u8>,
+    #[prost(message, optional, tag = "2")]
+    pub flight_descriptor: Option<FlightDescriptor>,
+    #[prost(message, repeated, tag = "3")]
+    pub endpoint: Vec<FlightEndpoint>,
+    #[prost(int64, tag = "4")]
+    pub total_records: i64,
+    #[prost(int64, tag = "5")]
+    pub total_bytes: i64,
+    #[prost(bool, tag = "6")]
+    pub ordered: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SchemaResult {
+    #[prost(bytes = "vec", tag = "1")]
+    pub schema: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FlightData {
+    #[prost(message, optional, tag = "1")]
+    pub flight_descriptor: Option<FlightDescriptor>,
+    /// The flatbuffers IPC message header.
+    #[prost(bytes = "vec", tag = "2")]
+    pub data_header: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub app_metadata: Vec<u8>,
+    /// The buffers of a record batch.
+    #[prost(bytes = "vec", tag = "1000")]
+    pub data_body: Vec<u8>,
+}
+
+impl From<EncodedData> for FlightData {
+    fn from(encoded: EncodedData) -> Self {
+        FlightData {
+            flight_descriptor: None,
+            data_header: encoded.ipc_message,
+            app_metadata: Vec::new(),
+            data_body: encoded.arrow_data,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, sync::Arc};
+
+    use arrow_array::{
+        Array, RecordBatch,
+        cast::AsArray,
+        types::{Decimal128Type, UInt16Type},
+    };
+    use arrow_ipc::{
+        reader::StreamReader,
+        writer::{EncodedData, IpcWriteOptions, write_message},
+    };
+    use prost::Message;
+    use tonic::codegen::{Service, http};
+
+    use crate::{
+        accounts::{Transaction, TransactionKind},
+        concurrent::ConcurrentClientsDatabase,
+        flight::{FlightData, FlightDescriptor, FlightInfo, FlightService, Ticket},
+        sharded::ShardedDatabase,
+    };
+
+    /// Call a method of the service with a message, returning its gRPC status and the messages
+    /// of the response.
+    async fn call(
+        service: &mut FlightService,
+        method: &str,
+        message: impl Message,
+    ) -> (String, Vec<Vec<u8>>) {
+        let message = message.encode_to_vec();
+        let mut body = vec![0];
+        body.extend((message.len() as u32).to_be_bytes());
+        body.extend(message);
+        let req = http::Request::post(format!("/arrow.flight.protocol.FlightService/{method}"))
+            .header("content-type", "application/grpc")
+            .body(axum::body::Body::from(body))
+            .unwrap();
+        let res = service.call(req).await.unwrap();
+        let status = res
+            .headers()
+            .get("grpc-status")
+            .map_or("0", |s| s.to_str().unwrap())
+            .to_owned();
+        let body = axum::body::to_bytes(axum::body::Body::new(res.into_body()), usize::MAX)
+            .await
+            .unwrap();
+        let mut messages = Vec::new();
+        let mut rest = &body[..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[1..5].try_into().unwrap()) as usize;
+            messages.push(rest[5..5 + len].to_vec());
+            rest = &rest[5 + len..];
+        }
+        (status, messages)
+    }
+
+    /// Record batches of a `DoGet` response, read as an IPC stream.
+    fn read_batches(messages: &[Vec<u8>]) -> Vec<RecordBatch> {
+        let options = IpcWriteOptions::default();
+        let mut stream = Vec::new();
+        for message in messages {
+            let data = FlightData::decode(&message[..]).unwrap();
+            let encoded = EncodedData {
+                ipc_message: data.data_header,
+                arrow_data: data.data_body,
+            };
+            write_message(&mut stream, encoded, &options).unwrap();
+        }
+        StreamReader::try_new(Cursor::new(stream), None)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_flight() {
+        let mut db = ShardedDatabase::new(2);
+        for shard in db.shards_mut() {
+            shard.enable_audit();
+        }
+        let db = Arc::new(ConcurrentClientsDatabase::from_sharded(db));
+        for (client_id, kind, id, amount) in [
+            (1, TransactionKind::Deposit, 1, "2.5"),
+            (2, TransactionKind::Deposit, 2, "1"),
+            (1, TransactionKind::Dispute, 1, "0"),
+        ] {
+            db.process_transaction(
+                client_id,
+                Transaction {
+                    kind,
+                    id,
+                    amount: amount.parse().unwrap(),
+                },
+            )
+            .unwrap();
+        }
+        let mut service = FlightService::new(db);
+
+        let (status, messages) = call(
+            &mut service,
+            "DoGet",
+            Ticket {
+                ticket: b"accounts".to_vec(),
+            },
+        )
+        .await;
+        assert_eq!(status, "0");
+        let batches = read_batches(&messages);
+        let mut rows = Vec::new();
+        for batch in &batches {
+            let clients = batch.column(0).as_primitive::<UInt16Type>();
+            let held = batch.column(2).as_primitive::<Decimal128Type>();
+            for i in 0..batch.num_rows() {
+                rows.push((clients.value(i), held.value_as_string(i)));
+            }
+        }
+        rows.sort();
+        assert_eq!(rows, [(1, "2.5000".to_owned()), (2, "0.0000".to_owned())]);
+
+        let (_, messages) = call(
+            &mut service,
+            "DoGet",
+            Ticket {
+                ticket: b"transactions".to_vec(),
+            },
+        )
+        .await;
+        let batches = read_batches(&messages);
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 3);
+        let types = batches
+            .iter()
+            .flat_map(|b| {
+                let types = b.column(2).as_string::<i32>();
+                let amounts = b.column(4);
+                (0..b.num_rows())
+                    .map(|i| (types.value(i).to_owned(), amounts.is_null(i)))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert!(types.contains(&("dispute".to_owned(), true)));
+        assert!(types.contains(&("deposit".to_owned(), false)));
+
+        let (status, messages) = call(
+            &mut service,
+            "GetFlightInfo",
+            FlightDescriptor {
+                r#type: FlightDescriptor::PATH,
+                cmd: Vec::new(),
+                path: vec!["transactions".into()],
+            },
+        )
+        .await;
+        assert_eq!(status, "0");
+        let info = FlightInfo::decode(&messages[0][..]).unwrap();
+        assert_eq!(
+            info.endpoint[0].ticket.as_ref().unwrap().ticket,
+            b"transactions"
+        );
+        assert!(!info.schema.is_empty());
+
+        let (status, _) = call(
+            &mut service,
+            "DoGet",
+            Ticket {
+                ticket: b"deposits".to_vec(),
+            },
+        )
+        .await;
+        assert_eq!(status, "5");
+        let (status, _) = call(&mut service, "DoPut", Ticket::default()).await;
+        assert_eq!(status, "12");
+    }
+}
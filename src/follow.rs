@@ -0,0 +1,84 @@
+use std::io::{BufRead, BufReader, Read};
+
+use crate::{error::ParseError, parser::Row, pipeline::ParseFailure};
+
+/// Rows appended to a growing input, like `tail -f`.
+///
+/// Each [`Self::poll`] parses the complete lines written since the previous one, without waiting
+/// for more. A last line without its newline yet is kept until the rest of it arrives. The header
+/// line is skipped, as in [`crate::pipeline::RowStream`].
+pub struct Follow<R> {
+    reader: BufReader<R>,
+    // Start of a line whose end wasn't written yet.
+    partial: Vec<u8>,
+    line: usize,
+}
+
+impl<R: Read> Follow<R> {
+    pub fn new(reader: R) -> Self {
+        Follow {
+            reader: BufReader::new(reader),
+            partial: Vec::new(),
+            line: 0,
+        }
+    }
+
+    /// Rows of the lines completed since the last call, in order. Empty if nothing was appended.
+    /// Read errors are returned as failures of the line being read, and can be retried.
+    pub fn poll(&mut self) -> Vec<Result<Row, ParseFailure>> {
+        let mut rows = Vec::new();
+        loop {
+            match self.reader.read_until(b'\n', &mut self.partial) {
+                // At the current end of the input.
+                Ok(0) => return rows,
+                Ok(_) if !self.partial.ends_with(b"\n") => return rows,
+                Ok(_) => {
+                    self.line += 1;
+                    if self.line > 1 {
+                        let line = self.line;
+                        rows.push(
+                            Row::parse(&self.partial).map_err(|error| {
+                                ParseFailure::with_raw(line, error, &self.partial)
+                            }),
+                        );
+                    }
+                    self.partial.clear();
+                }
+                Err(e) => {
+                    rows.push(Err(ParseFailure::new(self.line + 1, ParseError::Io(e))));
+                    return rows;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use crate::follow::Follow;
+
+    #[test]
+    fn test_follow() {
+        let path = std::env::temp_dir().join(format!("payengine-follow-{}", std::process::id()));
+        let mut w = std::fs::File::create(&path).unwrap();
+        let mut follow = Follow::new(std::fs::File::open(&path).unwrap());
+        assert!(follow.poll().is_empty());
+
+        w.write_all(b"type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,")
+            .unwrap();
+        let rows = follow.poll();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].as_ref().unwrap().client_id, 1);
+        assert!(follow.poll().is_empty());
+
+        // Completes the partial line.
+        w.write_all(b"2,5\nbogus\n").unwrap();
+        let rows = follow.poll();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].as_ref().unwrap().transaction.id, 2);
+        assert_eq!(rows[1].as_ref().unwrap_err().line, 4);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use crate::accounts::{AccountView, ClientId};
+
+/// Point in time used for as-of queries.
+///
+/// The input format carries no timestamps, so by default this is the logical position of the
+/// transaction in the stream: the number of transactions submitted to the database before it.
+pub type Timestamp = u64;
+
+/// Per-account balance checkpoints, one per successfully applied transaction.
+#[derive(Debug, Default)]
+pub struct BalanceHistory {
+    accounts: HashMap<ClientId, Vec<(Timestamp, AccountView<'static>)>>,
+}
+
+impl BalanceHistory {
+    pub(crate) fn record(
+        &mut self,
+        client_id: ClientId,
+        at: Timestamp,
+        view: AccountView<'static>,
+    ) {
+        let checkpoints = self.accounts.entry(client_id).or_default();
+        // Timestamps are normally increasing, so this is an append. Equal timestamps keep
+        // application order.
+        let idx = checkpoints.partition_point(|(t, _)| *t <= at);
+        checkpoints.insert(idx, (at, view));
+    }
+
+    /// Balances of the client right after the last transaction applied at or before `at`.
+    /// Returns None if the account didn't exist yet.
+    pub fn as_of(&self, client_id: ClientId, at: Timestamp) -> Option<AccountView<'static>> {
+        let checkpoints = self.accounts.get(&client_id)?;
+        let idx = checkpoints.partition_point(|(t, _)| *t <= at);
+        idx.checked_sub(1).map(|idx| checkpoints[idx].1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::{ClientsDatabase, Transaction, TransactionKind::*},
+        amount::Amount,
+    };
+
+    fn amount(v: &str) -> Amount {
+        Amount::parse(v.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_balance_as_of() {
+        let mut db = ClientsDatabase::with_history();
+        for (client, kind, id, a) in [
+            (1, Deposit, 1, "10"),     // t=0
+            (2, Deposit, 2, "1"),      // t=1
+            (1, Withdrawal, 3, "4"),   // t=2
+            (1, Withdrawal, 4, "100"), // t=3, rejected
+            (1, Dispute, 1, "0"),      // t=4
+        ] {
+            let _ = db.process_transaction(
+                client,
+                Transaction {
+                    kind,
+                    id,
+                    amount: amount(a),
+                },
+            );
+        }
+
+        let total = |at| db.balance_as_of(1, at).map(|v| v.total);
+        assert_eq!(total(0), Some(amount("10")));
+        assert_eq!(total(1), Some(amount("10")));
+        assert_eq!(total(2), Some(amount("6")));
+        assert_eq!(total(3), Some(amount("6")));
+        assert_eq!(db.balance_as_of(1, 4).unwrap().held, amount("10"));
+        assert_eq!(db.balance_as_of(1, 100).unwrap().held, amount("10"));
+
+        assert!(db.balance_as_of(2, 0).is_none());
+        assert_eq!(db.balance_as_of(2, 1).unwrap().total, amount("1"));
+
+        // Without history enabled nothing is retained.
+        assert!(ClientsDatabase::new().balance_as_of(1, 0).is_none());
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{Mutex, PoisonError},
+};
+
+/// Responses to requests by their idempotency key, so that a client retrying a request whose
+/// response it didn't get is answered the same rather than having it applied twice.
+///
+/// Each key is tied to the request it came with, by a hash of its body, and is forgotten once
+/// `capacity` newer keys were seen, oldest first.
+pub(crate) struct IdempotencyCache<R> {
+    capacity: usize,
+    entries: HashMap<String, Entry<R>>,
+    // Keys by age, oldest first, with the sequence number of their entry. Those of entries
+    // forgotten meanwhile are skipped.
+    order: VecDeque<(u64, String)>,
+    next: u64,
+}
+
+struct Entry<R> {
+    seq: u64,
+    fingerprint: u64,
+    // None while the request is in progress.
+    response: Option<R>,
+}
+
+/// What to do with a request with an idempotency key.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Begin<R> {
+    /// Handle it, then [`IdempotencyCache::finish`] or [`IdempotencyCache::forget`] the key, e.g.
+    /// through a [`Reservation`].
+    New,
+    /// Answer the response to the first request with the key.
+    Replay(R),
+    /// The first request with the key isn't answered yet.
+    InProgress,
+    /// The key came with another request.
+    Mismatch,
+}
+
+impl<R: Clone> IdempotencyCache<R> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        IdempotencyCache {
+            capacity: capacity.max(1),
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            next: 0,
+        }
+    }
+
+    pub(crate) fn begin(&mut self, key: &str, request: &[u8]) -> Begin<R> {
+        let fingerprint = fingerprint(request);
+        if let Some(entry) = self.entries.get(key) {
+            return match &entry.response {
+                _ if entry.fingerprint != fingerprint => Begin::Mismatch,
+                Some(response) => Begin::Replay(response.clone()),
+                None => Begin::InProgress,
+            };
+        }
+        let seq = self.next;
+        self.next += 1;
+        self.entries.insert(
+            key.to_owned(),
+            Entry {
+                seq,
+                fingerprint,
+                response: None,
+            },
+        );
+        self.order.push_back((seq, key.to_owned()));
+        // Stale keys are skipped as they come up, and dropped once the queue is twice the capacity.
+        while self.entries.len() > self.capacity || self.order.len() > 2 * self.capacity {
+            let (seq, key) = self.order.pop_front().unwrap();
+            if self.entries.get(&key).is_some_and(|entry| entry.seq == seq) {
+                self.entries.remove(&key);
+            }
+        }
+        Begin::New
+    }
+}
+
+impl<R> IdempotencyCache<R> {
+    /// Keep the response to the request with the key.
+    pub(crate) fn finish(&mut self, key: &str, response: R) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.response = Some(response);
+        }
+    }
+
+    /// Forget the key, e.g. as the request wasn't handled, so that it can be retried.
+    pub(crate) fn forget(&mut self, key: &str) {
+        self.entries.remove(key);
+    }
+}
+
+/// A key [`Begin::New`] was returned for. Dropping it without [`Reservation::finish`] forgets the
+/// key, so that a request whose handling panicked or was cancelled can be retried instead of
+/// being answered as in progress forever.
+pub(crate) struct Reservation<'a, R> {
+    cache: &'a Mutex<IdempotencyCache<R>>,
+    key: &'a str,
+    finished: bool,
+}
+
+impl<'a, R> Reservation<'a, R> {
+    pub(crate) fn new(cache: &'a Mutex<IdempotencyCache<R>>, key: &'a str) -> Self {
+        Self {
+            cache,
+            key,
+            finished: false,
+        }
+    }
+
+    pub(crate) fn finish(mut self, response: R) {
+        let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+        cache.finish(self.key, response);
+        self.finished = true;
+    }
+}
+
+impl<R> Drop for Reservation<'_, R> {
+    fn drop(&mut self) {
+        if !self.finished {
+            // Possibly while unwinding, so don't panic on a poisoned lock.
+            let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+            cache.forget(self.key);
+        }
+    }
+}
+
+fn fingerprint(request: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    request.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use crate::idempotency::{Begin, IdempotencyCache, Reservation};
+
+    #[test]
+    fn test_idempotency_cache() {
+        let mut cache = IdempotencyCache::new(2);
+        assert_eq!(cache.begin("a", b"1"), Begin::New);
+        assert_eq!(cache.begin("a", b"1"), Begin::InProgress);
+        cache.finish("a", 200);
+        assert_eq!(cache.begin("a", b"1"), Begin::Replay(200));
+        assert_eq!(cache.begin("a", b"2"), Begin::Mismatch);
+
+        // Forgotten keys start over, and their stale place doesn't count towards the capacity.
+        assert_eq!(cache.begin("b", b"1"), Begin::New);
+        cache.forget("b");
+        assert_eq!(cache.begin("b", b"1"), Begin::New);
+        cache.finish("b", 429);
+        assert_eq!(cache.begin("c", b"1"), Begin::New);
+        assert_eq!(cache.begin("b", b"1"), Begin::Replay(429));
+        // Evicted, oldest first.
+        assert_eq!(cache.begin("a", b"2"), Begin::New);
+    }
+
+    #[test]
+    fn test_reservation() {
+        let cache = Mutex::new(IdempotencyCache::new(2));
+        assert_eq!(cache.lock().unwrap().begin("a", b"1"), Begin::New);
+        Reservation::new(&cache, "a").finish(200);
+        assert_eq!(cache.lock().unwrap().begin("a", b"1"), Begin::Replay(200));
+
+        // Forgotten if handling the request panics.
+        assert_eq!(cache.lock().unwrap().begin("b", b"1"), Begin::New);
+        let panicked = std::panic::catch_unwind(|| {
+            let _reservation = Reservation::new(&cache, "b");
+            panic!("handler");
+        });
+        assert!(panicked.is_err());
+        assert_eq!(cache.lock().unwrap().begin("b", b"1"), Begin::New);
+    }
+}
@@ -0,0 +1,413 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, BufRead},
+};
+
+use quick_xml::{Reader, events::Event};
+
+use crate::{
+    accounts::{ClientId, TransactionId, TransactionKind},
+    config::Pain001Config,
+    error::ParseError,
+    parser::Row,
+    pipeline::ParseFailure,
+};
+
+/// Rows of an ISO 20022 pain.001 customer credit transfer initiation, as exported by banks and
+/// accounting software, one per credit transfer (`CdtTrfTxInf`). The file is streamed, so it can be
+/// of any size.
+///
+/// A transfer is a withdrawal of the client of its debtor account, that of its `PmtInf`, if
+/// `config.accounts` has it, or else a deposit to the client of its creditor account. Accounts are
+/// matched by IBAN or other identification. Transfers between two known accounts aren't supported,
+/// as their two rows would be applied independently, and have an invalid client id, as do those
+/// between two unknown accounts. Amounts in another currency than `config.currency`, if set, are
+/// invalid.
+///
+/// The transaction id is a 32-bit FNV-1a hash of the message id, the payment information id, and
+/// the transfer's instruction id, else its end-to-end id, else its position in the payment
+/// information. Importing a file again thus gives the same ids, so its deposits are rejected as
+/// duplicates, but not its withdrawals, as with CSV. Distinct transfers may get the same id,
+/// which is an invalid transaction id within a file, and a duplicate across files.
+///
+/// Rows are numbered by transfer, from 1. Malformed XML, or another document, ends the rows with an
+/// I/O error.
+pub fn read_pain001<R: BufRead>(
+    r: R,
+    config: &Pain001Config,
+) -> impl Iterator<Item = Result<Row, ParseFailure>> + use<R> {
+    let mut reader = Reader::from_reader(r);
+    reader.config_mut().trim_text(true);
+    Transfers {
+        reader,
+        buf: Vec::new(),
+        accounts: config
+            .accounts
+            .iter()
+            .map(|(account, &client_id)| (normalize(account), client_id))
+            .collect(),
+        currency: config.currency.clone(),
+        path: Vec::new(),
+        text: String::new(),
+        initiation: false,
+        msg_id: String::new(),
+        payment: Payment::default(),
+        transfer: Transfer::default(),
+        transfers: 0,
+        ids: HashSet::new(),
+        done: false,
+    }
+}
+
+struct Transfers<R> {
+    reader: Reader<R>,
+    buf: Vec<u8>,
+    /// Normalized accounts.
+    accounts: HashMap<String, ClientId>,
+    currency: Option<String>,
+    /// Local names of the open elements.
+    path: Vec<Vec<u8>>,
+    /// Text of the innermost element.
+    text: String,
+    /// Whether the document is a `CstmrCdtTrfInitn`.
+    initiation: bool,
+    msg_id: String,
+    payment: Payment,
+    transfer: Transfer,
+    transfers: usize,
+    ids: HashSet<TransactionId>,
+    done: bool,
+}
+
+/// The `PmtInf` being read.
+#[derive(Default)]
+struct Payment {
+    id: String,
+    debtor: Option<String>,
+    transfers: usize,
+}
+
+/// The `CdtTrfTxInf` being read.
+#[derive(Default)]
+struct Transfer {
+    instr_id: Option<String>,
+    end_to_end_id: Option<String>,
+    amount: Option<String>,
+    currency: Option<String>,
+    creditor: Option<String>,
+}
+
+impl<R: BufRead> Iterator for Transfers<R> {
+    type Item = Result<Row, ParseFailure>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.step() {
+                Ok(Some(row)) => return Some(row),
+                Ok(None) => {}
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(ParseFailure::new(
+                        self.transfers + 1,
+                        ParseError::Io(e),
+                    )));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<R: BufRead> Transfers<R> {
+    /// Read an event, returning the row of the transfer it ends, if it does.
+    fn step(&mut self) -> io::Result<Option<Result<Row, ParseFailure>>> {
+        self.buf.clear();
+        match self
+            .reader
+            .read_event_into(&mut self.buf)
+            .map_err(invalid)?
+        {
+            Event::Start(e) => {
+                let name = e.local_name().as_ref().to_vec();
+                match (self.path.len(), name.as_slice()) {
+                    (0, b"Document") => {}
+                    (1, b"CstmrCdtTrfInitn") => self.initiation = true,
+                    (0 | 1, _) => return Err(not_pain001()),
+                    (_, b"PmtInf") => self.payment = Payment::default(),
+                    (_, b"CdtTrfTxInf") => self.transfer = Transfer::default(),
+                    (_, b"InstdAmt") => {
+                        self.transfer.currency = match e.try_get_attribute("Ccy") {
+                            Ok(Some(ccy)) => Some(ccy.unescape_value().map_err(invalid)?.into()),
+                            Ok(None) => None,
+                            Err(e) => return Err(invalid(e)),
+                        }
+                    }
+                    _ => {}
+                }
+                self.path.push(name);
+                self.text.clear();
+            }
+            Event::Empty(_) if self.path.len() < 2 => return Err(not_pain001()),
+            Event::Text(e) => self.text.push_str(&e.unescape().map_err(invalid)?),
+            Event::CData(e) => self.text.push_str(&e.decode().map_err(invalid)?),
+            Event::End(_) => {
+                let row = self.end_element();
+                self.path.pop();
+                return Ok(row);
+            }
+            Event::Eof if !self.initiation => return Err(not_pain001()),
+            Event::Eof if !self.path.is_empty() => {
+                return Err(invalid("unexpected end of the document"));
+            }
+            Event::Eof => self.done = true,
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    /// Keep the text of the innermost element if it's a field, returning the row of a transfer
+    /// it ends.
+    fn end_element(&mut self) -> Option<Result<Row, ParseFailure>> {
+        let text = std::mem::take(&mut self.text);
+        let path = self.path.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        match path.as_slice() {
+            [.., b"GrpHdr", b"MsgId"] => self.msg_id = text,
+            [.., b"PmtInf", b"PmtInfId"] => self.payment.id = text,
+            [.., b"PmtInf", b"DbtrAcct", b"Id", b"IBAN"]
+            | [.., b"PmtInf", b"DbtrAcct", b"Id", b"Othr", b"Id"] => {
+                self.payment.debtor = Some(text)
+            }
+            [.., b"CdtTrfTxInf", b"PmtId", b"InstrId"] => self.transfer.instr_id = Some(text),
+            [.., b"CdtTrfTxInf", b"PmtId", b"EndToEndId"] => {
+                self.transfer.end_to_end_id = Some(text)
+            }
+            [.., b"CdtTrfTxInf", b"Amt", b"InstdAmt"] => self.transfer.amount = Some(text),
+            [.., b"CdtTrfTxInf", b"CdtrAcct", b"Id", b"IBAN"]
+            | [.., b"CdtTrfTxInf", b"CdtrAcct", b"Id", b"Othr", b"Id"] => {
+                self.transfer.creditor = Some(text)
+            }
+            [.., b"PmtInf", b"CdtTrfTxInf"] => return Some(self.transfer_row()),
+            _ => {}
+        }
+        None
+    }
+
+    /// Row of the transfer just read.
+    fn transfer_row(&mut self) -> Result<Row, ParseFailure> {
+        self.transfers += 1;
+        self.payment.transfers += 1;
+        let transfer = std::mem::take(&mut self.transfer);
+        let key = transfer
+            .instr_id
+            .or(transfer.end_to_end_id.filter(|id| id != "NOTPROVIDED"))
+            .unwrap_or_else(|| self.payment.transfers.to_string());
+        let raw = format!("{}/{}/{key}", self.msg_id, self.payment.id);
+        let fail = |error| ParseFailure::with_raw(self.transfers, error, raw.as_bytes());
+
+        let id = transaction_id([&self.msg_id, &self.payment.id, &key]);
+        if !self.ids.insert(id) {
+            return Err(fail(ParseError::InvalidTxId));
+        }
+        let amount = transfer
+            .amount
+            .ok_or_else(|| fail(ParseError::MissingColumn))?;
+        if self.currency.is_some() && transfer.currency != self.currency {
+            return Err(fail(ParseError::InvalidAmount));
+        }
+        let client = |account: Option<String>| {
+            account.and_then(|account| self.accounts.get(&normalize(&account)).copied())
+        };
+        let (kind, client_id) = match (
+            client(self.payment.debtor.clone()),
+            client(transfer.creditor),
+        ) {
+            (Some(client_id), None) => (TransactionKind::Withdrawal, client_id),
+            (None, Some(client_id)) => (TransactionKind::Deposit, client_id),
+            _ => return Err(fail(ParseError::InvalidClientId)),
+        };
+        Row::with_amount(kind, client_id, id, amount.as_bytes()).map_err(fail)
+    }
+}
+
+/// An account without spaces, in upper case, as IBANs are often written in groups.
+fn normalize(account: &str) -> String {
+    account
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// FNV-1a of the parts, separated by NUL.
+fn transaction_id(parts: [&str; 3]) -> TransactionId {
+    let mut hash: u32 = 0x811c_9dc5;
+    for (i, part) in parts.iter().enumerate() {
+        let separator = if i > 0 { &b"\0"[..] } else { b"" };
+        for &b in separator.iter().chain(part.as_bytes()) {
+            hash ^= u32::from(b);
+            hash = hash.wrapping_mul(0x0100_0193);
+        }
+    }
+    hash
+}
+
+fn invalid(e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn not_pain001() -> io::Error {
+    invalid("not a pain.001 customer credit transfer initiation")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::{
+        accounts::TransactionKind,
+        amount::Amount,
+        config::Pain001Config,
+        error::ParseError,
+        iso20022::{read_pain001, transaction_id},
+    };
+
+    const PAIN001: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:pain.001.001.09">
+  <CstmrCdtTrfInitn>
+    <GrpHdr>
+      <MsgId>MSG-1</MsgId>
+      <NbOfTxs>6</NbOfTxs>
+    </GrpHdr>
+    <PmtInf>
+      <PmtInfId>PAY-1</PmtInfId>
+      <DbtrAcct><Id><IBAN>DE89 3704 0044 0532 0130 00</IBAN></Id></DbtrAcct>
+      <CdtTrfTxInf>
+        <PmtId><InstrId>I-1</InstrId><EndToEndId>E-1</EndToEndId></PmtId>
+        <Amt><InstdAmt Ccy="EUR">12.50</InstdAmt></Amt>
+        <CdtrAcct><Id><IBAN>FR7630006000011234567890189</IBAN></Id></CdtrAcct>
+      </CdtTrfTxInf>
+      <CdtTrfTxInf>
+        <PmtId><EndToEndId>NOTPROVIDED</EndToEndId></PmtId>
+        <Amt><InstdAmt Ccy="USD">3</InstdAmt></Amt>
+      </CdtTrfTxInf>
+      <CdtTrfTxInf>
+        <PmtId><EndToEndId>NOTPROVIDED</EndToEndId></PmtId>
+        <Amt><InstdAmt Ccy="EUR">1.5</InstdAmt></Amt>
+        <CdtrAcct><Id><IBAN>de02100100100006820101</IBAN></Id></CdtrAcct>
+      </CdtTrfTxInf>
+    </PmtInf>
+    <PmtInf>
+      <PmtInfId>PAY-2</PmtInfId>
+      <DbtrAcct><Id><Othr><Id>EXTERNAL-9</Id></Othr></Id></DbtrAcct>
+      <CdtTrfTxInf>
+        <PmtId><EndToEndId>E-4</EndToEndId></PmtId>
+        <Amt><InstdAmt Ccy="EUR">100</InstdAmt></Amt>
+        <CdtrAcct><Id><IBAN>DE02100100100006820101</IBAN></Id></CdtrAcct>
+      </CdtTrfTxInf>
+      <CdtTrfTxInf>
+        <PmtId><EndToEndId>E-4</EndToEndId></PmtId>
+        <Amt><InstdAmt Ccy="EUR">100</InstdAmt></Amt>
+        <CdtrAcct><Id><IBAN>DE02100100100006820101</IBAN></Id></CdtrAcct>
+      </CdtTrfTxInf>
+      <CdtTrfTxInf>
+        <PmtId><EndToEndId>E-6</EndToEndId></PmtId>
+        <Amt><InstdAmt Ccy="EUR">5</InstdAmt></Amt>
+        <CdtrAcct><Id><IBAN>GB29NWBK60161331926819</IBAN></Id></CdtrAcct>
+      </CdtTrfTxInf>
+    </PmtInf>
+  </CstmrCdtTrfInitn>
+</Document>
+"#;
+
+    fn config() -> Pain001Config {
+        Pain001Config {
+            accounts: [
+                ("DE89370400440532013000".to_owned(), 1),
+                ("DE02 1001 0010 0006 8201 01".to_owned(), 2),
+            ]
+            .into(),
+            currency: Some("EUR".to_owned()),
+        }
+    }
+
+    #[test]
+    fn test_read_pain001() {
+        let rows = read_pain001(PAIN001.as_bytes(), &config()).collect::<Vec<_>>();
+        assert_eq!(rows.len(), 6);
+
+        let row = rows[0].as_ref().unwrap();
+        assert_eq!(row.client_id, 1);
+        assert_eq!(row.transaction.kind, TransactionKind::Withdrawal);
+        assert_eq!(
+            row.transaction.id,
+            transaction_id(["MSG-1", "PAY-1", "I-1"])
+        );
+        assert_eq!(row.transaction.amount, Amount::parse(b"12.5").unwrap());
+
+        // Wrong currency.
+        let failure = rows[1].as_ref().unwrap_err();
+        assert_eq!(failure.line, 2);
+        assert!(matches!(failure.error, ParseError::InvalidAmount));
+        assert_eq!(failure.raw.as_deref(), Some("MSG-1/PAY-1/2"));
+
+        // Between two known accounts.
+        assert!(matches!(
+            rows[2].as_ref().unwrap_err().error,
+            ParseError::InvalidClientId
+        ));
+
+        let row = rows[3].as_ref().unwrap();
+        assert_eq!(row.client_id, 2);
+        assert_eq!(row.transaction.kind, TransactionKind::Deposit);
+        assert_eq!(
+            row.transaction.id,
+            transaction_id(["MSG-1", "PAY-2", "E-4"])
+        );
+
+        // The same end-to-end id again.
+        assert!(matches!(
+            rows[4].as_ref().unwrap_err().error,
+            ParseError::InvalidTxId
+        ));
+        // Between two unknown accounts.
+        assert!(matches!(
+            rows[5].as_ref().unwrap_err().error,
+            ParseError::InvalidClientId
+        ));
+
+        // Any currency is accepted without one configured.
+        let config = Pain001Config {
+            currency: None,
+            ..config()
+        };
+        let rows = read_pain001(PAIN001.as_bytes(), &config).collect::<Vec<_>>();
+        let row = rows[1].as_ref().unwrap();
+        assert_eq!(row.transaction.kind, TransactionKind::Withdrawal);
+        assert_eq!(row.transaction.id, transaction_id(["MSG-1", "PAY-1", "2"]));
+    }
+
+    #[test]
+    fn test_read_pain001_invalid() {
+        let io_error = |xml: &str| {
+            let rows = read_pain001(xml.as_bytes(), &config()).collect::<Vec<_>>();
+            match rows.last().map(|row| &row.as_ref().unwrap_err().error) {
+                Some(ParseError::Io(e)) => e.kind(),
+                _ => panic!("expected an I/O error: {rows:?}"),
+            }
+        };
+        assert_eq!(io_error(""), io::ErrorKind::InvalidData);
+        assert_eq!(
+            io_error("<Document><CstmrDrctDbtInitn/></Document>"),
+            io::ErrorKind::InvalidData
+        );
+        // Cut off after the first transfer, which is still read.
+        let cut = &PAIN001[..PAIN001.find("</CdtTrfTxInf>").unwrap() + 14];
+        let rows = read_pain001(cut.as_bytes(), &config()).collect::<Vec<_>>();
+        assert!(rows[0].is_ok());
+        assert!(matches!(
+            &rows[1].as_ref().unwrap_err().error,
+            ParseError::Io(e) if e.kind() == io::ErrorKind::InvalidData
+        ));
+        assert_eq!(rows.len(), 2);
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::report::push_json_string;
+
+/// Bulk uploads by id, with their progress. Once `capacity` are kept, the oldest
+/// finished ones are forgotten as new ones start. Running jobs are always kept, and at most
+/// `max_running` run at once.
+pub(crate) struct Jobs {
+    capacity: usize,
+    max_running: usize,
+    jobs: BTreeMap<u64, Arc<Job>>,
+    next: u64,
+}
+
+pub(crate) struct Job {
+    pub(crate) id: u64,
+    pub(crate) progress: Mutex<Progress>,
+}
+
+/// Where a job is at, updated as rows are applied.
+#[derive(Debug, Default)]
+pub(crate) struct Progress {
+    pub(crate) done: bool,
+    /// Why the upload ended early, if it did.
+    pub(crate) failure: Option<String>,
+    pub(crate) bytes: u64,
+    /// Rows read, malformed or not. Header and blank lines aren't counted.
+    pub(crate) rows: usize,
+    pub(crate) applied: usize,
+    pub(crate) rejected: usize,
+    pub(crate) malformed: usize,
+    /// The first malformed rows and rejected transactions, by 1-based line, up to `max_errors`.
+    pub(crate) errors: Vec<(usize, crate::Error)>,
+    pub(crate) max_errors: usize,
+}
+
+impl Jobs {
+    pub(crate) fn new(capacity: usize, max_running: usize) -> Self {
+        Jobs {
+            capacity: capacity.max(1),
+            max_running,
+            jobs: BTreeMap::new(),
+            next: 1,
+        }
+    }
+
+    /// A new running job, keeping the first `max_errors` errors, or None if `max_running` are
+    /// running already.
+    pub(crate) fn start(&mut self, max_errors: usize) -> Option<Arc<Job>> {
+        if self.running() >= self.max_running {
+            return None;
+        }
+        while self.jobs.len() >= self.capacity {
+            let finished = self
+                .jobs
+                .iter()
+                .find(|(_, job)| job.progress.lock().unwrap().done)
+                .map(|(&id, _)| id);
+            match finished {
+                Some(id) => self.jobs.remove(&id),
+                None => break,
+            };
+        }
+        let job = Arc::new(Job {
+            id: self.next,
+            progress: Mutex::new(Progress {
+                max_errors,
+                ..Default::default()
+            }),
+        });
+        self.jobs.insert(job.id, job.clone());
+        self.next += 1;
+        Some(job)
+    }
+
+    /// Jobs not done yet.
+    pub(crate) fn running(&self) -> usize {
+        self.jobs
+            .values()
+            .filter(|job| !job.progress.lock().unwrap().done)
+            .count()
+    }
+
+    pub(crate) fn get(&self, id: u64) -> Option<Arc<Job>> {
+        self.jobs.get(&id).cloned()
+    }
+}
+
+impl Progress {
+    /// Count a row at `line`, applied or not.
+    pub(crate) fn add(&mut self, line: usize, result: Result<(), crate::Error>) {
+        self.rows += 1;
+        let e = match result {
+            Ok(()) => {
+                self.applied += 1;
+                return;
+            }
+            Err(e) => e,
+        };
+        match e {
+            crate::Error::Parse(_) => self.malformed += 1,
+            _ => self.rejected += 1,
+        }
+        if self.errors.len() < self.max_errors {
+            self.errors.push((line, e));
+        }
+    }
+}
+
+impl Job {
+    /// E.g. `{"id":1,"state":"done","bytes":62,"rows":2,"applied":1,"rejected":1,
+    /// "malformed":0,"errors":[{"line":3,"error":"...","code":"E_..."}]}`, with the `"error"` the
+    /// upload failed with if its state is `failed`.
+    pub(crate) fn to_json(&self) -> String {
+        let progress = self.progress.lock().unwrap();
+        let state = match (progress.done, &progress.failure) {
+            (false, _) => "running",
+            (true, None) => "done",
+            (true, Some(_)) => "failed",
+        };
+        let mut out = format!(
+            "{{\"id\":{},\"state\":\"{state}\",\"bytes\":{},\"rows\":{},\"applied\":{},\
+             \"rejected\":{},\"malformed\":{},\"errors\":[",
+            self.id,
+            progress.bytes,
+            progress.rows,
+            progress.applied,
+            progress.rejected,
+            progress.malformed
+        );
+        for (i, (line, e)) in progress.errors.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            out.push_str(&format!("{{\"line\":{line},\"error\":"));
+            push_json_string(&mut out, &e.to_string());
+            out.push_str(",\"code\":");
+            push_json_string(&mut out, e.code());
+            out.push('}');
+        }
+        out.push(']');
+        if let Some(failure) = &progress.failure {
+            out.push_str(",\"error\":");
+            push_json_string(&mut out, failure);
+        }
+        out.push('}');
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::{LedgerError, ParseError},
+        jobs::Jobs,
+    };
+
+    #[test]
+    fn test_jobs() {
+        let mut jobs = Jobs::new(2, 2);
+        let a = jobs.start(1).unwrap();
+        let b = jobs.start(1).unwrap();
+        // Both are running.
+        assert!(jobs.start(1).is_none());
+        {
+            let mut progress = a.progress.lock().unwrap();
+            progress.add(2, Ok(()));
+            progress.add(3, Err(LedgerError::WithdrawOverflow.into()));
+            progress.add(4, Err(ParseError::InvalidAmount.into()));
+            progress.bytes = 40;
+            progress.done = true;
+        }
+        assert_eq!(
+            a.to_json(),
+            "{\"id\":1,\"state\":\"done\",\"bytes\":40,\"rows\":3,\"applied\":1,\"rejected\":1,\
+             \"malformed\":1,\"errors\":[{\"line\":3,\"error\":\"withdraw overflowed - not \
+             enough money in the account\",\"code\":\"E_INSUFFICIENT_FUNDS\"}]}"
+        );
+
+        // The finished job makes room, running ones stay.
+        assert_eq!(jobs.running(), 1);
+        let c = jobs.start(1).unwrap();
+        assert!(jobs.get(a.id).is_none());
+        assert!(jobs.start(1).is_none());
+        assert!(jobs.get(b.id).is_some() && jobs.get(c.id).is_some());
+
+        let mut progress = b.progress.lock().unwrap();
+        progress.done = true;
+        progress.failure = Some("connection closed".into());
+        drop(progress);
+        assert_eq!(
+            b.to_json(),
+            "{\"id\":2,\"state\":\"failed\",\"bytes\":0,\"rows\":0,\"applied\":0,\"rejected\":0,\
+             \"malformed\":0,\"errors\":[],\"error\":\"connection closed\"}"
+        );
+    }
+}
@@ -0,0 +1,429 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    io,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use rdkafka::{
+    ClientConfig, ClientContext, Message, Offset, TopicPartitionList,
+    consumer::{BaseConsumer, CommitMode, Consumer},
+    error::{KafkaError, RDKafkaErrorCode},
+    message::DeliveryResult,
+    producer::{BaseProducer, BaseRecord, Producer, ProducerContext},
+};
+use tracing::{debug, warn};
+
+use crate::{
+    accounts::{ClientId, TransactionId},
+    parser::message_rows,
+    report::{ReportFormat, push_json_string},
+    sharded::ShardedDatabase,
+};
+
+// How long a poll waits for a message, bounding how late checkpoints and stopping are.
+const POLL: Duration = Duration::from_millis(100);
+// How long a checkpoint waits for the messages produced to be acknowledged.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Settings of [`run`].
+#[derive(Clone, Debug)]
+pub struct KafkaSettings {
+    /// `bootstrap.servers`, e.g. `localhost:9092`.
+    pub brokers: String,
+    /// Consumer group whose offsets of the input topics are committed.
+    pub group: String,
+    /// Topics of the input, each message one or more rows of the CSV input.
+    pub topics: Vec<String>,
+    /// Topic of a JSON event for each rejected or malformed row, keyed by client id.
+    pub rejections: Option<String>,
+    /// Topic of the balances of each account changed since the previous checkpoint, one message
+    /// per account keyed by client id, e.g. for a compacted topic of the latest balances.
+    pub balances: Option<String>,
+    /// Time between checkpoints.
+    pub interval: Duration,
+    /// Further librdkafka properties of the consumer and producer, e.g. `security.protocol`.
+    pub options: Vec<(String, String)>,
+}
+
+impl KafkaSettings {
+    fn client_config(&self) -> ClientConfig {
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", &self.brokers);
+        for (key, value) in &self.options {
+            config.set(key, value);
+        }
+        config
+    }
+}
+
+/// Counts of a [`run`] so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KafkaStats {
+    pub messages: u64,
+    pub applied: u64,
+    /// Rows rejected by the database or failing to parse.
+    pub rejected: u64,
+    pub checkpoints: u64,
+}
+
+/// Apply the rows of the input topics to `db` until `stop` is set.
+///
+/// Every `interval`, and once more when stopping, the balances of changed accounts are produced,
+/// all messages produced are waited for, `checkpoint` is called, e.g. to save a snapshot, and only
+/// then the offsets of the messages applied are committed for the group. A restart from the state
+/// saved by the last checkpoint thus resumes where it left off, applying again at most the
+/// messages of a checkpoint that failed before committing.
+///
+/// Offsets are tracked per partition, so instances sharing a group each need the partitions of
+/// their clients, i.e. input keyed by client id.
+///
+/// Fails if messages can't be produced, a checkpoint or a commit fails. Errors of the consumer
+/// itself, e.g. brokers down, are logged and retried by librdkafka.
+pub fn run(
+    settings: &KafkaSettings,
+    db: &mut ShardedDatabase,
+    mut checkpoint: impl FnMut(&ShardedDatabase) -> io::Result<()>,
+    stop: &AtomicBool,
+) -> io::Result<KafkaStats> {
+    let consumer: BaseConsumer = settings
+        .client_config()
+        .set("group.id", &settings.group)
+        .set("enable.auto.commit", "false")
+        .set("auto.offset.reset", "earliest")
+        .create()
+        .map_err(io::Error::other)?;
+    let topics = settings
+        .topics
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    consumer.subscribe(&topics).map_err(io::Error::other)?;
+    let producer: BaseProducer<Deliveries> = settings
+        .client_config()
+        .create_with_context(Deliveries::default())
+        .map_err(io::Error::other)?;
+
+    let mut stats = KafkaStats::default();
+    // Next offset of each partition, by topic and partition, since the last commit.
+    let mut offsets = BTreeMap::<(String, i32), i64>::new();
+    let mut changed = BTreeSet::new();
+    let mut last_checkpoint = Instant::now();
+    loop {
+        let stopping = stop.load(Ordering::Relaxed);
+        if stopping || last_checkpoint.elapsed() >= settings.interval {
+            if let Some(topic) = &settings.balances {
+                for &client_id in &changed {
+                    if let Some(account) = db.get(client_id) {
+                        let mut line = Vec::new();
+                        ReportFormat::Ndjson.write(
+                            &mut line,
+                            [(client_id, account.view())],
+                            None,
+                        )?;
+                        send(&producer, topic, Some(client_id), line.trim_ascii_end())?;
+                    }
+                }
+            }
+            changed.clear();
+            producer.flush(FLUSH_TIMEOUT).map_err(io::Error::other)?;
+            let failed = producer.context().failed.swap(0, Ordering::Relaxed);
+            if failed > 0 {
+                return Err(io::Error::other(format!(
+                    "{failed} messages couldn't be produced"
+                )));
+            }
+            checkpoint(db)?;
+            if !offsets.is_empty() {
+                let mut partitions = TopicPartitionList::new();
+                for ((topic, partition), &offset) in &offsets {
+                    partitions
+                        .add_partition_offset(topic, *partition, Offset::Offset(offset))
+                        .map_err(io::Error::other)?;
+                }
+                consumer
+                    .commit(&partitions, CommitMode::Sync)
+                    .map_err(io::Error::other)?;
+                offsets.clear();
+            }
+            stats.checkpoints += 1;
+            debug!(
+                messages = stats.messages,
+                applied = stats.applied,
+                rejected = stats.rejected,
+                "kafka checkpoint"
+            );
+            if stopping {
+                return Ok(stats);
+            }
+            last_checkpoint = Instant::now();
+        }
+
+        producer.poll(Duration::ZERO);
+        let message = match consumer.poll(POLL) {
+            None => continue,
+            Some(Ok(message)) => message,
+            Some(Err(e)) => {
+                warn!(error = %e, "kafka consumer error");
+                continue;
+            }
+        };
+        stats.messages += 1;
+        let source = Source {
+            topic: message.topic(),
+            partition: message.partition(),
+            offset: message.offset(),
+        };
+        for (line, row) in message_rows(message.payload().unwrap_or_default()) {
+            let (ids, result) = match row {
+                Ok(row) => {
+                    changed.insert(row.client_id);
+                    let result = db.process_transaction(row.client_id, row.transaction);
+                    (
+                        Some((row.client_id, row.transaction.id)),
+                        result.map_err(crate::Error::from),
+                    )
+                }
+                Err(e) => (None, Err(e.into())),
+            };
+            let Err(e) = result else {
+                stats.applied += 1;
+                continue;
+            };
+            stats.rejected += 1;
+            if let Some(topic) = &settings.rejections {
+                let event = rejection_json(&source, line, ids, &e);
+                let client_id = ids.map(|(client_id, _)| client_id);
+                send(&producer, topic, client_id, event.as_bytes())?;
+            }
+        }
+        offsets.insert(
+            (source.topic.to_owned(), source.partition),
+            source.offset + 1,
+        );
+    }
+}
+
+/// Where a message was consumed from.
+struct Source<'a> {
+    topic: &'a str,
+    partition: i32,
+    offset: i64,
+}
+
+/// One JSON object, e.g. `{"topic":"transactions","partition":0,"offset":12,"line":1,
+/// "client":7,"tx":9,"error":"withdraw overflowed - not enough money in the account",
+/// "code":"E_INSUFFICIENT_FUNDS"}`. The client and transaction are left out of rows which don't
+/// parse.
+fn rejection_json(
+    source: &Source,
+    line: usize,
+    row: Option<(ClientId, TransactionId)>,
+    e: &crate::Error,
+) -> String {
+    let mut out = String::from("{\"topic\":");
+    push_json_string(&mut out, source.topic);
+    write!(
+        out,
+        ",\"partition\":{},\"offset\":{},\"line\":{line}",
+        source.partition, source.offset
+    )
+    .unwrap();
+    if let Some((client_id, tx_id)) = row {
+        write!(out, ",\"client\":{client_id},\"tx\":{tx_id}").unwrap();
+    }
+    out.push_str(",\"error\":");
+    push_json_string(&mut out, &e.to_string());
+    out.push_str(",\"code\":");
+    push_json_string(&mut out, e.code());
+    out.push('}');
+    out
+}
+
+/// Produce a message keyed by client id if known, waiting for room in the producer's queue while
+/// it's full.
+fn send(
+    producer: &BaseProducer<Deliveries>,
+    topic: &str,
+    client_id: Option<ClientId>,
+    payload: &[u8],
+) -> io::Result<()> {
+    let key = client_id.map(|client_id| client_id.to_string());
+    let mut record = BaseRecord::<str, [u8]>::to(topic).payload(payload);
+    if let Some(key) = &key {
+        record = record.key(key.as_str());
+    }
+    loop {
+        match producer.send(record) {
+            Ok(()) => return Ok(()),
+            Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), r)) => {
+                record = r;
+                producer.poll(POLL);
+            }
+            Err((e, _)) => return Err(io::Error::other(e)),
+        }
+    }
+}
+
+/// Counts messages which couldn't be delivered, checked at each checkpoint.
+#[derive(Default)]
+struct Deliveries {
+    failed: AtomicUsize,
+}
+
+impl ClientContext for Deliveries {}
+
+impl ProducerContext for Deliveries {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &DeliveryResult<'_>, _: ()) {
+        if let Err((e, message)) = result {
+            warn!(error = %e, topic = message.topic(), "error producing a kafka message");
+            self.failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        time::{Duration, Instant},
+    };
+
+    use rdkafka::{
+        ClientConfig, Message, Offset, TopicPartitionList,
+        consumer::{BaseConsumer, Consumer},
+        mocking::MockCluster,
+        producer::{BaseProducer, BaseRecord, Producer},
+    };
+
+    use crate::{
+        amount::Amount,
+        kafka::{KafkaSettings, run},
+        sharded::ShardedDatabase,
+    };
+
+    #[test]
+    fn test_kafka() {
+        let cluster = MockCluster::new(1).unwrap();
+        for topic in ["transactions", "rejections", "balances"] {
+            cluster.create_topic(topic, 1, 1).unwrap();
+        }
+        let brokers = cluster.bootstrap_servers();
+        let producer: BaseProducer = ClientConfig::new()
+            .set("bootstrap.servers", &brokers)
+            .create()
+            .unwrap();
+        for payload in [
+            "type,client,tx,amount\ndeposit,1,1,5\nwithdrawal,1,2,10",
+            "deposit,2,3,1.5\r\n\r\nbogus",
+        ] {
+            producer
+                .send(BaseRecord::<str, str>::to("transactions").payload(payload))
+                .unwrap();
+        }
+        producer.flush(Duration::from_secs(10)).unwrap();
+
+        let settings = KafkaSettings {
+            brokers: brokers.clone(),
+            group: "payengine".to_owned(),
+            topics: vec!["transactions".to_owned()],
+            rejections: Some("rejections".to_owned()),
+            balances: Some("balances".to_owned()),
+            interval: Duration::from_millis(100),
+            options: Vec::new(),
+        };
+        let mut db = ShardedDatabase::new(2);
+        let stop = AtomicBool::new(false);
+        let mut checkpoints = 0;
+        let (stats, mut received) = std::thread::scope(|s| {
+            let runner = s.spawn(|| {
+                let checkpoint = |_: &ShardedDatabase| {
+                    checkpoints += 1;
+                    Ok(())
+                };
+                run(&settings, &mut db, checkpoint, &stop)
+            });
+            let consumer: BaseConsumer = ClientConfig::new()
+                .set("bootstrap.servers", &brokers)
+                .set("group.id", "test")
+                .set("auto.offset.reset", "earliest")
+                .create()
+                .unwrap();
+            consumer.subscribe(&["rejections", "balances"]).unwrap();
+            let mut received = Vec::new();
+            let deadline = Instant::now() + Duration::from_secs(30);
+            while received.len() < 4 && Instant::now() < deadline {
+                if let Some(message) = consumer.poll(Duration::from_millis(100)) {
+                    let message = message.unwrap();
+                    received.push((
+                        message.topic().to_owned(),
+                        message
+                            .key()
+                            .map(|key| String::from_utf8(key.to_vec()).unwrap()),
+                        String::from_utf8(message.payload().unwrap().to_vec()).unwrap(),
+                    ));
+                }
+            }
+            stop.store(true, Ordering::Relaxed);
+            (runner.join().unwrap().unwrap(), received)
+        });
+
+        assert_eq!(stats.messages, 2);
+        assert_eq!(stats.applied, 2);
+        assert_eq!(stats.rejected, 2);
+        assert_eq!(stats.checkpoints, checkpoints);
+        assert_eq!(db.get(1).unwrap().total(), Amount::parse(b"5").unwrap());
+        received.sort();
+        let received = received
+            .iter()
+            .map(|(topic, key, payload)| (topic.as_str(), key.as_deref(), payload.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            received,
+            [
+                (
+                    "balances",
+                    Some("1"),
+                    r#"{"client":1,"available":"5","held":"0","total":"5","locked":false}"#
+                ),
+                (
+                    "balances",
+                    Some("2"),
+                    r#"{"client":2,"available":"1.5","held":"0","total":"1.5","locked":false}"#
+                ),
+                (
+                    "rejections",
+                    None,
+                    r#"{"topic":"transactions","partition":0,"offset":1,"line":3,"error":"CSV missing an expected column","code":"E_MISSING_COLUMN"}"#
+                ),
+                (
+                    "rejections",
+                    Some("1"),
+                    r#"{"topic":"transactions","partition":0,"offset":0,"line":3,"client":1,"tx":2,"error":"withdraw overflowed - not enough money in the account","code":"E_INSUFFICIENT_FUNDS"}"#
+                ),
+            ]
+        );
+
+        // Both messages are committed for the group.
+        let consumer: BaseConsumer = ClientConfig::new()
+            .set("bootstrap.servers", &brokers)
+            .set("group.id", "payengine")
+            .create()
+            .unwrap();
+        let mut partitions = TopicPartitionList::new();
+        partitions.add_partition("transactions", 0);
+        let committed = consumer
+            .committed_offsets(partitions, Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(
+            committed
+                .find_partition("transactions", 0)
+                .unwrap()
+                .offset(),
+            Offset::Offset(2)
+        );
+    }
+}
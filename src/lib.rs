@@ -1,6 +1,90 @@
 pub mod accounts;
+#[cfg(all(feature = "server", unix))]
+pub mod admin;
+pub mod alerts;
 pub mod amount;
+#[cfg(feature = "amqp")]
+pub mod amqp;
+pub mod anomaly;
+#[cfg(feature = "cli")]
+pub mod args;
+#[cfg(feature = "tokio")]
+pub mod async_io;
+pub mod audit;
+pub mod breaker;
+pub mod concurrent;
+pub mod config;
+pub mod context;
+pub mod convert;
+pub mod deposits;
+pub mod diff;
+mod digits;
+pub mod engine;
 pub mod error;
+pub mod events;
+mod evict;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "flight")]
+pub mod flight;
+pub mod follow;
+pub mod history;
+#[cfg(feature = "server")]
+mod idempotency;
+#[cfg(feature = "iso20022")]
+pub mod iso20022;
+#[cfg(feature = "server")]
+mod jobs;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod memory;
+pub mod metrics;
+pub mod multifile;
+#[cfg(feature = "nats")]
+pub mod nats;
 pub mod parser;
+pub mod pipeline;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod process;
+pub mod profile;
+pub mod progress;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod quality;
+pub mod ratelimit;
+pub mod readahead;
+pub mod reader;
+#[cfg(feature = "redis")]
+pub mod redis;
+pub mod repl;
+pub mod report;
+pub mod reporter;
+pub mod reservoir;
+mod retry;
+pub mod review;
+pub mod sample;
+pub mod saved;
+pub mod schema;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod sharded;
+mod slab;
+pub mod slowlog;
+pub mod source;
+mod spill;
+pub mod split;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod stats;
+pub mod synth;
+#[cfg(feature = "server")]
+pub mod tcp;
+pub mod threads;
+pub mod verify;
+#[cfg(feature = "webhooks")]
+pub mod webhooks;
 
 pub use error::Error;
@@ -0,0 +1,92 @@
+use std::{fs::OpenOptions, io, path::Path, sync::Mutex};
+
+use payengine::args::LogFormat;
+#[cfg(feature = "otel")]
+use tracing::Subscriber;
+use tracing::level_filters::LevelFilter;
+#[cfg(feature = "otel")]
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{
+    Layer, fmt::writer::BoxMakeWriter, layer::SubscriberExt, util::SubscriberInitExt,
+};
+
+/// Tracer of `--otlp`, kept to export buffered spans on exit.
+#[cfg(feature = "otel")]
+static TRACER_PROVIDER: std::sync::OnceLock<opentelemetry_sdk::trace::SdkTracerProvider> =
+    std::sync::OnceLock::new();
+
+/// Install the global subscriber, logging to stderr, or appending to `file`. With `otlp`, spans
+/// are also exported, see [`otlp_layer`]. Fails with a message.
+pub fn init(
+    level: LevelFilter,
+    format: LogFormat,
+    file: Option<&Path>,
+    otlp: bool,
+) -> Result<(), String> {
+    let writer = match file {
+        Some(path) => BoxMakeWriter::new(Mutex::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("error opening {}: {e}", path.display()))?,
+        )),
+        None => BoxMakeWriter::new(io::stderr),
+    };
+    let fmt = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(file.is_none());
+    let fmt = match format {
+        LogFormat::Text => fmt.boxed(),
+        LogFormat::Json => fmt
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_span_list(false)
+            .boxed(),
+    };
+    let subscriber = tracing_subscriber::registry().with(fmt.with_filter(level));
+    #[cfg(feature = "otel")]
+    let subscriber = subscriber.with(otlp.then(otlp_layer).transpose()?);
+    #[cfg(not(feature = "otel"))]
+    debug_assert!(!otlp);
+    subscriber.init();
+    Ok(())
+}
+
+/// Spans of this crate down to debug level, like `parse_batch` and `apply_batch`, exported in batches over
+/// OTLP/HTTP to `$OTEL_EXPORTER_OTLP_ENDPOINT`, by default `http://localhost:4318`, regardless of
+/// the log level.
+#[cfg(feature = "otel")]
+fn otlp_layer<S>() -> Result<impl Layer<S>, String>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::{Resource, trace::SdkTracerProvider};
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()
+        .map_err(|e| format!("error creating the OTLP exporter: {e}"))?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name("payengine").build())
+        .build();
+    let tracer = provider.tracer("payengine");
+    let _ = TRACER_PROVIDER.set(provider);
+    // Not the exporter's own HTTP client.
+    let targets =
+        tracing_subscriber::filter::Targets::new().with_target("payengine", LevelFilter::DEBUG);
+    Ok(tracing_opentelemetry::layer()
+        .with_tracer(tracer)
+        .with_filter(targets))
+}
+
+/// Export spans still buffered by `--otlp`, before exiting.
+pub fn flush() {
+    #[cfg(feature = "otel")]
+    if let Some(provider) = TRACER_PROVIDER.get() {
+        let _ = provider.shutdown();
+    }
+}
@@ -1,54 +1,116 @@
-use payengine::{accounts::ClientsDatabase, parser::Row};
-use std::io::{BufRead, BufReader};
-use tracing::trace;
+use std::{process::ExitCode, time::Duration};
 
-fn main() {
-    // set e.g. RUST_LOG=trace to debug
-    tracing_subscriber::fmt::init();
+use clap::{CommandFactory, Parser};
+use payengine::{
+    args::{Cli, ClientsFileError, Command},
+    config::Config,
+};
 
-    let filename = std::env::args()
-        .nth(1)
-        .expect("expected one argument - filename");
-    let file = std::fs::File::open(&filename).expect("error opening file");
-    let mut file = BufReader::new(file);
+use crate::cli::status::{Status, fail};
 
-    // NOTE: using mmap here would be even faster as there will be 0 syscalls for the main
-    // loop involved and no extra buffer allocation. Not doing it to avoid unsafe.
-    let mut buf = Vec::<u8>::new();
-    let mut db = ClientsDatabase::default();
+mod cli;
+mod logging;
 
-    // skip header. Ignore parsing it either, assume it has fixed format.
-    let _ = file
-        .read_until(b'\n', &mut buf)
-        .expect("error reading CSV header");
+/// Fail on a `--clients-file` that couldn't be read into the settings.
+fn clients_file_failed(e: ClientsFileError) -> ! {
+    let status = match e {
+        ClientsFileError::Io { .. } => Status::Io,
+        ClientsFileError::InvalidClientId { .. } => Status::Usage,
+    };
+    fail(status, e)
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    #[cfg(feature = "otel")]
+    let otlp = cli.otlp;
+    #[cfg(not(feature = "otel"))]
+    let otlp = false;
+    if let Err(e) = logging::init(cli.log_level, cli.log_format, cli.log_file.as_deref(), otlp) {
+        fail(Status::Io, e);
+    }
+    #[cfg(feature = "sentry")]
+    if cli.sentry {
+        let reporter = payengine::reporter::SentryReporter::new(None)
+            .unwrap_or_else(|e| fail(Status::Usage, e));
+        let _ = payengine::reporter::set_reporter(Box::new(reporter));
+    }
 
-    // Parse and process all the rows.
-    loop {
-        buf.clear();
-        let sz = file.read_until(b'\n', &mut buf).expect("error reading");
-        if sz == 0 {
-            break;
+    #[cfg(feature = "config")]
+    let path = cli.config.as_deref();
+    #[cfg(not(feature = "config"))]
+    let path = None;
+    let mut config = Config::load(path).unwrap_or_else(|e| fail(Status::Usage, e));
+
+    let result = match cli
+        .command
+        .unwrap_or(Command::Process(Box::new(cli.process)))
+    {
+        Command::Process(args) if args.follow.follow => {
+            if let Err(e) = args.apply(&mut config) {
+                clients_file_failed(e);
+            }
+            cli::process::apply_memory_limit(&mut config, args.inputs.len());
+            cli::follow::run(&config, &args.inputs, &args.follow).map(|()| None)
         }
-        let line = &buf[..sz];
-        let row = match Row::parse(line) {
-            Ok(row) => row,
-            Err(e) => {
-                trace!("error parsing line {:?}: {e}", std::str::from_utf8(line));
-                continue;
+        Command::Process(args) => {
+            if let Err(e) = args.apply(&mut config) {
+                clients_file_failed(e);
             }
-        };
-        if let Err(e) = db.process_transaction(row.client_id, row.transaction) {
-            trace!(?row, "error processing transaction: {e}")
+            cli::process::apply_memory_limit(&mut config, args.inputs.len());
+            let progress = args.progress.map(|secs| {
+                Duration::try_from_secs_f64(secs).unwrap_or_else(|e| {
+                    fail(Status::Usage, format_args!("invalid --progress: {e}"))
+                })
+            });
+            cli::process::run(&config, &args.inputs, args.rows, args.profile, progress).map(Some)
         }
-    }
-
-    // Print all client accounts
-    println!("client, available, held, total, locked");
-    for (client_id, account) in db.iter() {
-        let available = account.available_for_withdrawal();
-        let held = account.held();
-        let total = account.total();
-        let locked = account.is_frozen();
-        println!("{client_id},{available},{held},{total},{locked}")
-    }
+        Command::Validate(args) => {
+            args.threads.apply(&mut config.threads);
+            cli::validate::run(&config, &args.input, args.rows, args.verbose).map(Some)
+        }
+        Command::Convert(args) => cli::convert::run(&config, &args).map(|()| None),
+        Command::Report(args) => {
+            if let Err(e) = args.output.apply(&mut config.output) {
+                clients_file_failed(e);
+            }
+            cli::report::run(&config, &args.balances).map(|()| None)
+        }
+        Command::Repl(args) => cli::repl::run(&config, &args).map(|()| None),
+        Command::Sample(args) => cli::sample::run(&args).map(|()| None),
+        Command::Split(args) => cli::split::run(&args).map(|()| None),
+        Command::Diff(args) => match cli::diff::run(&args) {
+            Ok(false) => return ExitCode::from(Status::Differ as u8),
+            result => result.map(|_| None),
+        },
+        Command::Verify(args) => cli::verify::run(&config, &args).map(|()| None),
+        Command::Risk(args) => cli::risk::run(&config, &args).map(|()| None),
+        Command::Inspect(args) => cli::inspect::run(&args).map(|()| None),
+        Command::ExportSchema(args) => cli::schema::run(&args).map(|()| None),
+        Command::Completions(args) => {
+            cli::completions::run(args.shell, Cli::command()).map(|()| None)
+        }
+        #[cfg(feature = "server")]
+        Command::Serve(args) => cli::serve::run(&config, &args).map(|()| None),
+        #[cfg(feature = "kafka")]
+        Command::Kafka(args) => cli::kafka::run(&config, &args).map(|()| None),
+        #[cfg(feature = "nats")]
+        Command::Nats(args) => cli::nats::run(&config, &args).map(|()| None),
+        #[cfg(feature = "amqp")]
+        Command::Amqp(args) => cli::amqp::run(&config, &args).map(|()| None),
+        #[cfg(feature = "redis")]
+        Command::Redis(args) => {
+            if let Err(e) = args.output.apply(&mut config.output) {
+                clients_file_failed(e);
+            }
+            cli::redis::run(&config, &args).map(|()| None)
+        }
+    };
+    let code = match result {
+        Ok(Some(summary)) => summary.finish(),
+        Ok(None) => ExitCode::SUCCESS,
+        Err(e) => fail(Status::Io, format_args!("error writing output: {e}")),
+    };
+    logging::flush();
+    code
 }
@@ -0,0 +1,198 @@
+use std::{fmt, num::NonZeroUsize, str::FromStr};
+
+use crate::{
+    accounts::{Account, ClientId, Transaction},
+    digits,
+    parser::Row,
+    pipeline::{self, ParseFailure},
+    report, sharded,
+};
+
+/// A size in bytes, written as a number with an optional `K`, `M`, `G` or `T` suffix, in powers
+/// of 1024, e.g. `8G`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "String", into = "String"))]
+pub struct ByteSize(pub u64);
+
+const UNITS: [(u8, u32); 4] = [(b'T', 40), (b'G', 30), (b'M', 20), (b'K', 10)];
+
+impl FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid size {s:?}, expected e.g. 512M or 8G");
+        let bytes = s.trim().as_bytes();
+        let bytes = bytes.strip_suffix(b"B").unwrap_or(bytes);
+        let (number, shift) = match UNITS
+            .iter()
+            .find(|(unit, _)| bytes.last().map(u8::to_ascii_uppercase) == Some(*unit))
+        {
+            Some((_, shift)) => (&bytes[..bytes.len() - 1], *shift),
+            None => (bytes, 0),
+        };
+        digits::parse_u64(number)
+            .and_then(|n| n.checked_mul(1 << shift))
+            .map(ByteSize)
+            .ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for ByteSize {
+    /// In the largest unit that divides the size.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match UNITS
+            .iter()
+            .find(|(_, shift)| self.0 != 0 && self.0.is_multiple_of(1 << shift))
+        {
+            Some((unit, shift)) => write!(f, "{}{}", self.0 >> shift, char::from(*unit)),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl TryFrom<String> for ByteSize {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ByteSize> for String {
+    fn from(size: ByteSize) -> Self {
+        size.to_string()
+    }
+}
+
+// Bytes per undisputed deposit in memory: 12 in `SortedVecDeposits`, up to twice that after its
+// arrays grow, plus a 16-byte record while being spilled.
+const DEPOSIT_BYTES: u64 = 40;
+// Fewer deposits per shard between spills would write a file every few milliseconds.
+const MIN_SPILL_WINDOW: usize = 1 << 16;
+// Code, stacks and allocator overhead not accounted for otherwise.
+const BASE_BYTES: u64 = 16 << 20;
+
+/// Settings keeping a run under a memory limit, from [`MemoryPlan::new`].
+///
+/// Only deposits grow with the input, so bounding memory means spilling them to disk (see
+/// [`crate::accounts::ClientsDatabase::enable_spill`]) once a window of them is in memory. The
+/// rest of the limit covers read buffers, queues between threads, accounts and the report, which
+/// depend on the thread count. Half of what remains after those goes to the window, the other
+/// half is headroom for disputed deposits, which stay in memory, and the in-memory index of
+/// spilled ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryPlan {
+    /// At most the requested count, lowered if buffers of that many threads don't fit.
+    pub threads: NonZeroUsize,
+    /// Undisputed deposits per shard between spills.
+    pub spill_window: usize,
+}
+
+impl MemoryPlan {
+    /// Plan for `threads` applier threads reading `inputs` files, or the most that fit under
+    /// `limit`. Fails with what would be needed if not even one thread does.
+    pub fn new(limit: ByteSize, threads: usize, inputs: usize) -> Result<Self, String> {
+        let min_deposits = 2 * MIN_SPILL_WINDOW as u64 * DEPOSIT_BYTES;
+        let mut threads = threads.max(1);
+        loop {
+            let fixed = fixed_bytes(threads, inputs);
+            let deposits = limit.0.saturating_sub(fixed) / 2 / DEPOSIT_BYTES / threads as u64;
+            let spill_window = usize::try_from(deposits).unwrap_or(usize::MAX);
+            if spill_window >= MIN_SPILL_WINDOW {
+                return Ok(MemoryPlan {
+                    threads: NonZeroUsize::new(threads).unwrap(),
+                    spill_window,
+                });
+            }
+            if threads == 1 {
+                return Err(format!(
+                    "memory limit {limit} is too low, buffers need {} and deposits at least {}",
+                    ByteSize(fixed.next_multiple_of(1 << 20)),
+                    ByteSize(min_deposits.next_multiple_of(1 << 20)),
+                ));
+            }
+            threads -= 1;
+        }
+    }
+}
+
+/// Memory used regardless of the input's length.
+fn fixed_bytes(threads: usize, inputs: usize) -> u64 {
+    let size = |bytes: usize| bytes as u64;
+    let threads = threads as u64;
+    // Every client id may have an account, in a hash table at least half full.
+    let accounts = size((ClientId::MAX as usize + 1) * 2 * size_of::<(ClientId, Account)>());
+    // Input read by the parser thread, and batches of rows queued for distribution.
+    #[cfg(feature = "rayon")]
+    let read = size(3 * pipeline::PARALLEL_CHUNK_LEN * rayon::current_num_threads());
+    #[cfg(all(feature = "direct-io", not(feature = "rayon")))]
+    let read = size((crate::readahead::QUEUED_BUFFERS + 2) * crate::readahead::BUFFER_LEN);
+    #[cfg(not(any(feature = "rayon", feature = "direct-io")))]
+    let read = size(64 << 10);
+    let parsed = size(
+        (pipeline::QUEUED_BATCHES + 2)
+            * pipeline::BATCH_LEN
+            * size_of::<Result<Row, ParseFailure>>(),
+    );
+    let per_file = if inputs > 1 {
+        // Up to one file per thread is open, each with a queue to every worker.
+        let queues = size(
+            (crate::multifile::QUEUED_BATCHES + 2)
+                * crate::multifile::BATCH_LEN
+                * size_of::<(usize, ClientId, Transaction)>(),
+        );
+        (read + parsed + threads * queues) * threads.min(inputs as u64)
+    } else {
+        let queues = size(
+            (sharded::QUEUED_BATCHES + 2)
+                * sharded::BATCH_LEN
+                * size_of::<(ClientId, Transaction)>(),
+        );
+        read + parsed + threads * queues
+    };
+    // The output buffer, and every account formatted at once when the report is written per shard.
+    let report = size(report::BUFFER_CAPACITY + (ClientId::MAX as usize + 1) * 64);
+    BASE_BYTES + accounts + per_file + report
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::{ByteSize, MemoryPlan};
+
+    #[test]
+    fn test_byte_size() {
+        assert_eq!("8G".parse(), Ok(ByteSize(8 << 30)));
+        assert_eq!("512m".parse(), Ok(ByteSize(512 << 20)));
+        assert_eq!("64KB".parse(), Ok(ByteSize(64 << 10)));
+        assert_eq!("1000".parse(), Ok(ByteSize(1000)));
+        assert!("".parse::<ByteSize>().is_err());
+        assert!("G".parse::<ByteSize>().is_err());
+        assert!("1.5G".parse::<ByteSize>().is_err());
+        assert!("99999999999T".parse::<ByteSize>().is_err());
+        assert_eq!(ByteSize(8 << 30).to_string(), "8G");
+        assert_eq!(ByteSize(1536 << 20).to_string(), "1536M");
+        assert_eq!(ByteSize(1000).to_string(), "1000");
+    }
+
+    #[test]
+    fn test_memory_plan() {
+        let plan = MemoryPlan::new(ByteSize(8 << 30), 8, 1).unwrap();
+        assert_eq!(plan.threads.get(), 8);
+        // Most of half the limit.
+        let deposits = (plan.spill_window * 8) as u64 * 40;
+        assert!(deposits < 4 << 30 && deposits > 3 << 30);
+
+        // More threads need more buffers, leaving less for deposits.
+        let many = MemoryPlan::new(ByteSize(8 << 30), 64, 1).unwrap();
+        assert!(many.spill_window * 64 < plan.spill_window * 8);
+
+        // Too small for 64 threads, but fits fewer.
+        let small = MemoryPlan::new(ByteSize(256 << 20), 64, 64).unwrap();
+        assert!(small.threads.get() < 64);
+        assert!(small.spill_window >= 1 << 16);
+
+        let err = MemoryPlan::new(ByteSize(8 << 20), 4, 1).unwrap_err();
+        assert!(err.starts_with("memory limit 8M is too low, buffers need "));
+    }
+}
@@ -0,0 +1,280 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::{
+    accounts::{ClientId, TransactionKind},
+    error::ErrorKind,
+    process::RejectionCounts,
+};
+
+/// Counters of a bulk processing run, from [`crate::process::ProcessReport::metrics`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Transactions submitted to the database, applied or not. Rows that failed to parse before
+    /// getting there aren't counted.
+    pub rows: usize,
+    /// Applied transactions by kind, see [`Self::applied`].
+    pub applied: AppliedCounts,
+    /// Rejected transactions by kind of error.
+    pub rejections: RejectionCounts,
+    /// Accounts opened by a first deposit.
+    pub accounts_created: usize,
+    /// Applied disputes.
+    pub disputes_opened: usize,
+    /// Applied resolves and chargebacks.
+    pub disputes_closed: usize,
+}
+
+/// Number of applied transactions by kind.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AppliedCounts([usize; TransactionKind::ALL.len()]);
+
+impl AppliedCounts {
+    pub fn add(&mut self, kind: TransactionKind) {
+        self.0[kind as usize] += 1;
+    }
+
+    pub fn get(&self, kind: TransactionKind) -> usize {
+        self.0[kind as usize]
+    }
+
+    pub fn total(&self) -> usize {
+        self.0.iter().sum()
+    }
+
+    pub fn merge(&mut self, other: &AppliedCounts) {
+        for (count, other) in self.0.iter_mut().zip(other.0) {
+            *count += other;
+        }
+    }
+}
+
+impl Metrics {
+    /// Add the counters of another run or part of one.
+    pub fn merge(&mut self, other: &Metrics) {
+        self.rows += other.rows;
+        self.applied.merge(&other.applied);
+        self.rejections.merge(&other.rejections);
+        self.accounts_created += other.accounts_created;
+        self.disputes_opened += other.disputes_opened;
+        self.disputes_closed += other.disputes_closed;
+    }
+
+    /// One line of JSON, with rejections keyed by error kind and only kinds that occurred.
+    pub fn to_json(&self) -> String {
+        let mut out = format!("{{\"rows\":{},\"applied\":{{", self.rows);
+        for (idx, kind) in TransactionKind::ALL.into_iter().enumerate() {
+            if idx > 0 {
+                out.push(',');
+            }
+            write!(out, "\"{}\":{}", kind.as_str(), self.applied.get(kind)).unwrap();
+        }
+        out.push_str("},\"rejected\":{");
+        for (idx, (kind, count)) in self.rejections.iter().enumerate() {
+            if idx > 0 {
+                out.push(',');
+            }
+            write!(out, "\"{kind:?}\":{count}").unwrap();
+        }
+        write!(
+            out,
+            "}},\"accounts_created\":{},\"disputes_opened\":{},\"disputes_closed\":{}}}",
+            self.accounts_created, self.disputes_opened, self.disputes_closed
+        )
+        .unwrap();
+        out
+    }
+}
+
+/// Transactions and rejections by client, to spot clients with most of their rows rejected,
+/// usually a broken integration upstream.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClientRejections {
+    // Transactions by client id, dense as ids are small.
+    rows: Vec<usize>,
+    // Only clients with rejections.
+    rejections: BTreeMap<ClientId, RejectionCounts>,
+}
+
+/// Counters of one client, from [`ClientRejections::worst`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientRejectionStats {
+    pub client_id: ClientId,
+    pub rows: usize,
+    pub rejections: RejectionCounts,
+}
+
+impl ClientRejections {
+    /// Count a transaction of the client, rejected with `rejection` if any.
+    pub fn add(&mut self, client_id: ClientId, rejection: Option<ErrorKind>) {
+        self.add_applied(client_id, 1);
+        if let Some(kind) = rejection {
+            self.rejections.entry(client_id).or_default().add(kind);
+        }
+    }
+
+    /// Count `count` applied transactions of the client.
+    pub fn add_applied(&mut self, client_id: ClientId, count: usize) {
+        let idx = client_id as usize;
+        if idx >= self.rows.len() {
+            self.rows.resize(idx + 1, 0);
+        }
+        self.rows[idx] += count;
+    }
+
+    pub fn merge(&mut self, other: &ClientRejections) {
+        if other.rows.len() > self.rows.len() {
+            self.rows.resize(other.rows.len(), 0);
+        }
+        for (rows, other) in self.rows.iter_mut().zip(&other.rows) {
+            *rows += other;
+        }
+        for (&client_id, counts) in &other.rejections {
+            self.rejections.entry(client_id).or_default().merge(counts);
+        }
+    }
+
+    pub fn get(&self, client_id: ClientId) -> ClientRejectionStats {
+        ClientRejectionStats {
+            client_id,
+            rows: self.rows.get(client_id as usize).copied().unwrap_or(0),
+            rejections: self.rejections.get(&client_id).copied().unwrap_or_default(),
+        }
+    }
+
+    /// Clients with rejections, the highest share of their rows rejected first, then the most
+    /// rejections, then by client id.
+    pub fn worst(&self) -> Vec<ClientRejectionStats> {
+        let mut clients = self
+            .rejections
+            .keys()
+            .map(|&client_id| self.get(client_id))
+            .collect::<Vec<_>>();
+        clients.sort_by(|a, b| {
+            let rate = |c: &ClientRejectionStats, other: &ClientRejectionStats| {
+                c.rejected() * other.rows.max(1)
+            };
+            rate(b, a)
+                .cmp(&rate(a, b))
+                .then(b.rejected().cmp(&a.rejected()))
+        });
+        clients
+    }
+}
+
+impl ClientRejectionStats {
+    pub fn rejected(&self) -> usize {
+        self.rejections.total()
+    }
+
+    /// Share of the client's rows rejected.
+    pub fn percent(&self) -> f64 {
+        self.rejected() as f64 * 100.0 / self.rows.max(1) as f64
+    }
+
+    /// One JSON object, e.g. `{"client":7,"rows":10,"rejected":4,"reasons":{"AccountNotFound":4}}`.
+    pub fn to_json(&self) -> String {
+        let mut out = format!(
+            "{{\"client\":{},\"rows\":{},\"rejected\":{},\"reasons\":{{",
+            self.client_id,
+            self.rows,
+            self.rejected()
+        );
+        for (idx, (kind, count)) in self.rejections.iter().enumerate() {
+            if idx > 0 {
+                out.push(',');
+            }
+            write!(out, "\"{kind:?}\":{count}").unwrap();
+        }
+        out.push_str("}}");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::{ClientsDatabase, Transaction, TransactionKind::*},
+        amount::Amount,
+        error::ErrorKind,
+        metrics::ClientRejections,
+        sharded::ShardedDatabase,
+    };
+
+    #[test]
+    fn test_metrics() {
+        let t = |kind, id, amount: &str| Transaction {
+            kind,
+            id,
+            amount: Amount::parse(amount.as_bytes()).unwrap_or_default(),
+        };
+        let transactions = [
+            (1, t(Deposit, 1, "10")),
+            (1, t(Deposit, 2, "5")),
+            (2, t(Deposit, 3, "1")),
+            (3, t(Withdrawal, 4, "1")),
+            (1, t(Dispute, 1, "")),
+            (1, t(Resolve, 1, "")),
+            (2, t(Dispute, 3, "")),
+            (2, t(Chargeback, 3, "")),
+            (1, t(Withdrawal, 5, "100")),
+            (1, t(Dispute, 2, "")),
+        ];
+
+        let mut db = ClientsDatabase::new();
+        let metrics = db.process_all(transactions).metrics();
+        assert_eq!(metrics.rows, 10);
+        assert_eq!(metrics.applied.get(Deposit), 3);
+        assert_eq!(metrics.applied.get(Withdrawal), 0);
+        assert_eq!(metrics.applied.total(), 8);
+        assert_eq!(metrics.rejections.get(ErrorKind::AccountNotFound), 1);
+        assert_eq!(metrics.rejections.get(ErrorKind::WithdrawOverflow), 1);
+        assert_eq!(metrics.accounts_created, 2);
+        assert_eq!(metrics.disputes_opened, 3);
+        assert_eq!(metrics.disputes_closed, 2);
+        assert_eq!(
+            metrics.to_json(),
+            "{\"rows\":10,\"applied\":{\"deposit\":3,\"withdrawal\":0,\"dispute\":3,\
+             \"resolve\":1,\"chargeback\":1},\"rejected\":{\"WithdrawOverflow\":1,\
+             \"AccountNotFound\":1},\"accounts_created\":2,\"disputes_opened\":3,\
+             \"disputes_closed\":2}"
+        );
+
+        // Same across shards.
+        let mut db = ShardedDatabase::new(3);
+        assert_eq!(db.process_parallel(transactions).metrics(), metrics);
+    }
+
+    #[test]
+    fn test_client_rejections() {
+        let mut clients = ClientRejections::default();
+        for (client_id, rejection) in [
+            (1, None),
+            (1, Some(ErrorKind::WithdrawOverflow)),
+            (1, None),
+            (1, None),
+            (3, Some(ErrorKind::AccountNotFound)),
+            (2, None),
+        ] {
+            clients.add(client_id, rejection);
+        }
+        let mut other = ClientRejections::default();
+        other.add(1, Some(ErrorKind::DuplicateDispute));
+        other.add(9, None);
+        clients.merge(&other);
+
+        let worst = clients.worst();
+        assert_eq!(
+            worst.iter().map(|c| c.client_id).collect::<Vec<_>>(),
+            [3, 1]
+        );
+        assert_eq!(worst[1].rows, 5);
+        assert_eq!(worst[1].percent(), 40.0);
+        assert_eq!(
+            worst[1].to_json(),
+            "{\"client\":1,\"rows\":5,\"rejected\":2,\"reasons\":{\"WithdrawOverflow\":1,\
+             \"DuplicateDispute\":1}}"
+        );
+        assert_eq!(clients.get(9).rows, 1);
+        assert_eq!(clients.get(9).rejected(), 0);
+    }
+}
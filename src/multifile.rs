@@ -0,0 +1,280 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::mpsc::{Receiver, SyncSender, sync_channel},
+};
+
+use tracing::{Span, debug_span, info_span};
+
+use crate::{
+    Error,
+    accounts::{ClientId, Transaction},
+    error::ParseError,
+    history::Timestamp,
+    pipeline::{ParseFailure, RowStream},
+    process::ProcessReport,
+    progress::CountingReader,
+    sharded::ShardedDatabase,
+};
+
+// Transactions sent to a worker at once.
+pub(crate) const BATCH_LEN: usize = 1024;
+// Batches buffered per file and worker before the file's reader blocks.
+pub(crate) const QUEUED_BATCHES: usize = 16;
+// Timestamps of a file's rows start at `file index << FILE_TIMESTAMP_BITS`, as row counts aren't
+// known upfront. Leaves room for 2^40 rows per file and 2^24 files.
+const FILE_TIMESTAMP_BITS: u32 = 40;
+
+type Batch = Vec<(usize, ClientId, Transaction)>;
+
+/// Outcome of processing one file with [`ShardedDatabase::process_files`].
+#[derive(Debug)]
+pub struct FileReport {
+    pub path: PathBuf,
+    /// Rejections are by 0-based index among the file's successfully parsed rows.
+    pub process: ProcessReport,
+    pub parse_failures: Vec<ParseFailure>,
+}
+
+impl ShardedDatabase {
+    /// Apply several input files which cover consecutive ranges of time, given in that order.
+    ///
+    /// Up to `max_open` files are read and parsed concurrently, each on its own thread, while
+    /// shard workers apply transactions. Every worker applies a file's transactions only after all
+    /// transactions of the previous files, so each client's rows are applied in global order, as if
+    /// the files were concatenated.
+    ///
+    /// Fails without processing anything if a file can't be opened.
+    pub fn process_files(
+        &mut self,
+        paths: &[impl AsRef<Path>],
+        max_open: usize,
+    ) -> Result<Vec<FileReport>, (PathBuf, Error)> {
+        let files = paths
+            .iter()
+            .map(|p| File::open(p).map_err(|e| (p.as_ref().to_owned(), ParseError::Io(e).into())))
+            .collect::<Result<Vec<_>, _>>()?;
+        let shard_count = self.shard_count();
+        let base = self.clock();
+        let progress = self.shards()[0].progress().cloned().unwrap_or_default();
+
+        // One channel per file and shard.
+        let (senders, receivers): (Vec<Vec<_>>, Vec<Vec<_>>) = (0..files.len())
+            .map(|_| {
+                (0..shard_count)
+                    .map(|_| sync_channel::<Batch>(QUEUED_BATCHES))
+                    .unzip()
+            })
+            .unzip();
+        // receivers[file][shard] -> by_shard[shard][file]
+        let mut by_shard = (0..shard_count).map(|_| Vec::new()).collect::<Vec<_>>();
+        for file_receivers in receivers {
+            for (shard, rx) in file_receivers.into_iter().enumerate() {
+                by_shard[shard].push(rx);
+            }
+        }
+
+        // Threads don't inherit the current span.
+        let parent = Span::current();
+        let (reports, parse_failures) = std::thread::scope(|s| {
+            let workers = self
+                .shards_mut()
+                .iter_mut()
+                .zip(by_shard)
+                .enumerate()
+                .map(|(idx, (shard, receivers))| {
+                    let span = info_span!(parent: &parent, "apply", shard = idx);
+                    s.spawn(move || {
+                        let _apply = span.entered();
+                        receivers
+                            .into_iter()
+                            .enumerate()
+                            .map(|(file, rx): (usize, Receiver<Batch>)| {
+                                let mut report = shard.new_report();
+                                let first = base + ((file as Timestamp) << FILE_TIMESTAMP_BITS);
+                                for batch in rx {
+                                    let _span = debug_span!(
+                                        "apply_batch",
+                                        file,
+                                        first_row = batch.first().map(|&(idx, ..)| idx),
+                                        transactions = batch.len()
+                                    )
+                                    .entered();
+                                    shard.process_indexed(batch, first, &mut report);
+                                    if report.aborted.is_some() {
+                                        break;
+                                    }
+                                }
+                                // Files are partitions in time, so deferred transactions don't
+                                // wait for the next one.
+                                shard.retry_remaining(first, &mut report);
+                                report
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            // Readers are started in file order, keeping at most max_open of them running.
+            // Workers are never ahead of the earliest unfinished file, so its reader can always
+            // make progress and this can't deadlock.
+            let mut readers = std::collections::VecDeque::new();
+            let mut parse_failures = Vec::with_capacity(files.len());
+            for (idx, (file, senders)) in files.into_iter().zip(senders).enumerate() {
+                if readers.len() == max_open.max(1) {
+                    let reader: std::thread::ScopedJoinHandle<_> = readers.pop_front().unwrap();
+                    parse_failures.push(reader.join().unwrap());
+                }
+                let span = info_span!(parent: &parent, "file", index = idx);
+                let file = CountingReader::new(file, progress.clone());
+                readers.push_back(s.spawn(move || span.in_scope(|| distribute(file, senders))));
+            }
+            parse_failures.extend(readers.into_iter().map(|r| r.join().unwrap()));
+
+            let mut reports = Vec::new();
+            for worker in workers {
+                for (file, r) in worker.join().unwrap().into_iter().enumerate() {
+                    if reports.len() <= file {
+                        reports.push(ProcessReport::default());
+                    }
+                    reports[file].merge(r);
+                }
+            }
+            (reports, parse_failures)
+        });
+        self.set_clock(base + ((paths.len() as Timestamp) << FILE_TIMESTAMP_BITS));
+
+        Ok(paths
+            .iter()
+            .zip(reports)
+            .zip(parse_failures)
+            .map(|((path, mut process), parse_failures)| {
+                process.rejections.sort_unstable_by_key(|(idx, _)| *idx);
+                process.dead_letters.sort_unstable_by_key(|d| d.index);
+                FileReport {
+                    path: path.as_ref().to_owned(),
+                    process,
+                    parse_failures,
+                }
+            })
+            .collect())
+    }
+}
+
+/// Parse a file and send its rows to the workers by shard. Returns the parse failures.
+fn distribute(file: CountingReader<File>, senders: Vec<SyncSender<Batch>>) -> Vec<ParseFailure> {
+    let shard_count = senders.len();
+    let mut failures = Vec::new();
+    let mut batches = (0..shard_count)
+        .map(|_| Vec::with_capacity(BATCH_LEN))
+        .collect::<Vec<_>>();
+    let rows = RowStream::spawn(BufReader::new(file)).filter_map(|r| match r {
+        Ok(row) => Some(row),
+        Err(f) => {
+            failures.push(f);
+            None
+        }
+    });
+    for (idx, row) in rows.enumerate() {
+        let shard = row.client_id as usize % shard_count;
+        batches[shard].push((idx, row.client_id, row.transaction));
+        if batches[shard].len() == BATCH_LEN {
+            let batch = std::mem::replace(&mut batches[shard], Vec::with_capacity(BATCH_LEN));
+            // Only fails if the worker panicked, which is propagated on join.
+            let _ = senders[shard].send(batch);
+        }
+    }
+    for (batch, tx) in batches.into_iter().zip(senders) {
+        let _ = tx.send(batch);
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::ClientsDatabase, parser::Row, pipeline::RowStream, sharded::ShardedDatabase,
+    };
+
+    #[test]
+    fn test_process_files_matches_concatenated() {
+        // Disputes refer to deposits of the same client up to one file back.
+        let line = |i: u32| match i % 5 {
+            0..=2 => format!("deposit, {}, {i}, 1.5\n", i % 13),
+            3 => format!("withdrawal, {}, {i}, 4\n", i % 13),
+            _ => format!("dispute, {}, {}, \n", i % 13, i - 13 * 41),
+        };
+        let dir = std::env::temp_dir();
+        let mut paths = Vec::new();
+        let mut concatenated = b"type, client, tx, amount\n".to_vec();
+        for file in 0..7u32 {
+            let mut contents = b"type, client, tx, amount\n".to_vec();
+            for i in file * 3000..(file + 1) * 3000 {
+                contents.extend_from_slice(line(i + 1000).as_bytes());
+                concatenated.extend_from_slice(line(i + 1000).as_bytes());
+            }
+            if file == 2 {
+                contents.extend_from_slice(b"bad\n");
+            }
+            let path = dir.join(format!("payengine-multifile-{}-{file}", std::process::id()));
+            std::fs::write(&path, contents).unwrap();
+            paths.push(path);
+        }
+
+        let mut sequential = ClientsDatabase::new();
+        let expected = sequential.process_all(
+            RowStream::spawn(std::io::Cursor::new(concatenated))
+                .map(|r| r.unwrap())
+                .map(
+                    |Row {
+                         client_id,
+                         transaction,
+                     }| (client_id, transaction),
+                ),
+        );
+
+        let mut db = ShardedDatabase::new(4);
+        let reports = db.process_files(&paths, 3).unwrap();
+        for path in &paths {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(reports.len(), 7);
+        assert_eq!(reports[2].parse_failures.len(), 1);
+        assert_eq!(reports[2].parse_failures[0].line, 3002);
+        assert!(reports.iter().all(|r| r.process.processed == 3000));
+        assert_eq!(
+            reports.iter().map(|r| r.process.applied).sum::<usize>(),
+            expected.applied
+        );
+        // Rejections are numbered within each file.
+        let rejections = reports
+            .iter()
+            .enumerate()
+            .flat_map(|(file, r)| {
+                r.process
+                    .rejections
+                    .iter()
+                    .map(move |(i, _)| file * 3000 + i)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rejections,
+            expected
+                .rejections
+                .iter()
+                .map(|(i, _)| *i)
+                .collect::<Vec<_>>()
+        );
+        assert!(
+            (0..13).any(|c| sequential.get(c).unwrap().view().held > crate::amount::Amount::zero())
+        );
+        for client_id in 0..13 {
+            assert_eq!(
+                db.get(client_id).unwrap().view(),
+                sequential.get(client_id).unwrap().view()
+            );
+        }
+    }
+}
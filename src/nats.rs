@@ -0,0 +1,264 @@
+use std::{
+    collections::HashSet,
+    io,
+    sync::mpsc::Receiver,
+    time::{Duration, Instant},
+};
+
+use async_nats::jetstream::{
+    self,
+    consumer::{AckPolicy, PullConsumer, pull},
+    context::PublishAckFuture,
+};
+use futures_util::{FutureExt, StreamExt};
+use tracing::{debug, trace};
+
+use crate::{events::AccountEvent, parser::message_rows, sharded::ShardedDatabase};
+
+// How long a fetch waits for messages, bounding how late checkpoints and stopping are.
+const POLL: Duration = Duration::from_millis(500);
+// Added to twice the checkpoint interval for the consumer's ack wait, covering slow checkpoints.
+const ACK_MARGIN: Duration = Duration::from_secs(30);
+
+/// Settings of [`run`].
+#[derive(Clone, Debug)]
+pub struct NatsSettings {
+    /// Server to connect to, e.g. `nats://localhost:4222`.
+    pub url: String,
+    /// JetStream stream of the input, each message one or more rows of the CSV input.
+    pub stream: String,
+    /// Durable pull consumer of the stream, created if missing.
+    pub consumer: String,
+    /// Only consume the stream's messages on this subject, e.g. `transactions.>`.
+    pub subject: Option<String>,
+    /// Subject prefix of the events of applied transactions, published as
+    /// `<prefix>.<client id>`, see [`AccountEvent::to_json`]. They need a stream of their own
+    /// to be acknowledged.
+    pub events: Option<String>,
+    /// Messages fetched at once.
+    pub batch: usize,
+    /// Time between checkpoints.
+    pub interval: Duration,
+    /// Messages applied but not acknowledged yet, after which a checkpoint is made early. Also the
+    /// `max_ack_pending` of a consumer created.
+    pub max_pending: usize,
+}
+
+/// Counts of a [`run`] so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NatsStats {
+    pub messages: u64,
+    /// Messages delivered again before being acknowledged, and not applied again.
+    pub redelivered: u64,
+    pub applied: u64,
+    /// Rows rejected by the database or failing to parse.
+    pub rejected: u64,
+    pub events: u64,
+    pub checkpoints: u64,
+}
+
+/// Apply the rows of a JetStream stream to `db` until `stop` completes.
+///
+/// Messages are only acknowledged at checkpoints, every `interval`, after `max_pending` messages,
+/// and once more when stopping: once the events of the transactions applied are acknowledged by
+/// their stream and `checkpoint` saved the state, e.g. as a snapshot. A crash mid-batch, or
+/// between checkpoints, thus loses nothing, as the messages not acknowledged yet are delivered
+/// again to the restart from the last state saved. Only those of a checkpoint interrupted after
+/// saving the state are applied twice.
+///
+/// Fails on errors of the server, if the events can't be published, or a checkpoint fails.
+pub async fn run(
+    settings: &NatsSettings,
+    db: &mut ShardedDatabase,
+    mut checkpoint: impl FnMut(&ShardedDatabase) -> io::Result<()>,
+    stop: impl Future<Output = ()>,
+) -> io::Result<NatsStats> {
+    let client = async_nats::connect(&settings.url)
+        .await
+        .map_err(io::Error::other)?;
+    let jetstream = jetstream::new(client.clone());
+    let consumer: PullConsumer = jetstream
+        .get_stream(&settings.stream)
+        .await
+        .map_err(io::Error::other)?
+        .get_or_create_consumer(
+            &settings.consumer,
+            pull::Config {
+                durable_name: Some(settings.consumer.clone()),
+                filter_subject: settings.subject.clone().unwrap_or_default(),
+                ack_policy: AckPolicy::Explicit,
+                // Not delivered again while waiting for a checkpoint.
+                ack_wait: settings.interval * 2 + ACK_MARGIN,
+                max_ack_pending: settings.max_pending as i64,
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(io::Error::other)?;
+    let events = settings.events.as_ref().map(|_| db.subscribe());
+
+    let mut stats = NatsStats::default();
+    let mut pending = Pending::<jetstream::Message>::default();
+    let mut published = Vec::<PublishAckFuture>::new();
+    let mut last_checkpoint = Instant::now();
+    tokio::pin!(stop);
+    loop {
+        let stopping = stop.as_mut().now_or_never().is_some();
+        if stopping
+            || last_checkpoint.elapsed() >= settings.interval
+            || pending.len() >= settings.max_pending
+        {
+            for ack in published.drain(..) {
+                ack.await.map_err(io::Error::other)?;
+            }
+            checkpoint(db)?;
+            for message in pending.take() {
+                message.ack().await.map_err(io::Error::other)?;
+            }
+            client.flush().await.map_err(io::Error::other)?;
+            stats.checkpoints += 1;
+            debug!(
+                messages = stats.messages,
+                applied = stats.applied,
+                rejected = stats.rejected,
+                "nats checkpoint"
+            );
+            if stopping {
+                return Ok(stats);
+            }
+            last_checkpoint = Instant::now();
+        }
+
+        let mut batch = consumer
+            .fetch()
+            .max_messages(settings.batch)
+            .expires(POLL)
+            .messages()
+            .await
+            .map_err(io::Error::other)?;
+        while let Some(message) = batch.next().await {
+            let message = message.map_err(io::Error::other)?;
+            stats.messages += 1;
+            let sequence = message.info().map_err(io::Error::other)?.stream_sequence;
+            let payload = message.payload.clone();
+            if !pending.add(sequence, message) {
+                stats.redelivered += 1;
+                continue;
+            }
+            for (line, row) in message_rows(&payload) {
+                let result = row.map_err(crate::Error::from).and_then(|row| {
+                    db.process_transaction(row.client_id, row.transaction)
+                        .map_err(Into::into)
+                });
+                match result {
+                    Ok(()) => stats.applied += 1,
+                    Err(e) => {
+                        stats.rejected += 1;
+                        trace!(sequence, line, code = e.code(), "rejected row: {e}");
+                    }
+                }
+            }
+            if let (Some(prefix), Some(events)) = (&settings.events, &events) {
+                stats.events += publish(&jetstream, prefix, events, &mut published).await?;
+            }
+        }
+    }
+}
+
+/// Publish the events received since the last call, keeping the futures of their
+/// acknowledgements. Returns how many there were.
+async fn publish(
+    jetstream: &jetstream::Context,
+    prefix: &str,
+    events: &Receiver<AccountEvent>,
+    published: &mut Vec<PublishAckFuture>,
+) -> io::Result<u64> {
+    let mut count = 0;
+    for event in events.try_iter() {
+        let subject = event_subject(prefix, &event);
+        let ack = jetstream
+            .publish(subject, event.to_json().into())
+            .await
+            .map_err(io::Error::other)?;
+        published.push(ack);
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn event_subject(prefix: &str, event: &AccountEvent) -> String {
+    format!("{prefix}.{}", event.client_id)
+}
+
+/// Messages applied but not acknowledged yet, by stream sequence.
+struct Pending<M> {
+    sequences: HashSet<u64>,
+    messages: Vec<M>,
+}
+
+impl<M> Default for Pending<M> {
+    fn default() -> Self {
+        Pending {
+            sequences: HashSet::new(),
+            messages: Vec::new(),
+        }
+    }
+}
+
+impl<M> Pending<M> {
+    /// Add a message delivered with `sequence` in its stream, false if it was already applied, e.g.
+    /// delivered again after its ack wait. It's kept either way, as acknowledging any delivery
+    /// acknowledges the message.
+    fn add(&mut self, sequence: u64, message: M) -> bool {
+        self.messages.push(message);
+        self.sequences.insert(sequence)
+    }
+
+    fn len(&self) -> usize {
+        self.sequences.len()
+    }
+
+    /// The messages to acknowledge.
+    fn take(&mut self) -> Vec<M> {
+        self.sequences.clear();
+        std::mem::take(&mut self.messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::{AccountView, Transaction, TransactionKind::Deposit},
+        amount::Amount,
+        events::AccountEvent,
+        nats::{Pending, event_subject},
+    };
+
+    #[test]
+    fn test_pending() {
+        let mut pending = Pending::default();
+        assert!(pending.add(7, "first"));
+        assert!(pending.add(8, "second"));
+        // Delivered again before the checkpoint.
+        assert!(!pending.add(7, "again"));
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending.take(), ["first", "second", "again"]);
+        assert_eq!(pending.len(), 0);
+        assert!(pending.add(7, "after"));
+    }
+
+    #[test]
+    fn test_event_subject() {
+        let event = AccountEvent {
+            at: 1,
+            client_id: 42,
+            transaction: Transaction {
+                kind: Deposit,
+                id: 1,
+                amount: Amount::zero(),
+            },
+            balances: AccountView::default(),
+        };
+        assert_eq!(event_subject("accounts", &event), "accounts.42");
+    }
+}
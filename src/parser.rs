@@ -1,47 +1,82 @@
 use crate::{
-    Error,
-    accounts::{ClientId, Transaction, TransactionId, TransactionKind},
+    accounts::{AccountView, ClientId, Transaction, TransactionId, TransactionKind},
     amount::Amount,
+    digits,
+    error::ParseError,
 };
 
 #[derive(Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Row {
     pub client_id: ClientId,
     pub transaction: Transaction,
 }
 
+/// Split a CSV line into trimmed columns.
+fn columns(buf: &[u8]) -> impl Iterator<Item = &[u8]> {
+    memchr::memchr_iter(b',', buf)
+        .chain(Some(buf.len()))
+        .scan(0usize, |start, end| {
+            let column = buf[*start..end].trim_ascii();
+            *start = end + 1;
+            Some(column)
+        })
+}
+
+pub(crate) fn parse_kind(ttype: &[u8]) -> Result<TransactionKind, ParseError> {
+    Ok(match ttype {
+        b"deposit" => TransactionKind::Deposit,
+        b"withdrawal" => TransactionKind::Withdrawal,
+        b"dispute" => TransactionKind::Dispute,
+        b"resolve" => TransactionKind::Resolve,
+        b"chargeback" => TransactionKind::Chargeback,
+        _ => return Err(ParseError::UnknownTransactionType),
+    })
+}
+
+/// Client id of a CSV input row, without parsing the other columns.
+pub fn client_of(buf: &[u8]) -> Option<ClientId> {
+    digits::parse_u16(columns(buf).nth(1)?)
+}
+
 impl Row {
     /// Parse a CSV row assuming header "type, client, tx, amount"
-    pub fn parse(buf: &[u8]) -> Result<Self, crate::Error> {
-        let mut columns =
-            memchr::memchr_iter(b',', buf)
-                .chain(Some(buf.len()))
-                .scan(0usize, |start, end| {
-                    let column = buf[*start..end].trim_ascii();
-                    *start = end + 1;
-                    Some(column)
-                });
-        let ttype = columns.next().ok_or(Error::CsvMissingColumn)?;
-        let client_id = columns.next().ok_or(Error::CsvMissingColumn)?;
-        let tx_id = columns.next().ok_or(Error::CsvMissingColumn)?;
-        let amount = columns.next().ok_or(Error::CsvMissingColumn)?;
-
-        let ttype = match ttype {
-            b"deposit" => TransactionKind::Deposit,
-            b"withdrawal" => TransactionKind::Withdrawal,
-            b"dispute" => TransactionKind::Dispute,
-            b"resolve" => TransactionKind::Resolve,
-            b"chargeback" => TransactionKind::Chargeback,
-            _ => return Err(Error::CsvUnknownTransactionType),
-        };
+    pub fn parse(buf: &[u8]) -> Result<Self, ParseError> {
+        let mut columns = columns(buf);
+        let ttype = columns.next().ok_or(ParseError::MissingColumn)?;
+        let client_id = columns.next().ok_or(ParseError::MissingColumn)?;
+        let tx_id = columns.next().ok_or(ParseError::MissingColumn)?;
+        let amount = columns.next().ok_or(ParseError::MissingColumn)?;
+        Self::from_columns(ttype, client_id, tx_id, amount)
+    }
 
-        let client_id: ClientId = atoi::atoi(client_id).ok_or(Error::CsvInvalidClientId)?;
-        let tx_id: TransactionId = atoi::atoi(tx_id).ok_or(Error::CsvInvalidTxId)?;
+    /// Same as [`Self::parse`] with the columns already split, e.g. values taken from another
+    /// format. `amount` is empty for transaction types without one.
+    pub(crate) fn from_columns(
+        ttype: &[u8],
+        client_id: &[u8],
+        tx_id: &[u8],
+        amount: &[u8],
+    ) -> Result<Self, ParseError> {
+        let ttype = parse_kind(ttype)?;
+        let client_id: ClientId =
+            digits::parse_u16(client_id).ok_or(ParseError::InvalidClientId)?;
+        let tx_id: TransactionId = digits::parse_u32(tx_id).ok_or(ParseError::InvalidTxId)?;
+        Self::with_amount(ttype, client_id, tx_id, amount)
+    }
 
+    /// A row with an amount still to parse, which must be empty for transaction types without
+    /// one.
+    pub(crate) fn with_amount(
+        ttype: TransactionKind,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        amount: &[u8],
+    ) -> Result<Self, ParseError> {
         let amount = if ttype.has_amount() {
-            Amount::parse(amount).ok_or(Error::CsvInvalidAmount)?
+            Amount::parse(amount).ok_or(ParseError::InvalidAmount)?
         } else if !amount.is_empty() {
-            return Err(Error::CsvUnexpectedAmount);
+            return Err(ParseError::UnexpectedAmount);
         } else {
             Amount::zero()
         };
@@ -56,9 +91,89 @@ impl Row {
     }
 }
 
+/// Rows of a message of several lines, e.g. from a queue, with their line within it, from 1. Blank
+/// lines and header lines are skipped.
+pub fn message_rows(payload: &[u8]) -> impl Iterator<Item = (usize, Result<Row, ParseError>)> {
+    payload
+        .split(|&b| b == b'\n')
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.trim_ascii()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with(b"type"))
+        .map(|(line, row)| (line, Row::parse(row)))
+}
+
+/// A row of the output report, used to seed balances from a previous run.
+#[derive(Debug, Eq, PartialEq)]
+pub struct BalanceRow {
+    pub client_id: ClientId,
+    pub balances: AccountView<'static>,
+}
+
+impl BalanceRow {
+    /// Parse a CSV row assuming header "client, available, held, total, locked", optionally
+    /// followed by "flagged"
+    pub fn parse(buf: &[u8]) -> Result<Self, ParseError> {
+        let mut columns = columns(buf);
+        let mut next = || columns.next().ok_or(ParseError::MissingColumn);
+        let client_id = next()?;
+        let available = next()?;
+        let held = next()?;
+        let total = next()?;
+        let locked = next()?;
+        // Only in reports of runs flagging accounts for review.
+        let flagged = columns.next() == Some(b"true");
+
+        let client_id: ClientId =
+            digits::parse_u16(client_id).ok_or(ParseError::InvalidClientId)?;
+        let available = Amount::parse(available).ok_or(ParseError::InvalidAmount)?;
+        let held = Amount::parse(held).ok_or(ParseError::InvalidAmount)?;
+        let total = Amount::parse(total).ok_or(ParseError::InvalidAmount)?;
+        let locked = match locked {
+            b"true" => true,
+            b"false" => false,
+            _ => return Err(ParseError::InvalidLocked),
+        };
+
+        // Same rule as Account::available_for_withdrawal.
+        let expected_available = if locked {
+            Amount::zero()
+        } else {
+            total.checked_sub(held).unwrap_or_default()
+        };
+        if available != expected_available {
+            return Err(ParseError::InconsistentBalances);
+        }
+
+        Ok(BalanceRow {
+            client_id,
+            balances: AccountView {
+                available,
+                held,
+                total,
+                locked,
+                flagged,
+                audit: &[],
+            },
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{Error, accounts::Transaction, amount::Amount, parser::Row};
+    use crate::{
+        accounts::{AccountView, Transaction},
+        amount::Amount,
+        error::ParseError,
+        parser::{BalanceRow, Row, message_rows},
+    };
+
+    #[test]
+    fn test_message_rows() {
+        let rows = message_rows(b"type, client, tx, amount\r\ndeposit, 1, 1, 5\r\n\r\nbogus\n")
+            .map(|(line, row)| (line, row.is_ok()))
+            .collect::<Vec<_>>();
+        assert_eq!(rows, [(2, true), (4, false)]);
+    }
 
     #[test]
     fn test_parse() {
@@ -136,43 +251,86 @@ mod tests {
         // invalid
         assert!(matches!(
             Row::parse(b"").unwrap_err(),
-            Error::CsvMissingColumn
+            ParseError::MissingColumn
         ));
         assert!(matches!(
             Row::parse(b",,").unwrap_err(),
-            Error::CsvMissingColumn
+            ParseError::MissingColumn
         ));
         assert!(matches!(
             Row::parse(b",,,,,,").unwrap_err(),
-            Error::CsvUnknownTransactionType
+            ParseError::UnknownTransactionType
         ));
         assert!(matches!(
             Row::parse(b",,,").unwrap_err(),
-            Error::CsvUnknownTransactionType
+            ParseError::UnknownTransactionType
         ));
         assert!(matches!(
             Row::parse(b"deposit,1,1").unwrap_err(),
-            Error::CsvMissingColumn
+            ParseError::MissingColumn
         ));
         assert!(matches!(
             Row::parse(b"deposit,1,1,").unwrap_err(),
-            Error::CsvInvalidAmount
+            ParseError::InvalidAmount
         ));
         assert!(matches!(
             Row::parse(b"resolve,1,1,1.0").unwrap_err(),
-            Error::CsvUnexpectedAmount
+            ParseError::UnexpectedAmount
         ));
         assert!(matches!(
             Row::parse(b"foo, 1, 1, 1.0").unwrap_err(),
-            Error::CsvUnknownTransactionType
+            ParseError::UnknownTransactionType
         ));
         assert!(matches!(
             Row::parse(b"withdrawal, foo, 1, 1.0").unwrap_err(),
-            Error::CsvInvalidClientId
+            ParseError::InvalidClientId
         ));
         assert!(matches!(
             Row::parse(b"withdrawal, 1, foo, 1.0").unwrap_err(),
-            Error::CsvInvalidTxId
+            ParseError::InvalidTxId
+        ));
+        assert!(matches!(
+            Row::parse(b"withdrawal, 1x, 1, 1.0").unwrap_err(),
+            ParseError::InvalidClientId
+        ));
+        assert!(matches!(
+            Row::parse(b"withdrawal, 1, +1, 1.0").unwrap_err(),
+            ParseError::InvalidTxId
+        ));
+    }
+
+    #[test]
+    fn test_parse_balance_row() {
+        let amount = |v: &str| Amount::parse(v.as_bytes()).unwrap();
+        assert_eq!(
+            BalanceRow::parse(b"1,1.5,0.5,2,false").unwrap(),
+            BalanceRow {
+                client_id: 1,
+                balances: AccountView {
+                    available: amount("1.5"),
+                    held: amount("0.5"),
+                    total: amount("2"),
+                    locked: false,
+                    flagged: false,
+                    audit: &[],
+                }
+            }
+        );
+        assert!(BalanceRow::parse(b"2, 0, 0, 9.5, true").is_ok());
+        // Held greater than total.
+        assert!(BalanceRow::parse(b"2,0,10,9,false").is_ok());
+
+        assert!(matches!(
+            BalanceRow::parse(b"1,1.5,0.5,2").unwrap_err(),
+            ParseError::MissingColumn
+        ));
+        assert!(matches!(
+            BalanceRow::parse(b"1,1.5,0.5,2,yes").unwrap_err(),
+            ParseError::InvalidLocked
+        ));
+        assert!(matches!(
+            BalanceRow::parse(b"1,1,0.5,2,false").unwrap_err(),
+            ParseError::InconsistentBalances
         ));
     }
 }
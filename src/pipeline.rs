@@ -0,0 +1,363 @@
+use std::{
+    io::BufRead,
+    sync::mpsc::{Receiver, SyncSender, sync_channel},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use tracing::{debug_span, info_span};
+
+use crate::{error::ParseError, parser::Row, profile::Stopwatch};
+
+// Rows sent to the consumer at once.
+pub(crate) const BATCH_LEN: usize = 1024;
+// Batches buffered before the reading thread blocks.
+pub(crate) const QUEUED_BATCHES: usize = 16;
+// Input read and parsed at once by one rayon task.
+#[cfg(feature = "rayon")]
+pub(crate) const PARALLEL_CHUNK_LEN: usize = 4 << 20;
+
+/// A line of input that couldn't be parsed, or a read error, which ends the stream.
+#[derive(Debug)]
+pub struct ParseFailure {
+    /// 1-based line number in the input, including the header.
+    pub line: usize,
+    pub error: ParseError,
+    /// The line as read, without its line ending and cut at [`RAW_LIMIT`] bytes, where it's text.
+    pub raw: Option<Box<str>>,
+}
+
+/// Bytes of a malformed line kept in [`ParseFailure::raw`].
+pub const RAW_LIMIT: usize = 256;
+
+impl ParseFailure {
+    pub fn new(line: usize, error: ParseError) -> Self {
+        ParseFailure {
+            line,
+            error,
+            raw: None,
+        }
+    }
+
+    pub(crate) fn with_raw(line: usize, error: ParseError, raw: &[u8]) -> Self {
+        ParseFailure {
+            raw: Some(raw_line(raw)),
+            ..ParseFailure::new(line, error)
+        }
+    }
+}
+
+/// A line as kept in [`ParseFailure::raw`].
+pub(crate) fn raw_line(raw: &[u8]) -> Box<str> {
+    let raw = raw.strip_suffix(b"\n").unwrap_or(raw);
+    let raw = raw.strip_suffix(b"\r").unwrap_or(raw);
+    String::from_utf8_lossy(&raw[..raw.len().min(RAW_LIMIT)]).into()
+}
+
+type Batch = Vec<Result<Row, ParseFailure>>;
+
+/// Rows parsed on a background thread, so reading and parsing overlap with applying them.
+///
+/// The header line is skipped without parsing, as in the CLI. The thread's work is traced as a
+/// `read` span, a child of the span current when spawning, with a `parse_batch` span per batch.
+pub struct RowStream {
+    rx: Receiver<Batch>,
+    current: std::vec::IntoIter<Result<Row, ParseFailure>>,
+    thread: Option<JoinHandle<()>>,
+    busy: Stopwatch,
+}
+
+impl RowStream {
+    /// Time the reading thread spent reading and parsing, excluding waits for the consumer to
+    /// take rows. Added once the input ends.
+    pub fn busy(&self) -> Stopwatch {
+        self.busy.clone()
+    }
+
+    pub fn spawn(reader: impl BufRead + Send + 'static) -> Self {
+        Self::spawn_pinned(reader, None)
+    }
+
+    /// Same as [`Self::spawn`], with the parsing thread pinned to the given core, see
+    /// [`crate::threads::pin_current`].
+    pub fn spawn_pinned(mut reader: impl BufRead + Send + 'static, core: Option<usize>) -> Self {
+        let (tx, rx) = sync_channel(QUEUED_BATCHES);
+        let busy = Stopwatch::default();
+        let thread_busy = busy.clone();
+        let span = info_span!("read");
+        let thread = std::thread::spawn(move || {
+            let _read = span.entered();
+            if let Some(core) = core {
+                crate::threads::pin_current(core);
+            }
+            let mut tx = TimedSender::new(tx, thread_busy);
+            let parse_batch = |first_line| debug_span!("parse_batch", first_line).entered();
+            // NOTE: using mmap here would be even faster as there will be 0 syscalls for the main
+            // loop involved and no extra buffer allocation. Not doing it to avoid unsafe.
+            let mut buf = Vec::new();
+            let mut batch = Vec::with_capacity(BATCH_LEN);
+            let mut line = 0;
+            let mut parsing = Some(parse_batch(2));
+            loop {
+                buf.clear();
+                line += 1;
+                match reader.read_until(b'\n', &mut buf) {
+                    Ok(0) => break,
+                    Ok(_) if line == 1 => continue,
+                    Ok(_) => batch.push(
+                        Row::parse(&buf).map_err(|error| ParseFailure::with_raw(line, error, &buf)),
+                    ),
+                    Err(e) => {
+                        batch.push(Err(ParseFailure::new(line, ParseError::Io(e))));
+                        break;
+                    }
+                }
+                if batch.len() == BATCH_LEN {
+                    let full = std::mem::replace(&mut batch, Vec::with_capacity(BATCH_LEN));
+                    // Waiting for the consumer isn't parsing.
+                    drop(parsing.take());
+                    if tx.send(full).is_err() {
+                        // Consumer is gone.
+                        return;
+                    }
+                    parsing = Some(parse_batch(line + 1));
+                }
+            }
+            drop(parsing);
+            let _ = tx.send(batch);
+        });
+        RowStream {
+            rx,
+            current: Vec::new().into_iter(),
+            thread: Some(thread),
+            busy,
+        }
+    }
+}
+
+/// Sender of the reading thread, adding its time apart from blocked sends to a stopwatch when
+/// dropped.
+struct TimedSender {
+    tx: SyncSender<Batch>,
+    start: Instant,
+    blocked: Duration,
+    busy: Stopwatch,
+}
+
+impl TimedSender {
+    fn new(tx: SyncSender<Batch>, busy: Stopwatch) -> Self {
+        TimedSender {
+            tx,
+            start: Instant::now(),
+            blocked: Duration::ZERO,
+            busy,
+        }
+    }
+
+    /// Fails if the consumer is gone.
+    fn send(&mut self, batch: Batch) -> Result<(), ()> {
+        let start = Instant::now();
+        let sent = self.tx.send(batch).map_err(|_| ());
+        self.blocked += start.elapsed();
+        sent
+    }
+}
+
+impl Drop for TimedSender {
+    fn drop(&mut self) {
+        self.busy
+            .add(self.start.elapsed().saturating_sub(self.blocked));
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl RowStream {
+    /// Same as [`Self::spawn`], but input is read in large chunks, which are parsed in parallel on
+    /// the rayon thread pool. Rows are still yielded in input order.
+    pub fn spawn_parallel(reader: impl std::io::Read + Send + 'static) -> Self {
+        Self::spawn_parallel_pinned(reader, None)
+    }
+
+    /// Same as [`Self::spawn_parallel`], with the reading thread pinned to the given core. Parsing
+    /// happens on the rayon pool, which isn't pinned.
+    pub fn spawn_parallel_pinned(
+        mut reader: impl std::io::Read + Send + 'static,
+        core: Option<usize>,
+    ) -> Self {
+        use rayon::prelude::*;
+
+        let (tx, rx) = sync_channel(QUEUED_BATCHES);
+        let busy = Stopwatch::default();
+        let thread_busy = busy.clone();
+        let span = info_span!("read");
+        let thread = std::thread::spawn(move || {
+            let _read = span.entered();
+            if let Some(core) = core {
+                crate::threads::pin_current(core);
+            }
+            let mut tx = TimedSender::new(tx, thread_busy);
+            let chunks_per_round = rayon::current_num_threads();
+            // Partial line at the end of the previous chunk.
+            let mut carry = Vec::new();
+            let mut line = 0;
+            let mut eof = false;
+            while !eof {
+                let mut chunks = Vec::with_capacity(chunks_per_round);
+                while chunks.len() < chunks_per_round && !eof {
+                    let mut chunk = std::mem::take(&mut carry);
+                    let start = chunk.len();
+                    chunk.resize(start + PARALLEL_CHUNK_LEN, 0);
+                    let mut filled = start;
+                    let read_result = loop {
+                        match reader.read(&mut chunk[filled..]) {
+                            Ok(0) => break Ok(true),
+                            Ok(n) => {
+                                filled += n;
+                                if filled == chunk.len() {
+                                    break Ok(false);
+                                }
+                            }
+                            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                            Err(e) => break Err(e),
+                        }
+                    };
+                    chunk.truncate(filled);
+                    match read_result {
+                        Ok(true) => eof = true,
+                        Ok(false) => {
+                            // Keep the incomplete last line for the next chunk.
+                            let split = memchr::memrchr(b'\n', &chunk).map_or(0, |i| i + 1);
+                            carry = chunk.split_off(split);
+                        }
+                        Err(e) => {
+                            let _ =
+                                tx.send(vec![Err(ParseFailure::new(line + 1, ParseError::Io(e)))]);
+                            return;
+                        }
+                    }
+                    if !chunk.is_empty() {
+                        chunks.push(chunk);
+                    }
+                }
+
+                // Rayon threads don't inherit the current span.
+                let parent = tracing::Span::current();
+                let parsed = chunks
+                    .par_iter()
+                    .map(|chunk| {
+                        debug_span!(parent: &parent, "parse_batch", bytes = chunk.len())
+                            .in_scope(|| parse_chunk(chunk))
+                    })
+                    .collect::<Vec<_>>();
+                // Line numbers were relative to the chunk, make them absolute.
+                for (mut batch, lines) in parsed {
+                    for failure in batch.iter_mut().filter_map(|r| r.as_mut().err()) {
+                        failure.line += line;
+                    }
+                    if line == 0 {
+                        // Skip the header.
+                        batch.drain(..1);
+                    }
+                    line += lines;
+                    if tx.send(batch).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        RowStream {
+            rx,
+            current: Vec::new().into_iter(),
+            thread: Some(thread),
+            busy,
+        }
+    }
+}
+
+/// Parse all lines in the chunk. Returns the results and the number of lines.
+#[cfg(feature = "rayon")]
+fn parse_chunk(chunk: &[u8]) -> (Batch, usize) {
+    let mut batch = Vec::new();
+    let mut start = 0;
+    for end in memchr::memchr_iter(b'\n', chunk).chain(
+        // Last line without a trailing newline.
+        (chunk.last() != Some(&b'\n')).then_some(chunk.len()),
+    ) {
+        let line = batch.len() + 1;
+        let raw = &chunk[start..end];
+        batch.push(Row::parse(raw).map_err(|error| ParseFailure::with_raw(line, error, raw)));
+        start = end + 1;
+    }
+    let lines = batch.len();
+    (batch, lines)
+}
+
+impl Iterator for RowStream {
+    type Item = Result<Row, ParseFailure>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.current.next() {
+                return Some(row);
+            }
+            match self.rx.recv() {
+                Ok(batch) => self.current = batch.into_iter(),
+                Err(_) => {
+                    // Propagate a panic of the reading thread instead of silently truncating.
+                    if let Some(thread) = self.thread.take()
+                        && let Err(panic) = thread.join()
+                    {
+                        std::panic::resume_unwind(panic);
+                    }
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::{error::ParseError, pipeline::RowStream};
+
+    #[test]
+    fn test_row_stream() {
+        let mut input = b"type, client, tx, amount\n".to_vec();
+        for i in 0..3000 {
+            input.extend_from_slice(format!("deposit, 1, {i}, 1.0\n").as_bytes());
+        }
+        input.extend_from_slice(b"bad\n");
+
+        let stream = RowStream::spawn(Cursor::new(input));
+        let busy = stream.busy();
+        let rows = stream.collect::<Vec<_>>();
+        assert!(!busy.elapsed().is_zero());
+        assert_eq!(rows.len(), 3001);
+        assert_eq!(rows[2999].as_ref().unwrap().transaction.id, 2999);
+        let failure = rows[3000].as_ref().unwrap_err();
+        assert_eq!(failure.line, 3002);
+        assert!(matches!(failure.error, ParseError::MissingColumn));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_row_stream_parallel_matches_sequential() {
+        let mut input = b"type, client, tx, amount\n".to_vec();
+        for i in 0..500_000 {
+            if i % 1000 == 0 {
+                input.extend_from_slice(b"bad\n");
+            }
+            input.extend_from_slice(format!("deposit, {}, {i}, 1.0\n", i % 100).as_bytes());
+        }
+        // No trailing newline.
+        input.extend_from_slice(b"withdrawal, 1, 1, 1.0");
+
+        let summarize = |s: RowStream| s.map(|r| r.map_err(|f| f.line)).collect::<Vec<_>>();
+        let expected = summarize(RowStream::spawn(Cursor::new(input.clone())));
+        let parallel = summarize(RowStream::spawn_parallel(Cursor::new(input)));
+        assert_eq!(expected.len(), 500_501);
+        assert_eq!(parallel, expected);
+    }
+}
@@ -0,0 +1,213 @@
+use std::{fmt::Write, io};
+
+use ::postgres::{Client, NoTls, types::ToSql};
+
+use crate::{
+    accounts::{AccountView, ClientId},
+    events::AccountEvent,
+};
+
+// Columns of a row of each table written, bounding chunks by the 65535 parameters of a statement.
+const TRANSACTION_COLUMNS: usize = 8;
+const ACCOUNT_COLUMNS: usize = 5;
+const MAX_CHUNK: usize = u16::MAX as usize / TRANSACTION_COLUMNS;
+
+const SCHEMA: &str = "
+SET client_min_messages = warning;
+CREATE TABLE IF NOT EXISTS payengine_transactions (
+    id bigserial PRIMARY KEY,
+    client integer NOT NULL,
+    tx bigint NOT NULL,
+    type text NOT NULL,
+    amount numeric(20, 4),
+    available numeric(20, 4) NOT NULL,
+    held numeric(20, 4) NOT NULL,
+    total numeric(20, 4) NOT NULL,
+    locked boolean NOT NULL,
+    written_at timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS payengine_transactions_client
+    ON payengine_transactions (client, tx);
+CREATE TABLE IF NOT EXISTS payengine_accounts (
+    client integer PRIMARY KEY,
+    available numeric(20, 4) NOT NULL,
+    held numeric(20, 4) NOT NULL,
+    total numeric(20, 4) NOT NULL,
+    locked boolean NOT NULL,
+    updated_at timestamptz NOT NULL DEFAULT now()
+);
+";
+
+/// Writes applied transactions and balances to Postgres, for services to query rather than
+/// parsing reports.
+///
+/// Tables are created if missing: `payengine_transactions` gets a row per transaction, with the
+/// balances after it, in the order applied for each client, and `payengine_accounts` a row per
+/// client, replaced by later balances. Rows are inserted a chunk per statement, each in a
+/// transaction of its own, so a failure leaves whole chunks written.
+pub struct PostgresSink {
+    client: Client,
+    chunk: usize,
+}
+
+/// Rows written by a [`PostgresSink`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PostgresStats {
+    pub transactions: u64,
+    pub accounts: u64,
+    pub chunks: u64,
+}
+
+impl PostgresSink {
+    /// Connect with a connection string, e.g. `host=localhost user=payengine` or
+    /// `postgresql://payengine@localhost/warehouse`, without TLS, creating the tables if missing.
+    /// Chunks are of `chunk` rows, at most 8191.
+    pub fn connect(params: &str, chunk: usize) -> io::Result<Self> {
+        let mut client = Client::connect(params, NoTls).map_err(io::Error::other)?;
+        client.batch_execute(SCHEMA).map_err(io::Error::other)?;
+        Ok(PostgresSink {
+            client,
+            chunk: chunk.clamp(1, MAX_CHUNK),
+        })
+    }
+
+    /// Insert a row for each applied transaction, e.g. received from
+    /// [`crate::sharded::ShardedDatabase::subscribe`] until unsubscribed.
+    pub fn write_transactions(
+        &mut self,
+        events: impl IntoIterator<Item = AccountEvent>,
+        stats: &mut PostgresStats,
+    ) -> io::Result<()> {
+        let mut chunk = Vec::with_capacity(self.chunk);
+        for event in events {
+            chunk.push(event);
+            if chunk.len() == self.chunk {
+                self.insert_transactions(&chunk)?;
+                stats.transactions += chunk.len() as u64;
+                stats.chunks += 1;
+                chunk.clear();
+            }
+        }
+        if !chunk.is_empty() {
+            self.insert_transactions(&chunk)?;
+            stats.transactions += chunk.len() as u64;
+            stats.chunks += 1;
+        }
+        Ok(())
+    }
+
+    /// Insert or replace the balances of each account.
+    pub fn write_accounts(
+        &mut self,
+        accounts: impl IntoIterator<Item = (ClientId, AccountView<'static>)>,
+        stats: &mut PostgresStats,
+    ) -> io::Result<()> {
+        let accounts = accounts.into_iter().collect::<Vec<_>>();
+        for chunk in accounts.chunks(self.chunk) {
+            let mut params =
+                Vec::<Box<dyn ToSql + Sync>>::with_capacity(chunk.len() * ACCOUNT_COLUMNS);
+            for (client_id, view) in chunk {
+                params.push(Box::new(i32::from(*client_id)));
+                params.push(Box::new(view.available.to_string()));
+                params.push(Box::new(view.held.to_string()));
+                params.push(Box::new(view.total.to_string()));
+                params.push(Box::new(view.locked));
+            }
+            let statement = format!(
+                "INSERT INTO payengine_accounts (client, available, held, total, locked) VALUES {} \
+                 ON CONFLICT (client) DO UPDATE SET available = excluded.available, \
+                 held = excluded.held, total = excluded.total, locked = excluded.locked, \
+                 updated_at = now()",
+                values(&ACCOUNT_CASTS, chunk.len())
+            );
+            self.execute(&statement, &params)?;
+            stats.accounts += chunk.len() as u64;
+            stats.chunks += 1;
+        }
+        Ok(())
+    }
+
+    fn insert_transactions(&mut self, events: &[AccountEvent]) -> io::Result<()> {
+        let mut params =
+            Vec::<Box<dyn ToSql + Sync>>::with_capacity(events.len() * TRANSACTION_COLUMNS);
+        for event in events {
+            let (t, b) = (event.transaction, event.balances);
+            params.push(Box::new(i32::from(event.client_id)));
+            params.push(Box::new(i64::from(t.id)));
+            params.push(Box::new(t.kind.as_str()));
+            params.push(Box::new(t.kind.has_amount().then(|| t.amount.to_string())));
+            params.push(Box::new(b.available.to_string()));
+            params.push(Box::new(b.held.to_string()));
+            params.push(Box::new(b.total.to_string()));
+            params.push(Box::new(b.locked));
+        }
+        let statement = format!(
+            "INSERT INTO payengine_transactions \
+             (client, tx, type, amount, available, held, total, locked) VALUES {}",
+            values(&TRANSACTION_CASTS, events.len())
+        );
+        self.execute(&statement, &params)
+    }
+
+    fn execute(&mut self, statement: &str, params: &[Box<dyn ToSql + Sync>]) -> io::Result<()> {
+        let params = params.iter().map(|p| p.as_ref()).collect::<Vec<_>>();
+        let mut transaction = self.client.transaction().map_err(io::Error::other)?;
+        transaction
+            .execute(statement, &params)
+            .map_err(io::Error::other)?;
+        transaction.commit().map_err(io::Error::other)
+    }
+}
+
+// Amounts are sent as text, for exact conversion to numeric.
+const TRANSACTION_CASTS: [&str; TRANSACTION_COLUMNS] = [
+    "",
+    "",
+    "",
+    "::text::numeric",
+    "::text::numeric",
+    "::text::numeric",
+    "::text::numeric",
+    "",
+];
+const ACCOUNT_CASTS: [&str; ACCOUNT_COLUMNS] = [
+    "",
+    "::text::numeric",
+    "::text::numeric",
+    "::text::numeric",
+    "",
+];
+
+/// The tuples of parameters of `rows` rows of an `INSERT`, each column cast as given, e.g.
+/// `($1, $2::text::numeric), ($3, $4::text::numeric)`.
+fn values(casts: &[&str], rows: usize) -> String {
+    let mut out = String::new();
+    for row in 0..rows {
+        if row > 0 {
+            out.push_str(", ");
+        }
+        out.push('(');
+        for (column, cast) in casts.iter().enumerate() {
+            if column > 0 {
+                out.push_str(", ");
+            }
+            write!(out, "${}{cast}", row * casts.len() + column + 1).unwrap();
+        }
+        out.push(')');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::postgres::values;
+
+    #[test]
+    fn test_values() {
+        assert_eq!(
+            values(&["", "::text::numeric"], 2),
+            "($1, $2::text::numeric), ($3, $4::text::numeric)"
+        );
+        assert_eq!(values(&[""], 1), "($1)");
+    }
+}
@@ -0,0 +1,581 @@
+use std::hash::BuildHasher;
+
+use tracing::{trace, warn};
+
+use crate::{
+    Error,
+    accounts::{ClientId, ClientsDatabase, Transaction, TransactionKind},
+    amount::Amount,
+    audit::Provenance,
+    convert::push_csv,
+    deposits::DepositStore,
+    error::ErrorKind,
+    history::Timestamp,
+    metrics::{AppliedCounts, ClientRejections, Metrics},
+    reservoir::RejectSamples,
+    threads::WorkerStats,
+};
+
+/// What to keep about rejected transactions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RejectionMode {
+    /// Keep every error with its index, in addition to counting them.
+    #[default]
+    Record,
+    /// Only count rejections by kind. Much cheaper on inputs with many bad rows.
+    Count,
+}
+
+/// What bulk processing does with a transaction that can't be applied.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum ErrorPolicy {
+    /// Skip it and carry on.
+    #[default]
+    Skip,
+    /// Skip it like `Skip`, also logging a warning with the error.
+    Warn,
+    /// Stop processing at the first rejection, which is kept in [`ProcessReport::aborted`].
+    Abort,
+    /// Skip it, keeping the transaction in [`ProcessReport::dead_letters`] to be handled
+    /// elsewhere, e.g. written to a rejects file.
+    DeadLetter,
+}
+
+impl ErrorPolicy {
+    pub const ALL: [ErrorPolicy; 4] = [
+        ErrorPolicy::Skip,
+        ErrorPolicy::Warn,
+        ErrorPolicy::Abort,
+        ErrorPolicy::DeadLetter,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorPolicy::Skip => "skip",
+            ErrorPolicy::Warn => "warn",
+            ErrorPolicy::Abort => "abort",
+            ErrorPolicy::DeadLetter => "dead-letter",
+        }
+    }
+}
+
+impl std::str::FromStr for ErrorPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|p| p.as_str() == s)
+            .ok_or_else(|| format!("unknown error policy {s:?}"))
+    }
+}
+
+/// The [`ErrorPolicy`] of each kind of rejection, e.g. aborting a run at a duplicate transaction
+/// id while only counting disputes of unknown transactions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PolicyMap([ErrorPolicy; ErrorKind::ALL.len()]);
+
+impl PolicyMap {
+    /// The same policy for every kind.
+    pub fn new(policy: ErrorPolicy) -> Self {
+        PolicyMap([policy; ErrorKind::ALL.len()])
+    }
+
+    pub fn set(&mut self, kind: ErrorKind, policy: ErrorPolicy) {
+        self.0[kind as usize] = policy;
+    }
+
+    pub fn get(&self, kind: ErrorKind) -> ErrorPolicy {
+        self.0[kind as usize]
+    }
+
+    /// Whether any kind has the policy.
+    pub fn uses(&self, policy: ErrorPolicy) -> bool {
+        self.0.contains(&policy)
+    }
+}
+
+impl Default for PolicyMap {
+    fn default() -> Self {
+        PolicyMap::new(ErrorPolicy::default())
+    }
+}
+
+impl From<ErrorPolicy> for PolicyMap {
+    fn from(policy: ErrorPolicy) -> Self {
+        PolicyMap::new(policy)
+    }
+}
+
+/// Parse a policy for one kind of rejection, written `KIND=POLICY`, e.g.
+/// `DuplicateTransactionId=abort`.
+pub fn parse_kind_policy(s: &str) -> Result<(ErrorKind, ErrorPolicy), String> {
+    let (kind, policy) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected KIND=POLICY, got {s:?}"))?;
+    Ok((kind.trim().parse()?, policy.trim().parse()?))
+}
+
+/// A rejected transaction kept under [`ErrorPolicy::DeadLetter`].
+#[derive(Debug)]
+pub struct DeadLetter {
+    /// 0-based index in the input, as in [`ProcessReport::rejections`].
+    pub index: usize,
+    /// None if the row couldn't be parsed.
+    pub transaction: Option<(ClientId, Transaction)>,
+    pub error: Error,
+}
+
+/// Number of rejections by kind of error.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RejectionCounts([usize; ErrorKind::ALL.len()]);
+
+impl RejectionCounts {
+    pub fn add(&mut self, kind: ErrorKind) {
+        self.0[kind as usize] += 1;
+    }
+
+    pub fn get(&self, kind: ErrorKind) -> usize {
+        self.0[kind as usize]
+    }
+
+    pub fn total(&self) -> usize {
+        self.0.iter().sum()
+    }
+
+    /// Kinds which occurred, with their counts.
+    pub fn iter(&self) -> impl Iterator<Item = (ErrorKind, usize)> + '_ {
+        ErrorKind::ALL
+            .into_iter()
+            .map(|kind| (kind, self.get(kind)))
+            .filter(|(_, count)| *count > 0)
+    }
+
+    pub fn merge(&mut self, other: &RejectionCounts) {
+        for (count, other) in self.0.iter_mut().zip(other.0) {
+            *count += other;
+        }
+    }
+}
+
+/// Outcome of [`ClientsDatabase::process_all`].
+#[derive(Debug, Default)]
+pub struct ProcessReport {
+    pub processed: usize,
+    pub applied: usize,
+    /// Rejected transactions by their 0-based index in the input, in input order. Empty with
+    /// [`RejectionMode::Count`]. Only those whose [`ErrorPolicy`] is `Skip` or `Warn`, the others
+    /// are kept in `aborted` or `dead_letters` instead.
+    pub rejections: Vec<(usize, Error)>,
+    /// Rejections by kind, in any mode.
+    pub counts: RejectionCounts,
+    /// With [`ErrorPolicy::Abort`], the rejection processing stopped at. The earliest one if
+    /// several workers stopped.
+    pub aborted: Option<(usize, Error)>,
+    /// Rejected transactions with [`ErrorPolicy::DeadLetter`], in input order.
+    pub dead_letters: Vec<DeadLetter>,
+    /// Counters per applier thread, in shard order. Only filled by parallel processing.
+    pub workers: Vec<WorkerStats>,
+    /// Applied transactions by kind.
+    pub applied_kinds: AppliedCounts,
+    /// Accounts opened by a first deposit.
+    pub accounts_created: usize,
+    /// Transactions applied when retried after failing, see [`ClientsDatabase::enable_retry`].
+    pub retried: usize,
+    /// Transactions and rejections by client.
+    pub clients: ClientRejections,
+    /// Deposits and withdrawals of zero, applied or not.
+    pub zero_amounts: usize,
+    /// Rejected transactions as CSV rows, sampled by kind, in any mode. None unless enabled by
+    /// [`ClientsDatabase::set_reject_samples`].
+    pub samples: RejectSamples,
+    mode: RejectionMode,
+    policy: PolicyMap,
+}
+
+impl ProcessReport {
+    pub fn new(mode: RejectionMode, policy: PolicyMap) -> Self {
+        ProcessReport {
+            mode,
+            policy,
+            ..Default::default()
+        }
+    }
+
+    pub fn rejected(&self) -> usize {
+        self.counts.total()
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        let kinds = &self.applied_kinds;
+        Metrics {
+            rows: self.processed,
+            applied: *kinds,
+            rejections: self.counts,
+            accounts_created: self.accounts_created,
+            disputes_opened: kinds.get(TransactionKind::Dispute),
+            disputes_closed: kinds.get(TransactionKind::Resolve)
+                + kinds.get(TransactionKind::Chargeback),
+        }
+    }
+
+    /// Count a processed transaction with its result. Callers stop once `aborted` is set.
+    pub(crate) fn record(
+        &mut self,
+        idx: usize,
+        transaction: Option<(ClientId, Transaction)>,
+        res: Result<(), impl Into<Error>>,
+    ) {
+        self.processed += 1;
+        if let Some((_, t)) = transaction
+            && t.kind.has_amount()
+            && t.amount == Amount::zero()
+        {
+            self.zero_amounts += 1;
+        }
+        let Err(e) = res.map_err(Into::into) else {
+            self.applied += 1;
+            if let Some((client_id, t)) = transaction {
+                self.applied_kinds.add(t.kind);
+                self.clients.add(client_id, None);
+            }
+            return;
+        };
+        self.counts.add(e.kind());
+        if let Some((client_id, t)) = transaction {
+            self.clients.add(client_id, Some(e.kind()));
+            self.samples.add(e.kind(), || {
+                let mut row = Vec::new();
+                push_csv(&mut row, client_id, t);
+                String::from_utf8(row).unwrap()
+            });
+        }
+        let policy = self.policy.get(e.kind());
+        // Fields of the transaction rather than in the message, so events can be queried by them.
+        let client_id = transaction.map(|(client_id, _)| client_id);
+        let tx_id = transaction.map(|(_, t)| t.id);
+        let kind = transaction.map(|(_, t)| t.kind.as_str());
+        if policy == ErrorPolicy::Warn {
+            warn!(
+                index = idx,
+                client_id,
+                tx_id,
+                kind,
+                code = e.code(),
+                error = %e,
+                "rejected transaction"
+            );
+        } else if self.mode == RejectionMode::Record {
+            trace!(
+                index = idx,
+                client_id,
+                tx_id,
+                kind,
+                code = e.code(),
+                error = %e,
+                "rejected transaction"
+            );
+        }
+        match policy {
+            ErrorPolicy::Abort => self.aborted = Some((idx, e)),
+            ErrorPolicy::DeadLetter => self.dead_letters.push(DeadLetter {
+                index: idx,
+                transaction,
+                error: e,
+            }),
+            ErrorPolicy::Skip | ErrorPolicy::Warn if self.mode == RejectionMode::Record => {
+                self.rejections.push((idx, e))
+            }
+            ErrorPolicy::Skip | ErrorPolicy::Warn => {}
+        }
+    }
+
+    /// Count transactions of one client applied one after another, the same as recording each.
+    pub(crate) fn record_applied(
+        &mut self,
+        client_id: ClientId,
+        applied: AppliedCounts,
+        zero_amounts: usize,
+    ) {
+        let count = applied.total();
+        self.processed += count;
+        self.applied += count;
+        self.zero_amounts += zero_amounts;
+        self.applied_kinds.merge(&applied);
+        self.clients.add_applied(client_id, count);
+    }
+
+    /// Add the results of another part of the same input. Rejections need sorting afterwards if
+    /// the parts were interleaved.
+    pub(crate) fn merge(&mut self, other: ProcessReport) {
+        self.processed += other.processed;
+        self.applied += other.applied;
+        self.rejections.extend(other.rejections);
+        self.counts.merge(&other.counts);
+        if let Some(aborted) = other.aborted
+            && self
+                .aborted
+                .as_ref()
+                .is_none_or(|(idx, _)| aborted.0 < *idx)
+        {
+            self.aborted = Some(aborted);
+        }
+        self.dead_letters.extend(other.dead_letters);
+        self.workers.extend(other.workers);
+        self.applied_kinds.merge(&other.applied_kinds);
+        self.accounts_created += other.accounts_created;
+        self.retried += other.retried;
+        self.zero_amounts += other.zero_amounts;
+        self.clients.merge(&other.clients);
+        self.samples.merge(other.samples);
+    }
+}
+
+impl<S: BuildHasher, D: DepositStore> ClientsDatabase<S, D> {
+    /// Apply all transactions in order. Rejected ones are skipped and collected in the report, or
+    /// stop processing, depending on the [`ErrorPolicy`].
+    pub fn process_all(
+        &mut self,
+        transactions: impl IntoIterator<Item = (ClientId, Transaction)>,
+    ) -> ProcessReport {
+        let mut report = self.new_report();
+        let first = self.clock;
+        let transactions = transactions
+            .into_iter()
+            .enumerate()
+            .map(|(idx, (client_id, t))| (idx, client_id, t));
+        self.process_indexed(transactions, first, &mut report);
+        self.retry_remaining(first, &mut report);
+        // Retried transactions are recorded late.
+        report.rejections.sort_unstable_by_key(|(idx, _)| *idx);
+        report.dead_letters.sort_unstable_by_key(|d| d.index);
+        self.clock = first + report.processed as Timestamp;
+        report
+    }
+
+    /// Same as [`Self::process_all`] for transactions of the input `source` with their line,
+    /// recorded for deposits as in [`Self::process_transaction_from`]. Transactions are applied
+    /// one at a time, without retries.
+    pub fn process_all_from(
+        &mut self,
+        source: u32,
+        transactions: impl IntoIterator<Item = (u64, ClientId, Transaction)>,
+    ) -> ProcessReport {
+        let mut report = self.new_report();
+        for (idx, (line, client_id, t)) in transactions.into_iter().enumerate() {
+            let new = self.get(client_id).is_none();
+            let res = self.process_transaction_from(client_id, t, Provenance { source, line });
+            report.accounts_created += (new && res.is_ok()) as usize;
+            report.record(idx, Some((client_id, t)), res);
+            if report.aborted.is_some() {
+                break;
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        Error,
+        accounts::{ClientsDatabase, Transaction, TransactionKind::*},
+        amount::Amount,
+        error::{ErrorKind, LedgerError},
+        process::{ErrorPolicy, PolicyMap, RejectionMode, parse_kind_policy},
+    };
+
+    #[test]
+    fn test_process_all() {
+        let t = |kind, id, amount: &str| Transaction {
+            kind,
+            id,
+            amount: Amount::parse(amount.as_bytes()).unwrap_or_default(),
+        };
+        let mut db = ClientsDatabase::new();
+        let report = db.process_all([
+            (1, t(Deposit, 1, "1")),
+            (1, t(Withdrawal, 2, "2")),
+            (2, t(Dispute, 3, "")),
+            (1, t(Withdrawal, 4, "1")),
+        ]);
+        assert_eq!(report.processed, 4);
+        assert_eq!(report.applied, 2);
+        assert_eq!(report.rejected(), 2);
+        assert!(matches!(
+            report.rejections[0],
+            (1, Error::Ledger(LedgerError::WithdrawOverflow))
+        ));
+        assert!(matches!(
+            report.rejections[1],
+            (2, Error::Ledger(LedgerError::AccountNotFound))
+        ));
+        assert_eq!(report.counts.get(ErrorKind::WithdrawOverflow), 1);
+
+        // Same outcome, without keeping the errors.
+        let mut db = ClientsDatabase::new();
+        db.set_rejection_mode(RejectionMode::Count);
+        let report = db.process_all([
+            (1, t(Deposit, 1, "1")),
+            (1, t(Withdrawal, 2, "2")),
+            (2, t(Dispute, 3, "")),
+            (1, t(Withdrawal, 4, "1")),
+            (2, t(Resolve, 3, "")),
+        ]);
+        assert_eq!(report.applied, 2);
+        assert_eq!(report.rejected(), 3);
+        assert!(report.rejections.is_empty());
+        assert_eq!(
+            report.counts.iter().collect::<Vec<_>>(),
+            [
+                (ErrorKind::WithdrawOverflow, 1),
+                (ErrorKind::AccountNotFound, 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_error_policy() {
+        let t = |kind, id, amount: &str| Transaction {
+            kind,
+            id,
+            amount: Amount::parse(amount.as_bytes()).unwrap_or_default(),
+        };
+        let transactions = [
+            (1, t(Deposit, 1, "1")),
+            (1, t(Withdrawal, 2, "2")),
+            (1, t(Deposit, 3, "1")),
+            (2, t(Dispute, 3, "")),
+        ];
+
+        let mut db = ClientsDatabase::new();
+        db.set_error_policy(ErrorPolicy::Abort);
+        let report = db.process_all(transactions);
+        assert_eq!(report.processed, 2);
+        assert_eq!(report.applied, 1);
+        assert!(matches!(
+            report.aborted,
+            Some((1, Error::Ledger(LedgerError::WithdrawOverflow)))
+        ));
+        assert!(report.rejections.is_empty());
+        assert_eq!(
+            db.get(1).unwrap().view().total,
+            Amount::parse(b"1").unwrap()
+        );
+
+        let mut db = ClientsDatabase::new();
+        db.set_error_policy(ErrorPolicy::DeadLetter);
+        let report = db.process_all(transactions);
+        assert_eq!(report.applied, 2);
+        assert!(report.aborted.is_none());
+        assert_eq!(
+            report
+                .dead_letters
+                .iter()
+                .map(|d| (d.index, d.transaction, d.error.kind()))
+                .collect::<Vec<_>>(),
+            [
+                (1, Some(transactions[1]), ErrorKind::WithdrawOverflow),
+                (3, Some(transactions[3]), ErrorKind::AccountNotFound)
+            ]
+        );
+
+        // Dead-letter unknown accounts, skip the rest.
+        let mut policies = PolicyMap::new(ErrorPolicy::Skip);
+        policies.set(ErrorKind::AccountNotFound, ErrorPolicy::DeadLetter);
+        let mut db = ClientsDatabase::new();
+        db.set_error_policy(policies);
+        let report = db.process_all(transactions);
+        assert_eq!(report.rejected(), 2);
+        assert!(report.aborted.is_none());
+        assert!(matches!(
+            report.rejections[..],
+            [(1, Error::Ledger(LedgerError::WithdrawOverflow))]
+        ));
+        assert_eq!(report.dead_letters.len(), 1);
+        assert_eq!(report.dead_letters[0].index, 3);
+
+        // Abort only at the dispute.
+        let mut policies = PolicyMap::new(ErrorPolicy::Warn);
+        policies.set(ErrorKind::AccountNotFound, ErrorPolicy::Abort);
+        let mut db = ClientsDatabase::new();
+        db.set_error_policy(policies);
+        let report = db.process_all(transactions);
+        assert_eq!(report.processed, 4);
+        assert!(matches!(report.aborted, Some((3, _))));
+        assert_eq!(report.rejections.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_kind_policy() {
+        assert_eq!(
+            parse_kind_policy("DuplicateTransactionId=abort"),
+            Ok((ErrorKind::DuplicateTransactionId, ErrorPolicy::Abort))
+        );
+        assert_eq!(
+            parse_kind_policy(" TransactionNotFound = warn"),
+            Ok((ErrorKind::TransactionNotFound, ErrorPolicy::Warn))
+        );
+        assert!(parse_kind_policy("TransactionNotFound").is_err());
+        assert!(parse_kind_policy("NotAKind=skip").is_err());
+        assert!(parse_kind_policy("AccountFrozen=retry").is_err());
+    }
+
+    #[test]
+    fn test_process_all_runs_match_single_transactions() {
+        let t = |kind, id, amount: &str| Transaction {
+            kind,
+            id,
+            amount: Amount::parse(amount.as_bytes()).unwrap_or_default(),
+        };
+        // Runs that start before the account exists, and fail in the middle.
+        let transactions = [
+            (7, t(Dispute, 1, "")),
+            (7, t(Withdrawal, 2, "1")),
+            (7, t(Deposit, 3, "5")),
+            (7, t(Withdrawal, 4, "10")),
+            (7, t(Dispute, 3, "")),
+            (8, t(Deposit, 5, "1")),
+            (8, t(Deposit, 5, "1")),
+            (8, t(Withdrawal, 7, "0")),
+            (7, t(Chargeback, 3, "")),
+            (7, t(Deposit, 6, "1")),
+        ];
+
+        let mut single = ClientsDatabase::new();
+        let mut expected = Vec::new();
+        for (idx, (client_id, t)) in transactions.iter().copied().enumerate() {
+            if let Err(e) = single.process_transaction(client_id, t) {
+                expected.push((idx, e.to_string()));
+            }
+        }
+
+        let mut runs = ClientsDatabase::new();
+        let report = runs.process_all(transactions);
+        assert_eq!(
+            report
+                .rejections
+                .iter()
+                .map(|(i, e)| (*i, e.to_string()))
+                .collect::<Vec<_>>(),
+            expected
+        );
+        for client_id in [7, 8] {
+            assert_eq!(
+                runs.get(client_id).unwrap().view(),
+                single.get(client_id).unwrap().view()
+            );
+        }
+        // Clock advanced by every transaction, as with single ones.
+        assert_eq!(runs.clock, single.clock);
+
+        // History needs the database between transactions, so they're applied one at a time.
+        let mut one_by_one = ClientsDatabase::with_history();
+        let expected = one_by_one.process_all(transactions);
+        assert_eq!(report.metrics(), expected.metrics());
+        assert_eq!(report.clients, expected.clients);
+        assert_eq!(report.zero_amounts, 1);
+    }
+}
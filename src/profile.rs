@@ -0,0 +1,122 @@
+use std::{
+    io::{self, BufRead, Read},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// Time added up across threads, e.g. spent in one phase of a run. Clones share the total.
+#[derive(Clone, Debug, Default)]
+pub struct Stopwatch {
+    nanos: Arc<AtomicU64>,
+}
+
+impl Stopwatch {
+    pub fn add(&self, elapsed: Duration) {
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// Run `f`, adding the time it took.
+    pub fn time<T>(&self, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.add(start.elapsed());
+        result
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::Relaxed))
+    }
+}
+
+/// Reader adding the time spent waiting for data to a [`Stopwatch`].
+pub struct TimedReader<R> {
+    inner: R,
+    stopwatch: Stopwatch,
+}
+
+impl<R> TimedReader<R> {
+    pub fn new(inner: R, stopwatch: Stopwatch) -> Self {
+        TimedReader { inner, stopwatch }
+    }
+}
+
+impl<R: Read> Read for TimedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (inner, stopwatch) = (&mut self.inner, &self.stopwatch);
+        stopwatch.time(|| inner.read(buf))
+    }
+}
+
+impl<R: BufRead> BufRead for TimedReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let start = Instant::now();
+        let buf = self.inner.fill_buf();
+        self.stopwatch.add(start.elapsed());
+        buf
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.inner.consume(amount)
+    }
+}
+
+/// Iterator adding the time spent producing each item to a [`Stopwatch`].
+pub struct TimedIter<I> {
+    inner: I,
+    stopwatch: Stopwatch,
+}
+
+impl<I> TimedIter<I> {
+    pub fn new(inner: I, stopwatch: Stopwatch) -> Self {
+        TimedIter { inner, stopwatch }
+    }
+}
+
+impl<I: Iterator> Iterator for TimedIter<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (inner, stopwatch) = (&mut self.inner, &self.stopwatch);
+        stopwatch.time(|| inner.next())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        thread::sleep,
+        time::Duration,
+    };
+
+    use crate::profile::{Stopwatch, TimedIter, TimedReader};
+
+    #[test]
+    fn test_stopwatch() {
+        let stopwatch = Stopwatch::default();
+        let shared = stopwatch.clone();
+        std::thread::spawn(move || shared.time(|| sleep(Duration::from_millis(5))))
+            .join()
+            .unwrap();
+        stopwatch.add(Duration::from_millis(1));
+        assert!(stopwatch.elapsed() >= Duration::from_millis(6));
+
+        let io = Stopwatch::default();
+        let lines = BufReader::new(TimedReader::new(b"a\nb\n".as_slice(), io.clone())).lines();
+        let produce = Stopwatch::default();
+        let items = TimedIter::new(lines, produce.clone())
+            .map(|line| {
+                sleep(Duration::from_millis(5));
+                line.unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(items, ["a", "b"]);
+        assert!(produce.elapsed() >= io.elapsed());
+        // Time spent by the consumer isn't counted.
+        assert!(produce.elapsed() < Duration::from_millis(10));
+    }
+}
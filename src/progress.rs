@@ -0,0 +1,224 @@
+use std::{
+    io::{self, BufRead, Read},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::memory::ByteSize;
+
+/// Counters of a run in progress, added to by the threads doing the work and sampled by
+/// [`Progress::report_every`]. Clones share the counters.
+#[derive(Clone, Debug, Default)]
+pub struct Progress(Arc<Counters>);
+
+#[derive(Debug, Default)]
+struct Counters {
+    rows: AtomicU64,
+    bytes: AtomicU64,
+    rejected: AtomicU64,
+}
+
+/// Totals of a [`Progress`] so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProgressCounts {
+    /// Transactions submitted to the database, as in [`crate::metrics::Metrics::rows`].
+    pub rows: u64,
+    /// Of input read.
+    pub bytes: u64,
+    pub rejected: u64,
+}
+
+/// Rates over the last interval, from [`Progress::report_every`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProgressRecord {
+    /// Since reporting started.
+    pub elapsed: Duration,
+    pub totals: ProgressCounts,
+    pub rows_per_sec: f64,
+    /// In MiB.
+    pub mb_per_sec: f64,
+    pub rejects_per_sec: f64,
+    /// Resident memory of the process, where known.
+    pub memory: Option<ByteSize>,
+}
+
+impl Progress {
+    pub fn add_rows(&self, rows: usize, rejected: usize) {
+        self.0.rows.fetch_add(rows as u64, Ordering::Relaxed);
+        self.0
+            .rejected
+            .fetch_add(rejected as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes(&self, bytes: usize) {
+        self.0.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn counts(&self) -> ProgressCounts {
+        ProgressCounts {
+            rows: self.0.rows.load(Ordering::Relaxed),
+            bytes: self.0.bytes.load(Ordering::Relaxed),
+            rejected: self.0.rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Call `on_record` every `interval` on another thread, until the returned reporter is
+    /// dropped.
+    pub fn report_every(
+        &self,
+        interval: Duration,
+        mut on_record: impl FnMut(ProgressRecord) + Send + 'static,
+    ) -> ProgressReporter {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let progress = self.clone();
+        let thread = std::thread::spawn(move || {
+            let start = Instant::now();
+            let mut last = (start, progress.counts());
+            // Woken early only to stop.
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let now = (Instant::now(), progress.counts());
+                on_record(ProgressRecord::between(start, last, now));
+                last = now;
+            }
+        });
+        ProgressReporter {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl ProgressRecord {
+    fn between(
+        start: Instant,
+        (last_at, last): (Instant, ProgressCounts),
+        (now_at, now): (Instant, ProgressCounts),
+    ) -> Self {
+        let secs = (now_at - last_at).as_secs_f64().max(f64::EPSILON);
+        let rate = |now: u64, last: u64| now.saturating_sub(last) as f64 / secs;
+        ProgressRecord {
+            elapsed: now_at - start,
+            totals: now,
+            rows_per_sec: rate(now.rows, last.rows),
+            mb_per_sec: rate(now.bytes, last.bytes) / (1 << 20) as f64,
+            rejects_per_sec: rate(now.rejected, last.rejected),
+            memory: resident_memory(),
+        }
+    }
+}
+
+/// Stops reporting progress when dropped.
+pub struct ProgressReporter {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for ProgressReporter {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Reader adding the bytes read to a [`Progress`].
+pub struct CountingReader<R> {
+    inner: R,
+    progress: Progress,
+}
+
+impl<R> CountingReader<R> {
+    pub fn new(inner: R, progress: Progress) -> Self {
+        CountingReader { inner, progress }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.progress.add_bytes(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.progress.add_bytes(amount);
+        self.inner.consume(amount)
+    }
+}
+
+/// From the kernel's accounting, on Linux.
+fn resident_memory() -> Option<ByteSize> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kib = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(ByteSize(kib << 10))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read},
+        sync::mpsc,
+        time::Duration,
+    };
+
+    use crate::progress::{CountingReader, Progress, ProgressCounts};
+
+    #[test]
+    fn test_report_progress() {
+        let progress = Progress::default();
+        let mut reader = CountingReader::new(BufReader::new(&b"abc\ndef\n"[..]), progress.clone());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        reader.read_to_string(&mut line).unwrap();
+        progress.add_rows(2, 1);
+        assert_eq!(
+            progress.counts(),
+            ProgressCounts {
+                rows: 2,
+                bytes: 8,
+                rejected: 1
+            }
+        );
+
+        let (tx, rx) = mpsc::channel();
+        let reporter = progress.report_every(Duration::from_millis(10), move |r| {
+            let _ = tx.send(r);
+        });
+        let first = rx.recv().unwrap();
+        assert_eq!(first.totals.rows, 2);
+        // Nothing since reporting started.
+        assert_eq!(first.rows_per_sec, 0.0);
+        progress.add_rows(100, 0);
+        let later = rx.iter().find(|r| r.totals.rows == 102).unwrap();
+        assert!(later.rows_per_sec > 0.0);
+        assert!(later.elapsed >= Duration::from_millis(20));
+        if cfg!(target_os = "linux") {
+            assert!(later.memory.unwrap().0 > 0);
+        }
+        // Stops reporting.
+        drop(reporter);
+        while rx.recv().is_ok() {}
+    }
+}
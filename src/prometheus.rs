@@ -0,0 +1,257 @@
+use std::{fmt::Write, sync::Mutex, time::Duration};
+
+use crate::{accounts::TransactionKind, error::ErrorKind, metrics::Metrics, stats::DatabaseStats};
+
+/// Upper bounds in seconds of the latency histogram buckets, from 10µs to 1s.
+const BUCKETS: [f64; 11] = [
+    0.00001, 0.00005, 0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0,
+];
+
+#[derive(Clone, Copy, Default)]
+struct Histogram {
+    // Not cumulative, the last one counts values above every bound.
+    buckets: [u64; BUCKETS.len() + 1],
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        let idx = BUCKETS.partition_point(|&bound| bound < seconds);
+        self.buckets[idx] += 1;
+        self.sum += seconds;
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    metrics: Metrics,
+    latency: [Histogram; TransactionKind::ALL.len()],
+}
+
+/// [`Metrics`] of transactions submitted to a long-running process, with a histogram of the time
+/// taken to apply them per type, rendered in the Prometheus text format.
+#[derive(Default)]
+pub struct Registry {
+    inner: Mutex<Inner>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a submitted transaction: `Ok` with whether it opened the client's account if it
+    /// was applied, or the kind of error it was rejected with, and the time it took.
+    pub fn record(
+        &self,
+        kind: TransactionKind,
+        outcome: Result<bool, ErrorKind>,
+        elapsed: Duration,
+    ) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let metrics = &mut inner.metrics;
+        metrics.rows += 1;
+        match outcome {
+            Ok(opened) => {
+                metrics.applied.add(kind);
+                metrics.accounts_created += opened as usize;
+                match kind {
+                    TransactionKind::Dispute => metrics.disputes_opened += 1,
+                    TransactionKind::Resolve | TransactionKind::Chargeback => {
+                        metrics.disputes_closed += 1
+                    }
+                    TransactionKind::Deposit | TransactionKind::Withdrawal => {}
+                }
+            }
+            Err(e) => metrics.rejections.add(e),
+        }
+        inner.latency[kind as usize].observe(elapsed.as_secs_f64());
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).metrics
+    }
+
+    /// All metrics in the Prometheus text exposition format, with gauges of the database's
+    /// current state from `stats`.
+    pub fn render(&self, stats: &DatabaseStats) -> String {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let m = &inner.metrics;
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "transactions_total",
+            "counter",
+            "Transactions submitted, applied or not.",
+        );
+        writeln!(out, "payengine_transactions_total {}", m.rows).unwrap();
+        header(
+            &mut out,
+            "applied_total",
+            "counter",
+            "Applied transactions by type.",
+        );
+        for kind in TransactionKind::ALL {
+            writeln!(
+                out,
+                "payengine_applied_total{{type=\"{}\"}} {}",
+                kind.as_str(),
+                m.applied.get(kind)
+            )
+            .unwrap();
+        }
+        header(
+            &mut out,
+            "rejected_total",
+            "counter",
+            "Rejected transactions by reason.",
+        );
+        for (kind, count) in m.rejections.iter() {
+            writeln!(
+                out,
+                "payengine_rejected_total{{reason=\"{kind:?}\"}} {count}"
+            )
+            .unwrap();
+        }
+        for (name, help, value) in [
+            (
+                "accounts_created_total",
+                "Accounts opened by a first deposit.",
+                m.accounts_created,
+            ),
+            (
+                "disputes_opened_total",
+                "Applied disputes.",
+                m.disputes_opened,
+            ),
+            (
+                "disputes_closed_total",
+                "Applied resolves and chargebacks.",
+                m.disputes_closed,
+            ),
+        ] {
+            header(&mut out, name, "counter", help);
+            writeln!(out, "payengine_{name} {value}").unwrap();
+        }
+
+        for (name, help, value) in [
+            ("accounts", "Accounts in the database.", stats.accounts),
+            (
+                "open_disputes",
+                "Deposits currently under dispute.",
+                stats.open_disputes,
+            ),
+            (
+                "frozen_accounts",
+                "Accounts locked by a chargeback.",
+                stats.frozen_accounts,
+            ),
+            (
+                "flagged_accounts",
+                "Accounts flagged for review by their chargeback rate.",
+                stats.flagged_accounts,
+            ),
+        ] {
+            header(&mut out, name, "gauge", help);
+            writeln!(out, "payengine_{name} {value}").unwrap();
+        }
+
+        header(
+            &mut out,
+            "transaction_duration_seconds",
+            "histogram",
+            "Time taken to apply or reject a transaction, by type.",
+        );
+        for kind in TransactionKind::ALL {
+            let histogram = &inner.latency[kind as usize];
+            let name = "payengine_transaction_duration_seconds";
+            let kind = kind.as_str();
+            let mut cumulative = 0;
+            for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                writeln!(
+                    out,
+                    "{name}_bucket{{type=\"{kind}\",le=\"{bound}\"}} {cumulative}"
+                )
+                .unwrap();
+            }
+            cumulative += histogram.buckets[BUCKETS.len()];
+            writeln!(
+                out,
+                "{name}_bucket{{type=\"{kind}\",le=\"+Inf\"}} {cumulative}"
+            )
+            .unwrap();
+            writeln!(out, "{name}_sum{{type=\"{kind}\"}} {}", histogram.sum).unwrap();
+            writeln!(out, "{name}_count{{type=\"{kind}\"}} {cumulative}").unwrap();
+        }
+        out
+    }
+}
+
+/// `HELP` and `TYPE` lines of a metric.
+fn header(out: &mut String, name: &str, ty: &str, help: &str) {
+    writeln!(
+        out,
+        "# HELP payengine_{name} {help}\n# TYPE payengine_{name} {ty}"
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        accounts::TransactionKind::*, error::ErrorKind, prometheus::Registry, stats::DatabaseStats,
+    };
+
+    #[test]
+    fn test_render() {
+        let registry = Registry::new();
+        registry.record(Deposit, Ok(true), Duration::from_micros(20));
+        registry.record(Deposit, Ok(false), Duration::from_millis(2));
+        registry.record(Dispute, Ok(false), Duration::from_secs(2));
+        registry.record(
+            Withdrawal,
+            Err(ErrorKind::WithdrawOverflow),
+            Duration::from_micros(5),
+        );
+        let metrics = registry.metrics();
+        assert_eq!(metrics.rows, 4);
+        assert_eq!(metrics.accounts_created, 1);
+        assert_eq!(metrics.disputes_opened, 1);
+
+        let text = registry.render(&DatabaseStats {
+            accounts: 1,
+            ..Default::default()
+        });
+        let has = |line: &str| text.lines().any(|l| l == line);
+        assert!(has("payengine_transactions_total 4"));
+        assert!(has("payengine_applied_total{type=\"deposit\"} 2"));
+        assert!(has("payengine_applied_total{type=\"withdrawal\"} 0"));
+        assert!(has(
+            "payengine_rejected_total{reason=\"WithdrawOverflow\"} 1"
+        ));
+        assert!(has("payengine_accounts 1"));
+        assert!(has(
+            "# TYPE payengine_transaction_duration_seconds histogram"
+        ));
+        let bucket = "payengine_transaction_duration_seconds_bucket{type=\"deposit\"";
+        assert!(has(&format!("{bucket},le=\"0.00001\"}} 0")));
+        assert!(has(&format!("{bucket},le=\"0.00005\"}} 1")));
+        assert!(has(&format!("{bucket},le=\"0.005\"}} 2")));
+        assert!(has(&format!("{bucket},le=\"+Inf\"}} 2")));
+        let bucket = "payengine_transaction_duration_seconds_bucket{type=\"dispute\"";
+        assert!(has(&format!("{bucket},le=\"1\"}} 0")));
+        assert!(has(&format!("{bucket},le=\"+Inf\"}} 1")));
+        assert!(has(
+            "payengine_transaction_duration_seconds_count{type=\"withdrawal\"} 1"
+        ));
+        // Every sample line is a metric name, optional labels and a number.
+        for line in text.lines().filter(|l| !l.starts_with('#')) {
+            let (_, value) = line.rsplit_once(' ').unwrap();
+            assert!(value.parse::<f64>().is_ok(), "{line}");
+        }
+    }
+}
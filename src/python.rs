@@ -0,0 +1,369 @@
+//! The `payengine` Python module, built with maturin from `pyproject.toml`, e.g. `maturin develop`
+//! in a virtualenv. Amounts, parsing and the database are the same as the binary's, so scenarios
+//! replayed from notebooks follow the real dispute logic:
+//!
+//! ```python
+//! import payengine
+//!
+//! db = payengine.ClientsDatabase()
+//! db.process_line("deposit, 1, 1, 10")
+//! db.process_line("dispute, 1, 1,")
+//! assert db.account(1).held == payengine.Amount("10")
+//! ```
+//!
+//! Malformed rows raise `payengine.ParseError` and rejected transactions raise
+//! `payengine.LedgerError`, both `ValueError`s with the reason code and message as arguments.
+
+use pyo3::{create_exception, exceptions::PyValueError, prelude::*};
+
+use crate::{
+    accounts::{ClientId, ClientsDatabase, Transaction, TransactionId},
+    amount::Amount,
+    parser::{Row, message_rows, parse_kind},
+    report::ReportFormat,
+};
+
+create_exception!(payengine, ParseError, PyValueError, "A malformed row.");
+create_exception!(
+    payengine,
+    LedgerError,
+    PyValueError,
+    "A rejected transaction."
+);
+
+fn parse_error(e: crate::error::ParseError) -> PyErr {
+    ParseError::new_err((e.code(), e.to_string()))
+}
+
+fn ledger_error(e: crate::error::LedgerError) -> PyErr {
+    LedgerError::new_err((e.code(), e.to_string()))
+}
+
+/// A decimal amount with 4 places, e.g. `Amount("2.5")`.
+#[pyclass(name = "Amount", module = "payengine", frozen, eq, ord, from_py_object)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct PyAmount(Amount);
+
+#[pymethods]
+impl PyAmount {
+    #[new]
+    fn new(s: &str) -> PyResult<Self> {
+        Amount::parse(s.as_bytes())
+            .map(PyAmount)
+            .ok_or_else(|| parse_error(crate::error::ParseError::InvalidAmount))
+    }
+
+    /// The amount in ten-thousandths, e.g. 25000 for 2.5.
+    #[getter]
+    fn raw(&self) -> u64 {
+        self.0.to_raw()
+    }
+
+    #[staticmethod]
+    fn from_raw(raw: u64) -> Self {
+        PyAmount(Amount::from_raw(raw))
+    }
+
+    fn __str__(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Amount('{}')", self.0)
+    }
+
+    fn __hash__(&self) -> u64 {
+        self.0.to_raw()
+    }
+
+    fn __float__(&self) -> f64 {
+        self.0.to_raw() as f64 / Amount::ONE.to_raw() as f64
+    }
+}
+
+/// An amount given as an `Amount` or a string.
+#[derive(FromPyObject)]
+enum AmountArg {
+    Amount(PyAmount),
+    Str(String),
+}
+
+/// A transaction of a client, e.g. `Row("deposit", 1, 1, "10")`, or parsed by `parse_row`.
+#[pyclass(name = "Row", module = "payengine", frozen, from_py_object)]
+#[derive(Clone, Copy)]
+struct PyRow {
+    client_id: ClientId,
+    transaction: Transaction,
+}
+
+#[pymethods]
+impl PyRow {
+    #[new]
+    #[pyo3(signature = (kind, client, tx, amount = None))]
+    fn new(
+        kind: &str,
+        client: ClientId,
+        tx: TransactionId,
+        amount: Option<AmountArg>,
+    ) -> PyResult<Self> {
+        let kind = parse_kind(kind.as_bytes()).map_err(parse_error)?;
+        let amount = match amount {
+            Some(AmountArg::Amount(amount)) => amount.0.to_string(),
+            Some(AmountArg::Str(s)) => s,
+            None => String::new(),
+        };
+        let row = Row::with_amount(kind, client, tx, amount.as_bytes()).map_err(parse_error)?;
+        Ok(row.into())
+    }
+
+    #[getter(r#type)]
+    fn kind(&self) -> &'static str {
+        self.transaction.kind.as_str()
+    }
+
+    #[getter]
+    fn client(&self) -> ClientId {
+        self.client_id
+    }
+
+    #[getter]
+    fn tx(&self) -> TransactionId {
+        self.transaction.id
+    }
+
+    /// None for types without one.
+    #[getter]
+    fn amount(&self) -> Option<PyAmount> {
+        self.transaction
+            .kind
+            .has_amount()
+            .then_some(PyAmount(self.transaction.amount))
+    }
+
+    fn __repr__(&self) -> String {
+        let t = self.transaction;
+        match self.amount() {
+            Some(amount) => format!(
+                "Row('{}', {}, {}, '{}')",
+                t.kind.as_str(),
+                self.client_id,
+                t.id,
+                amount.0
+            ),
+            None => format!("Row('{}', {}, {})", t.kind.as_str(), self.client_id, t.id),
+        }
+    }
+}
+
+impl From<Row> for PyRow {
+    fn from(row: Row) -> Self {
+        PyRow {
+            client_id: row.client_id,
+            transaction: row.transaction,
+        }
+    }
+}
+
+/// Parse a row of the CSV input, e.g. `deposit, 1, 1, 10`.
+#[pyfunction]
+fn parse_row(line: &str) -> PyResult<PyRow> {
+    Row::parse(line.trim_ascii().as_bytes())
+        .map(PyRow::from)
+        .map_err(parse_error)
+}
+
+/// Balances of an account.
+#[pyclass(
+    name = "Account",
+    module = "payengine",
+    frozen,
+    get_all,
+    skip_from_py_object
+)]
+#[derive(Clone, Copy)]
+struct PyAccount {
+    client: ClientId,
+    available: PyAmount,
+    held: PyAmount,
+    total: PyAmount,
+    locked: bool,
+}
+
+#[pymethods]
+impl PyAccount {
+    fn __repr__(&self) -> String {
+        format!(
+            "Account(client={}, available='{}', held='{}', total='{}', locked={})",
+            self.client,
+            self.available.0,
+            self.held.0,
+            self.total.0,
+            if self.locked { "True" } else { "False" }
+        )
+    }
+}
+
+/// Accounts of clients with their deposits, applying transactions like the binary.
+#[pyclass(name = "ClientsDatabase", module = "payengine")]
+#[derive(Default)]
+struct PyDatabase {
+    db: ClientsDatabase,
+}
+
+#[pymethods]
+impl PyDatabase {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply a transaction, raising `LedgerError` if rejected.
+    fn process(&mut self, row: PyRow) -> PyResult<()> {
+        self.db
+            .process_transaction(row.client_id, row.transaction)
+            .map_err(ledger_error)
+    }
+
+    /// Apply a row of the CSV input, raising `ParseError` if malformed or `LedgerError` if
+    /// rejected. Returns False for a blank or header line.
+    fn process_line(&mut self, line: &str) -> PyResult<bool> {
+        let Some((_, row)) = message_rows(line.as_bytes()).next() else {
+            return Ok(false);
+        };
+        self.process(row.map_err(parse_error)?.into())?;
+        Ok(true)
+    }
+
+    /// Apply the rows of a CSV input, e.g. a file's contents, with or without its header. Returns
+    /// the rows which were malformed or rejected, as `(line, code)` with lines from 1.
+    fn process_csv(&mut self, text: &str) -> Vec<(usize, &'static str)> {
+        let mut failed = Vec::new();
+        for (line, row) in message_rows(text.as_bytes()) {
+            let result = row.map_err(crate::Error::from).and_then(|row| {
+                self.db
+                    .process_transaction(row.client_id, row.transaction)
+                    .map_err(Into::into)
+            });
+            if let Err(e) = result {
+                failed.push((line, e.code()));
+            }
+        }
+        failed
+    }
+
+    /// The client's balances, None without an account.
+    fn account(&self, client: ClientId) -> Option<PyAccount> {
+        let view = self.db.get(client)?.view();
+        Some(PyAccount {
+            client,
+            available: PyAmount(view.available),
+            held: PyAmount(view.held),
+            total: PyAmount(view.total),
+            locked: view.locked,
+        })
+    }
+
+    /// All accounts by client id.
+    fn accounts(&self) -> Vec<PyAccount> {
+        let mut clients = self.db.iter().map(|(client, _)| client).collect::<Vec<_>>();
+        clients.sort_unstable();
+        clients
+            .into_iter()
+            .filter_map(|client| self.account(client))
+            .collect()
+    }
+
+    /// The report of all accounts by client id, as the binary writes it.
+    fn to_csv(&self) -> String {
+        let accounts = self
+            .accounts()
+            .into_iter()
+            .filter_map(|account| Some((account.client, self.db.get(account.client)?.view())));
+        let mut report = Vec::new();
+        ReportFormat::Csv
+            .write(&mut report, accounts, None)
+            .unwrap();
+        String::from_utf8(report).unwrap()
+    }
+
+    fn __len__(&self) -> usize {
+        self.db.iter().count()
+    }
+}
+
+#[pymodule(name = "payengine")]
+fn init(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyAmount>()?;
+    m.add_class::<PyRow>()?;
+    m.add_class::<PyAccount>()?;
+    m.add_class::<PyDatabase>()?;
+    m.add_function(wrap_pyfunction!(parse_row, m)?)?;
+    m.add("ParseError", m.py().get_type::<ParseError>())?;
+    m.add("LedgerError", m.py().get_type::<LedgerError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pyo3::{
+        ffi::c_str,
+        prelude::*,
+        types::{PyDict, PyModule},
+    };
+
+    use crate::python::init;
+
+    #[test]
+    fn test_python_module() {
+        Python::initialize();
+        Python::attach(|py| {
+            let m = PyModule::new(py, "payengine").unwrap();
+            init(&m).unwrap();
+            // As if imported from the built extension.
+            let modules = py.import("sys").unwrap().getattr("modules").unwrap();
+            modules.set_item("payengine", &m).unwrap();
+            let globals = PyDict::new(py);
+            py.run(
+                c_str!(
+                    r#"
+from payengine import Amount, ClientsDatabase, LedgerError, ParseError, Row, parse_row
+
+assert Amount("2.5") == Amount.from_raw(25000)
+assert Amount("2.5") < Amount("3")
+assert str(Amount("2.50")) == "2.5" and float(Amount("2.5")) == 2.5
+assert repr(parse_row("deposit, 1, 1, 10")) == "Row('deposit', 1, 1, '10')"
+assert parse_row("dispute, 1, 1,").amount is None
+try:
+    parse_row("refund, 1, 2, 1")
+    assert False
+except ParseError as e:
+    assert e.args[0] == "E_UNKNOWN_TYPE"
+
+db = ClientsDatabase()
+assert not db.process_line("type, client, tx, amount")
+assert db.process_line("deposit, 1, 1, 10")
+db.process(Row("dispute", 1, 1))
+assert db.account(1).held == Amount("10")
+try:
+    db.process(Row("withdrawal", 1, 2, Amount("1")))
+    assert False
+except LedgerError as e:
+    assert e.args[0] == "E_INSUFFICIENT_FUNDS"
+db.process_line("chargeback, 1, 1,")
+assert db.account(1).locked and db.account(2) is None
+
+assert db.process_csv("type,client,tx,amount\ndeposit,2,3,1.5\nbogus\ndeposit,1,4,1\n") == [
+    (3, "E_MISSING_COLUMN"),
+    (4, "E_ACCOUNT_FROZEN"),
+]
+assert [a.client for a in db.accounts()] == [1, 2] and len(db) == 2
+assert db.to_csv().splitlines()[2] == "2,1.5,0,1.5,false"
+"#
+                ),
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
@@ -0,0 +1,135 @@
+use std::fmt::Write;
+
+use crate::{
+    error::ErrorKind,
+    process::{ProcessReport, RejectionCounts},
+};
+
+/// Problems of an input regardless of the ledger's state, to track the quality of the data sent
+/// to us over time: malformed rows, values out of range, reused transaction ids and amounts that
+/// don't make sense.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DataQuality {
+    /// Rows read, well-formed or not.
+    pub rows: usize,
+    /// Malformed rows by kind of error.
+    pub malformed: RejectionCounts,
+    /// Deposits and withdrawals reusing the id of an earlier one.
+    pub duplicate_tx_ids: usize,
+    /// Deposits and withdrawals of zero, applied or not.
+    pub zero_amounts: usize,
+}
+
+/// Columns whose values can be out of range, by the error of a row with one.
+const FIELDS: [(&str, ErrorKind); 4] = [
+    ("type", ErrorKind::CsvUnknownTransactionType),
+    ("client", ErrorKind::CsvInvalidClientId),
+    ("tx", ErrorKind::CsvInvalidTxId),
+    ("amount", ErrorKind::CsvInvalidAmount),
+];
+
+impl DataQuality {
+    /// Add a processing run, or part of one, and the rows which failed to parse before it.
+    pub fn add(&mut self, report: &ProcessReport, malformed: &RejectionCounts) {
+        self.rows += report.processed + malformed.total();
+        self.malformed.merge(malformed);
+        self.duplicate_tx_ids += report.counts.get(ErrorKind::DuplicateTransactionId);
+        self.zero_amounts += report.zero_amounts;
+    }
+
+    /// Rows with a value out of range, by column.
+    pub fn out_of_range(&self) -> impl Iterator<Item = (&'static str, usize)> + '_ {
+        FIELDS
+            .into_iter()
+            .map(|(field, kind)| (field, self.malformed.get(kind)))
+    }
+
+    /// Rows with an amount of zero, or any amount for a transaction type without one.
+    pub fn unexpected_amounts(&self) -> usize {
+        self.zero_amounts + self.malformed.get(ErrorKind::CsvUnexpectedAmount)
+    }
+
+    /// Rows with none of the problems counted, as a percentage of all rows.
+    pub fn clean_percent(&self) -> f64 {
+        let problems = self.malformed.total() + self.duplicate_tx_ids + self.zero_amounts;
+        if self.rows == 0 {
+            100.0
+        } else {
+            100.0 * self.rows.saturating_sub(problems) as f64 / self.rows as f64
+        }
+    }
+
+    /// One JSON object, e.g. `{"rows":100,"clean_percent":97.0,"malformed":{"CsvInvalidAmount":1},
+    /// "out_of_range":{"type":0,"client":0,"tx":0,"amount":1},"duplicate_tx_ids":1,
+    /// "unexpected_amounts":1}`.
+    pub fn to_json(&self) -> String {
+        let mut out = format!(
+            "{{\"rows\":{},\"clean_percent\":{:.1},\"malformed\":{{",
+            self.rows,
+            self.clean_percent()
+        );
+        for (idx, (kind, count)) in self.malformed.iter().enumerate() {
+            if idx > 0 {
+                out.push(',');
+            }
+            write!(out, "\"{kind:?}\":{count}").unwrap();
+        }
+        out.push_str("},\"out_of_range\":{");
+        for (idx, (field, count)) in self.out_of_range().enumerate() {
+            if idx > 0 {
+                out.push(',');
+            }
+            write!(out, "\"{field}\":{count}").unwrap();
+        }
+        write!(
+            out,
+            "}},\"duplicate_tx_ids\":{},\"unexpected_amounts\":{}}}",
+            self.duplicate_tx_ids,
+            self.unexpected_amounts()
+        )
+        .unwrap();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::{ClientsDatabase, Transaction, TransactionKind},
+        amount::Amount,
+        error::ErrorKind,
+        process::RejectionCounts,
+        quality::DataQuality,
+    };
+
+    #[test]
+    fn test_data_quality() {
+        let t = |kind, id, amount: &str| Transaction {
+            kind,
+            id,
+            amount: Amount::parse(amount.as_bytes()).unwrap(),
+        };
+        let mut db = ClientsDatabase::new();
+        let report = db.process_all([
+            (1, t(TransactionKind::Deposit, 1, "10")),
+            (1, t(TransactionKind::Deposit, 1, "10")),
+            (1, t(TransactionKind::Withdrawal, 2, "0")),
+            (1, t(TransactionKind::Dispute, 1, "0")),
+        ]);
+        let mut malformed = RejectionCounts::default();
+        malformed.add(ErrorKind::CsvInvalidClientId);
+        malformed.add(ErrorKind::CsvUnexpectedAmount);
+
+        let mut quality = DataQuality::default();
+        quality.add(&report, &malformed);
+        assert_eq!(quality.rows, 6);
+        assert_eq!(quality.duplicate_tx_ids, 1);
+        assert_eq!(quality.zero_amounts, 1);
+        assert_eq!(quality.unexpected_amounts(), 2);
+        assert_eq!(
+            quality.to_json(),
+            r#"{"rows":6,"clean_percent":33.3,"malformed":{"CsvInvalidClientId":1,"CsvUnexpectedAmount":1},"out_of_range":{"type":0,"client":1,"tx":0,"amount":0},"duplicate_tx_ids":1,"unexpected_amounts":2}"#
+        );
+        assert_eq!(DataQuality::default().clean_percent(), 100.0);
+    }
+}
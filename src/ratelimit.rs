@@ -0,0 +1,136 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::accounts::ClientId;
+
+/// Transactions a client may submit while serving or following an input, beyond which they're
+/// rejected with [`crate::error::LedgerError::RateLimited`]. Limits which are unset aren't
+/// checked.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct RateLimits {
+    pub per_second: Option<u32>,
+    pub per_minute: Option<u32>,
+}
+
+impl RateLimits {
+    pub fn is_empty(&self) -> bool {
+        self.per_second.is_none() && self.per_minute.is_none()
+    }
+
+    // Capacity and refill period of each bucket.
+    fn buckets(&self) -> impl Iterator<Item = (f64, Duration)> + '_ {
+        [
+            (self.per_second, Duration::from_secs(1)),
+            (self.per_minute, Duration::from_secs(60)),
+        ]
+        .into_iter()
+        .filter_map(|(limit, period)| Some((f64::from(limit?), period)))
+    }
+}
+
+/// Enforces [`RateLimits`] per client with token buckets, so a client may use its whole limit in
+/// a burst, then only as fast as the limit refills. Shared between threads; state is kept for
+/// every client seen, at most one entry per client id.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    limits: RateLimits,
+    clients: Mutex<HashMap<ClientId, Buckets>>,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    // Tokens left in each bucket of `RateLimits::buckets`, in order.
+    tokens: [f64; 2],
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        RateLimiter {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    pub fn limits(&self) -> &RateLimits {
+        &self.limits
+    }
+
+    /// Count a transaction of the client, if within its limits. Otherwise, returns how long until
+    /// it would be.
+    pub fn check(&self, client_id: ClientId) -> Result<(), Duration> {
+        self.check_at(client_id, Instant::now())
+    }
+
+    /// [`Self::check`] at the time `now`, which doesn't go backwards.
+    pub fn check_at(&self, client_id: ClientId, now: Instant) -> Result<(), Duration> {
+        if self.limits.is_empty() {
+            return Ok(());
+        }
+        let mut clients = self.clients.lock().unwrap();
+        let buckets = clients.entry(client_id).or_insert_with(|| {
+            let mut tokens = [0.0; 2];
+            for (tokens, (capacity, _)) in tokens.iter_mut().zip(self.limits.buckets()) {
+                *tokens = capacity;
+            }
+            Buckets {
+                tokens,
+                refilled: now,
+            }
+        });
+        let elapsed = now.saturating_duration_since(buckets.refilled);
+        buckets.refilled = now;
+        let mut wait = Duration::ZERO;
+        for (tokens, (capacity, period)) in buckets.tokens.iter_mut().zip(self.limits.buckets()) {
+            *tokens =
+                (*tokens + capacity * elapsed.as_secs_f64() / period.as_secs_f64()).min(capacity);
+            if *tokens < 1.0 {
+                wait = wait.max(period.mul_f64((1.0 - *tokens) / capacity));
+            }
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        for tokens in &mut buckets.tokens {
+            *tokens -= 1.0;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::ratelimit::{RateLimiter, RateLimits};
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(RateLimits {
+            per_second: Some(2),
+            per_minute: Some(3),
+        });
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        assert_eq!(limiter.check_at(1, at(0)), Ok(()));
+        assert_eq!(limiter.check_at(1, at(0)), Ok(()));
+        assert_eq!(limiter.check_at(1, at(0)), Err(Duration::from_millis(500)));
+        // Other clients have their own limits.
+        assert_eq!(limiter.check_at(2, at(0)), Ok(()));
+        assert_eq!(limiter.check_at(1, at(500)), Ok(()));
+        // The minute's limit is used up, a token refills every 20 seconds.
+        let wait = limiter.check_at(1, at(2000)).unwrap_err();
+        assert!((wait.as_secs_f64() - 18.0).abs() < 0.01, "{wait:?}");
+        assert_eq!(limiter.check_at(1, at(21_000)), Ok(()));
+
+        let unlimited = RateLimiter::default();
+        for _ in 0..1000 {
+            assert_eq!(unlimited.check(1), Ok(()));
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    io::{BufRead, Read},
+    ops::Range,
+    sync::mpsc::{Receiver, SyncSender, sync_channel},
+};
+
+// Bytes requested from the OS at once. A multiple of any block size O_DIRECT may require.
+pub(crate) const BUFFER_LEN: usize = 4 << 20;
+// Alignment of buffers, as required by O_DIRECT.
+const ALIGN: usize = 4096;
+// Buffers filled ahead of the consumer; with the one being consumed this is double buffering.
+pub(crate) const QUEUED_BUFFERS: usize = 1;
+
+/// A buffer with its filled range, kept aligned to ALIGN by offsetting into an over-allocated Vec.
+type Chunk = (Vec<u8>, Range<usize>);
+
+/// Reader that fills large buffers on a dedicated thread, so disk reads overlap with the consumer
+/// processing the previous buffer instead of stalling it between reads.
+///
+/// Buffers are aligned, so it can read files opened with O_DIRECT (see [`open_direct`]).
+pub struct ReadAhead {
+    rx: Receiver<std::io::Result<Chunk>>,
+    // Consumed buffers go back to the reading thread to avoid allocating new ones.
+    recycle: SyncSender<Vec<u8>>,
+    current: Chunk,
+}
+
+impl ReadAhead {
+    pub fn new(reader: impl Read + Send + 'static) -> Self {
+        Self::with_buffer_len(reader, BUFFER_LEN)
+    }
+
+    /// Same as [`Self::new`] with a custom buffer size. For O_DIRECT files it must be a multiple
+    /// of the device block size.
+    pub fn with_buffer_len(mut reader: impl Read + Send + 'static, buffer_len: usize) -> Self {
+        assert!(buffer_len > 0, "buffer length must be positive");
+        let (tx, rx) = sync_channel(QUEUED_BUFFERS);
+        let (recycle, recycled) = sync_channel::<Vec<u8>>(QUEUED_BUFFERS + 1);
+        // Detached: it exits on EOF, on error, or once this reader is dropped.
+        std::thread::spawn(move || {
+            loop {
+                let mut buf = recycled
+                    .try_recv()
+                    .unwrap_or_else(|_| vec![0; buffer_len + ALIGN]);
+                let start = buf.as_ptr().align_offset(ALIGN);
+                let chunk = fill(&mut reader, &mut buf[start..start + buffer_len])
+                    .map(|len| (buf, start..start + len));
+                let eof = matches!(&chunk, Ok((_, r)) if r.is_empty()) || chunk.is_err();
+                if tx.send(chunk).is_err() || eof {
+                    return;
+                }
+            }
+        });
+        ReadAhead {
+            rx,
+            recycle,
+            current: (Vec::new(), 0..0),
+        }
+    }
+}
+
+/// Read until `buf` is full or EOF. Returns the number of bytes read.
+fn fill(reader: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+impl Read for ReadAhead {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for ReadAhead {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.current.1.is_empty() {
+            // A closed channel means EOF or an error was already returned.
+            if let Ok(next) = self.rx.recv() {
+                let (done, _) = std::mem::replace(&mut self.current, next?);
+                if !done.is_empty() {
+                    let _ = self.recycle.try_send(done);
+                }
+            }
+        }
+        let (buf, range) = &self.current;
+        Ok(&buf[range.clone()])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.current.1.start += amt;
+    }
+}
+
+/// Open a file for reading with O_DIRECT, bypassing the page cache, to be read through
+/// [`ReadAhead`]. Falls back to a regular open on other platforms, or if the filesystem doesn't
+/// support it.
+#[cfg(feature = "direct-io")]
+pub fn open_direct(path: impl AsRef<std::path::Path>) -> std::io::Result<std::fs::File> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        match std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(path.as_ref())
+        {
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => std::fs::File::open(path),
+            res => res,
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    std::fs::File::open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufRead;
+
+    use crate::readahead::ReadAhead;
+
+    #[test]
+    fn test_read_ahead_lines() {
+        let input = (0..1000)
+            .map(|i| format!("deposit, {i}, {i}, 1.5\n"))
+            .collect::<String>();
+        // Small buffers, so lines span them.
+        let reader = ReadAhead::with_buffer_len(std::io::Cursor::new(input.clone()), 64);
+        let lines = reader.lines().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(lines, input.lines().collect::<Vec<_>>());
+    }
+
+    #[cfg(feature = "direct-io")]
+    #[test]
+    fn test_read_ahead_direct() {
+        let path = std::env::temp_dir().join(format!("payengine-direct-{}", std::process::id()));
+        // Not a multiple of the block size, so the last read is short.
+        let input = vec![b'x'; 3 * 4096 + 100];
+        std::fs::write(&path, &input).unwrap();
+        let file = crate::readahead::open_direct(&path).unwrap();
+        let mut read = Vec::new();
+        std::io::Read::read_to_end(&mut ReadAhead::with_buffer_len(file, 8192), &mut read).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read, input);
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    ops::RangeBounds,
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    accounts::{Account, AccountView, ClientId, client_ids},
+    deposits::DepositStore,
+};
+
+// Snapshots are split into fixed chunks of consecutive client ids. A new snapshot shares all chunks
+// that didn't change since the previous one, so taking it costs proportional to the number of
+// modified chunks, not accounts.
+const CHUNK_BITS: u32 = 8;
+const CHUNK_LEN: usize = 1 << CHUNK_BITS;
+const CHUNKS: usize = (ClientId::MAX as usize + 1) / CHUNK_LEN;
+
+type Chunk = [Option<AccountView<'static>>; CHUNK_LEN];
+
+fn chunk_of(client_id: ClientId) -> usize {
+    client_id as usize >> CHUNK_BITS
+}
+
+/// Set of chunks modified since the last snapshot.
+#[derive(Debug)]
+pub(crate) struct DirtyChunks([u64; CHUNKS / 64]);
+
+impl Default for DirtyChunks {
+    fn default() -> Self {
+        DirtyChunks([0; CHUNKS / 64])
+    }
+}
+
+impl DirtyChunks {
+    pub(crate) fn mark(&mut self, client_id: ClientId) {
+        let chunk = chunk_of(client_id);
+        self.0[chunk / 64] |= 1 << (chunk % 64);
+    }
+
+    fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..CHUNKS).filter(|c| self.0[c / 64] & (1 << (c % 64)) != 0)
+    }
+
+    fn clear(&mut self) {
+        self.0 = [0; CHUNKS / 64];
+    }
+}
+
+/// An immutable copy of all account balances taken at one point in time.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    chunks: Vec<Option<Arc<Chunk>>>,
+    len: usize,
+}
+
+impl Default for Snapshot {
+    fn default() -> Self {
+        Snapshot {
+            chunks: vec![None; CHUNKS],
+            len: 0,
+        }
+    }
+}
+
+impl Snapshot {
+    pub(crate) fn capture<'a, D: DepositStore + 'a>(
+        accounts: impl Iterator<Item = (ClientId, &'a Account<D>)>,
+    ) -> Self {
+        let mut snapshot = Snapshot::default();
+        for (client_id, account) in accounts {
+            let chunk = snapshot.chunks[chunk_of(client_id)]
+                .get_or_insert_with(|| Arc::new([None; CHUNK_LEN]));
+            // Freshly created, so never shared.
+            Arc::get_mut(chunk).unwrap()[client_id as usize % CHUNK_LEN] = Some(account.view());
+            snapshot.len += 1;
+        }
+        snapshot
+    }
+
+    /// A copy of this snapshot with the dirty chunks re-read through `get`. Clears `dirty`.
+    pub(crate) fn update(
+        &self,
+        dirty: &mut DirtyChunks,
+        get: impl Fn(ClientId) -> Option<AccountView<'static>>,
+    ) -> Self {
+        let mut snapshot = self.clone();
+        for chunk_idx in dirty.iter() {
+            let first = chunk_idx * CHUNK_LEN;
+            let old_len = snapshot.chunks[chunk_idx]
+                .as_ref()
+                .map(|c| c.iter().flatten().count())
+                .unwrap_or_default();
+            let chunk: Chunk = std::array::from_fn(|i| get((first + i) as ClientId));
+            let new_len = chunk.iter().flatten().count();
+            snapshot.len = snapshot.len - old_len + new_len;
+            snapshot.chunks[chunk_idx] = Some(Arc::new(chunk));
+        }
+        dirty.clear();
+        snapshot
+    }
+
+    pub fn get(&self, client_id: ClientId) -> Option<AccountView<'static>> {
+        self.chunks[chunk_of(client_id)].as_ref()?[client_id as usize % CHUNK_LEN]
+    }
+
+    /// Iterate accounts in client id order.
+    pub fn iter(&self) -> impl Iterator<Item = (ClientId, AccountView<'static>)> + '_ {
+        self.chunks
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, c)| Some((idx, c.as_ref()?)))
+            .flat_map(|(idx, chunk)| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(i, v)| Some(((idx * CHUNK_LEN + i) as ClientId, (*v)?)))
+            })
+    }
+
+    /// Iterate accounts with client ids within the range, in client id order.
+    pub fn range(
+        &self,
+        range: impl RangeBounds<ClientId>,
+    ) -> impl Iterator<Item = (ClientId, AccountView<'static>)> + '_ {
+        let mut ids = client_ids(range).peekable();
+        std::iter::from_fn(move || {
+            loop {
+                let client_id = ids.next()?;
+                let Some(chunk) = &self.chunks[chunk_of(client_id)] else {
+                    // Skip the rest of the missing chunk.
+                    while ids
+                        .next_if(|c| chunk_of(*c) == chunk_of(client_id))
+                        .is_some()
+                    {}
+                    continue;
+                };
+                if let Some(view) = chunk[client_id as usize % CHUNK_LEN] {
+                    return Some((client_id, view));
+                }
+            }
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Thread-safe read access to balances while another thread keeps processing transactions.
+///
+/// The writer periodically publishes a fresh [`Snapshot`] (see `ClientsDatabase::publish`);
+/// readers grab the latest one, which stays consistent for as long as they hold it. The lock is
+/// only held to swap or clone the `Arc`, so neither side ever waits on the other's work.
+#[derive(Debug, Default)]
+pub struct DatabaseReader {
+    current: RwLock<Arc<Snapshot>>,
+}
+
+impl DatabaseReader {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// The latest published snapshot. Use it for multiple lookups that need to agree with each other.
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn get(&self, client_id: ClientId) -> Option<AccountView<'static>> {
+        self.snapshot().get(client_id)
+    }
+
+    pub(crate) fn publish(&self, snapshot: Arc<Snapshot>) {
+        *self.current.write().unwrap() = snapshot;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        accounts::{ClientId, ClientsDatabase, Transaction, TransactionKind::*},
+        amount::Amount,
+        reader::{DatabaseReader, Snapshot, chunk_of},
+    };
+
+    #[test]
+    fn test_reader_sees_published_state_only() {
+        let reader = DatabaseReader::new();
+        let mut db = ClientsDatabase::new();
+        db.process_transaction(
+            1,
+            Transaction {
+                kind: Deposit,
+                id: 1,
+                amount: Amount::parse(b"5").unwrap(),
+            },
+        )
+        .unwrap();
+        assert!(reader.get(1).is_none());
+
+        db.publish(&reader);
+        let before = reader.snapshot();
+        assert_eq!(before.get(1).unwrap().total, Amount::parse(b"5").unwrap());
+
+        db.process_transaction(
+            1,
+            Transaction {
+                kind: Withdrawal,
+                id: 2,
+                amount: Amount::parse(b"2").unwrap(),
+            },
+        )
+        .unwrap();
+        db.publish(&reader);
+
+        // Old snapshot is unaffected, new one has the withdrawal.
+        assert_eq!(before.get(1).unwrap().total, Amount::parse(b"5").unwrap());
+        assert_eq!(reader.get(1).unwrap().total, Amount::parse(b"3").unwrap());
+    }
+
+    #[test]
+    fn test_snapshot_shares_unchanged_chunks() {
+        let mut db = ClientsDatabase::new();
+        for client in [1, 2, 1000] {
+            db.process_transaction(
+                client,
+                Transaction {
+                    kind: Deposit,
+                    id: client as u32,
+                    amount: Amount::parse(b"1").unwrap(),
+                },
+            )
+            .unwrap();
+        }
+        let first = db.snapshot();
+        assert_eq!(first.len(), 3);
+        assert_eq!(
+            first.iter().map(|(c, _)| c).collect::<Vec<_>>(),
+            vec![1, 2, 1000]
+        );
+
+        db.process_transaction(
+            1000,
+            Transaction {
+                kind: Withdrawal,
+                id: 5000,
+                amount: Amount::parse(b"1").unwrap(),
+            },
+        )
+        .unwrap();
+        db.process_transaction(
+            3000,
+            Transaction {
+                kind: Deposit,
+                id: 5001,
+                amount: Amount::parse(b"1").unwrap(),
+            },
+        )
+        .unwrap();
+        let second = db.snapshot();
+        assert_eq!(second.len(), 4);
+        assert_eq!(first.get(1000).unwrap().total, Amount::parse(b"1").unwrap());
+        assert_eq!(second.get(1000).unwrap().total, Amount::zero());
+        assert!(first.get(3000).is_none());
+
+        let chunk = |s: &Snapshot, c: ClientId| s.chunks[chunk_of(c)].clone().unwrap();
+        assert!(Arc::ptr_eq(&chunk(&first, 1), &chunk(&second, 1)));
+        assert!(!Arc::ptr_eq(&chunk(&first, 1000), &chunk(&second, 1000)));
+    }
+}
@@ -0,0 +1,78 @@
+-- Apply a transaction to an account, atomically as a script runs alone. Mirrors
+-- `Account::process`, see redis.rs for the keys.
+--
+-- KEYS: the account's hash, its deposits' hash and the set of its deposits under dispute.
+-- ARGV: type as in the CSV input, transaction id, and amount in ten-thousandths.
+-- Returns 'OK', or the `LedgerError` variant rejecting the transaction, changing nothing.
+
+local account, deposits, disputed = KEYS[1], KEYS[2], KEYS[3]
+local kind, tx, amount = ARGV[1], ARGV[2], tonumber(ARGV[3])
+
+-- Lua numbers are doubles, exact for integers up to 2^53.
+local MAX = 9007199254740992
+
+local function format(n)
+  return string.format('%.0f', n)
+end
+
+if redis.call('EXISTS', account) == 0 then
+  if kind ~= 'deposit' then
+    return 'AccountNotFound'
+  end
+elseif redis.call('HGET', account, 'locked') == '1' then
+  return 'AccountFrozen'
+end
+local total = tonumber(redis.call('HGET', account, 'total') or '0')
+local held = tonumber(redis.call('HGET', account, 'held') or '0')
+
+if kind == 'deposit' then
+  if total + amount > MAX then
+    return 'DepositOverflow'
+  end
+  if redis.call('HSETNX', deposits, tx, ARGV[3]) == 0 then
+    return 'DuplicateTransactionId'
+  end
+  redis.call('HSET', account, 'total', format(total + amount), 'held', format(held), 'locked', '0')
+  return 'OK'
+end
+
+if kind == 'withdrawal' then
+  if total - held < amount then
+    return 'WithdrawOverflow'
+  end
+  redis.call('HSET', account, 'total', format(total - amount))
+  return 'OK'
+end
+
+local deposit = redis.call('HGET', deposits, tx)
+if not deposit then
+  return 'TransactionNotFound'
+end
+deposit = tonumber(deposit)
+local is_disputed = redis.call('SISMEMBER', disputed, tx) == 1
+
+if kind == 'dispute' then
+  if is_disputed then
+    return 'DuplicateDispute'
+  end
+  if held + deposit > MAX then
+    return 'HeldOverflow'
+  end
+  redis.call('SADD', disputed, tx)
+  redis.call('HSET', account, 'held', format(held + deposit))
+  return 'OK'
+end
+
+if not is_disputed then
+  if kind == 'resolve' then
+    return 'ResolveNotDisputed'
+  end
+  return 'ChargebackNotDisputed'
+end
+redis.call('SREM', disputed, tx)
+redis.call('HSET', account, 'held', format(held - deposit))
+if kind == 'chargeback' then
+  -- Funds already withdrawn leave the total at 0 rather than negative.
+  redis.call('HSET', account, 'total', format(math.max(total - deposit, 0)), 'locked', '1')
+end
+return 'OK'
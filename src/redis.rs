@@ -0,0 +1,202 @@
+use std::io;
+
+use ::redis::{Client, Commands, Connection, Script};
+
+use crate::{
+    accounts::{AccountView, ClientId, Transaction},
+    amount::Amount,
+    error::LedgerError,
+};
+
+/// Accounts kept in Redis rather than in memory, so several instances using the same prefix share
+/// them, and they outlive restarts.
+///
+/// Each client has a hash `<prefix>:{<client id>}` with its `total`, `held` and `locked` fields,
+/// a hash `<prefix>:{<client id>}:deposits` of the amounts of its deposits by transaction id, and
+/// a set `<prefix>:{<client id>}:disputed` of those under dispute. Amounts are in ten-thousandths
+/// like [`Amount::to_raw`]. The braces keep a client's keys in the same slot of a cluster.
+///
+/// Transactions are applied by a Lua script, atomically with respect to other instances, with
+/// the same rules as [`crate::accounts::Account::process`]. As Lua numbers are doubles, balances
+/// are limited to 2^53 ten-thousandths, about 900 billion, beyond which deposits and disputes
+/// are rejected as overflowing. Review flags aren't kept.
+pub struct RedisLedger {
+    conn: Connection,
+    prefix: String,
+    script: Script,
+}
+
+impl RedisLedger {
+    /// Connect to the server at `url`, e.g. `redis://localhost:6379`, keeping accounts under
+    /// `prefix`.
+    pub fn open(url: &str, prefix: impl Into<String>) -> io::Result<Self> {
+        let conn = Client::open(url)
+            .and_then(|client| client.get_connection())
+            .map_err(io::Error::other)?;
+        Ok(RedisLedger {
+            conn,
+            prefix: prefix.into(),
+            script: Script::new(include_str!("redis.lua")),
+        })
+    }
+
+    /// Apply a transaction, one round trip. The outer error is the server's, in which case the
+    /// transaction may or may not have been applied.
+    pub fn process_transaction(
+        &mut self,
+        client_id: ClientId,
+        t: Transaction,
+    ) -> io::Result<Result<(), LedgerError>> {
+        let [account, deposits, disputed] = keys(&self.prefix, client_id);
+        let reply: String = self
+            .script
+            .key(account)
+            .key(deposits)
+            .key(disputed)
+            .arg(t.kind.as_str())
+            .arg(t.id)
+            .arg(t.amount.to_raw())
+            .invoke(&mut self.conn)
+            .map_err(io::Error::other)?;
+        if reply == "OK" {
+            return Ok(Ok(()));
+        }
+        rejection(&reply).map(Err).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected reply of the script: {reply}"),
+            )
+        })
+    }
+
+    /// The client's balances, None without an account.
+    pub fn get(&mut self, client_id: ClientId) -> io::Result<Option<AccountView<'static>>> {
+        let [account, _, _] = keys(&self.prefix, client_id);
+        let fields: [Option<String>; 3] = ::redis::cmd("HMGET")
+            .arg(account)
+            .arg(&["total", "held", "locked"])
+            .query(&mut self.conn)
+            .map_err(io::Error::other)?;
+        let [Some(total), held, locked] = fields else {
+            return Ok(None);
+        };
+        let amount = |s: &str| {
+            s.parse().map(Amount::from_raw).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid amount of client {client_id}: {e}"),
+                )
+            })
+        };
+        Ok(Some(view(
+            amount(&total)?,
+            amount(held.as_deref().unwrap_or("0"))?,
+            locked.as_deref() == Some("1"),
+        )))
+    }
+
+    /// All accounts by client id, including those of other instances. Scans the keyspace.
+    pub fn accounts(&mut self) -> io::Result<Vec<(ClientId, AccountView<'static>)>> {
+        let pattern = format!("{}:{{*}}", self.prefix);
+        let mut clients = Vec::new();
+        for key in self
+            .conn
+            .scan_match::<_, String>(pattern)
+            .map_err(io::Error::other)?
+        {
+            if let Some(client_id) = client_of_key(&self.prefix, &key.map_err(io::Error::other)?) {
+                clients.push(client_id);
+            }
+        }
+        clients.sort_unstable();
+        let mut accounts = Vec::with_capacity(clients.len());
+        for client_id in clients {
+            if let Some(view) = self.get(client_id)? {
+                accounts.push((client_id, view));
+            }
+        }
+        Ok(accounts)
+    }
+}
+
+/// Keys of the client's account, deposits and disputed deposits.
+fn keys(prefix: &str, client_id: ClientId) -> [String; 3] {
+    let account = format!("{prefix}:{{{client_id}}}");
+    let deposits = format!("{account}:deposits");
+    let disputed = format!("{account}:disputed");
+    [account, deposits, disputed]
+}
+
+/// The client of an account's key, None for other keys.
+fn client_of_key(prefix: &str, key: &str) -> Option<ClientId> {
+    key.strip_prefix(prefix)?
+        .strip_prefix(":{")?
+        .strip_suffix('}')?
+        .parse()
+        .ok()
+}
+
+fn view(total: Amount, held: Amount, locked: bool) -> AccountView<'static> {
+    AccountView {
+        available: if locked {
+            Amount::zero()
+        } else {
+            total.checked_sub(held).unwrap_or_default()
+        },
+        held,
+        total,
+        locked,
+        flagged: false,
+        audit: &[],
+    }
+}
+
+/// The error of a rejection by the script, by variant name.
+fn rejection(name: &str) -> Option<LedgerError> {
+    Some(match name {
+        "DepositOverflow" => LedgerError::DepositOverflow,
+        "DuplicateTransactionId" => LedgerError::DuplicateTransactionId,
+        "WithdrawOverflow" => LedgerError::WithdrawOverflow,
+        "TransactionNotFound" => LedgerError::TransactionNotFound,
+        "DuplicateDispute" => LedgerError::DuplicateDispute,
+        "ResolveNotDisputed" => LedgerError::ResolveNotDisputed,
+        "ChargebackNotDisputed" => LedgerError::ChargebackNotDisputed,
+        "HeldOverflow" => LedgerError::HeldOverflow,
+        "AccountFrozen" => LedgerError::AccountFrozen,
+        "AccountNotFound" => LedgerError::AccountNotFound,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::redis::{client_of_key, keys, rejection};
+
+    #[test]
+    fn test_keys() {
+        let [account, deposits, disputed] = keys("payengine", 7);
+        assert_eq!(account, "payengine:{7}");
+        assert_eq!(deposits, "payengine:{7}:deposits");
+        assert_eq!(disputed, "payengine:{7}:disputed");
+        assert_eq!(client_of_key("payengine", &account), Some(7));
+        assert_eq!(client_of_key("payengine", &deposits), None);
+        assert_eq!(client_of_key("other", &account), None);
+        assert_eq!(client_of_key("payengine", "payengine:{x}"), None);
+    }
+
+    #[test]
+    fn test_script_rejections() {
+        // Every variant the script returns is known.
+        let script = include_str!("redis.lua");
+        let names = script
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("return '"))
+            .filter_map(|rest| rest.strip_suffix('\''))
+            .filter(|name| *name != "OK")
+            .collect::<Vec<_>>();
+        assert_eq!(names.len(), 10);
+        for name in names {
+            assert!(rejection(name).is_some(), "{name}");
+        }
+    }
+}
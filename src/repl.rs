@@ -0,0 +1,227 @@
+use std::{
+    fmt::Write as _,
+    hash::BuildHasher,
+    io::{BufRead, IsTerminal, Write},
+};
+
+use crate::{
+    accounts::{ClientId, ClientsDatabase},
+    audit::Provenance,
+    deposits::DepositStore,
+    parser::Row,
+};
+
+const HELP: &str = "\
+balance <client>                       balances of the account
+disputes <client>                      deposits under dispute, with their input line if recorded
+history <client>                       applied transactions with balances before and after, and
+                                       the input line of their deposit if recorded
+apply <type> <client> <tx> [amount]    apply a transaction, e.g. apply deposit 42 999 10.0
+freeze <client>, unfreeze <client>     lock or unlock the account
+flag <client>, unflag <client>         flag the account for review or clear its flag
+stats                                  totals over all accounts
+help                                   this list
+quit                                   leave
+";
+
+/// Read commands line by line and write their results, until the input ends or `quit`. With
+/// `interactive`, a prompt is shown before each command.
+pub fn run<S: BuildHasher, D: DepositStore>(
+    db: &mut ClientsDatabase<S, D>,
+    mut input: impl BufRead,
+    mut out: impl Write,
+    interactive: bool,
+) -> std::io::Result<()> {
+    let mut line = String::new();
+    loop {
+        if interactive {
+            out.write_all(b"> ")?;
+            out.flush()?;
+        }
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        match line.split_whitespace().next() {
+            None => continue,
+            Some("quit" | "exit") => return Ok(()),
+            Some(_) => {}
+        }
+        match execute(db, &line) {
+            Ok(s) => out.write_all(s.as_bytes())?,
+            Err(e) => writeln!(out, "error: {e}")?,
+        }
+        out.flush()?;
+    }
+}
+
+/// Same as [`run`] on stdin and stdout, prompting only if stdin is a terminal, so command files
+/// can be piped in.
+pub fn run_stdio<S: BuildHasher, D: DepositStore>(
+    db: &mut ClientsDatabase<S, D>,
+) -> std::io::Result<()> {
+    let stdin = std::io::stdin();
+    let interactive = stdin.is_terminal();
+    run(db, stdin.lock(), std::io::stdout().lock(), interactive)
+}
+
+/// Run one command, returning its output with a trailing newline.
+pub fn execute<S: BuildHasher, D: DepositStore>(
+    db: &mut ClientsDatabase<S, D>,
+    line: &str,
+) -> Result<String, String> {
+    let words = line.split_whitespace().collect::<Vec<_>>();
+    let mut out = String::new();
+    match words.as_slice() {
+        ["balance", client] => {
+            let client_id = parse_client(client)?;
+            let view = db.get(client_id).ok_or("account not found")?.view();
+            writeln!(
+                out,
+                "available {}, held {}, total {}, locked {}",
+                view.available, view.held, view.total, view.locked
+            )
+            .unwrap();
+        }
+        ["disputes", client] => {
+            let client_id = parse_client(client)?;
+            let account = db.get(client_id).ok_or("account not found")?;
+            for (id, amount) in account.disputed_deposits() {
+                writeln!(
+                    out,
+                    "tx {id}: {amount}{}",
+                    origin(db, db.provenance(client_id, id))
+                )
+                .unwrap();
+            }
+            if out.is_empty() {
+                out.push_str("no open disputes\n");
+            }
+        }
+        ["history", client] => {
+            let client_id = parse_client(client)?;
+            for r in db.audit(client_id) {
+                writeln!(
+                    out,
+                    "{}: {} tx {} {}, total {} -> {}, held {} -> {}{}",
+                    r.at,
+                    r.transaction.kind.as_str(),
+                    r.transaction.id,
+                    r.transaction.amount,
+                    r.before.total,
+                    r.after.total,
+                    r.before.held,
+                    r.after.held,
+                    origin(db, r.deposit)
+                )
+                .unwrap();
+            }
+            if out.is_empty() {
+                out.push_str("no history\n");
+            }
+        }
+        ["apply", kind, client, tx, amount @ ..] if amount.len() <= 1 => {
+            // Same rules as input rows.
+            let csv = format!("{kind},{client},{tx},{}", amount.first().unwrap_or(&""));
+            let row = Row::parse(csv.as_bytes()).map_err(|e| e.to_string())?;
+            db.process_transaction(row.client_id, row.transaction)
+                .map_err(|e| format!("rejected: {e}"))?;
+            out.push_str("ok\n");
+        }
+        [cmd @ ("freeze" | "unfreeze"), client] => {
+            let client_id = parse_client(client)?;
+            db.set_frozen(client_id, *cmd == "freeze")
+                .map_err(|e| e.to_string())?;
+            out.push_str("ok\n");
+        }
+        [cmd @ ("flag" | "unflag"), client] => {
+            let client_id = parse_client(client)?;
+            db.set_flagged(client_id, *cmd == "flag")
+                .map_err(|e| e.to_string())?;
+            out.push_str("ok\n");
+        }
+        ["stats"] => {
+            let s = db.stats();
+            writeln!(
+                out,
+                "accounts {}, frozen {}, flagged {}, deposits {}, open disputes {}, \
+                 available {}, held {}, total {}",
+                s.accounts,
+                s.frozen_accounts,
+                s.flagged_accounts,
+                s.deposits,
+                s.open_disputes,
+                s.available,
+                s.held,
+                s.total
+            )
+            .unwrap();
+        }
+        ["help"] => out.push_str(HELP),
+        _ => return Err("unknown command, try help".to_string()),
+    }
+    Ok(out)
+}
+
+/// Where a deposit came from, e.g. `, from input.csv:12`, or nothing if unknown.
+fn origin<S: BuildHasher, D: DepositStore>(
+    db: &ClientsDatabase<S, D>,
+    deposit: Option<Provenance>,
+) -> String {
+    match deposit {
+        Some(p) => format!(", from {}:{}", db.source(p.source).unwrap_or("?"), p.line),
+        None => String::new(),
+    }
+}
+
+fn parse_client(s: &str) -> Result<ClientId, String> {
+    s.parse().map_err(|_| format!("invalid client id {s:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{accounts::ClientsDatabase, repl::run};
+
+    #[test]
+    fn test_repl() {
+        let mut db = ClientsDatabase::new();
+        db.enable_audit();
+        let commands = "\
+            apply deposit 42 1 10.0\n\
+            apply deposit 42 2 2.5\n\
+            apply dispute 42 1\n\
+            \n\
+            balance 42\n\
+            disputes 42\n\
+            freeze 42\n\
+            apply withdrawal 42 3 1\n\
+            unfreeze 42\n\
+            flag 42\n\
+            unflag 7\n\
+            history 42\n\
+            balance 7\n\
+            apply refund 1 1\n\
+            quit\n\
+            balance 42\n";
+        let mut out = Vec::new();
+        run(&mut db, commands.as_bytes(), &mut out, false).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "ok\n\
+             ok\n\
+             ok\n\
+             available 2.5, held 10, total 12.5, locked false\n\
+             tx 1: 10\n\
+             ok\n\
+             error: rejected: account if frozen\n\
+             ok\n\
+             ok\n\
+             error: account not found\n\
+             0: deposit tx 1 10, total 0 -> 10, held 0 -> 0\n\
+             1: deposit tx 2 2.5, total 10 -> 12.5, held 0 -> 0\n\
+             2: dispute tx 1 0, total 12.5 -> 12.5, held 0 -> 10\n\
+             error: account not found\n\
+             error: unknown transaction type\n"
+        );
+    }
+}
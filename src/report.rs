@@ -0,0 +1,525 @@
+use std::{
+    fmt::Write as _,
+    io::{BufWriter, Write},
+};
+
+use crate::{
+    accounts::{AccountView, ClientId},
+    amount::Amount,
+};
+
+// Large enough that writes to the underlying writer (usually stdout) are rare.
+pub(crate) const BUFFER_CAPACITY: usize = 1 << 20;
+
+const HEADER: &[u8] = b"client, available, held, total, locked\n";
+const HEADER_FLAGGED: &[u8] = b"client, available, held, total, locked, flagged\n";
+
+/// Format of the final balances report.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum ReportFormat {
+    #[default]
+    Csv,
+    /// One JSON array.
+    Json,
+    /// One JSON object per line.
+    Ndjson,
+    /// Apache Parquet, with amounts as decimal strings. Needs the "parquet" feature.
+    Parquet,
+}
+
+impl ReportFormat {
+    pub const ALL: [ReportFormat; 4] = [
+        ReportFormat::Csv,
+        ReportFormat::Json,
+        ReportFormat::Ndjson,
+        ReportFormat::Parquet,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportFormat::Csv => "csv",
+            ReportFormat::Json => "json",
+            ReportFormat::Ndjson => "ndjson",
+            ReportFormat::Parquet => "parquet",
+        }
+    }
+
+    /// Write the report in this format. Amounts are rounded to `precision` decimal places if
+    /// given, otherwise written with all significant ones.
+    pub fn write(
+        self,
+        w: impl Write,
+        accounts: impl IntoIterator<Item = (ClientId, AccountView<'static>)>,
+        precision: Option<u8>,
+    ) -> std::io::Result<()> {
+        self.write_with(w, accounts, precision, false)
+    }
+
+    /// Same as [`Self::write`], with a `flagged` column after `locked` if `flagged` is set, e.g.
+    /// when accounts are flagged for review by [`crate::review::ReviewRules`].
+    pub fn write_with(
+        self,
+        w: impl Write,
+        accounts: impl IntoIterator<Item = (ClientId, AccountView<'static>)>,
+        precision: Option<u8>,
+        flagged: bool,
+    ) -> std::io::Result<()> {
+        let _span = tracing::info_span!("write_report", format = self.as_str()).entered();
+        match self {
+            ReportFormat::Csv => write_csv(w, accounts, precision, flagged),
+            ReportFormat::Json => write_json(w, accounts, precision, flagged, false),
+            ReportFormat::Ndjson => write_json(w, accounts, precision, flagged, true),
+            #[cfg(feature = "parquet")]
+            ReportFormat::Parquet => write_parquet(w, accounts, precision, flagged),
+            #[cfg(not(feature = "parquet"))]
+            ReportFormat::Parquet => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "built without the \"parquet\" feature",
+            )),
+        }
+    }
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|f| f.as_str() == s)
+            .ok_or_else(|| format!("unknown report format {s:?}"))
+    }
+}
+
+/// Write the final balances report as CSV.
+///
+/// Rows are formatted into one reused buffer without going through `Display`, and written through
+/// a large `BufWriter`, so a million accounts don't mean a million locked `println!` calls.
+pub fn write_report(
+    w: impl Write,
+    accounts: impl IntoIterator<Item = (ClientId, AccountView<'static>)>,
+) -> std::io::Result<()> {
+    write_csv(w, accounts, None, false)
+}
+
+fn write_csv(
+    w: impl Write,
+    accounts: impl IntoIterator<Item = (ClientId, AccountView<'static>)>,
+    precision: Option<u8>,
+    flagged: bool,
+) -> std::io::Result<()> {
+    let mut w = BufWriter::with_capacity(BUFFER_CAPACITY, w);
+    w.write_all(if flagged { HEADER_FLAGGED } else { HEADER })?;
+    let mut row = Vec::with_capacity(128);
+    for (client_id, view) in accounts {
+        row.clear();
+        push_row(&mut row, client_id, view, precision, flagged);
+        w.write_all(&row)?;
+    }
+    w.flush()
+}
+
+/// Format report rows without the header, e.g. for one shard on its own thread. Concatenating
+/// the header and parts gives the same output as [`write_report`] over all accounts.
+pub fn format_rows(
+    out: &mut Vec<u8>,
+    accounts: impl IntoIterator<Item = (ClientId, AccountView<'static>)>,
+) {
+    for (client_id, view) in accounts {
+        push_row(out, client_id, view, None, false);
+    }
+}
+
+/// Write the report header followed by parts formatted with [`format_rows`], as they come.
+pub fn write_report_parts(
+    w: impl Write,
+    parts: impl IntoIterator<Item = Vec<u8>>,
+) -> std::io::Result<()> {
+    let _span = tracing::info_span!("write_report", format = "csv").entered();
+    let mut w = BufWriter::with_capacity(BUFFER_CAPACITY, w);
+    w.write_all(HEADER)?;
+    for part in parts {
+        w.write_all(&part)?;
+    }
+    w.flush()
+}
+
+/// Write the final balances report as a JSON array of objects with the same fields as the CSV
+/// report. Amounts are decimal strings, as with the "serde" feature, to avoid precision loss.
+pub fn write_report_json(
+    w: impl Write,
+    accounts: impl IntoIterator<Item = (ClientId, AccountView<'static>)>,
+) -> std::io::Result<()> {
+    write_json(w, accounts, None, false, false)
+}
+
+/// A JSON array, or with `lines`, one object per line.
+fn write_json(
+    w: impl Write,
+    accounts: impl IntoIterator<Item = (ClientId, AccountView<'static>)>,
+    precision: Option<u8>,
+    flagged: bool,
+    lines: bool,
+) -> std::io::Result<()> {
+    let mut w = BufWriter::with_capacity(BUFFER_CAPACITY, w);
+    let mut row = Vec::with_capacity(128);
+    for (idx, (client_id, view)) in accounts.into_iter().enumerate() {
+        row.clear();
+        if !lines {
+            row.extend_from_slice(if idx == 0 { b"[\n" } else { b",\n" });
+        }
+        row.extend_from_slice(b"{\"client\":");
+        row.extend_from_slice(itoa::Buffer::new().format(client_id).as_bytes());
+        for (name, amount) in [
+            (&b",\"available\":\""[..], view.available),
+            (b"\",\"held\":\"", view.held),
+            (b"\",\"total\":\"", view.total),
+        ] {
+            row.extend_from_slice(name);
+            push_amount(&mut row, amount, precision);
+        }
+        row.extend_from_slice(if view.locked {
+            b"\",\"locked\":true"
+        } else {
+            b"\",\"locked\":false"
+        });
+        if flagged {
+            row.extend_from_slice(if view.flagged {
+                b",\"flagged\":true"
+            } else {
+                b",\"flagged\":false"
+            });
+        }
+        row.push(b'}');
+        if lines {
+            row.push(b'\n');
+        }
+        w.write_all(&row)?;
+    }
+    if !lines {
+        // Either closes the array, or it was empty.
+        w.write_all(if row.is_empty() { b"[]\n" } else { b"\n]\n" })?;
+    }
+    w.flush()
+}
+
+#[cfg(feature = "parquet")]
+const PARQUET_SCHEMA: &str = "
+    message balances {
+        REQUIRED INT32 client (INTEGER(16, false));
+        REQUIRED BYTE_ARRAY available (STRING);
+        REQUIRED BYTE_ARRAY held (STRING);
+        REQUIRED BYTE_ARRAY total (STRING);
+        REQUIRED BOOLEAN locked;
+    }
+";
+
+#[cfg(feature = "parquet")]
+const PARQUET_SCHEMA_FLAGGED: &str = "
+    message balances {
+        REQUIRED INT32 client (INTEGER(16, false));
+        REQUIRED BYTE_ARRAY available (STRING);
+        REQUIRED BYTE_ARRAY held (STRING);
+        REQUIRED BYTE_ARRAY total (STRING);
+        REQUIRED BOOLEAN locked;
+        REQUIRED BOOLEAN flagged;
+    }
+";
+
+/// Write the report as one Parquet row group. There are at most 65536 accounts, so the columns
+/// are simply collected first.
+#[cfg(feature = "parquet")]
+fn write_parquet(
+    mut w: impl Write,
+    accounts: impl IntoIterator<Item = (ClientId, AccountView<'static>)>,
+    precision: Option<u8>,
+    flagged: bool,
+) -> std::io::Result<()> {
+    use std::sync::Arc;
+
+    use parquet::{
+        column::writer::ColumnWriter, data_type::ByteArray, errors::ParquetError,
+        file::writer::SerializedFileWriter, schema::parser::parse_message_type,
+    };
+
+    let mut clients = Vec::new();
+    let mut amounts: [Vec<ByteArray>; 3] = Default::default();
+    // Locked and flagged.
+    let mut flags: [Vec<bool>; 2] = Default::default();
+    for (client_id, view) in accounts {
+        clients.push(i32::from(client_id));
+        for (column, amount) in amounts
+            .iter_mut()
+            .zip([view.available, view.held, view.total])
+        {
+            let mut s = Vec::new();
+            push_amount(&mut s, amount, precision);
+            column.push(s.into());
+        }
+        flags[0].push(view.locked);
+        flags[1].push(view.flagged);
+    }
+
+    // The file writer needs a Send writer, which stdout isn't.
+    let mut out = Vec::new();
+    let write = |out: &mut Vec<u8>| -> Result<(), ParquetError> {
+        let schema = if flagged {
+            PARQUET_SCHEMA_FLAGGED
+        } else {
+            PARQUET_SCHEMA
+        };
+        let schema = Arc::new(parse_message_type(schema)?);
+        let mut writer = SerializedFileWriter::new(out, schema, Default::default())?;
+        let mut row_group = writer.next_row_group()?;
+        let mut amounts = amounts.iter();
+        let mut flags = flags.iter();
+        while let Some(mut column) = row_group.next_column()? {
+            match column.untyped() {
+                ColumnWriter::Int32ColumnWriter(c) => c.write_batch(&clients, None, None)?,
+                ColumnWriter::ByteArrayColumnWriter(c) => {
+                    c.write_batch(amounts.next().unwrap(), None, None)?
+                }
+                ColumnWriter::BoolColumnWriter(c) => {
+                    c.write_batch(flags.next().unwrap(), None, None)?
+                }
+                _ => unreachable!("not in the schema"),
+            };
+            column.close()?;
+        }
+        row_group.close()?;
+        writer.close()?;
+        Ok(())
+    };
+    write(&mut out).map_err(std::io::Error::other)?;
+    w.write_all(&out)?;
+    w.flush()
+}
+
+fn push_row(
+    row: &mut Vec<u8>,
+    client_id: ClientId,
+    view: AccountView,
+    precision: Option<u8>,
+    flagged: bool,
+) {
+    row.extend_from_slice(itoa::Buffer::new().format(client_id).as_bytes());
+    row.push(b',');
+    push_amount(row, view.available, precision);
+    row.push(b',');
+    push_amount(row, view.held, precision);
+    row.push(b',');
+    push_amount(row, view.total, precision);
+    row.extend_from_slice(if view.locked { b",true" } else { b",false" });
+    if flagged {
+        row.extend_from_slice(if view.flagged { b",true" } else { b",false" });
+    }
+    row.push(b'\n');
+}
+
+fn push_amount(row: &mut Vec<u8>, amount: Amount, precision: Option<u8>) {
+    match precision {
+        Some(places) => amount.write_ascii_fixed(places, row),
+        None => amount.write_ascii(row),
+    }
+}
+
+/// Append `s` as a quoted JSON string.
+pub fn push_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::{AccountView, ClientsDatabase},
+        amount::Amount,
+        report::{ReportFormat, format_rows, write_report, write_report_json, write_report_parts},
+    };
+
+    #[test]
+    fn test_write_report() {
+        let amount = |s: &str| Amount::parse(s.as_bytes()).unwrap();
+        let accounts = [
+            (
+                1,
+                AccountView {
+                    available: amount("1.5"),
+                    held: amount("0"),
+                    total: amount("1.5"),
+                    locked: false,
+                    flagged: false,
+                    audit: &[],
+                },
+            ),
+            (
+                65535,
+                AccountView {
+                    available: amount("0"),
+                    held: amount("2.0001"),
+                    total: amount("2.0001"),
+                    locked: true,
+                    flagged: false,
+                    audit: &[],
+                },
+            ),
+        ];
+        let mut out = Vec::new();
+        write_report(&mut out, accounts).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client, available, held, total, locked\n\
+             1,1.5,0,1.5,false\n\
+             65535,0,2.0001,2.0001,true\n"
+        );
+
+        // Same format as import_balances expects.
+        let mut db = ClientsDatabase::new();
+        let mut out = Vec::new();
+        write_report(&mut out, accounts).unwrap();
+        assert_eq!(db.import_balances(&out[..]).unwrap(), 2);
+        assert_eq!(db.get(65535).unwrap().view(), accounts[1].1);
+
+        // With flags, imported back too.
+        let flagged = [(
+            1,
+            AccountView {
+                flagged: true,
+                ..accounts[0].1
+            },
+        )];
+        let mut out = Vec::new();
+        ReportFormat::Csv
+            .write_with(&mut out, flagged, None, true)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out.clone()).unwrap(),
+            "client, available, held, total, locked, flagged\n\
+             1,1.5,0,1.5,false,true\n"
+        );
+        let mut db = ClientsDatabase::new();
+        assert_eq!(db.import_balances(&out[..]).unwrap(), 1);
+        assert_eq!(db.get(1).unwrap().view(), flagged[0].1);
+
+        let mut out = Vec::new();
+        write_report(&mut out, accounts).unwrap();
+        let mut parts = vec![Vec::new(), Vec::new()];
+        format_rows(&mut parts[0], accounts[..1].iter().copied());
+        format_rows(&mut parts[1], accounts[1..].iter().copied());
+        let mut from_parts = Vec::new();
+        write_report_parts(&mut from_parts, parts).unwrap();
+        assert_eq!(from_parts, out);
+    }
+
+    #[test]
+    fn test_write_report_json() {
+        let view = AccountView {
+            available: Amount::parse(b"1.5").unwrap(),
+            held: Amount::zero(),
+            total: Amount::parse(b"1.5").unwrap(),
+            locked: true,
+            flagged: false,
+            audit: &[],
+        };
+        let mut out = Vec::new();
+        write_report_json(&mut out, [(1, view), (2, view)]).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "[\n\
+             {\"client\":1,\"available\":\"1.5\",\"held\":\"0\",\"total\":\"1.5\",\"locked\":true},\n\
+             {\"client\":2,\"available\":\"1.5\",\"held\":\"0\",\"total\":\"1.5\",\"locked\":true}\n\
+             ]\n"
+        );
+
+        let mut out = Vec::new();
+        write_report_json(&mut out, []).unwrap();
+        assert_eq!(out, b"[]\n");
+
+        let flagged = AccountView {
+            flagged: true,
+            ..view
+        };
+        let mut out = Vec::new();
+        ReportFormat::Ndjson
+            .write_with(&mut out, [(1, flagged), (2, view)], None, true)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"client\":1,\"available\":\"1.5\",\"held\":\"0\",\"total\":\"1.5\",\"locked\":true,\"flagged\":true}\n\
+             {\"client\":2,\"available\":\"1.5\",\"held\":\"0\",\"total\":\"1.5\",\"locked\":true,\"flagged\":false}\n"
+        );
+
+        let mut out = Vec::new();
+        ReportFormat::Ndjson
+            .write(&mut out, [(1, view), (2, view)], Some(2))
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"client\":1,\"available\":\"1.50\",\"held\":\"0.00\",\"total\":\"1.50\",\"locked\":true}\n\
+             {\"client\":2,\"available\":\"1.50\",\"held\":\"0.00\",\"total\":\"1.50\",\"locked\":true}\n"
+        );
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_write_report_parquet() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let view = AccountView {
+            available: Amount::parse(b"1.5").unwrap(),
+            held: Amount::parse(b"0.0001").unwrap(),
+            total: Amount::parse(b"1.5001").unwrap(),
+            locked: false,
+            flagged: false,
+            audit: &[],
+        };
+        let path = std::env::temp_dir().join(format!("payengine-report-{}", std::process::id()));
+        ReportFormat::Parquet
+            .write(
+                std::fs::File::create(&path).unwrap(),
+                [(7, view), (65535, view)],
+                None,
+            )
+            .unwrap();
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+        let rows = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            [
+                "{client: 7, available: \"1.5\", held: \"0.0001\", total: \"1.5001\", locked: false}",
+                "{client: 65535, available: \"1.5\", held: \"0.0001\", total: \"1.5001\", locked: false}"
+            ]
+        );
+
+        ReportFormat::Parquet
+            .write_with(
+                std::fs::File::create(&path).unwrap(),
+                [(7, view)],
+                None,
+                true,
+            )
+            .unwrap();
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let row = reader.get_row_iter(None).unwrap().next().unwrap().unwrap();
+        assert!(row.to_string().ends_with("locked: false, flagged: false}"));
+    }
+}
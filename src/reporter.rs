@@ -0,0 +1,176 @@
+use std::sync::OnceLock;
+
+/// What went wrong, see [`Incident`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IncidentKind {
+    /// Processing stopped before the end: at a rejected transaction or malformed row with an abort
+    /// policy or strict parsing, or failing to read or write.
+    Aborted,
+    /// Balances failed a consistency check, a bug in the ledger rather than bad input.
+    Invariant,
+    /// A panic, e.g. in a request handler of `serve`.
+    Crash,
+}
+
+impl IncidentKind {
+    pub const ALL: [IncidentKind; 3] = [
+        IncidentKind::Aborted,
+        IncidentKind::Invariant,
+        IncidentKind::Crash,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IncidentKind::Aborted => "aborted",
+            IncidentKind::Invariant => "invariant",
+            IncidentKind::Crash => "crash",
+        }
+    }
+}
+
+/// An error bad enough to page someone, beyond logging it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Incident<'a> {
+    pub kind: IncidentKind,
+    /// Reason code of the row processing stopped at, e.g. `E_ACCOUNT_FROZEN`.
+    pub code: Option<&'a str>,
+    pub message: &'a str,
+}
+
+/// Where incidents are sent, e.g. an error tracker, like `SentryReporter` with the "sentry"
+/// feature. Installed once per process with [`set_reporter`].
+pub trait ErrorReporter: Send + Sync {
+    fn report(&self, incident: &Incident<'_>);
+
+    /// Send incidents still buffered, before exiting.
+    fn flush(&self) {}
+}
+
+static REPORTER: OnceLock<Box<dyn ErrorReporter>> = OnceLock::new();
+
+/// Install the reporter of [`report`], unless one already is. Panics are then reported too, as
+/// [`IncidentKind::Crash`], after the panic hook installed before.
+pub fn set_reporter(reporter: Box<dyn ErrorReporter>) -> Result<(), Box<dyn ErrorReporter>> {
+    REPORTER.set(reporter)?;
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        previous(info);
+        report(&Incident {
+            kind: IncidentKind::Crash,
+            code: None,
+            message: &info.to_string(),
+        });
+        flush();
+    }));
+    Ok(())
+}
+
+/// Send an incident to the installed reporter, if any.
+pub fn report(incident: &Incident<'_>) {
+    if let Some(reporter) = REPORTER.get() {
+        reporter.report(incident);
+    }
+}
+
+/// Flush the installed reporter, if any, e.g. before exiting.
+pub fn flush() {
+    if let Some(reporter) = REPORTER.get() {
+        reporter.flush();
+    }
+}
+
+/// Incidents sent to Sentry as events, fatal ones for invariants and crashes, tagged with the
+/// incident kind and reason code.
+#[cfg(feature = "sentry")]
+pub struct SentryReporter {
+    _guard: sentry::ClientInitGuard,
+}
+
+#[cfg(feature = "sentry")]
+impl SentryReporter {
+    /// A client sending to `dsn`, or to `$SENTRY_DSN` if not given. Fails with a message if the
+    /// DSN is invalid or there's none.
+    pub fn new(dsn: Option<&str>) -> Result<Self, String> {
+        let dsn = dsn
+            .map(|dsn| dsn.parse().map_err(|e| format!("invalid Sentry DSN: {e}")))
+            .transpose()?;
+        let guard = sentry::init(sentry::ClientOptions {
+            dsn,
+            release: sentry::release_name!(),
+            attach_stacktrace: true,
+            ..Default::default()
+        });
+        if !guard.is_enabled() {
+            return Err("no Sentry DSN, set $SENTRY_DSN".to_owned());
+        }
+        Ok(SentryReporter { _guard: guard })
+    }
+}
+
+#[cfg(feature = "sentry")]
+impl ErrorReporter for SentryReporter {
+    fn report(&self, incident: &Incident<'_>) {
+        let level = match incident.kind {
+            IncidentKind::Aborted => sentry::Level::Error,
+            IncidentKind::Invariant | IncidentKind::Crash => sentry::Level::Fatal,
+        };
+        sentry::with_scope(
+            |scope| {
+                scope.set_tag("incident", incident.kind.as_str());
+                if let Some(code) = incident.code {
+                    scope.set_tag("code", code);
+                }
+            },
+            || sentry::capture_message(incident.message, level),
+        );
+    }
+
+    fn flush(&self) {
+        if let Some(client) = sentry::Hub::main().client() {
+            client.flush(Some(std::time::Duration::from_secs(2)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::reporter::{ErrorReporter, Incident, IncidentKind, report, set_reporter};
+
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl ErrorReporter for Recorder {
+        fn report(&self, incident: &Incident<'_>) {
+            self.0.lock().unwrap().push(format!(
+                "{} {}: {}",
+                incident.kind.as_str(),
+                incident.code.unwrap_or("-"),
+                incident.message
+            ));
+        }
+    }
+
+    #[test]
+    fn test_reporter() {
+        let recorder = Recorder::default();
+        assert!(set_reporter(Box::new(recorder.clone())).is_ok());
+        assert!(set_reporter(Box::new(Recorder::default())).is_err());
+        report(&Incident {
+            kind: IncidentKind::Aborted,
+            code: Some("E_DUP_TX"),
+            message: "duplicate transaction id",
+        });
+        let _ = std::thread::spawn(|| panic!("boom")).join();
+
+        let incidents = recorder.0.lock().unwrap();
+        assert_eq!(incidents[0], "aborted E_DUP_TX: duplicate transaction id");
+        // Other tests may panic meanwhile.
+        assert!(
+            incidents[1..]
+                .iter()
+                .any(|i| i.starts_with("crash -: ") && i.contains("boom"))
+        );
+    }
+}
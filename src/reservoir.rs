@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+
+use crate::{error::ErrorKind, report::push_json_string, sample::splitmix64};
+
+/// Uniform sample of up to `capacity` items of a stream of unknown length, in bounded memory
+/// (Vitter's algorithm R). Items are only built when kept.
+#[derive(Clone, Debug)]
+pub struct Reservoir<T> {
+    capacity: usize,
+    seen: u64,
+    items: Vec<T>,
+    rng: u64,
+}
+
+impl<T> Reservoir<T> {
+    pub fn new(capacity: usize, seed: u64) -> Self {
+        Reservoir {
+            capacity,
+            seen: 0,
+            items: Vec::new(),
+            rng: seed,
+        }
+    }
+
+    pub fn add(&mut self, item: impl FnOnce() -> T) {
+        self.seen += 1;
+        if self.items.len() < self.capacity {
+            self.items.push(item());
+            return;
+        }
+        let slot = self.random(self.seen) as usize;
+        if slot < self.capacity {
+            self.items[slot] = item();
+        }
+    }
+
+    /// Sample of both streams, approximately uniform: each item is taken from either sample in
+    /// proportion to the items it still stands for.
+    pub fn merge(&mut self, other: Reservoir<T>) {
+        let mut ours = (self.seen, std::mem::take(&mut self.items));
+        let mut theirs = (other.seen, other.items);
+        self.seen += other.seen;
+        while self.items.len() < self.capacity && !(ours.1.is_empty() && theirs.1.is_empty()) {
+            let take_ours = theirs.1.is_empty()
+                || (!ours.1.is_empty() && self.random(ours.0 + theirs.0) < ours.0);
+            let (seen, items) = if take_ours { &mut ours } else { &mut theirs };
+            let idx = self.random(items.len() as u64) as usize;
+            self.items.push(items.swap_remove(idx));
+            // Each item kept stands for an equal share of those seen.
+            *seen -= (*seen / (items.len() as u64 + 1)).max(1).min(*seen);
+        }
+    }
+
+    /// Items offered so far, kept or not.
+    pub fn seen(&self) -> u64 {
+        self.seen
+    }
+
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    // Uniform in `0..bound`, near enough for sampling.
+    fn random(&mut self, bound: u64) -> u64 {
+        self.rng = splitmix64(self.rng);
+        self.rng % bound.max(1)
+    }
+}
+
+/// Raw lines of malformed rows and rejected transactions, sampled by kind of error, to diagnose
+/// dirty inputs without keeping every rejection.
+#[derive(Clone, Debug, Default)]
+pub struct RejectSamples {
+    per_kind: usize,
+    kinds: BTreeMap<ErrorKind, Reservoir<String>>,
+}
+
+impl RejectSamples {
+    /// Keep up to `per_kind` lines of each kind of error, none if 0.
+    pub fn new(per_kind: usize) -> Self {
+        RejectSamples {
+            per_kind,
+            kinds: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.per_kind > 0
+    }
+
+    pub fn add(&mut self, kind: ErrorKind, line: impl FnOnce() -> String) {
+        if self.per_kind == 0 {
+            return;
+        }
+        self.kinds
+            .entry(kind)
+            .or_insert_with(|| Reservoir::new(self.per_kind, kind as u64))
+            .add(line);
+    }
+
+    pub fn merge(&mut self, other: RejectSamples) {
+        self.per_kind = self.per_kind.max(other.per_kind);
+        for (kind, sample) in other.kinds {
+            match self.kinds.get_mut(&kind) {
+                Some(ours) => ours.merge(sample),
+                None => {
+                    self.kinds.insert(kind, sample);
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, kind: ErrorKind) -> Option<&Reservoir<String>> {
+        self.kinds.get(&kind)
+    }
+
+    /// One JSON object by kind, e.g. `{"AccountNotFound":{"seen":120,"lines":["withdrawal,2,3,1",
+    /// ...]}}`.
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("{");
+        for (idx, (kind, sample)) in self.kinds.iter().enumerate() {
+            if idx > 0 {
+                out.push(',');
+            }
+            out.push_str(&format!(
+                "\"{kind:?}\":{{\"seen\":{},\"lines\":[",
+                sample.seen()
+            ));
+            for (idx, line) in sample.items().iter().enumerate() {
+                if idx > 0 {
+                    out.push(',');
+                }
+                push_json_string(&mut out, line);
+            }
+            out.push_str("]}");
+        }
+        out.push('}');
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::ErrorKind,
+        reservoir::{RejectSamples, Reservoir},
+    };
+
+    #[test]
+    fn test_reservoir() {
+        let mut sample = Reservoir::new(10, 1);
+        for i in 0..10_000 {
+            sample.add(|| i);
+        }
+        assert_eq!(sample.seen(), 10_000);
+        assert_eq!(sample.items().len(), 10);
+        // Not just the first ones.
+        assert!(sample.items().iter().any(|&i| i >= 10));
+
+        let mut small = Reservoir::new(10, 2);
+        (0..3).for_each(|i| small.add(|| 100_000 + i));
+        sample.merge(small);
+        assert_eq!(sample.seen(), 10_003);
+        assert_eq!(sample.items().len(), 10);
+        let mut few = Reservoir::new(10, 3);
+        few.add(|| 1);
+        let mut other = Reservoir::new(10, 4);
+        other.add(|| 2);
+        few.merge(other);
+        assert_eq!(few.items().len(), 2);
+
+        // Lines are only built when kept.
+        let mut built = 0;
+        let mut lazy = Reservoir::new(2, 5);
+        for _ in 0..1000 {
+            lazy.add(|| built += 1);
+        }
+        assert!(built < 100);
+
+        let mut samples = RejectSamples::new(2);
+        for line in ["a", "b\"", "c"] {
+            samples.add(ErrorKind::AccountNotFound, || line.to_owned());
+        }
+        samples.add(ErrorKind::CsvMissingColumn, || "x".to_owned());
+        let json = samples.to_json();
+        assert!(json.starts_with(r#"{"AccountNotFound":{"seen":3,"lines":["#));
+        assert!(json.ends_with(r#"]},"CsvMissingColumn":{"seen":1,"lines":["x"]}}"#));
+        assert_eq!(
+            samples
+                .get(ErrorKind::AccountNotFound)
+                .unwrap()
+                .items()
+                .len(),
+            2
+        );
+
+        let mut disabled = RejectSamples::default();
+        disabled.add(ErrorKind::AccountNotFound, || unreachable!());
+        assert_eq!(disabled.to_json(), "{}");
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::VecDeque;
+
+use crate::{
+    accounts::{ClientId, Transaction, TransactionKind},
+    error::LedgerError,
+};
+
+/// Transactions referring to a deposit that hadn't arrived yet, deferred to be retried once
+/// `window` more rows were processed, as feeds sometimes have disputes shortly before the
+/// deposit.
+pub(crate) struct RetryQueue {
+    window: usize,
+    // By index in the input, oldest first.
+    pending: VecDeque<(usize, ClientId, Transaction)>,
+    // Index of the last transaction due retries were checked before.
+    latest: usize,
+}
+
+impl RetryQueue {
+    pub(crate) fn new(window: usize) -> Self {
+        RetryQueue {
+            window,
+            pending: VecDeque::new(),
+            latest: 0,
+        }
+    }
+
+    /// Whether the transaction may succeed later: a dispute, resolve or chargeback of a deposit,
+    /// or of the account if it's the client's first, not seen yet.
+    pub(crate) fn should_defer(t: &Transaction, e: &LedgerError) -> bool {
+        t.kind.refers_to_deposit()
+            && matches!(
+                e,
+                LedgerError::TransactionNotFound | LedgerError::AccountNotFound
+            )
+    }
+
+    pub(crate) fn defer(&mut self, idx: usize, client_id: ClientId, t: Transaction) {
+        self.pending.push_back((idx, client_id, t));
+    }
+
+    /// The oldest deferred transaction if `window` rows were processed since, before the one at
+    /// `idx`. Any with None, at the end of the input.
+    pub(crate) fn pop_due(&mut self, idx: Option<usize>) -> Option<(usize, ClientId, Transaction)> {
+        if let Some(idx) = idx {
+            self.latest = idx;
+        }
+        let &(deferred, ..) = self.pending.front()?;
+        match idx {
+            Some(idx) if idx - deferred < self.window => None,
+            _ => self.pending.pop_front(),
+        }
+    }
+
+    /// Index of the transaction retries happen before, for their timestamps.
+    pub(crate) fn latest(&self) -> usize {
+        self.latest
+    }
+}
+
+impl TransactionKind {
+    fn refers_to_deposit(self) -> bool {
+        matches!(
+            self,
+            TransactionKind::Dispute | TransactionKind::Resolve | TransactionKind::Chargeback
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        Error,
+        accounts::{ClientsDatabase, Transaction, TransactionId, TransactionKind::*},
+        amount::Amount,
+        error::LedgerError,
+    };
+
+    #[test]
+    fn test_retry_before_deposit() {
+        let t = |kind, id: TransactionId| Transaction {
+            kind,
+            id,
+            amount: match kind {
+                Deposit => Amount::parse(b"1").unwrap(),
+                _ => Amount::default(),
+            },
+        };
+        let transactions = [
+            (1, t(Dispute, 1)),
+            (1, t(Deposit, 1)),
+            (1, t(Deposit, 2)),
+            (2, t(Dispute, 9)),
+            (1, t(Resolve, 1)),
+            (1, t(Dispute, 3)),
+            (1, t(Deposit, 3)),
+            (1, t(Deposit, 4)),
+            (1, t(Deposit, 5)),
+        ];
+
+        let mut db = ClientsDatabase::new();
+        db.enable_retry(3);
+        let report = db.process_all(transactions);
+        assert_eq!(report.processed, 9);
+        // Both disputes of client 1 once their deposits arrived, the first one before the resolve.
+        assert_eq!(report.retried, 2);
+        // Client 2 never deposited.
+        assert!(matches!(
+            report.rejections[..],
+            [(3, Error::Ledger(LedgerError::AccountNotFound))]
+        ));
+        assert_eq!(db.get(1).unwrap().held(), Amount::parse(b"1").unwrap());
+        assert_eq!(db.get(1).unwrap().total(), Amount::parse(b"5").unwrap());
+
+        // Without retries, both disputes fail.
+        let mut db = ClientsDatabase::new();
+        let report = db.process_all(transactions);
+        assert_eq!(report.retried, 0);
+        assert_eq!(report.rejected(), 4);
+    }
+}
@@ -0,0 +1,65 @@
+use crate::amount::Amount;
+
+/// When an account is flagged for review by its chargebacks, our main fraud signal. Flagging
+/// doesn't affect processing, it's only surfaced in the report, and sticks until cleared, e.g. by
+/// [`crate::accounts::ClientsDatabase::set_flagged`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct ReviewRules {
+    /// Chargebacks of a client as a share of its deposits, in percent, e.g. `"1.5"`, above which
+    /// it's flagged. Nothing is flagged if unset.
+    pub chargeback_percent: Option<Amount>,
+    /// Deposits a client needs before its chargeback rate counts.
+    pub min_deposits: u64,
+}
+
+impl Default for ReviewRules {
+    fn default() -> Self {
+        ReviewRules {
+            chargeback_percent: None,
+            min_deposits: 10,
+        }
+    }
+}
+
+impl ReviewRules {
+    pub fn is_enabled(&self) -> bool {
+        self.chargeback_percent.is_some()
+    }
+
+    /// Whether a client with `chargebacks` out of `deposits` applied is to be flagged.
+    pub fn exceeded(&self, deposits: u64, chargebacks: u64) -> bool {
+        let Some(percent) = self.chargeback_percent else {
+            return false;
+        };
+        // The percent is scaled like any amount, so chargebacks are too.
+        deposits >= self.min_deposits.max(1)
+            && u128::from(chargebacks) * 100 * u128::from(Amount::ONE.to_raw())
+                > u128::from(percent.to_raw()) * u128::from(deposits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{amount::Amount, review::ReviewRules};
+
+    #[test]
+    fn test_review_rules() {
+        let rules = ReviewRules {
+            chargeback_percent: Amount::parse(b"1.5"),
+            min_deposits: 10,
+        };
+        assert!(rules.is_enabled());
+        assert!(!rules.exceeded(9, 9));
+        assert!(rules.exceeded(10, 1));
+        // 1.5% exactly isn't above.
+        assert!(!rules.exceeded(200, 3));
+        assert!(rules.exceeded(199, 3));
+        assert!(!rules.exceeded(u64::MAX, 0));
+
+        let disabled = ReviewRules::default();
+        assert!(!disabled.is_enabled());
+        assert!(!disabled.exceeded(100, 100));
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    collections::BTreeSet,
+    io::{self, BufRead, BufWriter, Write},
+};
+
+use crate::{accounts::ClientId, parser::client_of};
+
+/// Which clients' rows [`sample`] keeps.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Selection {
+    Clients(BTreeSet<ClientId>),
+    /// Each client with this probability, decided by hashing its id with the seed, so the same
+    /// seed selects the same clients in any input.
+    Random {
+        fraction: f64,
+        seed: u64,
+    },
+}
+
+impl Selection {
+    pub fn contains(&self, client_id: ClientId) -> bool {
+        match self {
+            Selection::Clients(clients) => clients.contains(&client_id),
+            Selection::Random { fraction, seed } => {
+                let hash = splitmix64(seed.wrapping_add(client_id.into()));
+                ((hash >> 11) as f64 / (1u64 << 53) as f64) < *fraction
+            }
+        }
+    }
+}
+
+/// Rows read and kept by [`sample`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SampleStats {
+    /// Rows after the header.
+    pub rows: usize,
+    pub kept: usize,
+    /// Rows dropped because their client id couldn't be read.
+    pub unparsed: usize,
+}
+
+/// Copy the header and the rows of selected clients of a CSV input, unchanged and in order.
+///
+/// All rows of a selected client are kept, so its disputes still find their deposits, and the
+/// sample reproduces the client's balances. Only the client column is parsed, so other malformed
+/// columns are kept as they are.
+pub fn sample(
+    mut input: impl BufRead,
+    w: impl Write,
+    selection: &Selection,
+) -> io::Result<SampleStats> {
+    let mut w = BufWriter::new(w);
+    let mut stats = SampleStats::default();
+    let mut line = Vec::with_capacity(64);
+    let mut header = true;
+    loop {
+        line.clear();
+        if input.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        if !line.ends_with(b"\n") {
+            line.push(b'\n');
+        }
+        if header {
+            header = false;
+            w.write_all(&line)?;
+            continue;
+        }
+        stats.rows += 1;
+        match client_of(&line) {
+            Some(client_id) if selection.contains(client_id) => {
+                stats.kept += 1;
+                w.write_all(&line)?;
+            }
+            Some(_) => {}
+            None => stats.unparsed += 1,
+        }
+    }
+    w.flush()?;
+    Ok(stats)
+}
+
+pub(crate) fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sample::{SampleStats, Selection, sample};
+
+    #[test]
+    fn test_sample() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 17, 1, 1.0\n\
+                     deposit, 2, 2, 5\n\
+                     dispute,42,1,\n\
+                     bogus, x, 3, 1\n\
+                     withdrawal, 17, 4, bad";
+        let mut out = Vec::new();
+        let stats = sample(
+            input.as_bytes(),
+            &mut out,
+            &Selection::Clients([17, 42].into()),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "type, client, tx, amount\n\
+             deposit, 17, 1, 1.0\n\
+             dispute,42,1,\n\
+             withdrawal, 17, 4, bad\n"
+        );
+        assert_eq!(
+            stats,
+            SampleStats {
+                rows: 5,
+                kept: 3,
+                unparsed: 1
+            }
+        );
+
+        // Reproducible, and roughly the requested share.
+        let random = Selection::Random {
+            fraction: 0.25,
+            seed: 1,
+        };
+        let selected = (0..10000)
+            .filter(|&c| random.contains(c))
+            .collect::<Vec<_>>();
+        assert!((2300..2700).contains(&selected.len()));
+        assert_eq!(
+            selected,
+            (0..10000)
+                .filter(|&c| random.contains(c))
+                .collect::<Vec<_>>()
+        );
+        let other = Selection::Random {
+            fraction: 0.25,
+            seed: 2,
+        };
+        assert_ne!(
+            selected,
+            (0..10000)
+                .filter(|&c| other.contains(c))
+                .collect::<Vec<_>>()
+        );
+    }
+}
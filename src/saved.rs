@@ -0,0 +1,307 @@
+use std::{
+    hash::BuildHasher,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+};
+
+use crate::{
+    accounts::{
+        AccountView, ClientId, ClientsDatabase, Transaction, TransactionId, TransactionKind,
+    },
+    amount::Amount,
+    audit::AuditRecord,
+    deposits::DepositStore,
+    error::LedgerError,
+    sharded::ShardedDatabase,
+};
+
+/// Leads saved snapshot files, with the format version.
+const MAGIC: &[u8; 8] = b"PAYENGS1";
+
+/// State of one account as saved in a snapshot file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SavedAccount {
+    pub client_id: ClientId,
+    pub balances: AccountView<'static>,
+    /// Deposits under dispute, by id.
+    pub disputes: Vec<(TransactionId, Amount)>,
+    /// The last applied transactions, oldest first. Empty unless audit was enabled.
+    pub history: Vec<AuditRecord>,
+}
+
+/// Balances, open disputes and recent history of every account at the end of a run, written to a
+/// file for later inspection without processing the input again.
+///
+/// Undisputed deposits aren't kept, so this can't resume processing.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SavedSnapshot {
+    // By client id.
+    accounts: Vec<SavedAccount>,
+}
+
+impl SavedSnapshot {
+    pub fn get(&self, client_id: ClientId) -> Option<&SavedAccount> {
+        let idx = self
+            .accounts
+            .binary_search_by_key(&client_id, |a| a.client_id)
+            .ok()?;
+        Some(&self.accounts[idx])
+    }
+
+    /// Accounts in client id order.
+    pub fn iter(&self) -> impl Iterator<Item = &SavedAccount> {
+        self.accounts.iter()
+    }
+
+    /// Whether a file starting with `prefix` is a snapshot rather than e.g. a CSV report.
+    pub fn is_snapshot(prefix: &[u8]) -> bool {
+        prefix.starts_with(MAGIC)
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    /// Write accounts in the binary snapshot format: a magic, then per account its balances,
+    /// disputes and history as little-endian integers, amounts raw.
+    pub fn write(
+        w: impl Write,
+        accounts: impl IntoIterator<Item = SavedAccount>,
+    ) -> io::Result<()> {
+        let mut w = BufWriter::new(w);
+        w.write_all(MAGIC)?;
+        for account in accounts {
+            w.write_all(&account.client_id.to_le_bytes())?;
+            write_view(&mut w, account.balances)?;
+            w.write_all(&(account.disputes.len() as u32).to_le_bytes())?;
+            for (id, amount) in account.disputes {
+                w.write_all(&id.to_le_bytes())?;
+                w.write_all(&amount.to_raw().to_le_bytes())?;
+            }
+            w.write_all(&(account.history.len() as u32).to_le_bytes())?;
+            for r in account.history {
+                w.write_all(&r.at.to_le_bytes())?;
+                w.write_all(&[r.transaction.kind as u8])?;
+                w.write_all(&r.transaction.id.to_le_bytes())?;
+                w.write_all(&r.transaction.amount.to_raw().to_le_bytes())?;
+                write_view(&mut w, r.before)?;
+                write_view(&mut w, r.after)?;
+            }
+        }
+        w.flush()
+    }
+
+    pub fn read(r: impl Read) -> io::Result<Self> {
+        let mut r = BufReader::new(r);
+        let mut magic = [0; MAGIC.len()];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a snapshot file"));
+        }
+        let mut accounts = Vec::new();
+        loop {
+            if r.fill_buf()?.is_empty() {
+                break;
+            }
+            let mut client_id = [0; 2];
+            r.read_exact(&mut client_id)?;
+            let client_id = ClientId::from_le_bytes(client_id);
+            let balances = read_view(&mut r)?;
+            let disputes = (0..read_u32(&mut r)?)
+                .map(|_| Ok((read_u32(&mut r)?, Amount::from_raw(read_u64(&mut r)?))))
+                .collect::<io::Result<_>>()?;
+            let history = (0..read_u32(&mut r)?)
+                .map(|_| {
+                    let at = read_u64(&mut r)?;
+                    let mut kind = [0];
+                    r.read_exact(&mut kind)?;
+                    let kind = *TransactionKind::ALL
+                        .get(usize::from(kind[0]))
+                        .ok_or_else(|| invalid("unknown transaction type"))?;
+                    Ok(AuditRecord {
+                        at,
+                        transaction: Transaction {
+                            kind,
+                            id: read_u32(&mut r)?,
+                            amount: Amount::from_raw(read_u64(&mut r)?),
+                        },
+                        before: read_view(&mut r)?,
+                        after: read_view(&mut r)?,
+                        deposit: None,
+                    })
+                })
+                .collect::<io::Result<_>>()?;
+            accounts.push(SavedAccount {
+                client_id,
+                balances,
+                disputes,
+                history,
+            });
+        }
+        accounts.sort_unstable_by_key(|a| a.client_id);
+        Ok(SavedSnapshot { accounts })
+    }
+}
+
+impl<S: BuildHasher, D: DepositStore> ClientsDatabase<S, D> {
+    /// The account's state to save, with up to `history` of its last audit records.
+    pub fn saved_account(&self, client_id: ClientId, history: usize) -> Option<SavedAccount> {
+        let account = self.get(client_id)?;
+        let audit = self.audit(client_id);
+        Some(SavedAccount {
+            client_id,
+            balances: account.view(),
+            disputes: account.disputed_deposits(),
+            history: audit[audit.len().saturating_sub(history)..].to_vec(),
+        })
+    }
+}
+
+impl ShardedDatabase {
+    /// Add the saved accounts with their open disputes, see [`ClientsDatabase::restore_account`].
+    pub fn restore_snapshot(&mut self, snapshot: &SavedSnapshot) -> Result<(), LedgerError> {
+        for account in snapshot.iter() {
+            let shard = self.shard_for(account.client_id);
+            self.shards_mut()[shard].restore_account(
+                account.client_id,
+                account.balances,
+                &account.disputes,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write all accounts as a [`SavedSnapshot`], with up to `history` audit records each.
+    pub fn save_snapshot(&self, w: impl Write, history: usize) -> io::Result<()> {
+        SavedSnapshot::write(
+            w,
+            self.shards()
+                .iter()
+                .flat_map(|shard| shard.iter().map(move |(client_id, _)| (shard, client_id)))
+                .filter_map(|(shard, client_id)| shard.saved_account(client_id, history)),
+        )
+    }
+}
+
+fn write_view(w: &mut impl Write, view: AccountView) -> io::Result<()> {
+    for amount in [view.available, view.held, view.total] {
+        w.write_all(&amount.to_raw().to_le_bytes())?;
+    }
+    // Flags, older snapshots only have the first.
+    w.write_all(&[u8::from(view.locked) | u8::from(view.flagged) << 1])
+}
+
+fn read_view(r: &mut impl Read) -> io::Result<AccountView<'static>> {
+    let available = Amount::from_raw(read_u64(r)?);
+    let held = Amount::from_raw(read_u64(r)?);
+    let total = Amount::from_raw(read_u64(r)?);
+    let mut flags = [0];
+    r.read_exact(&mut flags)?;
+    Ok(AccountView {
+        available,
+        held,
+        total,
+        locked: flags[0] & 1 != 0,
+        flagged: flags[0] & 2 != 0,
+        audit: &[],
+    })
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::{Transaction, TransactionKind::*},
+        amount::Amount,
+        review::ReviewRules,
+        saved::SavedSnapshot,
+        sharded::ShardedDatabase,
+    };
+
+    #[test]
+    fn test_save_snapshot() {
+        let t = |kind, id, amount: &str| Transaction {
+            kind,
+            id,
+            amount: Amount::parse(amount.as_bytes()).unwrap_or_default(),
+        };
+        let mut db = ShardedDatabase::new(2);
+        for shard in db.shards_mut() {
+            shard.enable_audit();
+        }
+        db.set_review(&ReviewRules {
+            chargeback_percent: Amount::parse(b"50"),
+            min_deposits: 1,
+        });
+        db.process_parallel([
+            (42, t(Deposit, 1, "10")),
+            (42, t(Deposit, 2, "2.5")),
+            (42, t(Dispute, 1, "")),
+            (7, t(Deposit, 3, "1")),
+            (7, t(Dispute, 3, "")),
+            (7, t(Chargeback, 3, "")),
+        ]);
+
+        let mut file = Vec::new();
+        db.save_snapshot(&mut file, 2).unwrap();
+        let saved = SavedSnapshot::read(file.as_slice()).unwrap();
+        assert_eq!(
+            saved.iter().map(|a| a.client_id).collect::<Vec<_>>(),
+            [7, 42]
+        );
+
+        let account = saved.get(42).unwrap();
+        assert_eq!(account.balances, db.get(42).unwrap().view());
+        assert_eq!(account.disputes, [(1, Amount::parse(b"10").unwrap())]);
+        // The last two.
+        assert_eq!(
+            account
+                .history
+                .iter()
+                .map(|r| r.transaction)
+                .collect::<Vec<_>>(),
+            [t(Deposit, 2, "2.5"), t(Dispute, 1, "")]
+        );
+        assert_eq!(account.history[1].after.held, Amount::parse(b"10").unwrap());
+        assert!(saved.get(7).unwrap().balances.locked);
+        assert!(saved.get(7).unwrap().balances.flagged);
+        assert!(!account.balances.flagged);
+        // Charged back, no longer disputed.
+        assert!(saved.get(7).unwrap().disputes.is_empty());
+        assert!(saved.get(8).is_none());
+
+        // Restored disputes can be resolved.
+        let mut restored = ShardedDatabase::new(3);
+        restored.restore_snapshot(&saved).unwrap();
+        assert_eq!(restored.get(42).unwrap().view(), account.balances);
+        assert!(restored.get(7).unwrap().is_flagged());
+        restored.process_parallel([(42, t(Resolve, 1, ""))]);
+        assert_eq!(
+            restored.get(42).unwrap().view().available,
+            Amount::parse(b"12.5").unwrap()
+        );
+        assert!(restored.restore_snapshot(&saved).is_err());
+
+        // Truncated.
+        assert!(SavedSnapshot::read(&file[..file.len() - 1]).is_err());
+        assert!(SavedSnapshot::read(b"PAYENGB1".as_slice()).is_err());
+    }
+}
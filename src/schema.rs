@@ -0,0 +1,357 @@
+use std::fmt::Write;
+
+use crate::{accounts::TransactionKind, report::ReportFormat};
+
+/// Language of the schemas written by [`Schema::render`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SchemaFormat {
+    /// JSON Schema (draft 2020-12) of a row as a JSON object keyed by column name.
+    #[default]
+    JsonSchema,
+    /// Arrow schema in the JSON form of Arrow's integration tests, with a field per column.
+    Arrow,
+}
+
+impl SchemaFormat {
+    pub const ALL: [SchemaFormat; 2] = [SchemaFormat::JsonSchema, SchemaFormat::Arrow];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SchemaFormat::JsonSchema => "json-schema",
+            SchemaFormat::Arrow => "arrow",
+        }
+    }
+}
+
+impl std::str::FromStr for SchemaFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|f| f.as_str() == s)
+            .ok_or_else(|| format!("unknown schema format {s:?}"))
+    }
+}
+
+/// Data described by a schema: input transactions, in any of the formats of
+/// [`crate::convert::TransactionFormat`], or the balances report in one format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Schema {
+    Input,
+    Report(ReportFormat),
+}
+
+impl Schema {
+    pub const ALL: [Schema; 5] = [
+        Schema::Input,
+        Schema::Report(ReportFormat::Csv),
+        Schema::Report(ReportFormat::Json),
+        Schema::Report(ReportFormat::Ndjson),
+        Schema::Report(ReportFormat::Parquet),
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Schema::Input => "input",
+            Schema::Report(format) => format.as_str(),
+        }
+    }
+
+    fn fields(self) -> &'static [Field] {
+        match self {
+            Schema::Input => INPUT_FIELDS,
+            Schema::Report(_) => REPORT_FIELDS,
+        }
+    }
+
+    /// The schema as one line of JSON.
+    ///
+    /// CSV values are text, so its JSON Schema applies to rows read as objects keyed by the
+    /// header. The JSON report is an array of such objects, the other formats have one per row,
+    /// so their Arrow schemas are the same.
+    pub fn render(self, format: SchemaFormat) -> String {
+        let mut out = String::new();
+        match format {
+            SchemaFormat::JsonSchema => {
+                let title = match self {
+                    Schema::Input => "payengine transaction",
+                    Schema::Report(_) => "payengine account balances",
+                };
+                out.push_str(
+                    "{\"$schema\":\"https://json-schema.org/draft/2020-12/schema\",\"title\":",
+                );
+                push_string(&mut out, title);
+                out.push(',');
+                if self == Schema::Report(ReportFormat::Json) {
+                    out.push_str("\"type\":\"array\",\"items\":{");
+                    push_object(&mut out, self.fields());
+                    out.push('}');
+                } else {
+                    push_object(&mut out, self.fields());
+                }
+                out.push('}');
+            }
+            SchemaFormat::Arrow => {
+                out.push_str("{\"fields\":[");
+                for (idx, field) in self.fields().iter().enumerate() {
+                    if idx > 0 {
+                        out.push(',');
+                    }
+                    out.push_str("{\"name\":");
+                    push_string(&mut out, field.name);
+                    let ty = match field.ty {
+                        FieldType::TransactionType | FieldType::Amount => "{\"name\":\"utf8\"}",
+                        FieldType::U16 => "{\"name\":\"int\",\"isSigned\":false,\"bitWidth\":16}",
+                        FieldType::U32 => "{\"name\":\"int\",\"isSigned\":false,\"bitWidth\":32}",
+                        FieldType::Bool => "{\"name\":\"bool\"}",
+                    };
+                    write!(
+                        out,
+                        ",\"nullable\":{},\"type\":{ty},\"children\":[]}}",
+                        field.optional
+                    )
+                    .unwrap();
+                }
+                out.push_str("]}");
+            }
+        }
+        out
+    }
+}
+
+impl std::str::FromStr for Schema {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|f| f.as_str() == s)
+            .ok_or_else(|| format!("unknown schema {s:?}"))
+    }
+}
+
+/// All schemas in one JSON object, keyed by [`Schema::as_str`].
+pub fn render_all(format: SchemaFormat) -> String {
+    let mut out = String::from("{");
+    for (idx, schema) in Schema::ALL.into_iter().enumerate() {
+        if idx > 0 {
+            out.push(',');
+        }
+        push_string(&mut out, schema.as_str());
+        out.push(':');
+        out.push_str(&schema.render(format));
+    }
+    out.push('}');
+    out
+}
+
+#[derive(Clone, Copy)]
+enum FieldType {
+    TransactionType,
+    U16,
+    U32,
+    /// Decimal text with up to 4 significant decimal places.
+    Amount,
+    Bool,
+}
+
+struct Field {
+    name: &'static str,
+    ty: FieldType,
+    /// Absent, null or empty in some rows.
+    optional: bool,
+    description: &'static str,
+}
+
+const INPUT_FIELDS: &[Field] = &[
+    Field {
+        name: "type",
+        ty: FieldType::TransactionType,
+        optional: false,
+        description: "transaction type",
+    },
+    Field {
+        name: "client",
+        ty: FieldType::U16,
+        optional: false,
+        description: "client id",
+    },
+    Field {
+        name: "tx",
+        ty: FieldType::U32,
+        optional: false,
+        description: "transaction id, of the disputed deposit for dispute, resolve and chargeback",
+    },
+    Field {
+        name: "amount",
+        ty: FieldType::Amount,
+        optional: true,
+        description: "required for deposit and withdrawal, empty or absent for other types; \
+                      digits past the 4th decimal place are truncated",
+    },
+];
+
+const REPORT_FIELDS: &[Field] = &[
+    Field {
+        name: "client",
+        ty: FieldType::U16,
+        optional: false,
+        description: "client id",
+    },
+    Field {
+        name: "available",
+        ty: FieldType::Amount,
+        optional: false,
+        description: "funds available for withdrawal, zero if locked",
+    },
+    Field {
+        name: "held",
+        ty: FieldType::Amount,
+        optional: false,
+        description: "funds held by open disputes",
+    },
+    Field {
+        name: "total",
+        ty: FieldType::Amount,
+        optional: false,
+        description: "available and held funds",
+    },
+    Field {
+        name: "locked",
+        ty: FieldType::Bool,
+        optional: false,
+        description: "frozen by a chargeback",
+    },
+];
+
+/// `"type":"object",...` of a JSON Schema with these properties, without braces.
+fn push_object(out: &mut String, fields: &[Field]) {
+    out.push_str("\"type\":\"object\",\"properties\":{");
+    for (idx, field) in fields.iter().enumerate() {
+        if idx > 0 {
+            out.push(',');
+        }
+        push_string(out, field.name);
+        out.push_str(":{\"description\":");
+        push_string(out, field.description);
+        match field.ty {
+            FieldType::TransactionType => {
+                out.push_str(",\"enum\":[");
+                for (idx, kind) in TransactionKind::ALL.into_iter().enumerate() {
+                    if idx > 0 {
+                        out.push(',');
+                    }
+                    push_string(out, kind.as_str());
+                }
+                out.push(']');
+            }
+            FieldType::U16 => write!(
+                out,
+                ",\"type\":\"integer\",\"minimum\":0,\"maximum\":{}",
+                u16::MAX
+            )
+            .unwrap(),
+            FieldType::U32 => write!(
+                out,
+                ",\"type\":\"integer\",\"minimum\":0,\"maximum\":{}",
+                u32::MAX
+            )
+            .unwrap(),
+            // Inputs may also have amounts as JSON numbers, or null.
+            FieldType::Amount if field.optional => out.push_str(
+                ",\"type\":[\"string\",\"number\",\"null\"],\"minimum\":0,\
+                 \"pattern\":\"^([0-9]+(\\\\.[0-9]*)?)?$\"",
+            ),
+            FieldType::Amount => {
+                out.push_str(",\"type\":\"string\",\"pattern\":\"^[0-9]+(\\\\.[0-9]+)?$\"")
+            }
+            FieldType::Bool => out.push_str(",\"type\":\"boolean\""),
+        }
+        out.push('}');
+    }
+    out.push_str("},\"required\":[");
+    for (idx, field) in fields.iter().filter(|f| !f.optional).enumerate() {
+        if idx > 0 {
+            out.push(',');
+        }
+        push_string(out, field.name);
+    }
+    out.push(']');
+}
+
+/// Quoted, none of the strings here need escaping.
+fn push_string(out: &mut String, s: &str) {
+    debug_assert!(!s.contains(['"', '\\']));
+    out.push('"');
+    out.push_str(s);
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use crate::{
+        accounts::AccountView,
+        amount::Amount,
+        report::ReportFormat,
+        schema::{Schema, SchemaFormat, render_all},
+    };
+
+    #[test]
+    fn test_render() {
+        let all: Value = serde_json::from_str(&render_all(SchemaFormat::JsonSchema)).unwrap();
+        assert_eq!(
+            all.as_object().unwrap().keys().collect::<Vec<_>>(),
+            ["csv", "input", "json", "ndjson", "parquet"]
+        );
+        let input = &all["input"];
+        assert_eq!(
+            input["required"],
+            serde_json::json!(["type", "client", "tx"])
+        );
+        assert_eq!(input["properties"]["type"]["enum"][4], "chargeback");
+        assert_eq!(input["properties"]["client"]["maximum"], 65535);
+        assert_eq!(all["json"]["type"], "array");
+        assert_eq!(all["json"]["items"], {
+            let mut row = all["ndjson"].clone();
+            row.as_object_mut()
+                .unwrap()
+                .retain(|k, _| !k.starts_with("$") && k != "title");
+            row
+        });
+
+        // Every key of the report is described.
+        let mut report = Vec::new();
+        let view = AccountView {
+            available: Amount::parse(b"1.5").unwrap(),
+            held: Amount::zero(),
+            total: Amount::parse(b"1.5").unwrap(),
+            locked: false,
+            flagged: false,
+            audit: &[],
+        };
+        ReportFormat::Ndjson
+            .write(&mut report, [(7, view)], None)
+            .unwrap();
+        let row: Value = serde_json::from_slice(&report).unwrap();
+        let properties = all["ndjson"]["properties"].as_object().unwrap();
+        assert!(row.as_object().unwrap().keys().eq(properties.keys()));
+        assert_eq!(all["ndjson"]["required"].as_array().unwrap().len(), 5);
+
+        let arrow: Value =
+            serde_json::from_str(&Schema::Input.render(SchemaFormat::Arrow)).unwrap();
+        let fields = arrow["fields"].as_array().unwrap();
+        assert_eq!(fields[2]["name"], "tx");
+        assert_eq!(fields[2]["type"]["bitWidth"], 32);
+        assert_eq!(fields[2]["nullable"], false);
+        assert_eq!(fields[3]["type"]["name"], "utf8");
+        assert_eq!(fields[3]["nullable"], true);
+        assert_eq!(
+            Schema::Report(ReportFormat::Csv).render(SchemaFormat::Arrow),
+            Schema::Report(ReportFormat::Parquet).render(SchemaFormat::Arrow)
+        );
+        assert_eq!("parquet".parse(), Ok(Schema::Report(ReportFormat::Parquet)));
+    }
+}
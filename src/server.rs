@@ -0,0 +1,1265 @@
+use std::{
+    collections::HashSet,
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use axum::{
+    Router,
+    body::{Body, Bytes, HttpBody},
+    extract::{
+        Path, RawQuery, Request, State,
+        ws::{
+            CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code,
+            rejection::WebSocketUpgradeRejection,
+        },
+    },
+    http::{HeaderMap, HeaderName, StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use tokio::{
+    net::TcpListener,
+    sync::broadcast::{self, error::RecvError},
+};
+use tracing::{Instrument, Span, debug, field, info_span, warn};
+
+#[cfg(feature = "prometheus")]
+use crate::prometheus::Registry;
+use crate::{
+    accounts::ClientId,
+    alerts::{Alert, AlertMonitor},
+    concurrent::ConcurrentClientsDatabase,
+    convert::parse_json_row,
+    error::{LedgerError, ParseError},
+    events::AccountEvent,
+    idempotency::{Begin, IdempotencyCache, Reservation},
+    jobs::{Job, Jobs},
+    parser::Row,
+    ratelimit::RateLimiter,
+    report::{ReportFormat, push_json_string},
+    tcp::LineListener,
+};
+
+/// HTTP API over a database shared by all requests:
+///
+/// - `POST /transactions` applies one transaction, given as a JSON object like a line of JSON
+///   lines input, or as a CSV row with `Content-Type: text/csv`. Answers 200 if applied, 400 if
+///   malformed and 422 if rejected, with the reason as `{"error":"...","code":"E_..."}`, see
+///   [`crate::error::ErrorKind::code`]. Transactions of a client over its [`RateLimiter`] limits
+///   aren't applied, and answered 429 with `E_RATE_LIMITED` and a `Retry-After` header.
+///
+///   A batch of transactions is given as JSON lines with `Content-Type: application/x-ndjson`,
+///   or as several CSV rows, optionally after the header line. They're applied in order, each as
+///   if submitted on its own, so a batch may be partly applied. Answers 200 with the outcome of
+///   each, e.g. `{"applied":1,"rejected":1,"results":[{"status":"applied"},{"error":"...",
+///   "code":"E_..."}]}`.
+///
+///   With an `Idempotency-Key` header, of up to 255 characters, the response to the first request
+///   with the key is kept and answered again to later ones with an `Idempotent-Replayed: true`
+///   header, without applying them, so clients can retry requests safely. Until the first one is
+///   answered, others are answered 409, and reusing a key for another body is answered 422. The
+///   last [`IDEMPOTENCY_KEYS`] keys are kept. Requests answered 429 weren't applied, so their keys
+///   are forgotten.
+/// - `POST /jobs` uploads transactions in bulk, as CSV, optionally after the header line, or as
+///   JSON lines with `Content-Type: application/x-ndjson`. It's answered 202 with the job's id,
+///   e.g. `{"id":1}`, and a `Location` header, as soon as the upload starts. Rows are applied as
+///   they're received, in order, while the upload goes on. Those of a client over its
+///   [`RateLimiter`] limits are rejected with `E_RATE_LIMITED`. Uploads beyond
+///   [`MAX_RUNNING_JOBS`] running at once are answered 503.
+/// - `GET /jobs/{id}` answers the progress of a job: whether it's `running`, `done`, or `failed`
+///   with the `"error"` its upload ended with, bytes and rows received so far, how many were
+///   applied, rejected and malformed, and the first [`JOB_ERRORS`] errors with their line, e.g.
+///   `{"id":1,"state":"done","bytes":62,"rows":2,"applied":1,"rejected":1,"malformed":0,
+///   "errors":[{"line":3,"error":"...","code":"E_..."}]}`. The last [`JOBS`] jobs are kept, or 404.
+/// - `GET /accounts/{client}` answers the client's balances like a row of the JSON report, or
+///   404.
+/// - `GET /accounts` answers the balances of all accounts by client id, as JSON lines.
+/// - `GET /events` is a WebSocket pushing a text message for every transaction applied from then
+///   on, with the balances after it, e.g. `{"at":3,"client":7,"type":"deposit","tx":9,
+///   "amount":"2.5","available":"2.5","held":"0","total":"2.5","locked":false}`. Only those of
+///   some clients with `?clients=7,42`. A listener falling behind by more than [`EVENT_BUFFER`]
+///   events misses the oldest, and gets `{"lagged":N}` with how many instead.
+/// - `GET /report?format=json` answers the report of all accounts by client id, in any
+///   [`ReportFormat`], CSV by default.
+/// - `GET /metrics` answers counters of submitted transactions and latency histograms in the
+///   Prometheus text format, with the "prometheus" feature.
+/// - `GET /alerts` answers the alerts raised so far by balances after applied transactions, as
+///   JSON lines like `{"alert":"account","client":7,"amount":"600","limit":"500"}`. They're also
+///   logged as warnings as they're raised.
+/// - `GET /healthz` answers 200 while the server runs, for liveness checks.
+/// - `GET /readyz` answers 200 if the server should get new requests and 503 otherwise, with the
+///   [`Readiness`] checks like `{"ready":true,"state":"s.bin","state_loaded":true,
+///   "storage_writable":true,"in_flight":3,"jobs":1,"max_in_flight":64}`, `storage_writable`
+///   being null without storage to check.
+///
+/// Each request is traced as a `request` span. With the "otel" feature, it continues the trace of
+/// the request's W3C `traceparent` header, if any.
+pub fn router(
+    db: Arc<ConcurrentClientsDatabase>,
+    readiness: Readiness,
+    alerts: AlertMonitor,
+    limiter: RateLimiter,
+) -> Router {
+    router_with(Shared::new(db, readiness, alerts, limiter))
+}
+
+fn router_with(shared: Arc<Shared>) -> Router {
+    let router = Router::new()
+        .route("/transactions", post(submit))
+        .route("/jobs", post(create_job))
+        .route("/jobs/{id}", get(job))
+        .route("/accounts", get(accounts))
+        .route("/accounts/{client}", get(account))
+        .route("/report", get(report))
+        .route("/alerts", get(list_alerts))
+        .route("/events", get(events));
+    #[cfg(feature = "prometheus")]
+    let router = router.route("/metrics", get(metrics));
+    router
+        // Health checks aren't counted, so they're answered however busy the server is.
+        .route_layer(middleware::from_fn_with_state(
+            shared.clone(),
+            count_in_flight,
+        ))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .layer(middleware::from_fn(trace))
+        .with_state(shared)
+}
+
+/// What `GET /readyz` checks, besides the server running.
+#[derive(Clone, Default)]
+pub struct Readiness {
+    /// Snapshot the state was loaded from, reported as such. The server only starts once it's
+    /// loaded.
+    pub state: Option<PathBuf>,
+    /// Requests in progress and running jobs at which the server isn't ready, so that
+    /// orchestration sends new ones elsewhere until it catches up. Unlimited if None.
+    pub max_in_flight: Option<usize>,
+    /// Whether the storage applied transactions are written to, e.g. `serve --sqlite`, takes
+    /// writes. The server isn't ready while it fails.
+    pub storage: Option<StorageCheck>,
+}
+
+/// Check of [`Readiness::storage`], run on a blocking thread for each `GET /readyz`.
+pub type StorageCheck = Arc<dyn Fn() -> std::io::Result<()> + Send + Sync>;
+
+/// `Idempotency-Key` headers of `POST /transactions` remembered, with the responses to them.
+pub const IDEMPOTENCY_KEYS: usize = 100_000;
+
+/// Jobs of `POST /jobs` remembered, oldest finished ones forgotten first.
+pub const JOBS: usize = 1000;
+
+/// Jobs of `POST /jobs` running at once.
+pub const MAX_RUNNING_JOBS: usize = 16;
+
+/// Errors kept per job, of its first malformed rows and rejected transactions.
+pub const JOB_ERRORS: usize = 100;
+
+/// Events kept for WebSocket listeners of `GET /events` which haven't been sent yet.
+pub const EVENT_BUFFER: usize = 4096;
+
+const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// State of all requests.
+pub(crate) struct Shared {
+    db: Arc<ConcurrentClientsDatabase>,
+    // Subscribed to on the first `GET /events`, so events cost nothing without listeners.
+    events: OnceLock<broadcast::Sender<AccountEvent>>,
+    readiness: Readiness,
+    alerts: Mutex<AlertMonitor>,
+    limiter: RateLimiter,
+    idempotency: Mutex<IdempotencyCache<(StatusCode, Bytes)>>,
+    jobs: Mutex<Jobs>,
+    // Requests in progress, other than health checks.
+    in_flight: AtomicUsize,
+    #[cfg(feature = "prometheus")]
+    registry: Registry,
+}
+
+impl Shared {
+    fn new(
+        db: Arc<ConcurrentClientsDatabase>,
+        readiness: Readiness,
+        alerts: AlertMonitor,
+        limiter: RateLimiter,
+    ) -> Arc<Self> {
+        Arc::new(Shared {
+            db,
+            events: OnceLock::new(),
+            readiness,
+            alerts: Mutex::new(alerts),
+            limiter,
+            idempotency: Mutex::new(IdempotencyCache::new(IDEMPOTENCY_KEYS)),
+            jobs: Mutex::new(Jobs::new(JOBS, MAX_RUNNING_JOBS)),
+            in_flight: AtomicUsize::new(0),
+            #[cfg(feature = "prometheus")]
+            registry: Registry::new(),
+        })
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<AccountEvent> {
+        self.events
+            .get_or_init(|| {
+                let (tx, _) = broadcast::channel(EVENT_BUFFER);
+                let events = self.db.subscribe();
+                let forward = tx.clone();
+                // Ends once the database is gone.
+                std::thread::spawn(move || {
+                    for event in events {
+                        // Without listeners, events are dropped.
+                        let _ = forward.send(event);
+                    }
+                });
+                tx
+            })
+            .subscribe()
+    }
+}
+
+/// Serve [`router`] on `listener` until `shutdown` completes, then finish requests in progress.
+/// With `lines`, transactions are also taken over its protocol, applied like those of
+/// `POST /transactions`, until then.
+pub async fn serve(
+    listener: TcpListener,
+    db: Arc<ConcurrentClientsDatabase>,
+    readiness: Readiness,
+    alerts: AlertMonitor,
+    limiter: RateLimiter,
+    lines: Option<LineListener>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    let shared = Shared::new(db, readiness, alerts, limiter);
+    let lines = lines.map(|lines| tokio::spawn(lines.run(shared.clone())));
+    let served = axum::serve(listener, router_with(shared))
+        .with_graceful_shutdown(shutdown)
+        .await;
+    if let Some(lines) = lines {
+        lines.abort();
+    }
+    served
+}
+
+async fn count_in_flight(
+    State(shared): State<Arc<Shared>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    shared.in_flight.fetch_add(1, Ordering::Relaxed);
+    let response = next.run(request).await;
+    shared.in_flight.fetch_sub(1, Ordering::Relaxed);
+    response
+}
+
+async fn trace(request: Request, next: Next) -> Response {
+    let span = info_span!(
+        "request",
+        method = %request.method(),
+        path = request.uri().path(),
+        status = field::Empty,
+        client_id = field::Empty,
+        tx_id = field::Empty,
+        kind = field::Empty,
+    );
+    #[cfg(feature = "otel")]
+    {
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+        let _ = span.set_parent(remote_context(request.headers()));
+    }
+    let response = next.run(request).instrument(span.clone()).await;
+    span.record("status", response.status().as_u16());
+    response
+}
+
+/// The trace context of a `traceparent` header, or an empty one.
+#[cfg(feature = "otel")]
+fn remote_context(headers: &HeaderMap) -> opentelemetry::Context {
+    use opentelemetry::propagation::{Extractor, TextMapPropagator};
+
+    struct Headers<'a>(&'a HeaderMap);
+
+    impl Extractor for Headers<'_> {
+        fn get(&self, key: &str) -> Option<&str> {
+            self.0.get(key)?.to_str().ok()
+        }
+
+        fn keys(&self) -> Vec<&str> {
+            self.0.keys().map(|k| k.as_str()).collect()
+        }
+    }
+
+    opentelemetry_sdk::propagation::TraceContextPropagator::new().extract(&Headers(headers))
+}
+
+async fn submit(State(shared): State<Arc<Shared>>, headers: HeaderMap, body: Bytes) -> Response {
+    let Some(key) = headers.get(IDEMPOTENCY_KEY) else {
+        return submit_body(&shared, &headers, &body);
+    };
+    let key = match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= 255 => key,
+        _ => return error(StatusCode::BAD_REQUEST, "invalid Idempotency-Key header"),
+    };
+    // Bound to the body as given, content type included.
+    let mut request = headers
+        .get(header::CONTENT_TYPE)
+        .map(|t| t.as_bytes().to_vec())
+        .unwrap_or_default();
+    request.push(b'\n');
+    request.extend_from_slice(&body);
+    let begin = shared.idempotency.lock().unwrap().begin(key, &request);
+    let reservation = match begin {
+        Begin::New => Reservation::new(&shared.idempotency, key),
+        Begin::Replay((status, body)) => {
+            let headers = [
+                (header::CONTENT_TYPE, "application/json"),
+                (IDEMPOTENT_REPLAYED, "true"),
+            ];
+            return (status, headers, body).into_response();
+        }
+        Begin::InProgress => {
+            return error(
+                StatusCode::CONFLICT,
+                "a request with the same Idempotency-Key is in progress",
+            );
+        }
+        Begin::Mismatch => {
+            return error(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "the Idempotency-Key was used for another request",
+            );
+        }
+    };
+    let response = submit_body(&shared, &headers, &body);
+    let (parts, body) = response.into_parts();
+    // Bodies are in memory already.
+    let body = axum::body::to_bytes(body, usize::MAX)
+        .await
+        .unwrap_or_default();
+    // Rate limited requests weren't handled, so the reservation is dropped and the key forgotten.
+    if parts.status != StatusCode::TOO_MANY_REQUESTS {
+        reservation.finish((parts.status, body.clone()));
+    }
+    Response::from_parts(parts, body.into())
+}
+
+fn submit_body(shared: &Shared, headers: &HeaderMap, body: &[u8]) -> Response {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .map(|t| t.as_bytes())
+        .unwrap_or_default();
+    let csv = content_type.starts_with(b"text/csv");
+    let body = body.trim_ascii();
+    if content_type.starts_with(b"application/x-ndjson") {
+        return submit_batch(shared, body.split(|&b| b == b'\n').map(parse_json_row));
+    }
+    if csv && body.contains(&b'\n') {
+        let mut lines = body.split(|&b| b == b'\n').peekable();
+        // The header line, if any.
+        lines.next_if(|l| l.starts_with(b"type"));
+        return submit_batch(shared, lines.map(Row::parse));
+    }
+    let row = if csv {
+        Row::parse(body)
+    } else {
+        parse_json_row(body)
+    };
+    let row = match row {
+        Ok(row) => row,
+        Err(e) => return rejection(StatusCode::BAD_REQUEST, e.into()),
+    };
+    let span = Span::current();
+    span.record("client_id", row.client_id);
+    span.record("tx_id", row.transaction.id);
+    span.record("kind", row.transaction.kind.as_str());
+    match apply(shared, row) {
+        (Ok(_), _) => json(StatusCode::OK, "{\"status\":\"applied\"}".into()),
+        (Err(e), Some(wait)) => {
+            let mut response = rejection(StatusCode::TOO_MANY_REQUESTS, e.into());
+            // Whole seconds, rounded up.
+            let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, secs.into());
+            response
+        }
+        (Err(e), None) => rejection(StatusCode::UNPROCESSABLE_ENTITY, e.into()),
+    }
+}
+
+/// Apply the transactions of a batch in order, skipping malformed ones, and answer the outcome of
+/// each.
+fn submit_batch(shared: &Shared, rows: impl Iterator<Item = Result<Row, ParseError>>) -> Response {
+    let (mut applied, mut rejected) = (0, 0);
+    let mut results = String::new();
+    for row in rows {
+        if !results.is_empty() {
+            results.push(',');
+        }
+        let result = row
+            .map_err(crate::Error::from)
+            .and_then(|row| apply(shared, row).0.map_err(Into::into));
+        match result {
+            Ok(_) => {
+                applied += 1;
+                results.push_str("{\"status\":\"applied\"}");
+            }
+            Err(e) => {
+                rejected += 1;
+                push_error(&mut results, &e);
+            }
+        }
+    }
+    json(
+        StatusCode::OK,
+        format!("{{\"applied\":{applied},\"rejected\":{rejected},\"results\":[{results}]}}"),
+    )
+}
+
+/// Apply a transaction within the rate limits of its client, returning whether it opened an
+/// account, and how long until the client may submit again if it's over its limits.
+pub(crate) fn apply(shared: &Shared, row: Row) -> (Result<bool, LedgerError>, Option<Duration>) {
+    // Checked first, so transactions turned away don't count towards the limits.
+    let limited = shared.limiter.check(row.client_id).err();
+    (
+        apply_unless_limited(shared, row, limited.is_some()),
+        limited,
+    )
+}
+
+/// Apply a transaction, or reject it as rate limited if `limited`, and record the outcome.
+fn apply_unless_limited(shared: &Shared, row: Row, limited: bool) -> Result<bool, LedgerError> {
+    #[cfg(feature = "prometheus")]
+    let start = std::time::Instant::now();
+    let result = if limited {
+        Err(LedgerError::RateLimited)
+    } else {
+        shared
+            .db
+            .process_transaction_opening(row.client_id, row.transaction)
+    };
+    #[cfg(feature = "prometheus")]
+    shared.registry.record(
+        row.transaction.kind,
+        result.as_ref().map(|opened| *opened).map_err(|e| e.kind()),
+        start.elapsed(),
+    );
+    if result.is_ok()
+        && let Some(view) = shared.db.get(row.client_id)
+    {
+        shared
+            .alerts
+            .lock()
+            .unwrap()
+            .update([(row.client_id, view)]);
+    }
+    result
+}
+
+async fn create_job(State(shared): State<Arc<Shared>>, headers: HeaderMap, body: Body) -> Response {
+    let ndjson = headers
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|t| t.as_bytes().starts_with(b"application/x-ndjson"));
+    let Some(job) = shared.jobs.lock().unwrap().start(JOB_ERRORS) else {
+        return error(StatusCode::SERVICE_UNAVAILABLE, "too many jobs are running");
+    };
+    let id = job.id;
+    let span = info_span!("job", id);
+    tokio::spawn(run_job(shared, job, body, ndjson).instrument(span));
+    (
+        StatusCode::ACCEPTED,
+        [
+            (header::CONTENT_TYPE, "application/json".to_owned()),
+            (header::LOCATION, format!("/jobs/{id}")),
+        ],
+        format!("{{\"id\":{id}}}"),
+    )
+        .into_response()
+}
+
+/// Apply the rows of an upload as they're received, which goes on after it's answered.
+async fn run_job(shared: Arc<Shared>, job: Arc<Job>, mut body: Body, ndjson: bool) {
+    let parse = if ndjson { parse_json_row } else { Row::parse };
+    // Received lines not complete yet.
+    let mut pending = Vec::new();
+    let mut line = 0;
+    loop {
+        let frame = std::future::poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await;
+        let data = match frame {
+            Some(Ok(frame)) => match frame.into_data() {
+                Ok(data) => data,
+                // Trailers.
+                Err(_) => continue,
+            },
+            Some(Err(e)) => {
+                debug!("job upload failed: {e}");
+                let mut progress = job.progress.lock().unwrap();
+                progress.failure = Some(e.to_string());
+                progress.done = true;
+                return;
+            }
+            None => break,
+        };
+        job.progress.lock().unwrap().bytes += data.len() as u64;
+        pending.extend_from_slice(&data);
+        if let Some(end) = pending.iter().rposition(|&b| b == b'\n') {
+            apply_lines(&shared, &job, &pending[..end], &mut line, parse);
+            pending.drain(..=end);
+        }
+    }
+    apply_lines(&shared, &job, &pending, &mut line, parse);
+    job.progress.lock().unwrap().done = true;
+}
+
+/// Apply the rows of complete lines of a job, after `line` of them so far, within the rate limits
+/// of their clients. Progress is updated row by row, so it can be read meanwhile.
+fn apply_lines(
+    shared: &Shared,
+    job: &Job,
+    lines: &[u8],
+    line: &mut usize,
+    parse: fn(&[u8]) -> Result<Row, ParseError>,
+) {
+    for row in lines.split(|&b| b == b'\n') {
+        *line += 1;
+        let row = row.trim_ascii();
+        if row.is_empty() || (*line == 1 && row.starts_with(b"type")) {
+            continue;
+        }
+        let result = parse(row)
+            .map_err(crate::Error::from)
+            .and_then(|row| Ok(apply(shared, row).0.map(|_| ())?));
+        job.progress.lock().unwrap().add(*line, result);
+    }
+}
+
+async fn job(State(shared): State<Arc<Shared>>, Path(id): Path<String>) -> Response {
+    let job = id
+        .parse()
+        .ok()
+        .and_then(|id| shared.jobs.lock().unwrap().get(id));
+    match job {
+        Some(job) => json(StatusCode::OK, job.to_json()),
+        None => error(StatusCode::NOT_FOUND, "job not found"),
+    }
+}
+
+async fn account(State(shared): State<Arc<Shared>>, Path(client): Path<String>) -> Response {
+    let Ok(client_id) = client.parse::<ClientId>() else {
+        return error(StatusCode::BAD_REQUEST, "invalid client id");
+    };
+    Span::current().record("client_id", client_id);
+    let Some(view) = shared.db.get(client_id) else {
+        return error(StatusCode::NOT_FOUND, "account not found");
+    };
+    let mut row = Vec::new();
+    ReportFormat::Ndjson
+        .write(&mut row, [(client_id, view)], None)
+        .unwrap();
+    json(StatusCode::OK, String::from_utf8(row).unwrap())
+}
+
+async fn accounts(State(shared): State<Arc<Shared>>) -> Response {
+    let mut accounts = shared.db.views();
+    accounts.sort_unstable_by_key(|(client_id, _)| *client_id);
+    let mut body = Vec::new();
+    ReportFormat::Ndjson
+        .write(&mut body, accounts, None)
+        .unwrap();
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        body,
+    )
+        .into_response()
+}
+
+async fn events(
+    State(shared): State<Arc<Shared>>,
+    RawQuery(query): RawQuery,
+    ws: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Response {
+    let clients = query
+        .as_deref()
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|param| param.strip_prefix("clients="))
+        .map(|clients| {
+            clients
+                .split(',')
+                .map(str::parse::<ClientId>)
+                .collect::<Result<HashSet<_>, _>>()
+        })
+        .transpose();
+    let Ok(clients) = clients else {
+        return error(StatusCode::BAD_REQUEST, "invalid client id");
+    };
+    let Ok(ws) = ws else {
+        return error(
+            StatusCode::UPGRADE_REQUIRED,
+            "expected a WebSocket handshake",
+        );
+    };
+    let events = shared.subscribe();
+    ws.on_upgrade(|socket| async move {
+        if let Err(e) = push_events(socket, events, clients).await {
+            debug!(error = %e, "event stream closed");
+        }
+    })
+}
+
+/// Send events of `clients`, or all, over a WebSocket until either side closes it. Pings and the
+/// closing handshake are answered while reading.
+async fn push_events(
+    mut socket: WebSocket,
+    mut events: broadcast::Receiver<AccountEvent>,
+    clients: Option<HashSet<ClientId>>,
+) -> Result<(), axum::Error> {
+    loop {
+        let message = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) if clients.as_ref().is_none_or(|c| c.contains(&event.client_id)) => {
+                    event.to_json()
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => format!("{{\"lagged\":{missed}}}"),
+                Err(RecvError::Closed) => {
+                    let close = CloseFrame {
+                        code: close_code::AWAY,
+                        reason: "".into(),
+                    };
+                    return socket.send(Message::Close(Some(close))).await;
+                }
+            },
+            received = socket.recv() => match received {
+                Some(received) => {
+                    received?;
+                    continue;
+                }
+                None => return Ok(()),
+            },
+        };
+        socket.send(Message::Text(message.into())).await?;
+    }
+}
+
+async fn report(State(shared): State<Arc<Shared>>, RawQuery(query): RawQuery) -> Response {
+    let format = query
+        .as_deref()
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|param| param.strip_prefix("format="))
+        .map_or(Ok(ReportFormat::Csv), str::parse);
+    let format = match format {
+        Ok(format) => format,
+        Err(e) => return error(StatusCode::BAD_REQUEST, &e),
+    };
+    let mut accounts = shared.db.views();
+    accounts.sort_unstable_by_key(|(client_id, _)| *client_id);
+    let mut body = Vec::new();
+    let flagged = shared.db.review().is_enabled();
+    if let Err(e) = format.write_with(&mut body, accounts, None, flagged) {
+        return error(StatusCode::NOT_IMPLEMENTED, &e.to_string());
+    }
+    let content_type = match format {
+        ReportFormat::Csv => "text/csv",
+        ReportFormat::Json => "application/json",
+        ReportFormat::Ndjson => "application/x-ndjson",
+        ReportFormat::Parquet => "application/vnd.apache.parquet",
+    };
+    (StatusCode::OK, [(header::CONTENT_TYPE, content_type)], body).into_response()
+}
+
+#[cfg(feature = "prometheus")]
+async fn metrics(State(shared): State<Arc<Shared>>) -> Response {
+    let body = shared.registry.render(&shared.db.stats());
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        body,
+    )
+        .into_response()
+}
+
+async fn list_alerts(State(shared): State<Arc<Shared>>) -> Response {
+    let alerts = shared.alerts.lock().unwrap();
+    let body = alerts
+        .raised()
+        .iter()
+        .map(|alert| Alert::to_json(alert) + "\n")
+        .collect::<String>();
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        body,
+    )
+        .into_response()
+}
+
+async fn healthz() -> Response {
+    json(StatusCode::OK, "{\"status\":\"ok\"}".into())
+}
+
+async fn readyz(State(shared): State<Arc<Shared>>) -> Response {
+    let readiness = &shared.readiness;
+    let writable = match readiness.storage.clone() {
+        Some(check) => {
+            let checked = tokio::task::spawn_blocking(move || check())
+                .await
+                .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+            if let Err(e) = &checked {
+                warn!(error = %e, "storage isn't writable");
+            }
+            Some(checked.is_ok())
+        }
+        None => None,
+    };
+    let in_flight = shared.in_flight.load(Ordering::Relaxed);
+    let jobs = shared.jobs.lock().unwrap().running();
+    let ready = writable != Some(false)
+        && readiness
+            .max_in_flight
+            .is_none_or(|max| in_flight + jobs < max);
+    let mut body = format!("{{\"ready\":{ready},\"state\":");
+    match &readiness.state {
+        Some(path) => push_json_string(&mut body, &path.display().to_string()),
+        None => body.push_str("null"),
+    }
+    body.push_str(&format!(
+        ",\"state_loaded\":{},\"storage_writable\":",
+        readiness.state.is_some()
+    ));
+    match writable {
+        Some(writable) => body.push_str(&writable.to_string()),
+        None => body.push_str("null"),
+    }
+    body.push_str(&format!(
+        ",\"in_flight\":{in_flight},\"jobs\":{jobs},\"max_in_flight\":"
+    ));
+    match readiness.max_in_flight {
+        Some(max) => body.push_str(&max.to_string()),
+        None => body.push_str("null"),
+    }
+    body.push('}');
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    json(status, body)
+}
+
+fn json(status: StatusCode, body: String) -> Response {
+    (status, [(header::CONTENT_TYPE, "application/json")], body).into_response()
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    let mut body = String::from("{\"error\":");
+    push_json_string(&mut body, message);
+    body.push('}');
+    json(status, body)
+}
+
+/// Same as [`error`], with the reason code of a malformed or rejected transaction.
+fn rejection(status: StatusCode, e: crate::Error) -> Response {
+    let mut body = String::new();
+    push_error(&mut body, &e);
+    json(status, body)
+}
+
+/// `{"error":"...","code":"E_..."}` of a malformed or rejected transaction.
+fn push_error(out: &mut String, e: &crate::Error) {
+    out.push_str("{\"error\":");
+    push_json_string(out, &e.to_string());
+    out.push_str(",\"code\":");
+    push_json_string(out, e.code());
+    out.push('}');
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::{
+        alerts::{AlertMonitor, AlertRules},
+        amount::Amount,
+        concurrent::ConcurrentClientsDatabase,
+        ratelimit::{RateLimiter, RateLimits},
+        server::{Readiness, serve},
+    };
+
+    async fn raw_request(addr: std::net::SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    /// Status line and body of the response.
+    async fn request(addr: std::net::SocketAddr, request: &str) -> (String, String) {
+        let response = raw_request(addr, request).await;
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_owned(), body.to_owned())
+    }
+
+    fn post(body: &str, content_type: &str) -> String {
+        format!(
+            "POST /transactions HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\
+             Content-Type: {content_type}\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    fn get(path: &str) -> String {
+        format!("GET {path} HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let db = Arc::new(ConcurrentClientsDatabase::new(2));
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let readiness = Readiness {
+            state: Some("s.bin".into()),
+            max_in_flight: Some(64),
+            storage: Some(Arc::new(|| Ok(()))),
+        };
+        let alerts = AlertMonitor::new(AlertRules {
+            account_total: Amount::parse(b"2"),
+            ..Default::default()
+        });
+        let limiter = RateLimiter::new(RateLimits {
+            per_minute: Some(2),
+            ..Default::default()
+        });
+        let server = tokio::spawn(serve(
+            listener,
+            db.clone(),
+            readiness,
+            alerts,
+            limiter,
+            None,
+            async {
+                stopped.await.ok();
+            },
+        ));
+
+        let json = "application/json";
+        let (status, body) = request(
+            addr,
+            &post(
+                r#"{"type":"deposit","client":7,"tx":1,"amount":"2.5"}"#,
+                json,
+            ),
+        )
+        .await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body, r#"{"status":"applied"}"#);
+        let (status, _) = request(addr, &post("deposit, 8, 2, 1", "text/csv")).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+
+        let (status, body) = request(
+            addr,
+            &post(
+                r#"{"type":"withdrawal","client":7,"tx":3,"amount":"5"}"#,
+                json,
+            ),
+        )
+        .await;
+        assert_eq!(status, "HTTP/1.1 422 Unprocessable Entity");
+        assert_eq!(
+            body,
+            r#"{"error":"withdraw overflowed - not enough money in the account","code":"E_INSUFFICIENT_FUNDS"}"#
+        );
+        let (status, body) = request(addr, &post("{}", json)).await;
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        assert_eq!(
+            body,
+            r#"{"error":"CSV missing an expected column","code":"E_MISSING_COLUMN"}"#
+        );
+
+        let (status, body) = request(addr, &get("/accounts/7")).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(
+            body,
+            "{\"client\":7,\"available\":\"2.5\",\"held\":\"0\",\"total\":\"2.5\",\"locked\":false}\n"
+        );
+        let (status, _) = request(addr, &get("/accounts/9")).await;
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+
+        let (status, body) = request(
+            addr,
+            &post(
+                "type, client, tx, amount\r\ndeposit, 9, 10, 1\r\nrefund, 9, 11, 1\r\n\
+                 withdrawal, 9, 12, 2\r\n",
+                "text/csv",
+            ),
+        )
+        .await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(
+            body,
+            r#"{"applied":1,"rejected":2,"results":[{"status":"applied"},{"error":"unknown transaction type","code":"E_UNKNOWN_TYPE"},{"error":"withdraw overflowed - not enough money in the account","code":"E_INSUFFICIENT_FUNDS"}]}"#
+        );
+        let (_, body) = request(
+            addr,
+            &post(
+                "{\"type\":\"deposit\",\"client\":10,\"tx\":13,\"amount\":\"1\"}\n\
+                 {\"type\":\"dispute\",\"client\":10,\"tx\":13}",
+                "application/x-ndjson",
+            ),
+        )
+        .await;
+        assert_eq!(
+            body,
+            r#"{"applied":2,"rejected":0,"results":[{"status":"applied"},{"status":"applied"}]}"#
+        );
+        let (status, body) = request(addr, &get("/accounts")).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body.lines().count(), 4);
+        assert_eq!(
+            body.lines().last().unwrap(),
+            "{\"client\":10,\"available\":\"0\",\"held\":\"1\",\"total\":\"1\",\"locked\":false}"
+        );
+
+        let (status, body) = request(addr, &get("/report")).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(
+            body,
+            "client, available, held, total, locked\n7,2.5,0,2.5,false\n8,1,0,1,false\n\
+             9,1,0,1,false\n10,0,1,1,false\n"
+        );
+        let (_, body) = request(addr, &get("/report?format=ndjson")).await;
+        assert_eq!(body.lines().count(), 4);
+        let (status, _) = request(addr, &get("/report?format=xml")).await;
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+
+        #[cfg(feature = "prometheus")]
+        {
+            let (status, body) = request(addr, &get("/metrics")).await;
+            assert_eq!(status, "HTTP/1.1 200 OK");
+            assert!(body.contains("\npayengine_transactions_total 7\n"));
+            assert!(body.contains("\npayengine_accounts_created_total 4\n"));
+            assert!(body.contains("\npayengine_rejected_total{reason=\"WithdrawOverflow\"} 2\n"));
+        }
+
+        // Client 7 used up its limit, rejected transactions included.
+        let (status, body) = request(addr, &post("deposit, 7, 4, 1", "text/csv")).await;
+        assert_eq!(status, "HTTP/1.1 429 Too Many Requests");
+        assert_eq!(
+            body,
+            r#"{"error":"too many transactions of the client, retry later","code":"E_RATE_LIMITED"}"#
+        );
+
+        let (status, body) = request(addr, &get("/alerts")).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(
+            body,
+            "{\"alert\":\"account\",\"client\":7,\"amount\":\"2.5\",\"limit\":\"2\"}\n"
+        );
+
+        let (status, body) = request(addr, &get("/healthz")).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body, r#"{"status":"ok"}"#);
+        let (status, body) = request(addr, &get("/readyz")).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(
+            body,
+            r#"{"ready":true,"state":"s.bin","state_loaded":true,"storage_writable":true,"#
+                .to_owned()
+                + r#""in_flight":0,"jobs":0,"max_in_flight":64}"#
+        );
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+
+        // Never ready without room for any request, or with storage failing.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let readiness = Readiness {
+            state: None,
+            max_in_flight: Some(0),
+            storage: Some(Arc::new(|| {
+                Err(std::io::ErrorKind::ReadOnlyFilesystem.into())
+            })),
+        };
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let alerts = AlertMonitor::default();
+        let limiter = RateLimiter::default();
+        let server = tokio::spawn(serve(
+            listener,
+            db,
+            readiness,
+            alerts,
+            limiter,
+            None,
+            async {
+                stopped.await.ok();
+            },
+        ));
+        let (status, body) = request(addr, &get("/readyz")).await;
+        assert_eq!(status, "HTTP/1.1 503 Service Unavailable");
+        assert_eq!(
+            body,
+            r#"{"ready":false,"state":null,"state_loaded":false,"storage_writable":false,"#
+                .to_owned()
+                + r#""in_flight":0,"jobs":0,"max_in_flight":0}"#
+        );
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let db = Arc::new(ConcurrentClientsDatabase::new(2));
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let limiter = RateLimiter::new(RateLimits {
+            per_minute: Some(2),
+            ..Default::default()
+        });
+        let server = tokio::spawn(serve(
+            listener,
+            db.clone(),
+            Readiness::default(),
+            AlertMonitor::default(),
+            limiter,
+            None,
+            async {
+                stopped.await.ok();
+            },
+        ));
+        let post = |body: &str, key: &str| {
+            post(body, "text/csv").replacen(
+                "\r\n\r\n",
+                &format!("\r\nIdempotency-Key: {key}\r\n\r\n"),
+                1,
+            )
+        };
+
+        let (status, body) = request(addr, &post("deposit, 7, 1, 2", "a")).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body, r#"{"status":"applied"}"#);
+        // Answered the same, without applying it again.
+        let response = raw_request(addr, &post("deposit, 7, 1, 2", "a")).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\nidempotent-replayed: true\r\n"));
+        assert!(response.ends_with("\r\n\r\n{\"status\":\"applied\"}"));
+        assert_eq!(db.get(7).unwrap().total, Amount::parse(b"2").unwrap());
+
+        let (status, body) = request(addr, &post("deposit, 7, 2, 2", "a")).await;
+        assert_eq!(status, "HTTP/1.1 422 Unprocessable Entity");
+        assert_eq!(
+            body,
+            r#"{"error":"the Idempotency-Key was used for another request"}"#
+        );
+        let (status, _) = request(addr, &post("deposit, 7, 2, 2", "")).await;
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+
+        // Rejections are kept like other responses, but not being over the rate limits.
+        let (status, _) = request(addr, &post("withdrawal, 7, 2, 5", "b")).await;
+        assert_eq!(status, "HTTP/1.1 422 Unprocessable Entity");
+        let (status, _) = request(addr, &post("withdrawal, 7, 2, 5", "b")).await;
+        assert_eq!(status, "HTTP/1.1 422 Unprocessable Entity");
+        let (status, _) = request(addr, &post("withdrawal, 7, 2, 1", "c")).await;
+        assert_eq!(status, "HTTP/1.1 429 Too Many Requests");
+        let response = raw_request(addr, &post("withdrawal, 7, 2, 1", "c")).await;
+        assert!(response.starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+        assert!(!response.contains("idempotent-replayed"));
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_jobs() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let db = Arc::new(ConcurrentClientsDatabase::new(2));
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        // Rows of jobs are rate limited, client 7's last one.
+        let limiter = RateLimiter::new(RateLimits {
+            per_minute: Some(3),
+            ..Default::default()
+        });
+        let server = tokio::spawn(serve(
+            listener,
+            db.clone(),
+            Readiness::default(),
+            AlertMonitor::default(),
+            limiter,
+            None,
+            async {
+                stopped.await.ok();
+            },
+        ));
+        let chunk = |data: &str| format!("{:x}\r\n{data}\r\n", data.len());
+
+        // Answered before the upload ends, with a row cut between chunks.
+        let mut upload = TcpStream::connect(addr).await.unwrap();
+        let head = "POST /jobs HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\
+                    Content-Type: text/csv\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let first = chunk("type,client,tx,amount\ndeposit,7,1,2\ndeposit,7,2");
+        upload
+            .write_all(format!("{head}{first}").as_bytes())
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        while !response.ends_with(b"{\"id\":1}") {
+            let mut buf = [0; 1024];
+            let n = upload.read(&mut buf).await.unwrap();
+            assert_ne!(n, 0);
+            response.extend_from_slice(&buf[..n]);
+        }
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 202 Accepted\r\n"));
+        assert!(response.contains("\r\nlocation: /jobs/1\r\n"));
+        let status = loop {
+            let (_, body) = request(addr, &get("/jobs/1")).await;
+            if body.contains("\"rows\":1,") {
+                break body;
+            }
+            tokio::task::yield_now().await;
+        };
+        assert!(status.starts_with(r#"{"id":1,"state":"running","bytes":47,"#));
+
+        let rest = chunk(",3\nwithdrawal,7,3,9\nnonsense\ndeposit,8,4,1\ndeposit,7,5,1");
+        upload
+            .write_all(format!("{rest}0\r\n\r\n").as_bytes())
+            .await
+            .unwrap();
+        let status = loop {
+            let (_, body) = request(addr, &get("/jobs/1")).await;
+            if !body.contains("running") {
+                break body;
+            }
+            tokio::task::yield_now().await;
+        };
+        assert_eq!(
+            status,
+            r#"{"id":1,"state":"done","bytes":103,"rows":6,"applied":3,"rejected":2,"#.to_owned()
+                + r#""malformed":1,"errors":[{"line":4,"error":"withdraw overflowed - not enough "#
+                + r#"money in the account","code":"E_INSUFFICIENT_FUNDS"},{"line":5,"error":"CSV "#
+                + r#"missing an expected column","code":"E_MISSING_COLUMN"},{"line":7,"error":"#
+                + r#""too many transactions of the client, retry later","code":"E_RATE_LIMITED"}]}"#
+        );
+        assert_eq!(db.get(7).unwrap().total, Amount::parse(b"5").unwrap());
+        assert_eq!(db.get(8).unwrap().total, Amount::parse(b"1").unwrap());
+
+        let (status, _) = request(addr, &get("/jobs/2")).await;
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_events() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let db = Arc::new(ConcurrentClientsDatabase::new(2));
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve(
+            listener,
+            db,
+            Readiness::default(),
+            AlertMonitor::default(),
+            RateLimiter::default(),
+            None,
+            async {
+                stopped.await.ok();
+            },
+        ));
+
+        let (status, _) = request(addr, &get("/events")).await;
+        assert_eq!(status, "HTTP/1.1 426 Upgrade Required");
+
+        let mut ws = TcpStream::connect(addr).await.unwrap();
+        ws.write_all(
+            b"GET /events?clients=8 HTTP/1.1\r\nHost: x\r\nUpgrade: websocket\r\n\
+              Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+              Sec-WebSocket-Version: 13\r\n\r\n",
+        )
+        .await
+        .unwrap();
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(ws.read_u8().await.unwrap());
+        }
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("sec-websocket-accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        for body in ["deposit, 7, 1, 1", "deposit, 8, 2, 2.5"] {
+            let (status, _) = request(addr, &post(body, "text/csv")).await;
+            assert_eq!(status, "HTTP/1.1 200 OK");
+        }
+        // Only client 8's, in a text frame.
+        let mut header = [0; 2];
+        ws.read_exact(&mut header).await.unwrap();
+        assert_eq!(header[0], 0x81);
+        let mut message = vec![0; usize::from(header[1])];
+        ws.read_exact(&mut message).await.unwrap();
+        assert_eq!(
+            String::from_utf8(message).unwrap(),
+            r#"{"at":1,"client":8,"type":"deposit","tx":2,"amount":"2.5","available":"2.5","held":"0","total":"2.5","locked":false}"#
+        );
+
+        // A masked ping is answered, then a close.
+        ws.write_all(&[0x89, 0x80, 0, 0, 0, 0]).await.unwrap();
+        ws.write_all(&[0x88, 0x82, 0, 0, 0, 0, 0x03, 0xe8])
+            .await
+            .unwrap();
+        let mut rest = Vec::new();
+        ws.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, [0x8a, 0, 0x88, 2, 0x03, 0xe8]);
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[cfg(feature = "otel")]
+    #[test]
+    fn test_remote_context() {
+        use axum::http::HeaderMap;
+        use opentelemetry::trace::TraceContextExt;
+
+        use crate::server::remote_context;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                .parse()
+                .unwrap(),
+        );
+        let cx = remote_context(&headers);
+        let span = cx.span();
+        let parent = span.span_context();
+        assert!(parent.is_remote());
+        assert_eq!(
+            parent.trace_id().to_string(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert_eq!(parent.span_id().to_string(), "00f067aa0ba902b7");
+
+        headers.insert("traceparent", "garbage".parse().unwrap());
+        assert!(!remote_context(&headers).span().span_context().is_valid());
+    }
+}
@@ -0,0 +1,534 @@
+use std::{
+    io::Write,
+    ops::RangeBounds,
+    sync::{
+        Arc,
+        mpsc::{Receiver, channel, sync_channel},
+    },
+    time::{Duration, Instant},
+};
+
+use tracing::{Span, debug_span, info_span};
+
+use crate::{
+    accounts::{Account, AccountView, ClientId, ClientsDatabase, Transaction, client_ids},
+    error::LedgerError,
+    events::AccountEvent,
+    history::Timestamp,
+    process::{PolicyMap, ProcessReport, RejectionMode},
+    progress::Progress,
+    reader::{DatabaseReader, Snapshot},
+    report::{format_rows, write_report_parts},
+    review::ReviewRules,
+    slowlog::SlowLog,
+    stats::DatabaseStats,
+    threads::{ThreadConfig, WorkerStats, pin_current},
+};
+
+// Transactions sent to a worker at once.
+pub(crate) const BATCH_LEN: usize = 1024;
+// Batches buffered per worker before the distributing thread blocks.
+pub(crate) const QUEUED_BATCHES: usize = 16;
+
+/// Clients partitioned across N independent databases by `client_id % N`.
+///
+/// Transactions for different clients never touch each other's state, so each shard can later be
+/// owned by its own thread (or lock) without any coordination.
+pub struct ShardedDatabase {
+    shards: Vec<ClientsDatabase>,
+    // Shared logical clock so timestamps are comparable across shards.
+    clock: Timestamp,
+    // Pinning of worker threads.
+    threads: ThreadConfig,
+}
+
+impl ShardedDatabase {
+    /// Create a database with `shards` partitions. Panics if `shards` is 0.
+    pub fn new(shards: usize) -> Self {
+        assert!(shards > 0, "shard count must be positive");
+        Self::from_shards((0..shards).map(|_| ClientsDatabase::new()).collect())
+    }
+
+    /// Same as [`Self::new`], with balance history enabled in every shard.
+    pub fn with_history(shards: usize) -> Self {
+        assert!(shards > 0, "shard count must be positive");
+        Self::from_shards(
+            (0..shards)
+                .map(|_| ClientsDatabase::with_history())
+                .collect(),
+        )
+    }
+
+    /// One shard per configured thread, with worker threads pinned to cores if configured.
+    pub fn with_threads(config: &ThreadConfig) -> Self {
+        let mut db = Self::new(config.threads());
+        db.threads = config.clone();
+        db
+    }
+
+    fn from_shards(shards: Vec<ClientsDatabase>) -> Self {
+        Self::from_parts(shards, 0)
+    }
+
+    pub(crate) fn from_parts(shards: Vec<ClientsDatabase>, clock: Timestamp) -> Self {
+        Self {
+            shards,
+            clock,
+            threads: ThreadConfig::default(),
+        }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Index of the shard owning the given client.
+    pub fn shard_for(&self, client_id: ClientId) -> usize {
+        client_id as usize % self.shards.len()
+    }
+
+    pub fn shards(&self) -> &[ClientsDatabase] {
+        &self.shards
+    }
+
+    pub fn shards_mut(&mut self) -> &mut [ClientsDatabase] {
+        &mut self.shards
+    }
+
+    pub fn into_shards(self) -> Vec<ClientsDatabase> {
+        self.shards
+    }
+
+    /// Set the [`RejectionMode`] of every shard.
+    pub fn set_rejection_mode(&mut self, mode: RejectionMode) {
+        for shard in &mut self.shards {
+            shard.set_rejection_mode(mode);
+        }
+    }
+
+    /// Set the [`crate::process::ErrorPolicy`] of every shard. With `Abort`, the other shards stop
+    /// too once one of them stops, though they may have applied a few later transactions by then.
+    pub fn set_error_policy(&mut self, policy: impl Into<PolicyMap>) {
+        let policy = policy.into();
+        for shard in &mut self.shards {
+            shard.set_error_policy(policy);
+        }
+    }
+
+    /// Sample rejected transactions of every shard, see [`ClientsDatabase::set_reject_samples`].
+    pub fn set_reject_samples(&mut self, per_kind: usize) {
+        for shard in &mut self.shards {
+            shard.set_reject_samples(per_kind);
+        }
+    }
+
+    pub fn reject_samples(&self) -> usize {
+        self.shards[0].reject_samples()
+    }
+
+    /// Flag accounts of every shard for review, see [`ClientsDatabase::set_review`].
+    pub fn set_review(&mut self, rules: &ReviewRules) {
+        for shard in &mut self.shards {
+            shard.set_review(rules.clone());
+        }
+    }
+
+    pub fn review(&self) -> &ReviewRules {
+        self.shards[0].review()
+    }
+
+    /// Record slow operations of every shard, see [`ClientsDatabase::set_slow_threshold`].
+    pub fn set_slow_threshold(&mut self, threshold: Option<Duration>) {
+        for shard in &mut self.shards {
+            shard.set_slow_threshold(threshold);
+        }
+    }
+
+    /// Slow operations of all shards.
+    pub fn slow_log(&self) -> SlowLog {
+        let mut log = SlowLog::new(self.shards[0].slow_log().threshold());
+        for shard in &self.shards {
+            log.merge(shard.slow_log());
+        }
+        log
+    }
+
+    /// Receive an event for every transaction applied from now on, in any shard, see
+    /// [`ClientsDatabase::subscribe`].
+    pub fn subscribe(&mut self) -> Receiver<AccountEvent> {
+        let (tx, rx) = channel();
+        for shard in &mut self.shards {
+            shard.subscribe_with(tx.clone());
+        }
+        rx
+    }
+
+    /// Stop sending events, see [`ClientsDatabase::unsubscribe_all`].
+    pub fn unsubscribe_all(&mut self) {
+        for shard in &mut self.shards {
+            shard.unsubscribe_all();
+        }
+    }
+
+    /// Count processing of every shard into `progress`, see [`ClientsDatabase::set_progress`].
+    /// [`Self::process_files`] also counts the bytes it reads.
+    pub fn set_progress(&mut self, progress: Progress) {
+        for shard in &mut self.shards {
+            shard.set_progress(progress.clone());
+        }
+    }
+
+    pub(crate) fn clock(&self) -> Timestamp {
+        self.clock
+    }
+
+    pub(crate) fn set_clock(&mut self, clock: Timestamp) {
+        self.clock = clock;
+    }
+
+    pub fn process_transaction(
+        &mut self,
+        client_id: ClientId,
+        t: Transaction,
+    ) -> Result<(), LedgerError> {
+        let at = self.clock;
+        self.clock += 1;
+        let shard = self.shard_for(client_id);
+        self.shards[shard].process_transaction_at(client_id, t, at)
+    }
+
+    /// Apply all transactions using one worker thread per shard, with the calling thread
+    /// distributing them. Per-client order is preserved, as all transactions of a client go to the
+    /// same worker in input order. Rejection indices in the report are positions in the input.
+    pub fn process_parallel(
+        &mut self,
+        transactions: impl IntoIterator<Item = (ClientId, Transaction)>,
+    ) -> ProcessReport {
+        self.run_parallel(transactions, None::<fn(Receiver<Vec<u8>>)>)
+            .0
+    }
+
+    /// Same as [`Self::process_parallel`], also writing the final balances report to `w`.
+    ///
+    /// Each worker formats the rows of its shard as soon as it's done with its transactions, and
+    /// they're written while other workers are still processing. So rows are grouped by shard in
+    /// the order workers finish, rather than in [`Self::iter`] order.
+    ///
+    /// The database is updated even if writing fails.
+    pub fn process_parallel_with_report(
+        &mut self,
+        transactions: impl IntoIterator<Item = (ClientId, Transaction)>,
+        w: impl Write,
+    ) -> (ProcessReport, std::io::Result<()>) {
+        let (report, written) = self.run_parallel(
+            transactions,
+            Some(|parts: Receiver<Vec<u8>>| write_report_parts(w, parts)),
+        );
+        (report, written.unwrap())
+    }
+
+    /// Process transactions in parallel. If `on_parts` is given, each worker formats its report
+    /// rows when done, and `on_parts` gets them in completion order after all input is
+    /// distributed.
+    fn run_parallel<R>(
+        &mut self,
+        transactions: impl IntoIterator<Item = (ClientId, Transaction)>,
+        on_parts: Option<impl FnOnce(Receiver<Vec<u8>>) -> R>,
+    ) -> (ProcessReport, Option<R>) {
+        let first = self.clock;
+        let shard_count = self.shards.len();
+        // Worker threads don't inherit the current span.
+        let parent = Span::current();
+        let (report, handed_out, parts_result) = std::thread::scope(|s| {
+            let mut senders = Vec::with_capacity(shard_count);
+            let mut workers = Vec::with_capacity(shard_count);
+            let threads = &self.threads;
+            let (parts_tx, parts_rx) = channel();
+            let format_parts = on_parts.is_some();
+            for (idx, shard) in self.shards.iter_mut().enumerate() {
+                let (tx, rx) = sync_channel::<Vec<(usize, ClientId, Transaction)>>(QUEUED_BATCHES);
+                senders.push(tx);
+                let parts_tx = parts_tx.clone();
+                let span = info_span!(parent: &parent, "apply", shard = idx);
+                workers.push(s.spawn(move || {
+                    let _apply = span.entered();
+                    if let Some(core) = threads.applier_core(idx) {
+                        pin_current(core);
+                    }
+                    let start = Instant::now();
+                    let mut idle = Duration::ZERO;
+                    let batches = std::iter::from_fn(|| {
+                        let wait = Instant::now();
+                        let batch = rx.recv().ok();
+                        idle += wait.elapsed();
+                        batch
+                    });
+                    let mut report = shard.new_report();
+                    for batch in batches {
+                        let _span = debug_span!(
+                            "apply_batch",
+                            first_row = batch.first().map(|&(idx, ..)| idx),
+                            transactions = batch.len()
+                        )
+                        .entered();
+                        shard.process_indexed(batch, first, &mut report);
+                        if report.aborted.is_some() {
+                            break;
+                        }
+                    }
+                    shard.retry_remaining(first, &mut report);
+                    report.workers.push(WorkerStats {
+                        transactions: report.processed,
+                        elapsed: start.elapsed(),
+                        idle,
+                    });
+                    if format_parts {
+                        let mut rows = Vec::new();
+                        format_rows(&mut rows, shard.iter().map(|(c, a)| (c, a.view())));
+                        let _ = parts_tx.send(rows);
+                    }
+                    report
+                }));
+            }
+            // Parts end when all workers are done.
+            drop(parts_tx);
+
+            // Batch to amortize channel synchronization.
+            let mut batches = (0..shard_count)
+                .map(|_| Vec::with_capacity(BATCH_LEN))
+                .collect::<Vec<_>>();
+            // Rows given timestamps, including those of shards that stopped before applying them.
+            let mut handed_out = 0;
+            for (idx, (client_id, t)) in transactions.into_iter().enumerate() {
+                let shard = client_id as usize % shard_count;
+                batches[shard].push((idx, client_id, t));
+                handed_out = idx + 1;
+                if batches[shard].len() == BATCH_LEN {
+                    let batch =
+                        std::mem::replace(&mut batches[shard], Vec::with_capacity(BATCH_LEN));
+                    // Fails if the worker aborted, so the others can stop early too, or if it
+                    // panicked, which is propagated on join below.
+                    if senders[shard].send(batch).is_err() {
+                        break;
+                    }
+                }
+            }
+            for (batch, tx) in batches.into_iter().zip(senders) {
+                let _ = tx.send(batch);
+            }
+            let parts_result = on_parts.map(|f| f(parts_rx));
+
+            let mut report = ProcessReport::default();
+            for worker in workers {
+                report.merge(worker.join().unwrap());
+            }
+            report.rejections.sort_unstable_by_key(|(idx, _)| *idx);
+            report.dead_letters.sort_unstable_by_key(|d| d.index);
+            (report, handed_out, parts_result)
+        });
+        // Not by the rows processed: when a shard aborts, the others may already have applied
+        // rows with higher timestamps.
+        self.clock += handed_out as Timestamp;
+        (report, parts_result)
+    }
+
+    pub fn balance_as_of(
+        &self,
+        client_id: ClientId,
+        at: Timestamp,
+    ) -> Option<AccountView<'static>> {
+        self.shards[self.shard_for(client_id)].balance_as_of(client_id, at)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ClientId, &Account)> {
+        self.shards.iter().flat_map(|s| s.iter())
+    }
+
+    pub fn get(&self, client_id: ClientId) -> Option<&Account> {
+        self.shards[self.shard_for(client_id)].get(client_id)
+    }
+
+    /// Accounts with client ids within the range, in client id order.
+    pub fn iter_range(
+        &self,
+        range: impl RangeBounds<ClientId>,
+    ) -> impl Iterator<Item = (ClientId, &Account)> {
+        client_ids(range).filter_map(|c| Some((c, self.get(c)?)))
+    }
+
+    /// Make the current balances of all shards visible to readers of `reader`.
+    ///
+    /// Unlike `ClientsDatabase::publish` this copies every account, as clients of one snapshot
+    /// chunk are spread across all shards.
+    pub fn publish(&self, reader: &DatabaseReader) {
+        reader.publish(Arc::new(Snapshot::capture(self.iter())))
+    }
+
+    pub fn stats(&self) -> DatabaseStats {
+        DatabaseStats::collect(self.iter().map(|(_, a)| a))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::{ClientsDatabase, Transaction, TransactionKind::*},
+        amount::Amount,
+        error::LedgerError,
+        process::ErrorPolicy,
+        report::write_report,
+        sharded::ShardedDatabase,
+    };
+
+    #[test]
+    fn test_sharded_routing() {
+        let mut db = ShardedDatabase::new(3);
+        for client_id in 0..10 {
+            db.process_transaction(
+                client_id,
+                Transaction {
+                    kind: Deposit,
+                    id: client_id as u32,
+                    amount: Amount::parse(b"1").unwrap(),
+                },
+            )
+            .unwrap();
+        }
+
+        for (idx, shard) in db.shards().iter().enumerate() {
+            assert!(shard.iter().all(|(c, _)| c as usize % 3 == idx));
+        }
+
+        let mut clients = db.iter().map(|(c, _)| c).collect::<Vec<_>>();
+        clients.sort();
+        assert_eq!(clients, (0..10).collect::<Vec<_>>());
+
+        assert!(matches!(
+            db.process_transaction(
+                42,
+                Transaction {
+                    kind: Dispute,
+                    id: 1,
+                    amount: Amount::zero(),
+                }
+            )
+            .unwrap_err(),
+            LedgerError::AccountNotFound
+        ));
+    }
+
+    #[test]
+    fn test_process_parallel_matches_sequential() {
+        let transactions = (0..10_000u32)
+            .map(|i| {
+                let client_id = (i % 13) as u16;
+                let t = match i % 5 {
+                    0..=2 => Transaction {
+                        kind: Deposit,
+                        id: i,
+                        amount: Amount::parse(b"1.5").unwrap(),
+                    },
+                    3 => Transaction {
+                        kind: Withdrawal,
+                        id: i,
+                        amount: Amount::parse(b"4").unwrap(),
+                    },
+                    _ => Transaction {
+                        kind: Dispute,
+                        id: i - 4,
+                        amount: Amount::zero(),
+                    },
+                };
+                (client_id, t)
+            })
+            .collect::<Vec<_>>();
+
+        let mut sequential = ClientsDatabase::new();
+        let expected = sequential.process_all(transactions.iter().copied());
+        let mut parallel = ShardedDatabase::new(4);
+        let report = parallel.process_parallel(transactions.iter().copied());
+
+        assert_eq!(report.processed, transactions.len());
+        assert_eq!(report.applied, expected.applied);
+        assert_eq!(report.workers.len(), 4);
+        assert_eq!(
+            report.workers.iter().map(|w| w.transactions).sum::<usize>(),
+            transactions.len()
+        );
+        assert_eq!(
+            report
+                .rejections
+                .iter()
+                .map(|(i, _)| *i)
+                .collect::<Vec<_>>(),
+            expected
+                .rejections
+                .iter()
+                .map(|(i, _)| *i)
+                .collect::<Vec<_>>()
+        );
+        for client_id in 0..13 {
+            assert_eq!(
+                parallel.get(client_id).unwrap().view(),
+                sequential.get(client_id).unwrap().view()
+            );
+        }
+    }
+
+    #[test]
+    fn test_process_parallel_abort_clock() {
+        let mut db = ShardedDatabase::with_history(2);
+        db.set_error_policy(ErrorPolicy::Abort);
+        let deposit = |id| Transaction {
+            kind: Deposit,
+            id,
+            amount: Amount::parse(b"1").unwrap(),
+        };
+        let dispute = Transaction {
+            kind: Dispute,
+            id: 0,
+            amount: Amount::zero(),
+        };
+        // Client 0's shard aborts at the first row, client 1's applies rows 501 to 1000.
+        let transactions = std::iter::once((0, dispute))
+            .chain((1..=1000u32).map(|i| ((i > 500) as u16, deposit(i))));
+        let report = db.process_parallel(transactions);
+        assert!(report.aborted.is_some());
+        assert_eq!(report.processed, 501);
+        assert_eq!(db.clock(), 1001);
+
+        // Applied after the run, so not part of the balance as of its last row.
+        db.process_transaction(1, deposit(1001)).unwrap();
+        assert_eq!(
+            db.balance_as_of(1, 1000).unwrap().total,
+            Amount::parse(b"500").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_process_parallel_with_report() {
+        let transactions = (0..1000u32).map(|i| {
+            let t = Transaction {
+                kind: Deposit,
+                id: i,
+                amount: Amount::parse(b"1.5").unwrap(),
+            };
+            ((i % 37) as u16, t)
+        });
+        let mut db = ShardedDatabase::new(4);
+        let mut out = Vec::new();
+        let (report, written) = db.process_parallel_with_report(transactions, &mut out);
+        written.unwrap();
+        assert_eq!(report.applied, 1000);
+
+        let mut expected = Vec::new();
+        write_report(&mut expected, db.iter().map(|(c, a)| (c, a.view()))).unwrap();
+        let sorted_lines = |out: &[u8]| {
+            let mut lines = out.split(|b| *b == b'\n').collect::<Vec<_>>();
+            // Keep the header first.
+            lines[1..].sort();
+            lines.into_iter().map(|l| l.to_vec()).collect::<Vec<_>>()
+        };
+        assert_eq!(sorted_lines(&out), sorted_lines(&expected));
+    }
+}
@@ -0,0 +1,79 @@
+/// Free lists of Vec buffers by power-of-two capacity, so buffers released by one owner (e.g. an
+/// account whose deposits outgrew them) are reused by others instead of going back to the
+/// allocator.
+///
+/// All buffers have power-of-two capacities, which keeps the heap free of odd-sized holes.
+pub(crate) struct SizeClassPool<T> {
+    // Free buffers of capacity 1 << class, by class.
+    classes: Vec<Vec<Vec<T>>>,
+    // Upper bound of bytes kept in free buffers of every class.
+    max_class_bytes: usize,
+}
+
+impl<T> SizeClassPool<T> {
+    pub(crate) const fn new(max_class_bytes: usize) -> Self {
+        SizeClassPool {
+            classes: Vec::new(),
+            max_class_bytes,
+        }
+    }
+
+    /// An empty buffer with capacity of at least `min_capacity`, rounded up to a power of two.
+    pub(crate) fn take(&mut self, min_capacity: usize) -> Vec<T> {
+        let capacity = min_capacity.next_power_of_two();
+        let class = capacity.trailing_zeros() as usize;
+        self.classes
+            .get_mut(class)
+            .and_then(|free| free.pop())
+            .unwrap_or_else(|| Vec::with_capacity(capacity))
+    }
+
+    /// Return a buffer for reuse. Its elements are dropped.
+    pub(crate) fn give(&mut self, mut buf: Vec<T>) {
+        let capacity = buf.capacity();
+        if capacity == 0 || !capacity.is_power_of_two() {
+            return;
+        }
+        let class = capacity.trailing_zeros() as usize;
+        if self.classes.len() <= class {
+            self.classes.resize_with(class + 1, Vec::new);
+        }
+        let free = &mut self.classes[class];
+        if (free.len() + 1) * capacity * size_of::<T>() <= self.max_class_bytes {
+            buf.clear();
+            free.push(buf);
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn free_buffers(&self) -> usize {
+        self.classes.iter().map(|c| c.len()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::slab::SizeClassPool;
+
+    #[test]
+    fn test_size_class_pool() {
+        let mut pool = SizeClassPool::<u64>::new(1024);
+        let buf = pool.take(5);
+        assert_eq!(buf.capacity(), 8);
+        let ptr = buf.as_ptr();
+        pool.give(buf);
+        assert_eq!(pool.free_buffers(), 1);
+        // Same class reuses the buffer.
+        let buf = pool.take(7);
+        assert_eq!(buf.as_ptr(), ptr);
+        pool.give(buf);
+
+        // Classes are bounded by bytes: 1024 bytes fit one buffer of 128 u64.
+        pool.give(Vec::with_capacity(128));
+        pool.give(Vec::with_capacity(128));
+        assert_eq!(pool.free_buffers(), 2);
+        // Odd capacities aren't pooled.
+        pool.give(Vec::with_capacity(3));
+        assert_eq!(pool.free_buffers(), 2);
+    }
+}
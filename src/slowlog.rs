@@ -0,0 +1,273 @@
+use std::{
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+use tracing::warn;
+
+use crate::accounts::{ClientId, Transaction};
+
+/// Slow operations kept with their context by [`SlowLog`], the slowest first.
+const SLOWEST: usize = 10;
+
+/// Settings of [`SlowLog`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct SlowLogConfig {
+    /// Operations taking at least this long are logged. Nothing is timed if unset.
+    pub threshold_ms: Option<u64>,
+}
+
+impl SlowLogConfig {
+    pub fn threshold(&self) -> Option<Duration> {
+        self.threshold_ms.map(Duration::from_millis)
+    }
+}
+
+/// What took long, see [`SlowOp`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlowOpKind {
+    /// Applying a transaction, or rejecting it.
+    Transaction,
+    /// Writing deposits to disk, see [`crate::accounts::ClientsDatabase::enable_spill`].
+    Spill,
+    /// Reading a spilled deposit back for a dispute.
+    SpillRead,
+}
+
+impl SlowOpKind {
+    pub const ALL: [SlowOpKind; 3] = [
+        SlowOpKind::Transaction,
+        SlowOpKind::Spill,
+        SlowOpKind::SpillRead,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SlowOpKind::Transaction => "transaction",
+            SlowOpKind::Spill => "spill",
+            SlowOpKind::SpillRead => "spill_read",
+        }
+    }
+}
+
+/// An operation which took at least the threshold of its [`SlowLog`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SlowOp {
+    pub kind: SlowOpKind,
+    pub duration: Duration,
+    pub client_id: Option<ClientId>,
+    /// The transaction applied, or the dispute a deposit was read for.
+    pub transaction: Option<Transaction>,
+    /// Deposits retained by the account for transactions, deposits written for spills, and files
+    /// searched for reads, usually why it took long.
+    pub size: usize,
+}
+
+impl SlowOp {
+    /// One JSON object, e.g.
+    /// `{"op":"transaction","ms":120.512,"client":7,"type":"deposit","tx":9,"size":100000}`.
+    pub fn to_json(&self) -> String {
+        let mut out = format!(
+            "{{\"op\":\"{}\",\"ms\":{:.3}",
+            self.kind.as_str(),
+            self.duration.as_secs_f64() * 1000.0
+        );
+        if let Some(client_id) = self.client_id {
+            write!(out, ",\"client\":{client_id}").unwrap();
+        }
+        if let Some(t) = self.transaction {
+            write!(out, ",\"type\":\"{}\",\"tx\":{}", t.kind.as_str(), t.id).unwrap();
+        }
+        write!(out, ",\"size\":{}}}", self.size).unwrap();
+        out
+    }
+}
+
+/// Operations of a database taking at least a threshold, to find pathological accounts and IO
+/// stalls without a profiler. Each one is logged as a warning when it happens, counted by kind,
+/// and the slowest are kept. Disabled without a threshold, costing nothing but a branch then.
+#[derive(Clone, Debug, Default)]
+pub struct SlowLog {
+    threshold: Option<Duration>,
+    counts: [usize; SlowOpKind::ALL.len()],
+    // Sorted by duration, longest first.
+    slowest: Vec<SlowOp>,
+}
+
+impl SlowLog {
+    pub fn new(threshold: Option<Duration>) -> Self {
+        SlowLog {
+            threshold,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.threshold.is_some()
+    }
+
+    pub fn threshold(&self) -> Option<Duration> {
+        self.threshold
+    }
+
+    /// When an operation starts, if timing it.
+    pub fn start(&self) -> Option<Instant> {
+        self.threshold.map(|_| Instant::now())
+    }
+
+    /// Record an operation which took `op.duration`, if that's at least the threshold.
+    pub fn record(&mut self, op: SlowOp) {
+        let Some(threshold) = self.threshold else {
+            return;
+        };
+        if op.duration < threshold {
+            return;
+        }
+        warn!(
+            op = op.kind.as_str(),
+            duration = ?op.duration,
+            client = op.client_id,
+            tx = op.transaction.map(|t| t.id),
+            size = op.size,
+            "slow operation"
+        );
+        self.counts[op.kind as usize] += 1;
+        self.keep(op);
+    }
+
+    fn keep(&mut self, op: SlowOp) {
+        let idx = self.slowest.partition_point(|o| o.duration >= op.duration);
+        if idx < SLOWEST {
+            self.slowest.insert(idx, op);
+            self.slowest.truncate(SLOWEST);
+        }
+    }
+
+    /// Add the operations of another log, e.g. of another shard.
+    pub fn merge(&mut self, other: &SlowLog) {
+        for (count, other) in self.counts.iter_mut().zip(other.counts) {
+            *count += other;
+        }
+        for &op in &other.slowest {
+            self.keep(op);
+        }
+    }
+
+    pub fn count(&self, kind: SlowOpKind) -> usize {
+        self.counts[kind as usize]
+    }
+
+    /// The slowest operations recorded, longest first.
+    pub fn slowest(&self) -> &[SlowOp] {
+        &self.slowest
+    }
+
+    /// One JSON object, e.g. `{"threshold_ms":100,"counts":{"transaction":1,"spill":0,
+    /// "spill_read":0},"slowest":[{"op":"transaction",...}]}`.
+    pub fn to_json(&self) -> String {
+        let threshold = self.threshold.unwrap_or_default().as_millis();
+        let mut out = format!("{{\"threshold_ms\":{threshold},\"counts\":{{");
+        for (idx, kind) in SlowOpKind::ALL.into_iter().enumerate() {
+            if idx > 0 {
+                out.push(',');
+            }
+            write!(out, "\"{}\":{}", kind.as_str(), self.count(kind)).unwrap();
+        }
+        out.push_str("},\"slowest\":[");
+        for (idx, op) in self.slowest.iter().enumerate() {
+            if idx > 0 {
+                out.push(',');
+            }
+            out.push_str(&op.to_json());
+        }
+        out.push_str("]}");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        accounts::{
+            ClientsDatabase, Transaction,
+            TransactionKind::{Deposit, Dispute},
+        },
+        amount::Amount,
+        slowlog::{SlowLog, SlowOp, SlowOpKind},
+    };
+
+    fn op(millis: u64) -> SlowOp {
+        SlowOp {
+            kind: SlowOpKind::Transaction,
+            duration: Duration::from_millis(millis),
+            client_id: Some(7),
+            transaction: Some(Transaction {
+                kind: Deposit,
+                id: millis as u32,
+                amount: Amount::zero(),
+            }),
+            size: 3,
+        }
+    }
+
+    #[test]
+    fn test_slow_log() {
+        let mut log = SlowLog::new(Some(Duration::from_millis(100)));
+        log.record(op(99));
+        for millis in 100..120 {
+            log.record(op(millis));
+        }
+        assert_eq!(log.count(SlowOpKind::Transaction), 20);
+        assert_eq!(log.slowest().len(), 10);
+        assert_eq!(log.slowest()[0].duration, Duration::from_millis(119));
+        assert_eq!(log.slowest()[9].duration, Duration::from_millis(110));
+        assert_eq!(
+            op(120).to_json(),
+            r#"{"op":"transaction","ms":120.000,"client":7,"type":"deposit","tx":120,"size":3}"#
+        );
+
+        let mut merged = SlowLog::new(log.threshold());
+        merged.merge(&log);
+        merged.merge(&SlowLog::new(log.threshold()));
+        assert_eq!(merged.slowest(), log.slowest());
+        assert!(
+            merged
+                .to_json()
+                .starts_with(r#"{"threshold_ms":100,"counts":{"transaction":20,"spill":0,"spill_read":0},"slowest":[{"op":"transaction","ms":119.000"#)
+        );
+
+        let mut disabled = SlowLog::default();
+        assert_eq!(disabled.start(), None);
+        disabled.record(op(1000));
+        assert_eq!(disabled.count(SlowOpKind::Transaction), 0);
+    }
+
+    #[test]
+    fn test_slow_database_operations() {
+        // Everything is slow with a zero threshold.
+        let mut db = ClientsDatabase::with_spill(std::env::temp_dir(), 10);
+        db.set_slow_threshold(Some(Duration::ZERO));
+        for id in 0..20 {
+            let t = Transaction {
+                kind: Deposit,
+                id,
+                amount: Amount::parse(b"1").unwrap(),
+            };
+            db.process_transaction(1, t).unwrap();
+        }
+        let t = Transaction {
+            kind: Dispute,
+            id: 0,
+            amount: Amount::zero(),
+        };
+        db.process_transaction(1, t).unwrap();
+        let log = db.slow_log();
+        assert_eq!(log.count(SlowOpKind::Transaction), 21);
+        assert_eq!(log.count(SlowOpKind::Spill), 2);
+        assert_eq!(log.count(SlowOpKind::SpillRead), 1);
+    }
+}
@@ -0,0 +1,145 @@
+use std::io::{BufRead, Read};
+
+use crate::{
+    error::ParseError,
+    parser::Row,
+    pipeline::{ParseFailure, RowStream},
+    readahead::ReadAhead,
+};
+
+/// A stream of parsed input rows, however they're read and parsed.
+///
+/// Parse failures are yielded in place of their rows, and a read error is yielded once and ends
+/// the source.
+pub trait TransactionSource {
+    /// The next row, or None at the end of input.
+    fn next_row(&mut self) -> Option<Result<Row, ParseFailure>>;
+}
+
+impl TransactionSource for RowStream {
+    fn next_row(&mut self) -> Option<Result<Row, ParseFailure>> {
+        self.next()
+    }
+}
+
+/// Rows parsed on the calling thread from buffers filled by a dedicated reader thread (see
+/// [`ReadAhead`]): while one buffer is parsed, the next one is being read.
+///
+/// Unlike [`RowStream`], parsing stays on the consumer's thread, so only one extra thread is used.
+/// Lines are parsed straight out of the buffers, only lines split between two buffers are copied.
+///
+/// The header line is skipped without parsing, as in the CLI.
+pub struct BufferedSource {
+    reader: ReadAhead,
+    // Start of a line that continues in the next buffer.
+    partial: Vec<u8>,
+    line: usize,
+    done: bool,
+}
+
+impl BufferedSource {
+    pub fn new(reader: impl Read + Send + 'static) -> Self {
+        Self::from_read_ahead(ReadAhead::new(reader))
+    }
+
+    pub fn from_read_ahead(reader: ReadAhead) -> Self {
+        BufferedSource {
+            reader,
+            partial: Vec::new(),
+            line: 0,
+            done: false,
+        }
+    }
+}
+
+/// Parse the next line, or None for the header.
+fn parse_line(line: &mut usize, buf: &[u8]) -> Option<Result<Row, ParseFailure>> {
+    *line += 1;
+    let line = *line;
+    (line > 1).then(|| Row::parse(buf).map_err(|error| ParseFailure::with_raw(line, error, buf)))
+}
+
+impl TransactionSource for BufferedSource {
+    fn next_row(&mut self) -> Option<Result<Row, ParseFailure>> {
+        while !self.done {
+            let buf = match self.reader.fill_buf() {
+                Ok(buf) => buf,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(ParseFailure::new(self.line + 1, ParseError::Io(e))));
+                }
+            };
+            if buf.is_empty() {
+                // Last line without a trailing newline.
+                self.done = true;
+                if self.partial.is_empty() {
+                    return None;
+                }
+                return parse_line(&mut self.line, &self.partial);
+            }
+            let Some(end) = memchr::memchr(b'\n', buf) else {
+                self.partial.extend_from_slice(buf);
+                let len = buf.len();
+                self.reader.consume(len);
+                continue;
+            };
+            let row = if self.partial.is_empty() {
+                parse_line(&mut self.line, &buf[..end])
+            } else {
+                self.partial.extend_from_slice(&buf[..end]);
+                let row = parse_line(&mut self.line, &self.partial);
+                self.partial.clear();
+                row
+            };
+            self.reader.consume(end + 1);
+            if row.is_some() {
+                return row;
+            }
+        }
+        None
+    }
+}
+
+impl Iterator for BufferedSource {
+    type Item = Result<Row, ParseFailure>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_row()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::ParseError,
+        readahead::ReadAhead,
+        source::{BufferedSource, TransactionSource},
+    };
+
+    #[test]
+    fn test_buffered_source() {
+        let mut input = String::from("type, client, tx, amount\n");
+        for i in 0..100 {
+            input.push_str(&format!("deposit, {i}, {i}, 1.5\n"));
+        }
+        input.push_str("bogus, 1, 1, 1\ndeposit, 1, 1000, 2");
+        // Small buffers, so most lines are split between two.
+        let reader = ReadAhead::with_buffer_len(std::io::Cursor::new(input), 16);
+        let mut source = BufferedSource::from_read_ahead(reader);
+
+        let mut rows = Vec::new();
+        while let Some(row) = source.next_row() {
+            rows.push(row);
+        }
+        assert_eq!(rows.len(), 102);
+        for (i, row) in rows[..100].iter().enumerate() {
+            let row = row.as_ref().unwrap();
+            assert_eq!(row.client_id, i as u16);
+            assert_eq!(row.transaction.id, i as u32);
+        }
+        let failure = rows[100].as_ref().unwrap_err();
+        assert_eq!(failure.line, 102);
+        assert!(matches!(failure.error, ParseError::UnknownTransactionType));
+        assert_eq!(rows[101].as_ref().unwrap().transaction.id, 1000);
+    }
+}
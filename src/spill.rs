@@ -0,0 +1,312 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    accounts::{ClientId, TransactionId},
+    amount::Amount,
+};
+
+// client (2) + tx (4) + amount (8), little endian.
+const RECORD_SIZE: usize = 14;
+// Every BLOCK_LEN-th key of a run is kept in memory, so a lookup reads at most one block from disk.
+const BLOCK_LEN: usize = 256;
+
+// Makes run file names unique across all stores in the process.
+static NEXT_RUN_ID: AtomicU64 = AtomicU64::new(0);
+
+type Key = (ClientId, TransactionId);
+
+/// One sorted file of spilled deposits with a sparse in-memory index.
+struct Run {
+    path: PathBuf,
+    file: File,
+    len: usize,
+    // First key of every block.
+    index: Vec<Key>,
+}
+
+impl Run {
+    fn write(path: PathBuf, records: &[(Key, Amount)]) -> std::io::Result<Self> {
+        let mut w = BufWriter::new(File::create_new(&path)?);
+        let mut index = Vec::with_capacity(records.len().div_ceil(BLOCK_LEN));
+        for (i, ((client_id, tx_id), amount)) in records.iter().enumerate() {
+            if i % BLOCK_LEN == 0 {
+                index.push((*client_id, *tx_id));
+            }
+            w.write_all(&client_id.to_le_bytes())?;
+            w.write_all(&tx_id.to_le_bytes())?;
+            w.write_all(&amount.to_raw().to_le_bytes())?;
+        }
+        let file = w.into_inner().map_err(|e| e.into_error())?;
+        Ok(Run {
+            path,
+            file,
+            len: records.len(),
+            index,
+        })
+    }
+
+    fn get(&self, key: Key) -> std::io::Result<Option<Amount>> {
+        let block = match self.index.partition_point(|k| *k <= key).checked_sub(1) {
+            Some(b) => b,
+            None => return Ok(None),
+        };
+        let start = block * BLOCK_LEN;
+        let count = BLOCK_LEN.min(self.len - start);
+        let mut buf = [0u8; BLOCK_LEN * RECORD_SIZE];
+        let buf = &mut buf[..count * RECORD_SIZE];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start((start * RECORD_SIZE) as u64))?;
+        file.read_exact(buf)?;
+
+        let found = buf
+            .chunks_exact(RECORD_SIZE)
+            .map(|r| {
+                let client_id = ClientId::from_le_bytes(r[0..2].try_into().unwrap());
+                let tx_id = TransactionId::from_le_bytes(r[2..6].try_into().unwrap());
+                let amount = u64::from_le_bytes(r[6..14].try_into().unwrap());
+                ((client_id, tx_id), amount)
+            })
+            .find(|(k, _)| *k == key)
+            .map(|(_, amount)| Amount::from_raw(amount));
+        Ok(found)
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// On-disk storage of undisputed deposits evicted from memory.
+///
+/// Deposits are written in batches as immutable sorted runs. Runs are searched newest first, so if
+/// a deposit was restored into memory and spilled again, the latest copy wins.
+pub(crate) struct SpillStore {
+    dir: PathBuf,
+    runs: Vec<Run>,
+    // Deposits kept in memory before spilling.
+    window: usize,
+    since_spill: usize,
+    // Deposits since the last successful spill, more than `since_spill` if spilling failed.
+    unspilled: usize,
+}
+
+impl SpillStore {
+    pub(crate) fn new(dir: &Path, window: usize) -> Self {
+        SpillStore {
+            dir: dir.to_owned(),
+            runs: Vec::new(),
+            window: window.max(1),
+            since_spill: 0,
+            unspilled: 0,
+        }
+    }
+
+    pub(crate) fn note_deposit(&mut self) {
+        self.since_spill += 1;
+        self.unspilled += 1;
+    }
+
+    pub(crate) fn window(&self) -> usize {
+        self.window
+    }
+
+    pub(crate) fn unspilled(&self) -> usize {
+        self.unspilled
+    }
+
+    pub(crate) fn should_spill(&self) -> bool {
+        self.since_spill >= self.window
+    }
+
+    pub(crate) fn spill(&mut self, records: &mut [(Key, Amount)]) -> std::io::Result<()> {
+        self.since_spill = 0;
+        if records.is_empty() {
+            return Ok(());
+        }
+        records.sort_unstable_by_key(|(k, _)| *k);
+        let path = self.dir.join(format!(
+            "payengine-spill-{}-{}.run",
+            std::process::id(),
+            NEXT_RUN_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        self.runs.push(Run::write(path, records)?);
+        self.unspilled = 0;
+        Ok(())
+    }
+
+    pub(crate) fn get(
+        &self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+    ) -> std::io::Result<Option<Amount>> {
+        for run in self.runs.iter().rev() {
+            if let Some(amount) = run.get((client_id, tx_id))? {
+                return Ok(Some(amount));
+            }
+        }
+        Ok(None)
+    }
+
+    pub(crate) fn spilled_runs(&self) -> usize {
+        self.runs.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::{ClientsDatabase, Transaction, TransactionKind::*},
+        amount::Amount,
+        breaker::{BreakerConfig, BreakerState},
+        error::LedgerError,
+    };
+
+    fn amount(v: &str) -> Amount {
+        Amount::parse(v.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_spilled_deposits_can_be_disputed() {
+        let dir = std::env::temp_dir();
+        let mut db = ClientsDatabase::with_spill(&dir, 100);
+        for id in 0..1000 {
+            db.process_transaction(
+                (id % 7) as u16,
+                Transaction {
+                    kind: Deposit,
+                    id,
+                    amount: amount("1.5"),
+                },
+            )
+            .unwrap();
+        }
+        assert_eq!(db.spilled_runs(), 9);
+        assert!(db.stats().deposits <= 100);
+
+        for id in [0, 500, 999] {
+            db.process_transaction(
+                (id % 7) as u16,
+                Transaction {
+                    kind: Dispute,
+                    id,
+                    amount: Amount::zero(),
+                },
+            )
+            .unwrap();
+        }
+        assert_eq!(db.stats().held, amount("4.5"));
+
+        // Wrong client.
+        assert!(matches!(
+            db.process_transaction(
+                1,
+                Transaction {
+                    kind: Dispute,
+                    id: 0,
+                    amount: Amount::zero(),
+                },
+            )
+            .unwrap_err(),
+            LedgerError::TransactionNotFound
+        ));
+
+        // Resolve, spill again, and dispute once more.
+        db.process_transaction(
+            0,
+            Transaction {
+                kind: Resolve,
+                id: 0,
+                amount: Amount::zero(),
+            },
+        )
+        .unwrap();
+        for id in 1000..1100 {
+            db.process_transaction(
+                1,
+                Transaction {
+                    kind: Deposit,
+                    id,
+                    amount: amount("1"),
+                },
+            )
+            .unwrap();
+        }
+        db.process_transaction(
+            0,
+            Transaction {
+                kind: Dispute,
+                id: 0,
+                amount: Amount::zero(),
+            },
+        )
+        .unwrap();
+        db.process_transaction(
+            0,
+            Transaction {
+                kind: Chargeback,
+                id: 0,
+                amount: Amount::zero(),
+            },
+        )
+        .unwrap();
+        assert_eq!(db.stats().frozen_accounts, 1);
+    }
+
+    #[test]
+    fn test_spill_breaker() {
+        let dir = std::env::temp_dir().join(format!("payengine-breaker-{}", std::process::id()));
+        let mut db = ClientsDatabase::with_spill(&dir, 10);
+        db.enable_breaker(BreakerConfig {
+            failures: 1,
+            cooldown_ms: 1,
+            max_buffered: 20,
+            max_pause_ms: 200,
+        });
+        let t = |kind, id| Transaction {
+            kind,
+            id,
+            amount: amount("1"),
+        };
+
+        // The directory is missing, deposits stay in memory up to the limit.
+        for id in 0..30 {
+            db.process_transaction(1, t(Deposit, id)).unwrap();
+        }
+        assert_eq!(db.spilled_runs(), 0);
+        assert_ne!(db.breaker().unwrap().state(), BreakerState::Closed);
+        // Then processing pauses, and gives up. Later transactions don't pause again.
+        let start = std::time::Instant::now();
+        assert!(matches!(
+            db.process_transaction(1, t(Deposit, 30)),
+            Err(LedgerError::SpillIo(_))
+        ));
+        assert!(start.elapsed().as_millis() >= 200);
+        let start = std::time::Instant::now();
+        assert!(matches!(
+            db.process_transaction(1, t(Deposit, 30)),
+            Err(LedgerError::SpillIo(_))
+        ));
+        assert!(start.elapsed().as_millis() < 100);
+        assert_eq!(db.stats().deposits, 30);
+
+        // Recovered, tried again after the cooldown.
+        std::fs::create_dir(&dir).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        db.process_transaction(1, t(Deposit, 30)).unwrap();
+        assert_eq!(db.spilled_runs(), 1);
+        assert_eq!(db.stats().deposits, 1);
+        assert_eq!(db.breaker().unwrap().state(), BreakerState::Closed);
+        db.process_transaction(1, t(Dispute, 0)).unwrap();
+        assert_eq!(db.stats().held, amount("1"));
+
+        drop(db);
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+use std::io::{self, BufRead, BufWriter, Write};
+
+use crate::parser::client_of;
+
+/// Rows written by [`split`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SplitStats {
+    /// Rows after the header, per output.
+    pub rows: Vec<usize>,
+    /// Rows whose client id couldn't be read, all in the first output.
+    pub unparsed: usize,
+}
+
+/// Partition the rows of a CSV input by client id across `outputs`, each starting with the
+/// header, unchanged and in order.
+///
+/// A client's rows all go to output `client % outputs.len()`, the same partition as
+/// [`crate::sharded::ShardedDatabase`], so each output can be processed on its own and the
+/// reports concatenated. Rows without a readable client id go to the first output, where
+/// processing reports them as malformed.
+pub fn split<W: Write>(mut input: impl BufRead, outputs: Vec<W>) -> io::Result<SplitStats> {
+    assert!(!outputs.is_empty(), "no outputs to split into");
+    let mut outputs = outputs.into_iter().map(BufWriter::new).collect::<Vec<_>>();
+    let mut stats = SplitStats {
+        rows: vec![0; outputs.len()],
+        unparsed: 0,
+    };
+    let mut line = Vec::with_capacity(64);
+    let mut header = true;
+    loop {
+        line.clear();
+        if input.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        if !line.ends_with(b"\n") {
+            line.push(b'\n');
+        }
+        if header {
+            header = false;
+            for w in &mut outputs {
+                w.write_all(&line)?;
+            }
+            continue;
+        }
+        let idx = match client_of(&line) {
+            Some(client_id) => client_id as usize % outputs.len(),
+            None => {
+                stats.unparsed += 1;
+                0
+            }
+        };
+        stats.rows[idx] += 1;
+        outputs[idx].write_all(&line)?;
+    }
+    for w in &mut outputs {
+        w.flush()?;
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::split::{SplitStats, split};
+
+    #[test]
+    fn test_split() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 1.0\n\
+                     deposit, 2, 2, 5\n\
+                     bogus, x, 3, 1\n\
+                     dispute, 4, 1,\n\
+                     withdrawal, 1, 4, 0.5";
+        let mut outputs = vec![Vec::new(); 3];
+        let stats = split(input.as_bytes(), outputs.iter_mut().collect()).unwrap();
+        assert_eq!(
+            outputs
+                .into_iter()
+                .map(|o| String::from_utf8(o).unwrap())
+                .collect::<Vec<_>>(),
+            [
+                "type, client, tx, amount\nbogus, x, 3, 1\n",
+                "type, client, tx, amount\n\
+                 deposit, 1, 1, 1.0\n\
+                 dispute, 4, 1,\n\
+                 withdrawal, 1, 4, 0.5\n",
+                "type, client, tx, amount\ndeposit, 2, 2, 5\n",
+            ]
+        );
+        assert_eq!(
+            stats,
+            SplitStats {
+                rows: vec![1, 3, 1],
+                unparsed: 1
+            }
+        );
+    }
+}
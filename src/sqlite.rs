@@ -0,0 +1,362 @@
+use std::{collections::HashMap, io, path::Path, sync::mpsc::Receiver, time::Duration};
+
+use rusqlite::{Connection, params};
+
+use crate::{
+    accounts::{AccountView, ClientId, TransactionId, TransactionKind},
+    amount::Amount,
+    events::AccountEvent,
+    sharded::ShardedDatabase,
+};
+
+/// Events written in one SQLite transaction at most, by [`SqliteStore::write_events`].
+pub const BATCH_LEN: usize = 1024;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS accounts (
+    client INTEGER PRIMARY KEY,
+    available TEXT NOT NULL,
+    held TEXT NOT NULL,
+    total TEXT NOT NULL,
+    locked INTEGER NOT NULL,
+    flagged INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS deposits (
+    client INTEGER NOT NULL,
+    tx INTEGER NOT NULL,
+    amount TEXT NOT NULL,
+    disputed INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (client, tx)
+) WITHOUT ROWID;
+CREATE TABLE IF NOT EXISTS journal (
+    seq INTEGER PRIMARY KEY,
+    at INTEGER NOT NULL,
+    client INTEGER NOT NULL,
+    tx INTEGER NOT NULL,
+    type TEXT NOT NULL,
+    amount TEXT,
+    available TEXT NOT NULL,
+    held TEXT NOT NULL,
+    total TEXT NOT NULL,
+    locked INTEGER NOT NULL
+);
+";
+
+/// State of a database kept in a single SQLite file, to be restored from on startup, for
+/// deployments without a database server.
+///
+/// Tables are created if missing: `accounts` has the balances of each client and whether it's
+/// flagged for review, `deposits` every deposit with whether it's under dispute (1), or charged
+/// back (2), and `journal` a row per applied transaction, with the balances after it, in the order
+/// applied for each client. Amounts are decimal text, as SQLite has no exact decimal type. The
+/// file is in WAL mode, and each batch of transactions is written in a transaction of its own, so
+/// a crash loses at most the batches not committed yet, and never leaves the tables out of step
+/// with each other.
+///
+/// Only applied transactions are written. Freezing or unfreezing an account with an admin command
+/// isn't a transaction, so it's lost on restart, and accounts come back locked only by their
+/// chargebacks. Snapshots keep freezes.
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    /// Open the file, or create it, with its tables.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let conn = Connection::open(path).map_err(io::Error::other)?;
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))
+            .map_err(io::Error::other)?;
+        conn.execute_batch(SCHEMA).map_err(io::Error::other)?;
+        Ok(SqliteStore { conn })
+    }
+
+    /// Move the transactions of the write-ahead log into the database file, and truncate the log,
+    /// which otherwise only shrinks when the last connection closes. Fails if other connections
+    /// are reading or writing meanwhile.
+    pub fn checkpoint(&self) -> io::Result<()> {
+        let busy: bool = self
+            .conn
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| row.get(0))
+            .map_err(io::Error::other)?;
+        if busy {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "the database is in use, try again",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check that the file takes writes, by starting a write transaction and rolling it back,
+    /// waiting up to `timeout` for other writers.
+    pub fn check_writable(&self, timeout: Duration) -> io::Result<()> {
+        self.conn.busy_timeout(timeout).map_err(io::Error::other)?;
+        self.conn
+            .execute_batch("BEGIN IMMEDIATE; ROLLBACK;")
+            .map_err(io::Error::other)
+    }
+
+    /// Balances of all accounts by client id.
+    pub fn accounts(&self) -> io::Result<Vec<(ClientId, AccountView<'static>)>> {
+        let mut statement = self
+            .conn
+            .prepare(
+                "SELECT client, available, held, total, locked, flagged FROM accounts \
+                 ORDER BY client",
+            )
+            .map_err(io::Error::other)?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, ClientId>(0)?,
+                    [row.get::<_, String>(1)?, row.get(2)?, row.get(3)?],
+                    [row.get::<_, bool>(4)?, row.get(5)?],
+                ))
+            })
+            .map_err(io::Error::other)?;
+        let mut accounts = Vec::new();
+        for row in rows {
+            let (client_id, [available, held, total], [locked, flagged]) =
+                row.map_err(io::Error::other)?;
+            accounts.push((
+                client_id,
+                AccountView {
+                    available: amount(&available)?,
+                    held: amount(&held)?,
+                    total: amount(&total)?,
+                    locked,
+                    flagged,
+                    audit: &[],
+                },
+            ));
+        }
+        Ok(accounts)
+    }
+
+    /// Add the stored accounts to `db`, which shouldn't have them yet, with all their deposits,
+    /// so that any of those can still be disputed, and disputes resolved or charged back. Returns
+    /// the number of accounts.
+    pub fn restore(&self, db: &mut ShardedDatabase) -> io::Result<usize> {
+        // Disputed, undisputed and charged back deposits by client.
+        let mut deposits = HashMap::<ClientId, [Vec<(TransactionId, Amount)>; 3]>::new();
+        let mut statement = self
+            .conn
+            .prepare("SELECT client, tx, amount, disputed FROM deposits ORDER BY client, tx")
+            .map_err(io::Error::other)?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, ClientId>(0)?,
+                    row.get::<_, TransactionId>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, i64>(3)?,
+                ))
+            })
+            .map_err(io::Error::other)?;
+        for row in rows {
+            let (client_id, tx, amount_text, state) = row.map_err(io::Error::other)?;
+            let state = match state {
+                1 => 0,
+                0 => 1,
+                _ => 2,
+            };
+            deposits.entry(client_id).or_default()[state].push((tx, amount(&amount_text)?));
+        }
+        let accounts = self.accounts()?;
+        for &(client_id, view) in &accounts {
+            let [disputed, undisputed, charged_back] =
+                deposits.remove(&client_id).unwrap_or_default();
+            let shard = db.shard_for(client_id);
+            let shard = &mut db.shards_mut()[shard];
+            let ids = charged_back.iter().map(|(tx, _)| *tx).collect::<Vec<_>>();
+            shard
+                .restore_account(client_id, view, &disputed)
+                .and_then(|()| shard.restore_deposits(client_id, &undisputed))
+                .and_then(|()| shard.restore_deposits(client_id, &charged_back))
+                .and_then(|()| shard.restore_charged_back(client_id, &ids))
+                .map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("client {client_id}: {e}"),
+                    )
+                })?;
+        }
+        Ok(accounts.len())
+    }
+
+    /// Write events of applied transactions, e.g. received from
+    /// [`crate::concurrent::ConcurrentClientsDatabase::subscribe`], until all senders are gone.
+    /// Those received meanwhile are written together, up to [`BATCH_LEN`] at once. Returns the
+    /// number written.
+    pub fn write_events(&mut self, events: Receiver<AccountEvent>) -> io::Result<u64> {
+        let mut written = 0;
+        while let Ok(first) = events.recv() {
+            let batch = std::iter::once(first)
+                .chain(events.try_iter().take(BATCH_LEN - 1))
+                .collect::<Vec<_>>();
+            self.write(&batch)?;
+            written += batch.len() as u64;
+        }
+        Ok(written)
+    }
+
+    /// Write events of applied transactions in one transaction: a journal row each, and the
+    /// accounts and deposits they changed.
+    pub fn write(&mut self, events: &[AccountEvent]) -> io::Result<()> {
+        let transaction = self.conn.transaction().map_err(io::Error::other)?;
+        {
+            let prepare = |sql| transaction.prepare_cached(sql).map_err(io::Error::other);
+            let mut journal = prepare(
+                "INSERT INTO journal (at, client, tx, type, amount, available, held, total, locked) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )?;
+            let mut account = prepare(
+                "INSERT INTO accounts (client, available, held, total, locked, flagged) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6) \
+                 ON CONFLICT (client) DO UPDATE SET available = excluded.available, \
+                 held = excluded.held, total = excluded.total, locked = excluded.locked, \
+                 flagged = excluded.flagged",
+            )?;
+            let mut deposit =
+                prepare("INSERT INTO deposits (client, tx, amount) VALUES (?1, ?2, ?3)")?;
+            let mut disputed =
+                prepare("UPDATE deposits SET disputed = ?3 WHERE client = ?1 AND tx = ?2")?;
+            for event in events {
+                let (client_id, t, b) = (event.client_id, event.transaction, event.balances);
+                let [available, held, total] =
+                    [b.available, b.held, b.total].map(|a| a.to_string());
+                journal
+                    .execute(params![
+                        event.at as i64,
+                        client_id,
+                        t.id,
+                        t.kind.as_str(),
+                        t.kind.has_amount().then(|| t.amount.to_string()),
+                        available,
+                        held,
+                        total,
+                        b.locked,
+                    ])
+                    .map_err(io::Error::other)?;
+                account
+                    .execute(params![
+                        client_id, available, held, total, b.locked, b.flagged
+                    ])
+                    .map_err(io::Error::other)?;
+                match t.kind {
+                    TransactionKind::Deposit => {
+                        deposit.execute(params![client_id, t.id, t.amount.to_string()])
+                    }
+                    TransactionKind::Dispute => disputed.execute(params![client_id, t.id, 1]),
+                    TransactionKind::Resolve => disputed.execute(params![client_id, t.id, 0]),
+                    TransactionKind::Chargeback => disputed.execute(params![client_id, t.id, 2]),
+                    TransactionKind::Withdrawal => Ok(0),
+                }
+                .map_err(io::Error::other)?;
+            }
+        }
+        transaction.commit().map_err(io::Error::other)
+    }
+}
+
+fn amount(s: &str) -> io::Result<Amount> {
+    s.parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid amount in the database: {s}"),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::{Transaction, TransactionKind},
+        amount::Amount,
+        error::LedgerError,
+        review::ReviewRules,
+        sharded::ShardedDatabase,
+        sqlite::SqliteStore,
+    };
+
+    fn t(kind: TransactionKind, id: u32, amount: &str) -> Transaction {
+        Transaction {
+            kind,
+            id,
+            amount: amount.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_restore() {
+        let path = std::env::temp_dir().join(format!("payengine-sqlite-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut store = SqliteStore::open(&path).unwrap();
+        let mut db = ShardedDatabase::new(2);
+        // Flagging client 2 for its chargeback.
+        db.set_review(&ReviewRules {
+            chargeback_percent: Some("20".parse().unwrap()),
+            min_deposits: 1,
+        });
+        let events = db.subscribe();
+        for (client_id, t) in [
+            (1, t(TransactionKind::Deposit, 1, "5")),
+            (1, t(TransactionKind::Deposit, 2, "3")),
+            (1, t(TransactionKind::Dispute, 2, "0")),
+            (1, t(TransactionKind::Withdrawal, 3, "1.5")),
+            (2, t(TransactionKind::Deposit, 4, "1")),
+            (2, t(TransactionKind::Dispute, 4, "0")),
+            (2, t(TransactionKind::Chargeback, 4, "0")),
+        ] {
+            db.process_transaction(client_id, t).unwrap();
+        }
+        db.unsubscribe_all();
+        assert_eq!(store.write_events(events).unwrap(), 7);
+        drop(store);
+
+        // Reopened as after a restart.
+        let store = SqliteStore::open(&path).unwrap();
+        store
+            .check_writable(std::time::Duration::from_millis(100))
+            .unwrap();
+        let mut restored = ShardedDatabase::new(2);
+        assert_eq!(store.restore(&mut restored).unwrap(), 2);
+        for client_id in [1, 2] {
+            assert_eq!(
+                restored.get(client_id).unwrap().view(),
+                db.get(client_id).unwrap().view()
+            );
+        }
+        assert!(restored.get(2).unwrap().view().flagged);
+        // Both the open dispute and the undisputed deposit are known.
+        restored
+            .process_transaction(1, t(TransactionKind::Resolve, 2, "0"))
+            .unwrap();
+        restored
+            .process_transaction(1, t(TransactionKind::Dispute, 1, "0"))
+            .unwrap();
+        assert!(matches!(
+            restored.process_transaction(1, t(TransactionKind::Deposit, 1, "1")),
+            Err(LedgerError::DuplicateTransactionId)
+        ));
+        assert_eq!(
+            restored.get(1).unwrap().view().held,
+            Amount::parse(b"5").unwrap()
+        );
+        // The charged back deposit stays settled once the account is unfrozen.
+        let shard = restored.shard_for(2);
+        restored.shards_mut()[shard].set_frozen(2, false).unwrap();
+        assert!(matches!(
+            restored.process_transaction(2, t(TransactionKind::Dispute, 4, "0")),
+            Err(LedgerError::DuplicateDispute)
+        ));
+
+        let journal: i64 = store
+            .conn
+            .query_row("SELECT count(*) FROM journal", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(journal, 7);
+        drop(store);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
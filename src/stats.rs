@@ -0,0 +1,108 @@
+use crate::{accounts::Account, amount::Amount, deposits::DepositStore};
+
+/// Aggregate counters over all accounts in a database.
+///
+/// Amount sums saturate at the max representable [`Amount`] rather than failing, as they're only
+/// informational.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DatabaseStats {
+    pub accounts: usize,
+    /// Deposits retained for potential disputes.
+    pub deposits: usize,
+    pub open_disputes: usize,
+    pub frozen_accounts: usize,
+    /// Accounts flagged for review, see [`crate::review::ReviewRules`].
+    pub flagged_accounts: usize,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+}
+
+impl DatabaseStats {
+    pub(crate) fn collect<'a, D: DepositStore + 'a>(
+        accounts: impl Iterator<Item = &'a Account<D>>,
+    ) -> Self {
+        let mut stats = DatabaseStats::default();
+        for account in accounts {
+            stats.add_account(account);
+        }
+        stats
+    }
+
+    /// Add the counters of another part of the database, e.g. a shard.
+    pub(crate) fn merge(&mut self, other: &DatabaseStats) {
+        self.accounts += other.accounts;
+        self.deposits += other.deposits;
+        self.open_disputes += other.open_disputes;
+        self.frozen_accounts += other.frozen_accounts;
+        self.flagged_accounts += other.flagged_accounts;
+        self.available = self.available.saturating_add(other.available);
+        self.held = self.held.saturating_add(other.held);
+        self.total = self.total.saturating_add(other.total);
+    }
+
+    fn add_account<D: DepositStore>(&mut self, account: &Account<D>) {
+        self.accounts += 1;
+        self.deposits += account.deposit_count();
+        self.open_disputes += account.open_disputes();
+        self.frozen_accounts += account.is_frozen() as usize;
+        self.flagged_accounts += account.is_flagged() as usize;
+        self.available = self
+            .available
+            .saturating_add(account.available_for_withdrawal());
+        self.held = self.held.saturating_add(account.held());
+        self.total = self.total.saturating_add(account.total());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::{ClientsDatabase, Transaction, TransactionKind::*},
+        amount::Amount,
+        stats::DatabaseStats,
+    };
+
+    fn amount(v: &str) -> Amount {
+        Amount::parse(v.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut db = ClientsDatabase::new();
+        for (client, kind, id, a) in [
+            (1, Deposit, 1, "10"),
+            (1, Deposit, 2, "5"),
+            (2, Deposit, 3, "3"),
+            (3, Deposit, 4, "1"),
+            (1, Dispute, 2, "0"),
+            (2, Dispute, 3, "0"),
+            (2, Chargeback, 3, "0"),
+        ] {
+            db.process_transaction(
+                client,
+                Transaction {
+                    kind,
+                    id,
+                    amount: amount(a),
+                },
+            )
+            .unwrap();
+        }
+
+        assert_eq!(
+            db.stats(),
+            DatabaseStats {
+                accounts: 3,
+                deposits: 4,
+                open_disputes: 1,
+                frozen_accounts: 1,
+                flagged_accounts: 0,
+                available: amount("11"),
+                held: amount("5"),
+                total: amount("16"),
+            }
+        );
+    }
+}
@@ -0,0 +1,265 @@
+use std::io::Write;
+
+use crate::{
+    accounts::{ClientId, Transaction, TransactionId, TransactionKind},
+    amount::Amount,
+};
+
+/// Order in which transaction ids appear in the generated stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdOrder {
+    /// Increasing, as in a single well-behaved producer.
+    Sequential,
+    /// Increasing, except that ids within consecutive blocks of this many are shuffled, as when
+    /// merging several producers.
+    Jittered(u32),
+    /// A random permutation.
+    Shuffled,
+}
+
+/// Parameters of a reproducible synthetic transaction stream.
+///
+/// Deposits and withdrawals get unique ids. Disputes refer to a recent deposit of the same client,
+/// and are later followed by a resolve or a chargeback of the same client. The same parameters
+/// always generate the same stream.
+#[derive(Clone, Debug)]
+pub struct Workload {
+    pub transactions: usize,
+    /// Clients are drawn uniformly from `0..clients`.
+    pub clients: u32,
+    /// Longest run of consecutive transactions of the same client. Each drawn client gets a run
+    /// of 1 to this many, as in feeds where a busy client's transactions arrive together.
+    pub run_length: u32,
+    /// Share of transactions which are withdrawals.
+    pub withdrawal_rate: f64,
+    /// Share of transactions which are disputes.
+    pub dispute_rate: f64,
+    /// Share of settled disputes which end in a chargeback rather than a resolve.
+    pub chargeback_rate: f64,
+    pub id_order: IdOrder,
+    pub seed: u64,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Workload {
+            transactions: 1_000_000,
+            clients: 65536,
+            run_length: 1,
+            withdrawal_rate: 0.2,
+            dispute_rate: 0.01,
+            chargeback_rate: 0.1,
+            id_order: IdOrder::Sequential,
+            seed: 0x2545_F491_4F6C_DD1D,
+        }
+    }
+}
+
+/// xorshift64, enough for benchmark inputs.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // xorshift gets stuck at zero.
+        Rng(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+}
+
+// Per-client state.
+#[derive(Clone, Copy, Default)]
+struct ClientState {
+    last_deposit: Option<TransactionId>,
+    open_dispute: Option<TransactionId>,
+}
+
+impl Workload {
+    /// Generate the stream in memory.
+    pub fn generate(&self) -> Vec<(ClientId, Transaction)> {
+        let clients = self.clients.clamp(1, ClientId::MAX as u32 + 1);
+        let mut rng = Rng::new(self.seed);
+        let mut states = vec![ClientState::default(); clients as usize];
+        let mut transactions = Vec::with_capacity(self.transactions);
+        // Ids are first assigned sequentially, then remapped to the requested order.
+        let mut next_id: TransactionId = 0;
+        let (mut client_id, mut run) = (0, 0);
+        while transactions.len() < self.transactions {
+            if run == 0 {
+                client_id = rng.below(clients as u64) as ClientId;
+                // Only drawn with runs, so streams without them stay the same for a seed.
+                run = match self.run_length {
+                    0 | 1 => 1,
+                    len => 1 + rng.below(len as u64),
+                };
+            }
+            run -= 1;
+            let state = &mut states[client_id as usize];
+            let t = if let Some(id) = state.open_dispute
+                && rng.chance(0.5)
+            {
+                state.open_dispute = None;
+                let kind = if rng.chance(self.chargeback_rate) {
+                    TransactionKind::Chargeback
+                } else {
+                    TransactionKind::Resolve
+                };
+                Transaction {
+                    kind,
+                    id,
+                    amount: Amount::zero(),
+                }
+            } else if let Some(id) = state.last_deposit
+                && state.open_dispute.is_none()
+                && rng.chance(self.dispute_rate)
+            {
+                state.open_dispute = Some(id);
+                state.last_deposit = None;
+                Transaction {
+                    kind: TransactionKind::Dispute,
+                    id,
+                    amount: Amount::zero(),
+                }
+            } else {
+                let kind = if rng.chance(self.withdrawal_rate) {
+                    TransactionKind::Withdrawal
+                } else {
+                    state.last_deposit = Some(next_id);
+                    TransactionKind::Deposit
+                };
+                // Up to 1000.0000, with varying digit counts.
+                let digits = 1 + rng.below(7) as u32;
+                let amount = Amount::from_raw(rng.below(10u64.pow(digits)));
+                next_id += 1;
+                Transaction {
+                    kind,
+                    id: next_id - 1,
+                    amount,
+                }
+            };
+            transactions.push((client_id, t));
+        }
+
+        if let Some(ids) = self.id_permutation(next_id, &mut rng) {
+            for (_, t) in &mut transactions {
+                t.id = ids[t.id as usize];
+            }
+        }
+        transactions
+    }
+
+    fn id_permutation(&self, len: TransactionId, rng: &mut Rng) -> Option<Vec<TransactionId>> {
+        let block = match self.id_order {
+            IdOrder::Sequential => return None,
+            IdOrder::Jittered(block) => block.max(1) as usize,
+            IdOrder::Shuffled => len as usize,
+        };
+        let mut ids = (0..len).collect::<Vec<_>>();
+        for chunk in ids.chunks_mut(block) {
+            // Fisher-Yates.
+            for i in (1..chunk.len()).rev() {
+                chunk.swap(i, rng.below(i as u64 + 1) as usize);
+            }
+        }
+        Some(ids)
+    }
+
+    /// Generate the stream as CSV input, including the header.
+    pub fn write_csv(&self, mut w: impl Write) -> std::io::Result<()> {
+        let mut w = std::io::BufWriter::new(&mut w);
+        writeln!(w, "type, client, tx, amount")?;
+        for (client_id, t) in self.generate() {
+            let kind = t.kind.as_str();
+            if t.kind.has_amount() {
+                writeln!(w, "{kind}, {client_id}, {}, {}", t.id, t.amount)?;
+            } else {
+                writeln!(w, "{kind}, {client_id}, {}, ", t.id)?;
+            }
+        }
+        w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::TransactionKind,
+        parser::Row,
+        synth::{IdOrder, Workload},
+    };
+
+    #[test]
+    fn test_workload() {
+        let workload = Workload {
+            transactions: 100_000,
+            clients: 100,
+            id_order: IdOrder::Jittered(64),
+            ..Default::default()
+        };
+        let transactions = workload.generate();
+        assert_eq!(transactions.len(), 100_000);
+        assert_eq!(transactions, workload.generate());
+
+        let count = |kind| transactions.iter().filter(|(_, t)| t.kind == kind).count();
+        let disputes = count(TransactionKind::Dispute);
+        assert!((500..1500).contains(&disputes), "{disputes}");
+        assert!(count(TransactionKind::Resolve) + count(TransactionKind::Chargeback) <= disputes);
+        assert!(count(TransactionKind::Chargeback) > 0);
+
+        // Ids are unique, and only moved within their block.
+        let mut ids = transactions
+            .iter()
+            .filter(|(_, t)| t.kind.has_amount())
+            .map(|(_, t)| t.id)
+            .collect::<Vec<_>>();
+        assert!(ids.iter().enumerate().any(|(i, id)| *id != i as u32));
+        assert!(
+            ids.iter()
+                .enumerate()
+                .all(|(i, id)| *id / 64 == i as u32 / 64)
+        );
+        ids.sort_unstable();
+        assert!(ids.iter().enumerate().all(|(i, id)| *id == i as u32));
+
+        let mut csv = Vec::new();
+        workload.write_csv(&mut csv).unwrap();
+        let rows = csv
+            .split(|b| *b == b'\n')
+            .skip(1)
+            .filter(|l| !l.is_empty())
+            .map(|l| Row::parse(l).unwrap())
+            .map(|r| (r.client_id, r.transaction))
+            .collect::<Vec<_>>();
+        assert_eq!(rows, transactions);
+    }
+
+    #[test]
+    fn test_workload_runs() {
+        let workload = Workload {
+            transactions: 100_000,
+            run_length: 16,
+            ..Default::default()
+        };
+        let transactions = workload.generate();
+        let runs = transactions
+            .chunk_by(|(a, _), (b, _)| a == b)
+            .map(|run| run.len())
+            .collect::<Vec<_>>();
+        assert!(runs.iter().all(|len| *len <= 32), "{runs:?}");
+        let mean = transactions.len() as f64 / runs.len() as f64;
+        assert!((7.0..10.0).contains(&mean), "{mean}");
+    }
+}
@@ -0,0 +1,170 @@
+use std::sync::Arc;
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
+    net::{TcpListener, TcpStream},
+    task::JoinSet,
+};
+use tracing::{debug, warn};
+
+use crate::{
+    parser::Row,
+    server::{Shared, apply},
+};
+
+// Longer lines than any row close the connection, rather than being buffered without a bound.
+const MAX_LINE: u64 = 1024;
+
+/// Transactions taken over TCP for systems which can open a socket but not speak HTTP, see
+/// [`crate::server::serve`]. Each line is a row of the CSV input, e.g. `deposit, 7, 9, 2.5`,
+/// applied like `POST /transactions` within the same rate limits. Blank lines and header lines
+/// are skipped.
+///
+/// With `replies`, every row is answered in order with a line `OK` if applied, or `ERR` and the
+/// reason code if malformed or rejected, e.g. `ERR E_INSUFFICIENT_FUNDS`. Otherwise nothing is
+/// written back, and rejections are only counted.
+pub struct LineListener {
+    pub listener: TcpListener,
+    pub replies: bool,
+}
+
+impl LineListener {
+    /// Accept connections until dropped, which closes those still open.
+    pub(crate) async fn run(self, shared: Arc<Shared>) {
+        let mut connections = JoinSet::new();
+        loop {
+            let (stream, addr) = match self.listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!(error = %e, "error accepting a line connection");
+                    continue;
+                }
+            };
+            debug!(%addr, "line connection opened");
+            let shared = shared.clone();
+            let replies = self.replies;
+            connections.spawn(async move {
+                match read_rows(stream, &shared, replies).await {
+                    Ok(rows) => debug!(%addr, rows, "line connection closed"),
+                    Err(e) => debug!(%addr, error = %e, "line connection failed"),
+                }
+            });
+            // Reap finished connections.
+            while connections.try_join_next().is_some() {}
+        }
+    }
+}
+
+/// Apply the rows of a connection until it's closed, returning how many there were.
+async fn read_rows(stream: TcpStream, shared: &Shared, replies: bool) -> std::io::Result<usize> {
+    let (reader, writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
+    let mut line = Vec::new();
+    let mut rows = 0;
+    loop {
+        line.clear();
+        if (&mut reader)
+            .take(MAX_LINE)
+            .read_until(b'\n', &mut line)
+            .await?
+            == 0
+        {
+            return Ok(rows);
+        }
+        if !line.ends_with(b"\n") && line.len() as u64 == MAX_LINE {
+            return Err(std::io::Error::other("line too long"));
+        }
+        let row = line.trim_ascii();
+        if row.is_empty() || row.starts_with(b"type") {
+            continue;
+        }
+        rows += 1;
+        let result = Row::parse(row)
+            .map_err(crate::Error::from)
+            .and_then(|row| apply(shared, row).0.map_err(Into::into));
+        if replies {
+            match result {
+                Ok(_) => writer.write_all(b"OK\n").await?,
+                Err(e) => {
+                    writer
+                        .write_all(format!("ERR {}\n", e.code()).as_bytes())
+                        .await?
+                }
+            }
+            // Replies are sent once the rows received so far are applied, not one at a time.
+            if reader.buffer().is_empty() {
+                writer.flush().await?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::{
+        alerts::AlertMonitor,
+        amount::Amount,
+        concurrent::ConcurrentClientsDatabase,
+        ratelimit::RateLimiter,
+        server::{Readiness, serve},
+        tcp::LineListener,
+    };
+
+    #[tokio::test]
+    async fn test_line_listener() {
+        let http = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let lines = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = lines.local_addr().unwrap();
+        let db = Arc::new(ConcurrentClientsDatabase::new(2));
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve(
+            http,
+            db.clone(),
+            Readiness::default(),
+            AlertMonitor::default(),
+            RateLimiter::default(),
+            Some(LineListener {
+                listener: lines,
+                replies: true,
+            }),
+            async {
+                stopped.await.ok();
+            },
+        ));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                b"type, client, tx, amount\r\ndeposit, 7, 1, 2.5\r\n\r\nwithdrawal, 7, 2, 5\n\
+                  refund, 7, 3, 1\ndeposit, 7, 4, 1",
+            )
+            .await
+            .unwrap();
+        stream.shutdown().await.unwrap();
+        let mut replies = String::new();
+        stream.read_to_string(&mut replies).await.unwrap();
+        assert_eq!(
+            replies,
+            "OK\nERR E_INSUFFICIENT_FUNDS\nERR E_UNKNOWN_TYPE\nOK\n"
+        );
+        assert_eq!(db.get(7).unwrap().total, Amount::parse(b"3.5").unwrap());
+
+        // Longer lines than any row close the connection.
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(&[b'x'; 2000]).await.unwrap();
+        let mut replies = Vec::new();
+        let _ = stream.read_to_end(&mut replies).await;
+        assert!(replies.is_empty());
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
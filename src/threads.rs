@@ -0,0 +1,98 @@
+use std::{num::NonZeroUsize, time::Duration};
+
+/// Thread layout of the parallel pipeline: one parser thread and one applier thread per shard.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct ThreadConfig {
+    /// Applier threads, and so shards. Detected from the available cores if unset.
+    pub threads: Option<NonZeroUsize>,
+    /// Pin the parser to the first core and appliers to the following ones, wrapping around.
+    ///
+    /// Keeps each shard's accounts in one core's cache. Only worth it when the machine isn't
+    /// shared with other busy processes. Needs the "cli" feature.
+    pub pin_cores: bool,
+}
+
+impl ThreadConfig {
+    /// Configured applier thread count, or the number of available cores.
+    pub fn threads(&self) -> usize {
+        self.threads.map_or_else(
+            || std::thread::available_parallelism().map_or(1, |n| n.get()),
+            |n| n.get(),
+        )
+    }
+
+    /// Core for the parser thread if pinning.
+    pub fn parser_core(&self) -> Option<usize> {
+        self.pin_cores.then_some(0)
+    }
+
+    /// Core for the applier thread of the given shard if pinning.
+    pub fn applier_core(&self, shard: usize) -> Option<usize> {
+        self.pin_cores.then_some(shard + 1)
+    }
+}
+
+/// Pin the current thread to the core with the given index, modulo the number of cores.
+///
+/// Returns false if the platform doesn't support it, or without the "cli" feature, in which case
+/// the thread stays unpinned.
+#[cfg(feature = "cli")]
+pub fn pin_current(core: usize) -> bool {
+    match core_affinity::get_core_ids() {
+        Some(ids) if !ids.is_empty() => core_affinity::set_for_current(ids[core % ids.len()]),
+        _ => false,
+    }
+}
+
+#[cfg(not(feature = "cli"))]
+pub fn pin_current(_core: usize) -> bool {
+    false
+}
+
+/// Counters of one applier thread in [`crate::sharded::ShardedDatabase::process_parallel`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WorkerStats {
+    pub transactions: usize,
+    /// Wall time from start to end of input.
+    pub elapsed: Duration,
+    /// Part of `elapsed` spent waiting for input.
+    pub idle: Duration,
+}
+
+impl WorkerStats {
+    /// Transactions per second of busy time.
+    pub fn throughput(&self) -> f64 {
+        let busy = self.elapsed.saturating_sub(self.idle).as_secs_f64();
+        if busy > 0.0 {
+            self.transactions as f64 / busy
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use crate::threads::ThreadConfig;
+
+    #[test]
+    fn test_thread_config() {
+        let config = ThreadConfig::default();
+        assert!(config.threads() >= 1);
+        assert_eq!(config.parser_core(), None);
+        assert_eq!(config.applier_core(0), None);
+
+        let config = ThreadConfig {
+            threads: NonZeroUsize::new(3),
+            pin_cores: true,
+        };
+        assert_eq!(config.threads(), 3);
+        assert_eq!(config.parser_core(), Some(0));
+        assert_eq!(config.applier_core(2), Some(3));
+    }
+}
@@ -0,0 +1,401 @@
+use std::{
+    fmt::Write,
+    hash::{BuildHasher, RandomState},
+    io,
+    sync::{
+        Arc,
+        mpsc::{Receiver, SyncSender, TrySendError, sync_channel},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, KeyInit, Mac};
+use reqwest::{StatusCode, blocking::Client, header};
+use sha2::Sha256;
+use tracing::{debug, warn};
+
+use crate::{
+    accounts::{AccountView, ClientId, TransactionKind},
+    alerts::{AlertMonitor, AlertRules},
+    config::WebhookConfig,
+    events::AccountEvent,
+};
+
+/// Callbacks waiting to be delivered to each URL, beyond which new ones are dropped with a
+/// warning.
+pub const WEBHOOK_QUEUE: usize = 10_000;
+
+/// Longest wait between attempts to deliver a callback.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Callbacks to webhook URLs on account events risk systems react to, posted as JSON:
+///
+/// - `{"event":"chargeback","at":3,"client":7,"type":"chargeback","tx":9,"available":"0",
+///   "held":"0","total":"0","locked":true}` for every chargeback, with the balances after it, like
+///   events of `GET /events` of `serve`.
+/// - `{"event":"frozen","at":3,"client":7,"available":"0","held":"0","total":"0","locked":true}`
+///   when an account is frozen, which is by a chargeback.
+/// - `{"event":"alert","alert":"account","client":7,"amount":"600","limit":"500"}` when balances
+///   cross a limit of the [`AlertRules`], see [`AlertMonitor`].
+///
+/// Each callback has an `X-Payengine-Delivery` id, the same for every attempt to deliver it so
+/// that receivers can tell repeats apart. With a secret, it's signed by an
+/// `X-Payengine-Signature: t=<unix time>,v1=<hex>` header, the HMAC-SHA256 of the time, a dot and
+/// the body, which receivers check with the same secret, refusing old times as replays.
+///
+/// Callbacks are delivered to each URL in order, by a thread of its own. One not answered with a
+/// 2xx status is retried with exponential backoff, up to the configured retries, then dropped with
+/// a warning. 4xx answers other than 408 and 429 aren't retried. Callbacks still queued when the
+/// process exits are lost.
+pub struct Webhooks {
+    dispatcher: JoinHandle<()>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+/// A callback to deliver, shared by the queues of all URLs.
+struct Callback {
+    id: String,
+    body: String,
+}
+
+impl Webhooks {
+    /// Send callbacks for `events`, e.g. from
+    /// [`crate::concurrent::ConcurrentClientsDatabase::subscribe`], until they end. Alerts are
+    /// raised from the balances of `accounts`, those loaded before, without callbacks for them.
+    pub fn start(
+        config: &WebhookConfig,
+        rules: AlertRules,
+        accounts: impl IntoIterator<Item = (ClientId, AccountView<'static>)>,
+        events: impl IntoIterator<Item = AccountEvent> + Send + 'static,
+    ) -> io::Result<Self> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(io::Error::other)?;
+        let mut queues = Vec::with_capacity(config.urls.len());
+        let mut workers = Vec::with_capacity(config.urls.len());
+        for url in &config.urls {
+            let (queue, callbacks) = sync_channel(WEBHOOK_QUEUE);
+            let worker = Worker {
+                client: client.clone(),
+                url: url.clone(),
+                secret: config.secret.clone(),
+                retries: config.retries(),
+                backoff: config.backoff(),
+            };
+            workers.push(thread::spawn(move || worker.run(callbacks)));
+            queues.push((url.clone(), queue));
+        }
+        // Alerts already raised aren't raised again, they're not news.
+        let mut monitor = AlertMonitor::new(rules).quiet();
+        monitor.update(accounts);
+        let dispatcher = thread::spawn(move || dispatch(events, monitor, queues));
+        Ok(Webhooks {
+            dispatcher,
+            workers,
+        })
+    }
+
+    /// Wait for the events to end, and the callbacks for them to be delivered or dropped.
+    pub fn join(self) {
+        let _ = self.dispatcher.join();
+        for worker in self.workers {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn dispatch(
+    events: impl IntoIterator<Item = AccountEvent>,
+    mut monitor: AlertMonitor,
+    queues: Vec<(String, SyncSender<Arc<Callback>>)>,
+) {
+    // Unique across runs, as ids start over.
+    let run = RandomState::new().hash_one(std::process::id());
+    let mut sent = 0u64;
+    for event in events {
+        for body in callbacks(&event, &mut monitor) {
+            let callback = Arc::new(Callback {
+                id: format!("{run:016x}-{sent}"),
+                body,
+            });
+            sent += 1;
+            for (url, queue) in &queues {
+                if let Err(TrySendError::Full(callback)) = queue.try_send(callback.clone()) {
+                    warn!(
+                        url,
+                        id = callback.id,
+                        "webhook queue full, callback dropped"
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Bodies of the callbacks for an event.
+fn callbacks(event: &AccountEvent, monitor: &mut AlertMonitor) -> Vec<String> {
+    let mut bodies = Vec::new();
+    if event.transaction.kind == TransactionKind::Chargeback {
+        bodies.push(with_event("chargeback", &event.to_json()));
+        if event.balances.locked {
+            let b = event.balances;
+            bodies.push(format!(
+                "{{\"event\":\"frozen\",\"at\":{},\"client\":{},\"available\":\"{}\",\
+                 \"held\":\"{}\",\"total\":\"{}\",\"locked\":true}}",
+                event.at, event.client_id, b.available, b.held, b.total
+            ));
+        }
+    }
+    for alert in monitor.update([(event.client_id, event.balances)]) {
+        bodies.push(with_event("alert", &alert.to_json()));
+    }
+    bodies
+}
+
+/// A JSON object with an `event` field first.
+fn with_event(event: &str, object: &str) -> String {
+    format!("{{\"event\":\"{event}\",{}", &object[1..])
+}
+
+/// Delivers the callbacks of one URL in order.
+struct Worker {
+    client: Client,
+    url: String,
+    secret: Option<String>,
+    retries: u32,
+    backoff: Duration,
+}
+
+impl Worker {
+    fn run(&self, callbacks: Receiver<Arc<Callback>>) {
+        for callback in callbacks {
+            self.deliver(&callback);
+        }
+    }
+
+    fn deliver(&self, callback: &Callback) {
+        let (url, id) = (self.url.as_str(), callback.id.as_str());
+        let mut backoff = self.backoff;
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            let mut request = self
+                .client
+                .post(url)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("x-payengine-delivery", id)
+                .body(callback.body.clone());
+            if let Some(secret) = &self.secret {
+                let time = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                request = request.header(
+                    "x-payengine-signature",
+                    signature(secret.as_bytes(), time, &callback.body),
+                );
+            }
+            match request.send() {
+                Ok(response) if response.status().is_success() => {
+                    debug!(url, id, attempt, "webhook delivered");
+                    return;
+                }
+                Ok(response) if !retryable(response.status()) => {
+                    let status = response.status().as_u16();
+                    warn!(url, id, status, "webhook refused, callback dropped");
+                    return;
+                }
+                Ok(response) => {
+                    let status = response.status().as_u16();
+                    debug!(url, id, attempt, status, "webhook failed");
+                }
+                Err(e) => debug!(url, id, attempt, error = %e, "webhook failed"),
+            }
+        }
+        warn!(
+            url,
+            id,
+            retries = self.retries,
+            "webhook failed after all retries, callback dropped"
+        );
+    }
+}
+
+fn retryable(status: StatusCode) -> bool {
+    !status.is_client_error()
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// `t=<time>,v1=<hex>` of the `X-Payengine-Signature` header.
+fn signature(secret: &[u8], time: u64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("any key length");
+    mac.update(format!("{time}.{body}").as_bytes());
+    let mut out = format!("t={time},v1=");
+    for b in mac.finalize().into_bytes() {
+        write!(out, "{b:02x}").unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::mpsc::channel,
+        thread,
+    };
+
+    use crate::{
+        accounts::{AccountView, Transaction, TransactionKind},
+        alerts::AlertRules,
+        amount::Amount,
+        config::WebhookConfig,
+        events::AccountEvent,
+        webhooks::{Webhooks, signature},
+    };
+
+    #[test]
+    fn test_signature() {
+        // As of `printf '1700000000.{}' | openssl dgst -sha256 -hmac s3cret`.
+        assert_eq!(
+            signature(b"s3cret", 1700000000, "{}"),
+            "t=1700000000,v1=\
+             97926816e98fbb41ccb1673225ff29a2f35369099990e1b1561651e7bd097ebf"
+        );
+    }
+
+    /// Headers, lowercased, and body of each request, answering 503 to the first and 200 to the
+    /// others.
+    fn receive(listener: TcpListener, requests: usize) -> Vec<(String, String)> {
+        let mut received = Vec::new();
+        for (i, stream) in listener.incoming().take(requests).enumerate() {
+            let mut stream = BufReader::new(stream.unwrap());
+            let mut head = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                stream.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                let line = line.to_ascii_lowercase();
+                if let Some(value) = line.strip_prefix("content-length: ") {
+                    length = value.trim().parse().unwrap();
+                }
+                head.push_str(&line);
+            }
+            let mut body = vec![0; length];
+            stream.read_exact(&mut body).unwrap();
+            let status = if i == 0 {
+                "503 Service Unavailable"
+            } else {
+                "200 OK"
+            };
+            write!(
+                stream.get_mut(),
+                "HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            )
+            .unwrap();
+            received.push((head, String::from_utf8(body).unwrap()));
+        }
+        received
+    }
+
+    #[test]
+    fn test_webhooks() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let receiver = thread::spawn(move || receive(listener, 4));
+
+        let amount = |s: &str| Amount::parse(s.as_bytes()).unwrap();
+        let config = WebhookConfig {
+            urls: vec![url],
+            secret: Some("s3cret".into()),
+            retries: Some(1),
+            backoff_ms: Some(1),
+        };
+        let rules = AlertRules {
+            account_total: Some(amount("1")),
+            ..Default::default()
+        };
+        let (events, subscribed) = channel();
+        let webhooks = Webhooks::start(&config, rules, [], subscribed).unwrap();
+        let view = |total: &str, locked| AccountView {
+            available: amount(total),
+            total: amount(total),
+            locked,
+            ..Default::default()
+        };
+        let event = |at, kind, total, locked| AccountEvent {
+            at,
+            client_id: 7,
+            transaction: Transaction {
+                kind,
+                id: 1,
+                amount: amount(if at == 1 { "2" } else { "0" }),
+            },
+            balances: view(total, locked),
+        };
+        events
+            .send(event(1, TransactionKind::Deposit, "2", false))
+            .unwrap();
+        events
+            .send(event(2, TransactionKind::Dispute, "2", false))
+            .unwrap();
+        events
+            .send(event(3, TransactionKind::Chargeback, "0", true))
+            .unwrap();
+        drop(events);
+        webhooks.join();
+
+        let received = receiver.join().unwrap();
+        let header = |head: &str, name: &str| {
+            head.lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(": "))
+                .unwrap()
+                .trim()
+                .to_owned()
+        };
+        // The first attempt failed, and was retried.
+        assert_eq!(received[0].1, received[1].1);
+        assert_eq!(
+            header(&received[0].0, "x-payengine-delivery"),
+            header(&received[1].0, "x-payengine-delivery")
+        );
+        let bodies = received[1..]
+            .iter()
+            .map(|(_, body)| body.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            bodies,
+            [
+                r#"{"event":"alert","alert":"account","client":7,"amount":"2","limit":"1"}"#,
+                r#"{"event":"chargeback","at":3,"client":7,"type":"chargeback","tx":1,"available":"0","held":"0","total":"0","locked":true}"#,
+                r#"{"event":"frozen","at":3,"client":7,"available":"0","held":"0","total":"0","locked":true}"#,
+            ]
+        );
+        let (head, body) = &received[1];
+        let time = header(head, "x-payengine-signature")
+            .strip_prefix("t=")
+            .unwrap()
+            .split_once(',')
+            .unwrap()
+            .0
+            .parse()
+            .unwrap();
+        assert_eq!(
+            header(head, "x-payengine-signature"),
+            signature(b"s3cret", time, body)
+        );
+        assert_ne!(
+            header(head, "x-payengine-delivery"),
+            header(&received[2].0, "x-payengine-delivery")
+        );
+    }
+}
@@ -0,0 +1,47 @@
+//! Build tasks of the workspace, run as `cargo xtask <task>`:
+//!
+//! - `man [DIR]` - write the man pages `payengine.1` and `payengine-<subcommand>.1` to DIR,
+//!   `target/man` by default, from the clap definitions of `payengine::args`. Subcommands of
+//!   optional features are only included when built with them, e.g. with
+//!   `cargo run -p xtask --features payengine/server -- man`.
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::CommandFactory;
+use payengine::args::Cli;
+
+fn main() -> ExitCode {
+    let mut args = env::args().skip(1);
+    match (args.next().as_deref(), args.next(), args.next()) {
+        (Some("man"), dir, None) => {
+            let dir = dir.map_or_else(default_man_dir, PathBuf::from);
+            match std::fs::create_dir_all(&dir)
+                .and_then(|()| clap_mangen::generate_to(Cli::command(), &dir))
+            {
+                Ok(()) => {
+                    println!("wrote man pages to {}", dir.display());
+                    ExitCode::SUCCESS
+                }
+                Err(e) => {
+                    eprintln!("error writing man pages to {}: {e}", dir.display());
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        _ => {
+            eprintln!("usage: cargo xtask man [DIR]");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn default_man_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .unwrap()
+        .join("target/man")
+}